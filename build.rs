@@ -1,8 +1,28 @@
-use cmake::Config;
+use std::path::Path;
+use std::process::Command;
 
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(native_parser)");
+    if cfg!(feature = "native-parser") && native_parser_available() {
+        build_native_parser();
+    }
+}
+
+// The parser needs its sources and CMake to be built. Without either, the
+// compiler is still built, and `parser.rs` reports the missing parser.
+fn native_parser_available() -> bool {
+    let cmake = Command::new("cmake").arg("--version").output();
+    if !Path::new("parser/CMakeLists.txt").exists() || cmake.is_err() {
+        println!("cargo:warning=native parser unavailable, only `.events` inputs can be compiled");
+        return false;
+    }
+    true
+}
+
+#[cfg(feature = "native-parser")]
+fn build_native_parser() {
     println!("cargo:rerun-if-changed=parser/parser.cpp");
-    let dst = Config::new("parser").profile("Release").build();
+    let dst = cmake::Config::new("parser").profile("Release").build();
     println!("cargo:rustc-link-search=native={}", dst.display());
     println!("cargo:rustc-link-search=native=parser",);
     println!("cargo:rustc-link-lib=static=CBaseListener");
@@ -11,4 +31,8 @@ fn main() {
     println!("cargo:rustc-link-lib=static=CParser");
     println!("cargo:rustc-link-lib=static=parser");
     println!("cargo:rustc-link-lib=dylib=antlr4-runtime");
+    println!("cargo:rustc-cfg=native_parser");
 }
+
+#[cfg(not(feature = "native-parser"))]
+fn build_native_parser() {}
@@ -0,0 +1,107 @@
+# A driver program that prints the content of the `eax` register in signed decimal.
+# Compile command: clang -e _drive driver-macos.s main.s -o main
+
+    .section __DATA,__data
+buffer:
+    .ascii "-----------"
+stack_limit:
+    .quad 0
+overflow:
+    .ascii "error: stack overflow, the recursion is too deep\n"
+
+    .section __TEXT,__text
+
+    .globl _drive
+_drive:
+    # Let functions compiled with `--stack-guard` use 512 KiB of the stack.
+    leaq -524288(%rsp), %rax
+    movq %rax, stack_limit(%rip)
+    # Save caller-saved registers.
+    pushq %rcx
+    pushq %rdx
+    pushq %r8
+    pushq %r9
+    pushq %r10
+    pushq %r11
+    # Allocate the stack frame.
+    subq $1024, %rsp
+    # Set the stack frame pointer.
+    movq %rsp, %rbp
+
+    # Call the `main` function.
+    call _main
+
+    # Clean the stack.
+    addq $1024, %rsp
+    # Restore caller-saved registers.
+    popq %r11
+    popq %r10
+    popq %r9
+    popq %r8
+    popq %rdx
+    popq %rcx
+
+    # Print the result.
+    call print_dec
+    # Exit the program, with the stack aligned as the System V ABI requires.
+    andq $-16, %rsp
+    xorl %edi, %edi
+    call _exit
+
+print_dec:
+    # Compute the decimal form of `eax`.
+    movl $10, %ebx
+    xorl %ecx, %ecx # Set a flag showing `eax` is not a negative number.
+    leaq buffer(%rip), %r8
+    movl $11, %r9d
+    cmpl $0, %eax
+    jge WhileNotZero
+    negl %eax
+    movl $1, %ecx # Set a flag showing `eax` is a negative number.
+WhileNotZero:
+    xorl %edx, %edx
+    divl %ebx
+    addl $48, %edx # Convert the remainder to an ASCII digit.
+    decl %r9d
+    movb %dl, (%r8,%r9)
+    cmpl $0, %eax
+    jg WhileNotZero
+    subl %ecx, %r9d # `r9d` will be the buffer offset.
+
+    # Print to the standard output.
+    movl $11, %edx
+    subl %r9d, %edx
+    leaq (%r8,%r9), %rsi
+    movl $1, %edi
+    call _write
+
+    ret
+
+# Check an `assert`: return if `rcx` is not zero, otherwise print the
+# message of `r8` bytes at `rdx` to the standard error and exit with 1.
+    .globl _eac_assert
+_eac_assert:
+    cmpq $0, %rcx
+    jne Holds
+    movq %rdx, %rsi
+    movq %r8, %rdx
+    movl $2, %edi
+    andq $-16, %rsp
+    call _write
+    movl $1, %edi
+    call _exit
+Holds:
+    ret
+
+# Check the stack of a function compiled with `--stack-guard`: return if it
+# is within the limit, otherwise fail like an `assert` with an overflow message.
+    .globl _eac_stack_check
+_eac_stack_check:
+    cmpq stack_limit(%rip), %rsp
+    jae Fits
+    xorq %rcx, %rcx
+    leaq overflow(%rip), %rdx
+    movq $49, %r8
+    jmp _eac_assert
+Fits:
+    ret
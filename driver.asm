@@ -8,12 +8,18 @@ extern main: proc
 
 .data
     std_out dword -11
+    std_err dword -12
     buffer byte '-----------'
     len dword 11
     written dword 0
+    stack_limit qword 0
+    overflow byte "error: stack overflow, the recursion is too deep", 10
 
 .code
     drive proc
+        ; Let functions compiled with `--stack-guard` use 512 KiB of the stack.
+        lea rax, [rsp-524288]
+        mov stack_limit, rax
         ; Save caller-saved registers.
         push RCX
         push RDX
@@ -22,7 +28,7 @@ extern main: proc
         push R10
         push R11
         ; Allocate the stack frame.
-        sub RSP, 512
+        sub RSP, 1024
         ; Set the stack frame pointer.
         mov RBP, RSP
 
@@ -30,7 +36,7 @@ extern main: proc
         call main
 
         ; Clean the stack.
-        add RSP, 512
+        add RSP, 1024
         ; Restore caller-saved registers.
         pop R11
         pop R10
@@ -78,4 +84,37 @@ extern main: proc
 
         ret
     print_dec endp
+
+    ; Check an `assert`: return if `rcx` is not zero, otherwise print the
+    ; message of `r8` bytes at `rdx` to the standard error and exit with 1.
+    eac_assert proc
+        cmp rcx, 0
+        jne Holds
+        mov rsi, rdx
+        mov edi, r8d
+        mov ecx, std_err
+        call GetStdHandle
+        mov rcx, rax
+        mov rdx, rsi
+        mov r8d, edi
+        lea r9, written
+        call WriteFile
+        mov rcx, 1
+        call ExitProcess
+        Holds:
+        ret
+    eac_assert endp
+
+    ; Check the stack of a function compiled with `--stack-guard`: return if it
+    ; is within the limit, otherwise fail like an `assert` with an overflow message.
+    eac_stack_check proc
+        cmp rsp, stack_limit
+        jae Fits
+        xor rcx, rcx
+        lea rdx, overflow
+        mov r8, 49
+        jmp eac_assert
+        Fits:
+        ret
+    eac_stack_check endp
 end
@@ -0,0 +1,121 @@
+# A driver program that prints the content of the `eax` register in signed decimal.
+# Compile command: gcc -nostartfiles -Wl,-e,drive driver.s main.s -o main.exe -lkernel32
+
+    .data
+std_out:
+    .long -11
+std_err:
+    .long -12
+buffer:
+    .ascii "-----------"
+len:
+    .long 11
+written:
+    .long 0
+stack_limit:
+    .quad 0
+overflow:
+    .ascii "error: stack overflow, the recursion is too deep\n"
+
+    .text
+
+    .globl drive
+drive:
+    # Let functions compiled with `--stack-guard` use 512 KiB of the stack.
+    leaq -524288(%rsp), %rax
+    movq %rax, stack_limit(%rip)
+    # Save caller-saved registers.
+    pushq %rcx
+    pushq %rdx
+    pushq %r8
+    pushq %r9
+    pushq %r10
+    pushq %r11
+    # Allocate the stack frame.
+    subq $1024, %rsp
+    # Set the stack frame pointer.
+    movq %rsp, %rbp
+
+    # Call the `main` function.
+    call main
+
+    # Clean the stack.
+    addq $1024, %rsp
+    # Restore caller-saved registers.
+    popq %r11
+    popq %r10
+    popq %r9
+    popq %r8
+    popq %rdx
+    popq %rcx
+
+    # Print the result.
+    call print_dec
+    # Exit the program.
+    xorq %rcx, %rcx
+    call ExitProcess
+
+print_dec:
+    # Compute the decimal form of `eax`.
+    movl $10, %ebx
+    xorl %ecx, %ecx # Set a flag showing `eax` is not a negative number.
+    leaq buffer(%rip), %r8
+    movl len(%rip), %r9d
+    cmpl $0, %eax
+    jge WhileNotZero
+    negl %eax
+    movl $1, %ecx # Set a flag showing `eax` is a negative number.
+WhileNotZero:
+    xorl %edx, %edx
+    divl %ebx
+    addl $48, %edx # Convert the remainder to an ASCII digit.
+    decl %r9d
+    movb %dl, (%r8,%r9)
+    cmpl $0, %eax
+    jg WhileNotZero
+    subl %ecx, %r9d # `r9d` will be the buffer offset.
+    subl %r9d, len(%rip) # `len` will be the buffer length.
+
+    # Print to the standard output.
+    movl std_out(%rip), %ecx
+    call GetStdHandle
+    movq %rax, %rcx
+    leaq (%r8,%r9), %rdx
+    movl len(%rip), %r8d
+    leaq written(%rip), %r9
+    call WriteFile
+
+    ret
+
+# Check an `assert`: return if `rcx` is not zero, otherwise print the
+# message of `r8` bytes at `rdx` to the standard error and exit with 1.
+    .globl eac_assert
+eac_assert:
+    cmpq $0, %rcx
+    jne Holds
+    movq %rdx, %rsi
+    movl %r8d, %edi
+    movl std_err(%rip), %ecx
+    call GetStdHandle
+    movq %rax, %rcx
+    movq %rsi, %rdx
+    movl %edi, %r8d
+    leaq written(%rip), %r9
+    call WriteFile
+    movq $1, %rcx
+    call ExitProcess
+Holds:
+    ret
+
+# Check the stack of a function compiled with `--stack-guard`: return if it
+# is within the limit, otherwise fail like an `assert` with an overflow message.
+    .globl eac_stack_check
+eac_stack_check:
+    cmpq stack_limit(%rip), %rsp
+    jae Fits
+    xorq %rcx, %rcx
+    leaq overflow(%rip), %rdx
+    movq $49, %r8
+    jmp eac_assert
+Fits:
+    ret
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+
+// IR used in the parser.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct SSAVar {
+    pub name: String,
+    pub subscript: Option<usize>,
+}
+
+impl SSAVar {
+    pub fn new(name: &str) -> Self {
+        SSAVar {
+            name: name.to_string(),
+            subscript: None,
+        }
+    }
+}
+
+// Integer constants and variables are 64-bit, the width of the x64 registers
+// holding them.
+pub type Int = i64;
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Expression {
+    Identifier(SSAVar),
+    Number(Int),
+    // A `double` constant, kept as its bits so that expressions stay hashable.
+    Float(u64),
+    Call {
+        function: Box<Expression>,
+        arguments: Box<Expression>,
+    },
+    Arguments(Vec<Expression>),
+    // `-`, `!`, the dereference `*`, and the address-of `&`, whose operand is
+    // a variable or an array element.
+    Prefix {
+        operator: &'static str,
+        expression: Box<Expression>,
+    },
+    Infix {
+        left: Box<Expression>,
+        operator: &'static str,
+        right: Box<Expression>,
+    },
+    // `array[i][j]...`, with one index per dimension. Arrays live on the stack
+    // rather than in SSA variables, so `array` never gets a subscript.
+    Index {
+        array: SSAVar,
+        indices: Vec<Expression>,
+    },
+    // `condition ? consequent : alternative`, which evaluates only one branch.
+    Conditional {
+        condition: Box<Expression>,
+        consequent: Box<Expression>,
+        alternative: Box<Expression>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Nop,                               // For CFG use only.
+    Phi(SSAVar, Vec<(usize, SSAVar)>), // For SSA use only: the value from each predecessor.
+    Declaration(SSAVar),
+    ArrayDeclaration(SSAVar, Vec<usize>), // The length of every dimension.
+    Compound(Vec<Statement>),
+    Expression(Expression),
+    If {
+        condition: Expression,
+        body: Box<Statement>,
+        alternative: Option<Box<Statement>>,
+    },
+    While {
+        condition: Expression,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+    },
+    Break,
+    Continue,
+    Return(Option<Expression>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub line: usize,
+    pub void: bool,
+    pub double: bool, // Whether it returns a `double`.
+    pub name: String,
+    pub parameters: Vec<SSAVar>,
+    // The variables and parameters declared `double`. A name is never
+    // declared with both types in one function, so shadowing keeps the type.
+    pub doubles: HashSet<String>,
+    pub body: Statement,
+}
+
+pub type Program = Vec<Function>;
+
+// A function declared by a prototype, such as `int f(int);`, so that it may be
+// called before its definition, or defined in another unit or nowhere at all.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Prototype {
+    pub line: usize,
+    pub void: bool,
+    pub double: bool,
+    pub name: String,
+    pub parameters: Vec<bool>, // Whether each parameter is a `double`.
+    pub variadic: bool,        // Whether `...` ends the parameters.
+}
+
+// An optimization that a function may turn off with an `// eac: no-<name>`
+// comment right above its definition.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Optimization {
+    Fold,      // `range::fold_comparisons`
+    Available, // `available::eliminate`
+    Pre,       // `pre::eliminate`
+    Lvn,       // `lvn::number`
+    Schedule,  // `scheduler::schedule`
+}
+
+// The pragmas of every function that has any, by name.
+pub type Pragmas = HashMap<String, HashSet<Optimization>>;
+
+impl Prototype {
+    // Whether both declare the same return and parameter types.
+    pub fn agrees(&self, other: &Prototype) -> bool {
+        (self.void, self.double, &self.parameters, self.variadic)
+            == (other.void, other.double, &other.parameters, other.variadic)
+    }
+}
+
+impl Function {
+    // The prototype the definition amounts to.
+    pub fn prototype(&self) -> Prototype {
+        Prototype {
+            line: self.line,
+            void: self.void,
+            double: self.double,
+            name: self.name.clone(),
+            parameters: self
+                .parameters
+                .iter()
+                .map(|param| self.doubles.contains(&param.name))
+                .collect(),
+            variadic: false,
+        }
+    }
+}
+
+// IR used in the data-flow analysis.
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub predecessors: HashSet<usize>,
+    pub successors: HashSet<usize>,
+    pub edges: HashMap<usize, Edge>, // The role of the edge to each successor.
+}
+
+// The role of a CFG edge, which tells the code generator how to reach the successor.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edge {
+    Next,     // Fall through to the next block.
+    Then,     // From an `if` condition into its body.
+    Else,     // From an `if` condition to its alternative, or past its body if there is none.
+    Skip,     // From the end of an `if` body over its alternative.
+    Body,     // From a `while` condition into its body.
+    Exit,     // From a loop condition out of the loop.
+    Back,     // From the end of a `while` body to its condition, or a `do` condition to its body.
+    Break,    // From a `break` past the end of its loop.
+    Continue, // From a `continue` to the condition of its loop.
+    Return,   // From a `return`, or the end of the body, to the exit block.
+}
+
+pub type CFG = Vec<Block>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SSAFunction {
+    pub void: bool,
+    pub double: bool,
+    pub name: String,
+    pub parameters: Vec<SSAVar>,
+    pub doubles: HashSet<String>,
+    pub body: CFG,
+}
+
+impl SSAFunction {
+    pub fn is_double(&self, var: &SSAVar) -> bool {
+        declared_double(&self.doubles, var)
+    }
+}
+
+// Whether `var` holds a `double`, whatever suffix tells it apart from the
+// variables it shadows.
+pub fn declared_double(doubles: &HashSet<String>, var: &SSAVar) -> bool {
+    doubles.contains(var.name.split('.').next().unwrap())
+}
+
+pub type SSAProgram = Vec<SSAFunction>;
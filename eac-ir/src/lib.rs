@@ -0,0 +1,18 @@
+//! The intermediate representations of `eac-compiler`, for passes and tools
+//! built outside of it:
+//!
+//! - `ir`: the AST the parser builds, and the SSA and CFG functions made from
+//!   it, whose bodies are blocks of the same statements.
+//! - `lir`: the lowered three-address form of a CFG function, over virtual
+//!   registers, that a backend selects instructions from.
+//! - `x64`: x64 assembly over virtual and physical registers, before and after
+//!   register allocation.
+//!
+//! The API follows semantic versioning. Before 1.0, a change a user of these
+//! types may have to adapt to, such as a new variant, field, or operator in an
+//! expression, bumps the minor version, and anything else only the patch
+//! version. Every release is listed in `CHANGELOG.md`. `eac-compiler`
+//! re-exports this crate as `eac_compiler::eac_ir`.
+pub mod ir;
+pub mod lir;
+pub mod x64;
@@ -0,0 +1,117 @@
+use crate::ir::Int;
+
+// The lowered IR between the CFG and a target. A function is a flat list of
+// three-address instructions over virtual registers: every operand is a
+// register, memory is only reached through explicit loads and stores, and
+// control flow is labels, jumps, and conditional jumps. Types, array
+// positions, and short-circuit and conditional operators are all resolved,
+// so a backend only picks instructions and lays out its calling convention.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Var {
+    Int(usize),
+    Double(usize),
+    // The result of a comparison that only a conditional jump reads, which a
+    // target may keep in its flags instead of a register. Numbered apart
+    // from the other two, which share one numbering.
+    Cond(usize),
+}
+
+impl Var {
+    pub fn is_double(&self) -> bool {
+        matches!(self, Var::Double(_))
+    }
+}
+
+// The position of an array element, counted in elements.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Element {
+    Const(usize),
+    Var(Var),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Lir {
+    Param(Var, usize), // Param(dst, index), at the entry of the function.
+    Const(Var, Int),
+    // Bits(dst, src) reinterprets the bits of an integer as a `double`.
+    Bits(Var, Var),
+    Copy(Var, Var), // Copy(dst, src), within one type.
+    // Convert(dst, src) converts between an integer and a `double`,
+    // truncating toward zero.
+    Convert(Var, Var),
+    Unary(Var, &'static str, Var), // Unary(dst, operator, src), with `-`.
+    // Binary(dst, left, operator, right), with `+`, `-`, `*`, and `/` on
+    // either type, and `%`, `&`, and `|` on integers.
+    Binary(Var, Var, &'static str, Var),
+    // Compare(dst, left, operator, right) sets a `Cond` to whether the
+    // comparison holds.
+    Compare(Var, Var, &'static str, Var),
+    Load(Var, Var),  // Load(dst, address)
+    Store(Var, Var), // Store(address, src)
+    // Arrays are numbered by position, as in `X64`.
+    LoadElement(Var, usize, Element), // LoadElement(dst, array, element)
+    StoreElement(usize, Element, Var), // StoreElement(array, element, src)
+    Address(Var, usize, Var),         // Address(dst, array, index)
+    // Call(name, args, dst, variadic)
+    Call(String, Vec<Var>, Var, bool),
+    Label(String),
+    Jump(String),
+    // Jump if a `Cond` holds or an integer is nonzero, or unless it does.
+    JumpIf(Var, String),
+    JumpUnless(Var, String),
+    Return(Option<Var>),
+}
+
+impl Lir {
+    // The register the instruction writes, if any.
+    pub fn writes(&self) -> Option<Var> {
+        match self {
+            Lir::Param(dst, _)
+            | Lir::Const(dst, _)
+            | Lir::Bits(dst, _)
+            | Lir::Copy(dst, _)
+            | Lir::Convert(dst, _)
+            | Lir::Unary(dst, _, _)
+            | Lir::Binary(dst, _, _, _)
+            | Lir::Compare(dst, _, _, _)
+            | Lir::Load(dst, _)
+            | Lir::LoadElement(dst, _, _)
+            | Lir::Address(dst, _, _)
+            | Lir::Call(_, _, dst, _) => Some(*dst),
+            _ => None,
+        }
+    }
+
+    // The registers the instruction reads, in order.
+    pub fn reads(&self) -> Vec<Var> {
+        match self {
+            Lir::Bits(_, src)
+            | Lir::Copy(_, src)
+            | Lir::Convert(_, src)
+            | Lir::Unary(_, _, src)
+            | Lir::Load(_, src)
+            | Lir::Address(_, _, src)
+            | Lir::JumpIf(src, _)
+            | Lir::JumpUnless(src, _)
+            | Lir::Return(Some(src)) => vec![*src],
+            Lir::Binary(_, left, _, right)
+            | Lir::Compare(_, left, _, right)
+            | Lir::Store(left, right) => vec![*left, *right],
+            Lir::LoadElement(_, _, Element::Var(index)) => vec![*index],
+            Lir::StoreElement(_, Element::Var(index), src) => vec![*index, *src],
+            Lir::StoreElement(_, Element::Const(_), src) => vec![*src],
+            Lir::Call(_, args, _, _) => args.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LirFunction {
+    pub name: String,
+    pub param_cnt: usize,
+    pub arrays: Vec<usize>, // The length of every array, in order of numbering.
+    pub body: Vec<Lir>,
+}
+
+pub type LirProgram = Vec<LirFunction>;
@@ -0,0 +1,328 @@
+use crate::ir::Int;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Register {
+    Virtual(VRegister),
+    X64(X64Register),
+    VirtualXmm(VRegister), // A virtual register holding a `double`.
+}
+
+impl Register {
+    pub fn class(&self) -> RegisterClass {
+        match self {
+            Register::VirtualXmm(_)
+            | Register::X64(
+                X64Register::XMM0
+                | X64Register::XMM1
+                | X64Register::XMM2
+                | X64Register::XMM3
+                | X64Register::XMM4
+                | X64Register::XMM5,
+            ) => RegisterClass::Xmm,
+            _ => RegisterClass::General,
+        }
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Register::Virtual(i) => write!(f, "VR{}", i),
+            Register::VirtualXmm(i) => write!(f, "VX{}", i),
+            Register::X64(reg) => write!(f, "{:?}", reg),
+        }
+    }
+}
+
+// General-purpose registers hold integers and addresses, and XMM registers
+// hold `double` values. A virtual register is only ever allocated a physical
+// register of its own class.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RegisterClass {
+    General,
+    Xmm,
+}
+
+pub type VRegister = usize;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum X64Register {
+    RAX,
+    RBX,
+    RCX,
+    RDX,
+    RBP,
+    RSI,
+    RDI,
+    RSP,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+    // Only the XMM registers a callee may clobber are used, so none of them
+    // is ever saved in a prologue.
+    XMM0,
+    XMM1,
+    XMM2,
+    XMM3,
+    XMM4,
+    XMM5,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum X64 {
+    // Param(reg, index) defines `reg` as the parameter at `index`, wherever
+    // the calling convention passes it. The entry of every function defines
+    // its parameters this way, and register allocation binds each register
+    // to its location instead of emitting anything.
+    Param(Register, usize),
+    MovNum(Register, Int),
+    // The moves take registers of either class: `movsd` moves a `double`
+    // between XMM registers or to and from the stack, and `movq` moves the
+    // bits between classes.
+    MovReg(Register, Register),
+    MovToStack(usize, Register),   // MovToStack(offset, reg)
+    MovFromStack(Register, usize), // MovFromStack(reg, offset)
+    // The array is numbered by the builder and placed at an offset from RSP
+    // by register allocation. The index is scaled by the element size.
+    LoadIndex(Register, usize, Register), // LoadIndex(reg, array, index)
+    StoreIndex(usize, Register, Register), // StoreIndex(array, index, reg)
+    LeaIndex(Register, usize, Register),  // LeaIndex(reg, array, index), the address of an element.
+    // Lea(reg, base, index, scale) computes `base + index * scale` without
+    // touching memory or the flags, where the scale is 1, 2, 4, or 8.
+    Lea(Register, Register, Register, u8),
+    // An element at a constant position, such as a struct member, is
+    // addressed by its offset in bytes from the start of the array.
+    LoadField(Register, usize, usize), // LoadField(reg, array, offset)
+    StoreField(usize, usize, Register), // StoreField(array, offset, reg)
+    Load(Register, Register),          // Load(reg, address)
+    Store(Register, Register),         // Store(address, reg)
+    // Call(name, args, ret_reg, variadic), where a variadic callee also takes
+    // the `double` arguments passed in registers in the general ones.
+    Call(String, Vec<Register>, Register, bool),
+    Neg(Register),
+    CmpNum(Register, i32),
+    CmpReg(Register, Register),
+    Jl(String),
+    Jg(String),
+    Jle(String),
+    Jge(String),
+    Je(String),
+    Jne(String),
+    Jmp(String),
+    Tag(String),
+    Imul(Register, Register),
+    Idiv(Register, Register), // The left operand is RAX once registers are allocated.
+    Irem(Register, Register), // Lowered to `idiv` by register allocation.
+    Cqo,
+    Add(Register, Register),
+    AddNum(Register, usize), // Used only in stack manipulation.
+    Sub(Register, Register),
+    SubNum(Register, usize), // Used only in stack manipulation.
+    And(Register, Register),
+    Or(Register, Register),
+    Xor(Register, Register),       // Only emitted by the final cleanup.
+    Test(Register, Register),      // Only emitted by the final cleanup.
+    Cvtsi2sd(Register, Register),  // Cvtsi2sd(xmm, reg), an integer to a `double`.
+    Cvttsd2si(Register, Register), // Cvttsd2si(reg, xmm), a `double` truncated to an integer.
+    Addsd(Register, Register),
+    Subsd(Register, Register),
+    Mulsd(Register, Register),
+    Divsd(Register, Register),
+    // Compares two `double` values, setting the flags the way an unsigned
+    // comparison does, so it is followed by `jb`, `ja`, `jbe`, or `jae`.
+    Ucomisd(Register, Register),
+    Jb(String),
+    Ja(String),
+    Jbe(String),
+    Jae(String),
+    Ret(Option<Register>),
+    Push(Register),
+    Pop(Register),
+}
+
+impl Display for X64 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            X64::Param(reg, index) => write!(f, "; {} = parameter {}", reg, index),
+            X64::MovNum(reg, num) => write!(f, "mov {}, {}", reg, num),
+            X64::MovReg(left, right) => {
+                let op = match (left.class(), right.class()) {
+                    (RegisterClass::General, RegisterClass::General) => "mov",
+                    (RegisterClass::Xmm, RegisterClass::Xmm) => "movsd",
+                    _ => "movq",
+                };
+                write!(f, "{} {}, {}", op, left, right)
+            }
+            X64::MovToStack(offset, reg) => write!(f, "{} {}[RBP], {}", mov(reg), offset, reg),
+            X64::MovFromStack(reg, offset) => write!(f, "{} {}, {}[RBP]", mov(reg), reg, offset),
+            X64::LoadIndex(reg, array, index) => {
+                write!(f, "mov {}, {}[RSP+{}*8]", reg, array, index)
+            }
+            X64::StoreIndex(array, index, reg) => {
+                write!(f, "mov {}[RSP+{}*8], {}", array, index, reg)
+            }
+            X64::LeaIndex(reg, array, index) => {
+                write!(f, "lea {}, {}[RSP+{}*8]", reg, array, index)
+            }
+            X64::Lea(reg, base, index, scale) => {
+                write!(f, "lea {}, [{}+{}*{}]", reg, base, index, scale)
+            }
+            X64::LoadField(reg, array, offset) => {
+                write!(f, "mov {}, {}[RSP+{}]", reg, array, offset)
+            }
+            X64::StoreField(array, offset, reg) => {
+                write!(f, "mov {}[RSP+{}], {}", array, offset, reg)
+            }
+            X64::Load(reg, address) => write!(f, "mov {}, [{}]", reg, address),
+            X64::Store(address, reg) => write!(f, "mov [{}], {}", address, reg),
+            X64::Call(name, ..) => write!(f, "call {}", name),
+            X64::Neg(reg) => write!(f, "neg {}", reg),
+            X64::CmpNum(reg, num) => write!(f, "cmp {}, {}", reg, num),
+            X64::CmpReg(left, right) => write!(f, "cmp {}, {}", left, right),
+            X64::Jl(tag) => write!(f, "jl {}", tag),
+            X64::Jg(tag) => write!(f, "jg {}", tag),
+            X64::Jle(tag) => write!(f, "jle {}", tag),
+            X64::Jge(tag) => write!(f, "jge {}", tag),
+            X64::Je(tag) => write!(f, "je {}", tag),
+            X64::Jne(tag) => write!(f, "jne {}", tag),
+            X64::Jmp(tag) => write!(f, "jmp {}", tag),
+            X64::Tag(tag) => write!(f, "{}:", tag),
+            X64::Imul(left, right) => write!(f, "imul {}, {}", left, right),
+            // The dividend is implied, as `cqo` extends RAX into RDX:RAX.
+            X64::Idiv(_, right) => write!(f, "idiv {}", right),
+            X64::Irem(left, right) => write!(f, "irem {}, {}", left, right),
+            X64::Cqo => write!(f, "cqo"),
+            X64::Add(left, right) => write!(f, "add {}, {}", left, right),
+            X64::AddNum(reg, offset) => write!(f, "add {}, {}", reg, offset),
+            X64::Sub(left, right) => write!(f, "sub {}, {}", left, right),
+            X64::SubNum(reg, offset) => write!(f, "sub {}, {}", reg, offset),
+            X64::And(left, right) => write!(f, "and {}, {}", left, right),
+            X64::Or(left, right) => write!(f, "or {}, {}", left, right),
+            X64::Xor(left, right) => write!(f, "xor {}, {}", left, right),
+            X64::Test(left, right) => write!(f, "test {}, {}", left, right),
+            X64::Cvtsi2sd(left, right) => write!(f, "cvtsi2sd {}, {}", left, right),
+            X64::Cvttsd2si(left, right) => write!(f, "cvttsd2si {}, {}", left, right),
+            X64::Addsd(left, right) => write!(f, "addsd {}, {}", left, right),
+            X64::Subsd(left, right) => write!(f, "subsd {}, {}", left, right),
+            X64::Mulsd(left, right) => write!(f, "mulsd {}, {}", left, right),
+            X64::Divsd(left, right) => write!(f, "divsd {}, {}", left, right),
+            X64::Ucomisd(left, right) => write!(f, "ucomisd {}, {}", left, right),
+            X64::Jb(tag) => write!(f, "jb {}", tag),
+            X64::Ja(tag) => write!(f, "ja {}", tag),
+            X64::Jbe(tag) => write!(f, "jbe {}", tag),
+            X64::Jae(tag) => write!(f, "jae {}", tag),
+            X64::Ret(_) => write!(f, "ret"),
+            X64::Push(reg) => write!(f, "push {}", reg),
+            X64::Pop(reg) => write!(f, "pop {}", reg),
+        }
+    }
+}
+
+impl X64 {
+    pub fn jump_target(&self) -> Option<&String> {
+        match self {
+            X64::Jl(tag)
+            | X64::Jg(tag)
+            | X64::Jle(tag)
+            | X64::Jge(tag)
+            | X64::Je(tag)
+            | X64::Jne(tag)
+            | X64::Jb(tag)
+            | X64::Ja(tag)
+            | X64::Jbe(tag)
+            | X64::Jae(tag)
+            | X64::Jmp(tag) => Some(tag),
+            _ => None,
+        }
+    }
+
+    // All registers the instruction reads or writes.
+    pub fn registers(&self) -> Vec<Register> {
+        match self {
+            X64::Param(reg, _)
+            | X64::MovNum(reg, _)
+            | X64::MovToStack(_, reg)
+            | X64::MovFromStack(reg, _)
+            | X64::Neg(reg)
+            | X64::CmpNum(reg, _)
+            | X64::AddNum(reg, _)
+            | X64::SubNum(reg, _)
+            | X64::LoadField(reg, _, _)
+            | X64::StoreField(_, _, reg)
+            | X64::Push(reg)
+            | X64::Pop(reg)
+            | X64::Ret(Some(reg)) => vec![*reg],
+            X64::MovReg(left, right)
+            | X64::CmpReg(left, right)
+            | X64::Imul(left, right)
+            | X64::Idiv(left, right)
+            | X64::Irem(left, right)
+            | X64::Add(left, right)
+            | X64::Sub(left, right)
+            | X64::And(left, right)
+            | X64::Or(left, right)
+            | X64::Xor(left, right)
+            | X64::Test(left, right)
+            | X64::Cvtsi2sd(left, right)
+            | X64::Cvttsd2si(left, right)
+            | X64::Addsd(left, right)
+            | X64::Subsd(left, right)
+            | X64::Mulsd(left, right)
+            | X64::Divsd(left, right)
+            | X64::Ucomisd(left, right)
+            | X64::Load(left, right)
+            | X64::Store(left, right) => vec![*left, *right],
+            X64::LoadIndex(reg, _, index)
+            | X64::StoreIndex(_, index, reg)
+            | X64::LeaIndex(reg, _, index) => vec![*reg, *index],
+            X64::Lea(reg, base, index, _) => vec![*reg, *base, *index],
+            X64::Call(_, args, ret, _) => {
+                let mut regs = args.clone();
+                regs.push(*ret);
+                regs
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn jump_target_mut(&mut self) -> Option<&mut String> {
+        match self {
+            X64::Jl(tag)
+            | X64::Jg(tag)
+            | X64::Jle(tag)
+            | X64::Jge(tag)
+            | X64::Je(tag)
+            | X64::Jne(tag)
+            | X64::Jb(tag)
+            | X64::Ja(tag)
+            | X64::Jbe(tag)
+            | X64::Jae(tag)
+            | X64::Jmp(tag) => Some(tag),
+            _ => None,
+        }
+    }
+}
+
+// The move between `reg` and the stack.
+fn mov(reg: &Register) -> &'static str {
+    match reg.class() {
+        RegisterClass::General => "mov",
+        RegisterClass::Xmm => "movsd",
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct X64Function {
+    pub name: String,
+    pub param_cnt: usize,
+    pub arrays: Vec<usize>, // The length of every array, in order of numbering.
+    pub body: Vec<X64>,
+}
+
+pub type X64Program = Vec<X64Function>;
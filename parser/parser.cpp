@@ -1,4 +1,5 @@
 #include <cstring>
+#include <vector>
 
 #include "CBaseListener.h"
 #include "CLexer.h"
@@ -8,11 +9,171 @@
 typedef char *(*RsGetStr)(size_t len);
 typedef void (*RsEmitEvent)(char *tag, char *text);
 
+// The name of the struct a type specifier names, "!" if the struct has no
+// name, or "" for other types.
+static std::string structName(CParser::TypeSpecifierContext *type) {
+    auto spec{type->structOrUnionSpecifier()};
+    if (!spec) {
+        return "";
+    }
+    return spec->Identifier() ? spec->Identifier()->getText() : "!";
+}
+
+static std::string structName(CParser::SpecifierQualifierListContext *list) {
+    for (; list; list = list->specifierQualifierList()) {
+        if (auto type{list->typeSpecifier()}) {
+            return structName(type);
+        }
+    }
+    return "";
+}
+
+static std::string structName(CParser::DeclarationSpecifiersContext *specs) {
+    for (auto spec : specs->declarationSpecifier()) {
+        if (auto type{spec->typeSpecifier()}) {
+            return structName(type);
+        }
+    }
+    return "";
+}
+
+// Whether the specifiers declare a `double`.
+static bool isDouble(CParser::DeclarationSpecifiersContext *specs) {
+    for (auto spec : specs->declarationSpecifier()) {
+        if (auto type{spec->typeSpecifier()}; type && type->Double()) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Whether the specifiers declare a `void`.
+static bool isVoid(CParser::DeclarationSpecifiersContext *specs) {
+    for (auto spec : specs->declarationSpecifier()) {
+        if (auto type{spec->typeSpecifier()}; type && type->Void()) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// A function whose only parameter is a pointer, as in `int f(int *p)`, is
+// parsed with its name taken for a typedef name among the specifiers, and
+// its parameter for the declarator `'(' typeSpecifier? pointer
+// directDeclarator ')'` of a function pointer. Returns that parameter then.
+static CParser::DirectDeclaratorContext *pointerParameter(
+    CParser::DeclarationSpecifiersContext *specs,
+    CParser::DeclaratorContext *declarator) {
+    auto direct{declarator->directDeclarator()};
+    if (!specs || !direct->pointer() || !direct->typeSpecifier()) {
+        return nullptr;
+    }
+    for (auto spec : specs->declarationSpecifier()) {
+        if (auto type{spec->typeSpecifier()}; type && type->typedefName()) {
+            return direct;
+        }
+    }
+    return nullptr;
+}
+
+// The identifier of the function the declarator declares, found by walking
+// the chain of its direct declarators, or nullptr if there is none.
+static antlr4::tree::TerminalNode *functionName(
+    CParser::DeclarationSpecifiersContext *specs,
+    CParser::DeclaratorContext *declarator) {
+    if (pointerParameter(specs, declarator)) {
+        for (auto spec : specs->declarationSpecifier()) {
+            if (auto type{spec->typeSpecifier()}; type && type->typedefName()) {
+                return type->typedefName()->Identifier();
+            }
+        }
+    }
+    auto direct{declarator->directDeclarator()};
+    while (direct && !direct->Identifier()) {
+        direct = direct->declarator() ? direct->declarator()->directDeclarator()
+                                      : direct->directDeclarator();
+    }
+    return direct ? direct->Identifier() : nullptr;
+}
+
+// Whether the node is part of a function definition rather than of a
+// declaration, such as a prototype, outside or inside of one.
+static bool inDefinition(antlr4::tree::ParseTree *node) {
+    for (; node; node = node->parent) {
+        if (dynamic_cast<CParser::DeclarationContext *>(node)) {
+            return false;
+        }
+        if (dynamic_cast<CParser::FunctionDefinitionContext *>(node)) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Whether the declarator declares a function by name, as in `f(int a)`.
+static bool isFunction(CParser::DeclaratorContext *declarator) {
+    auto direct{declarator->directDeclarator()};
+    return direct->LeftParen() && direct->directDeclarator() &&
+           direct->directDeclarator()->Identifier();
+}
+
+// Whether the declaration only declares functions, so it is a prototype.
+static bool isPrototype(CParser::DeclarationContext *ctx) {
+    auto list{ctx->initDeclaratorList()};
+    for (; list; list = list->initDeclaratorList()) {
+        auto init{list->initDeclarator()};
+        if (init->initializer() || !isFunction(init->declarator())) {
+            return false;
+        }
+    }
+    return ctx->initDeclaratorList();
+}
+
+// The type of a parameter in a prototype, which may leave it unnamed: "int",
+// "double", "double*", "struct", "[" for an array, or "void" for `(void)`.
+static std::string parameterType(CParser::ParameterDeclarationContext *param) {
+    auto specs{param->declarationSpecifiers()
+                   ? param->declarationSpecifiers()->declarationSpecifier()
+                   : param->declarationSpecifiers2()->declarationSpecifier()};
+    CParser::TypeSpecifierContext *type{nullptr};
+    for (auto spec : specs) {
+        if (spec->typeSpecifier()) {
+            type = spec->typeSpecifier();
+            break;
+        }
+    }
+    bool pointer{false};
+    if (auto declarator{param->declarator()}) {
+        pointer = declarator->pointer();
+        if (declarator->directDeclarator()->LeftBracket()) {
+            return "[";
+        }
+    } else if (auto abstract{param->abstractDeclarator()}) {
+        pointer = abstract->pointer();
+    }
+    if (!type) {
+        return "int";
+    } else if (type->Void() && !pointer && !param->declarator()) {
+        return "void";
+    } else if (type->Double()) {
+        return pointer ? "double*" : "double";
+    } else if (!structName(type).empty() && !pointer) {
+        return "struct";
+    }
+    return "int";
+}
+
 class EventListener : public CBaseListener {
    public:
     EventListener(RsGetStr rsGetStr, RsEmitEvent rsEmitEvent)
         : rsGetStr(rsGetStr), rsEmitEvent(rsEmitEvent) {}
 
+    // Report the number of syntax errors instead of walking the tree, whose
+    // contexts may miss children the listener expects after error recovery.
+    void syntaxErrors(size_t count) {
+        this->emitEvent("SyntaxError", std::to_string(count).c_str());
+    }
+
     void exitPrimaryExpression(
         CParser::PrimaryExpressionContext *ctx) override {
         if (ctx->Identifier() || ctx->Constant()) {
@@ -22,8 +183,25 @@ class EventListener : public CBaseListener {
 
     void exitPostfixExpression(
         CParser::PostfixExpressionContext *ctx) override {
-        if (ctx->LeftParen() || ctx->argumentExpressionList()) {
-            this->emitEvent("ExitPostfixExpression", "");
+        if (ctx->LeftBracket()) {
+            this->emitEvent("ExitPostfixExpression", "[]");
+        } else if (ctx->Dot()) {
+            std::string member{"."};
+            member.append(ctx->Identifier()->getText());
+            this->emitEvent("ExitPostfixExpression", member.c_str());
+        } else if (ctx->LeftParen() || ctx->argumentExpressionList()) {
+            auto args = ctx->argumentExpressionList();
+            if (args && ctx->postfixExpression()->getText() == "assert") {
+                // Record the line and the source text of the condition.
+                std::string text{std::to_string(ctx->getStart()->getLine())};
+                text.push_back(' ');
+                text.append(ctx->getStart()->getInputStream()->getText(
+                    antlr4::misc::Interval(args->getStart()->getStartIndex(),
+                                           args->getStop()->getStopIndex())));
+                this->emitEvent("ExitAssertion", text.c_str());
+            } else {
+                this->emitEvent("ExitPostfixExpression", "");
+            }
         }
     }
 
@@ -38,6 +216,10 @@ class EventListener : public CBaseListener {
                 this->emitEvent("ExitUnaryExpression", "!");
             } else if (op->Minus()) {
                 this->emitEvent("ExitUnaryExpression", "-");
+            } else if (op->And()) {
+                this->emitEvent("ExitUnaryExpression", "&");
+            } else if (op->Star()) {
+                this->emitEvent("ExitUnaryExpression", "*");
             }
         }
     }
@@ -48,6 +230,8 @@ class EventListener : public CBaseListener {
             this->emitEvent("ExitMultiplicativeExpression", "*");
         } else if (ctx->Div()) {
             this->emitEvent("ExitMultiplicativeExpression", "/");
+        } else if (ctx->Mod()) {
+            this->emitEvent("ExitMultiplicativeExpression", "%");
         }
     }
 
@@ -103,14 +287,143 @@ class EventListener : public CBaseListener {
         }
     }
 
+    void exitConditionalExpression(
+        CParser::ConditionalExpressionContext *ctx) override {
+        if (ctx->Question()) {
+            this->emitEvent("ExitConditionalExpression", "");
+        }
+    }
+
+    // A struct definition as "struct name member...", where a member is
+    // "name" for an `int` or a pointer, "name:struct" for a struct, and "!"
+    // followed by the member as written if it is not supported.
+    void exitStructOrUnionSpecifier(
+        CParser::StructOrUnionSpecifierContext *ctx) override {
+        if (!ctx->LeftBrace()) {
+            return;
+        }
+        std::string text{ctx->structOrUnion()->getText()};
+        text.push_back(' ');
+        if (ctx->Identifier()) {
+            text.append(ctx->Identifier()->getText());
+        }
+        // Both lists are left-recursive, so their last items come first.
+        std::vector<CParser::StructDeclarationContext *> declarations;
+        for (auto list{ctx->structDeclarationList()}; list;
+             list = list->structDeclarationList()) {
+            declarations.push_back(list->structDeclaration());
+        }
+        for (auto it{declarations.rbegin()}; it != declarations.rend(); ++it) {
+            auto type{structName((*it)->specifierQualifierList())};
+            if (!(*it)->structDeclaratorList()) {
+                // `int x;` parses as the type `int x`, where `x` names a type.
+                auto list{(*it)->specifierQualifierList()};
+                while (list->specifierQualifierList()) {
+                    list = list->specifierQualifierList();
+                }
+                auto spec{list->typeSpecifier()};
+                text.push_back(' ');
+                if (list != (*it)->specifierQualifierList() && spec &&
+                    spec->typedefName()) {
+                    text.append(spec->getText());
+                    if (!type.empty()) {
+                        text.push_back(':');
+                        text.append(type);
+                    }
+                } else {
+                    text.push_back('!');
+                    text.append((*it)->getText());
+                }
+                continue;
+            }
+            std::vector<CParser::StructDeclaratorContext *> declarators;
+            for (auto list{(*it)->structDeclaratorList()}; list;
+                 list = list->structDeclaratorList()) {
+                declarators.push_back(list->structDeclarator());
+            }
+            for (auto d{declarators.rbegin()}; d != declarators.rend(); ++d) {
+                text.push_back(' ');
+                auto declarator{(*d)->declarator()};
+                if (!declarator || (*d)->Colon() ||
+                    !declarator->directDeclarator()->Identifier()) {
+                    text.push_back('!');
+                    text.append((*d)->getText());
+                    continue;
+                }
+                text.append(declarator->directDeclarator()->getText());
+                if (!type.empty() && !declarator->pointer()) {
+                    text.push_back(':');
+                    text.append(type);
+                }
+            }
+        }
+        this->emitEvent("StructDefinition", text.c_str());
+    }
+
+    void enterEnumSpecifier(CParser::EnumSpecifierContext *ctx) override {
+        this->emitEvent("EnterEnumSpecifier", "");
+    }
+
+    void exitEnumerator(CParser::EnumeratorContext *ctx) override {
+        std::string text{ctx->enumerationConstant()->getText()};
+        if (ctx->constantExpression()) {
+            // The value is already on the expression stack.
+            text.append(" =");
+        }
+        this->emitEvent("ExitEnumerator", text.c_str());
+    }
+
     void exitDeclaration(CParser::DeclarationContext *ctx) override {
-        this->emitEvent("ExitDeclaration", ctx->initDeclaratorList()
-                                               ->initDeclarator()
-                                               ->declarator()
-                                               ->directDeclarator()
-                                               ->Identifier()
-                                               ->getText()
-                                               .c_str());
+        if (isPrototype(ctx)) {
+            this->emitPrototypes(ctx);
+            return;
+        }
+        // The list is left-recursive, so its last declarator comes first.
+        std::vector<CParser::InitDeclaratorContext *> declarators;
+        for (auto list{ctx->initDeclaratorList()}; list;
+             list = list->initDeclaratorList()) {
+            declarators.push_back(list->initDeclarator());
+        }
+        // The type of the declared names, "int" unless it is a `double` or a
+        // struct. A `long` is as wide as an `int`, so it is an "int" too.
+        std::string text{"int"};
+        if (isDouble(ctx->declarationSpecifiers())) {
+            text = "double";
+        } else if (auto name{structName(ctx->declarationSpecifiers())};
+                   !name.empty()) {
+            text = "struct ";
+            text.append(name);
+        }
+        text.push_back(':');
+        for (auto it{declarators.rbegin()}; it != declarators.rend(); ++it) {
+            if (it != declarators.rbegin()) {
+                text.push_back(',');
+            }
+            if ((*it)->declarator()->pointer()) {
+                text.push_back('*');
+            }
+            auto declarator{(*it)->declarator()->directDeclarator()};
+            auto direct{declarator};
+            std::string dims;
+            // The sizes are already on the expression stack.
+            while (direct->LeftBracket() && direct->assignmentExpression()) {
+                dims.append("[]");
+                direct = direct->directDeclarator();
+            }
+            if (direct->Identifier()) {
+                text.append(direct->Identifier()->getText());
+                text.append(dims);
+            } else {
+                // Marked as unsupported, with the declarator as written.
+                text.push_back('!');
+                text.append(declarator->getText());
+            }
+            if ((*it)->initializer()) {
+                // The initializer is already on the expression stack.
+                text.append(" =");
+            }
+        }
+        this->emitEvent("ExitDeclaration", text.c_str());
     }
 
     void enterCompoundStatement(
@@ -123,6 +436,30 @@ class EventListener : public CBaseListener {
         this->emitEvent("ExitCompoundStatement", "");
     }
 
+    // Every statement, and every declaration in a function body, is bracketed
+    // by the line it starts on, which the AST keeps apart from the statements.
+    void enterStatement(CParser::StatementContext *ctx) override {
+        std::string line{std::to_string(ctx->getStart()->getLine())};
+        this->emitEvent("EnterStatement", line.c_str());
+    }
+
+    void exitStatement(CParser::StatementContext *ctx) override {
+        this->emitEvent("ExitStatement", "");
+    }
+
+    void enterBlockItem(CParser::BlockItemContext *ctx) override {
+        if (ctx->declaration()) {
+            std::string line{std::to_string(ctx->getStart()->getLine())};
+            this->emitEvent("EnterStatement", line.c_str());
+        }
+    }
+
+    void exitBlockItem(CParser::BlockItemContext *ctx) override {
+        if (ctx->declaration()) {
+            this->emitEvent("ExitStatement", "");
+        }
+    }
+
     void exitExpressionStatement(
         CParser::ExpressionStatementContext *ctx) override {
         this->emitEvent("ExitExpressionStatement", "");
@@ -141,8 +478,30 @@ class EventListener : public CBaseListener {
 
     void exitIterationStatement(
         CParser::IterationStatementContext *ctx) override {
-        if (ctx->While()) {
+        if (ctx->Do()) {
+            this->emitEvent("ExitDoStatement", "");
+        } else if (ctx->While()) {
             this->emitEvent("ExitIterationStatement", "");
+        } else if (auto cond = ctx->forCondition()) {
+            // List the clauses present, since each of them may be omitted.
+            std::string clauses;
+            if (cond->forDeclaration()) {
+                clauses.append("decl");
+            } else if (cond->expression()) {
+                clauses.append("init");
+            }
+            auto second_semi = cond->Semi(1)->getSymbol()->getTokenIndex();
+            for (auto expr : cond->forExpression()) {
+                if (!clauses.empty()) {
+                    clauses.push_back(' ');
+                }
+                if (expr->getStart()->getTokenIndex() < second_semi) {
+                    clauses.append("cond");
+                } else {
+                    clauses.append("step");
+                }
+            }
+            this->emitEvent("ExitForStatement", clauses.c_str());
         }
     }
 
@@ -153,42 +512,61 @@ class EventListener : public CBaseListener {
             } else {
                 this->emitEvent("ExitJumpStatement", "");
             }
+        } else if (ctx->Break()) {
+            this->emitEvent("ExitJumpStatement", "break");
+        } else if (ctx->Continue()) {
+            this->emitEvent("ExitJumpStatement", "continue");
         }
     }
 
     void exitFunctionDefinition(
         CParser::FunctionDefinitionContext *ctx) override {
-        std::string sig;
+        // Record the line where the function starts.
+        std::string sig{std::to_string(ctx->getStart()->getLine())};
+        sig.push_back(' ');
         // Determine the return type.
-        if (ctx->declarationSpecifiers()
-                ->declarationSpecifier(0)
-                ->typeSpecifier()
-                ->Void()) {
+        auto type{
+            ctx->declarationSpecifiers()->declarationSpecifier(0)->typeSpecifier()};
+        if (type->Void()) {
             sig.append("void");
+        } else if (type->Double() && !ctx->declarator()->pointer()) {
+            sig.append("double");
+        } else if (!structName(type).empty() && !ctx->declarator()->pointer()) {
+            sig.append("struct");
         } else {
             sig.append("int");
         }
         // Determine the function name.
         sig.push_back(' ');
-        auto name = ctx->declarator()
-                        ->directDeclarator()
-                        ->directDeclarator()
-                        ->Identifier()
-                        ->getText();
-        sig.append(name);
+        auto specs{ctx->declarationSpecifiers()};
+        sig.append(functionName(specs, ctx->declarator())->getText());
         // Determine the argument list.
-        if (auto param_list =
+        if (auto param{pointerParameter(specs, ctx->declarator())}) {
+            sig.push_back(' ');
+            if (param->typeSpecifier()->Double()) {
+                sig.append("double:*");
+            }
+            sig.append(param->directDeclarator()->getText());
+        } else if (auto param_list =
                 ctx->declarator()->directDeclarator()->parameterTypeList()) {
             auto parameter = param_list->parameterList();
             while (parameter) {
-                auto param = parameter->parameterDeclaration()
-                                 ->declarator()
-                                 ->directDeclarator()
-                                 ->getText();
+                auto declaration{parameter->parameterDeclaration()};
+                auto declarator{declaration->declarator()};
                 sig.push_back(' ');
-                sig.append(param);
+                // Structs are only passed through pointers.
+                if (!structName(declaration->declarationSpecifiers()).empty() &&
+                    !declarator->pointer()) {
+                    sig.push_back('!');
+                } else if (isDouble(declaration->declarationSpecifiers())) {
+                    sig.append(declarator->pointer() ? "double:*" : "double:");
+                }
+                sig.append(declarator->directDeclarator()->getText());
                 parameter = parameter->parameterList();
             }
+            if (param_list->Ellipsis()) {
+                sig.append(" ...");
+            }
         }
         this->emitEvent("ExitFunctionDefinition", sig.c_str());
     }
@@ -204,6 +582,360 @@ class EventListener : public CBaseListener {
         std::strcpy(rsText, text);
         this->rsEmitEvent(rsTag, rsText);
     }
+
+    // Emit "line type name parameter-types" for every function the
+    // declaration declares, with the types as `parameterType` gives them, last
+    // first, after "..." if the function is variadic.
+    void emitPrototypes(CParser::DeclarationContext *ctx) {
+        auto specs{ctx->declarationSpecifiers()};
+        for (auto list{ctx->initDeclaratorList()}; list;
+             list = list->initDeclaratorList()) {
+            auto declarator{list->initDeclarator()->declarator()};
+            std::string sig{std::to_string(ctx->getStart()->getLine())};
+            sig.push_back(' ');
+            auto pointer{declarator->pointer()};
+            if (isDouble(specs) && !pointer) {
+                sig.append("double");
+            } else if (!structName(specs).empty() && !pointer) {
+                sig.append("struct");
+            } else if (isVoid(specs) && !pointer) {
+                sig.append("void");
+            } else {
+                sig.append("int");
+            }
+            auto direct{declarator->directDeclarator()};
+            sig.push_back(' ');
+            sig.append(functionName(specs, declarator)->getText());
+            if (auto param{pointerParameter(specs, declarator)}) {
+                sig.push_back(' ');
+                sig.append(param->typeSpecifier()->Double() ? "double*" : "int");
+            } else if (auto types{direct->parameterTypeList()}) {
+                if (types->Ellipsis()) {
+                    sig.append(" ...");
+                }
+                for (auto param{types->parameterList()}; param;
+                     param = param->parameterList()) {
+                    sig.push_back(' ');
+                    sig.append(parameterType(param->parameterDeclaration()));
+                }
+            }
+            this->emitEvent("ExitPrototype", sig.c_str());
+        }
+    }
+};
+
+// Reports every construct outside the subset `EventListener` understands,
+// as "line column construct" events. Tokens are checked against an allowlist,
+// and a few constructs made only of allowed tokens are checked by rule.
+class SubsetListener : public CBaseListener {
+   public:
+    SubsetListener(RsGetStr rsGetStr, RsEmitEvent rsEmitEvent)
+        : rsGetStr(rsGetStr), rsEmitEvent(rsEmitEvent) {}
+
+    void visitTerminal(antlr4::tree::TerminalNode *node) override {
+        auto token = node->getSymbol();
+        switch (token->getType()) {
+            case antlr4::Token::EOF:
+            case CParser::Int:
+            case CParser::Double:
+            case CParser::Void:
+            case CParser::If:
+            case CParser::Else:
+            case CParser::While:
+            case CParser::For:
+            case CParser::Do:
+            case CParser::Break:
+            case CParser::Continue:
+            case CParser::Return:
+            case CParser::Identifier:
+            case CParser::LeftParen:
+            case CParser::RightParen:
+            case CParser::LeftBrace:
+            case CParser::RightBrace:
+            case CParser::LeftBracket:
+            case CParser::RightBracket:
+            case CParser::Struct:
+            case CParser::Union:
+            case CParser::Enum:
+            case CParser::Dot:
+            case CParser::Less:
+            case CParser::LessEqual:
+            case CParser::Greater:
+            case CParser::GreaterEqual:
+            case CParser::Plus:
+            case CParser::Minus:
+            case CParser::Star:
+            case CParser::And:
+            case CParser::Div:
+            case CParser::Mod:
+            case CParser::AndAnd:
+            case CParser::OrOr:
+            case CParser::Not:
+            case CParser::Question:
+            case CParser::Colon:
+            case CParser::Semi:
+            case CParser::Comma:
+            case CParser::Assign:
+            case CParser::Equal:
+            case CParser::NotEqual:
+            case CParser::Ellipsis:
+                return;
+            case CParser::Constant: {
+                // Only decimal integers and decimal floating constants
+                // without a suffix are read as numbers.
+                auto text{token->getText()};
+                if (text.find_first_not_of("0123456789") == std::string::npos ||
+                    (text.find_first_not_of("0123456789.eE+-") ==
+                         std::string::npos &&
+                     text.find_first_of(".eE") != std::string::npos)) {
+                    return;
+                }
+                break;
+            }
+        }
+        this->report(token, "`" + token->getText() + "`");
+    }
+
+    void enterExternalDeclaration(
+        CParser::ExternalDeclarationContext *ctx) override {
+        // A struct may be defined and a function declared outside of
+        // functions.
+        if (ctx->declaration() && ctx->declaration()->initDeclaratorList() &&
+            !isPrototype(ctx->declaration())) {
+            this->report(ctx->getStart(), "global declaration");
+        }
+    }
+
+    void enterFunctionDefinition(
+        CParser::FunctionDefinitionContext *ctx) override {
+        if (ctx->declarationList()) {
+            this->report(ctx->declarationList()->getStart(),
+                         "old-style parameter declarations");
+        }
+        auto specs{ctx->declarationSpecifiers()};
+        if (specs && !structName(specs).empty() &&
+            !ctx->declarator()->pointer()) {
+            this->report(ctx->getStart(), "struct return value");
+        }
+        // Only a prototype may declare a variadic function.
+        auto types{ctx->declarator()->directDeclarator()->parameterTypeList()};
+        if (types && types->Ellipsis()) {
+            this->report(types->Ellipsis()->getSymbol(),
+                         "variadic function definition");
+        }
+    }
+
+    void enterParameterDeclaration(
+        CParser::ParameterDeclarationContext *ctx) override {
+        // A prototype may leave its parameters unnamed.
+        if (!ctx->declarator() && inDefinition(ctx)) {
+            this->report(ctx->getStart(), "unnamed parameter");
+        } else if (ctx->declarationSpecifiers() &&
+                   !structName(ctx->declarationSpecifiers()).empty() &&
+                   !ctx->declarator()->pointer()) {
+            this->report(ctx->getStart(), "struct parameter");
+        }
+    }
+
+    void enterStructOrUnionSpecifier(
+        CParser::StructOrUnionSpecifierContext *ctx) override {
+        if (!ctx->Identifier()) {
+            this->report(ctx->getStart(),
+                         "anonymous " + ctx->structOrUnion()->getText());
+        }
+    }
+
+    void enterStructDeclaration(
+        CParser::StructDeclarationContext *ctx) override {
+        for (auto list{ctx->specifierQualifierList()}; list;
+             list = list->specifierQualifierList()) {
+            if (list->typeSpecifier() && list->typeSpecifier()->Double()) {
+                this->report(ctx->getStart(), "`double` member");
+                return;
+            }
+        }
+    }
+
+    void enterStructDeclarator(
+        CParser::StructDeclaratorContext *ctx) override {
+        if (ctx->Colon()) {
+            this->report(ctx->Colon()->getSymbol(), "bit-field");
+        }
+    }
+
+    void enterInitializer(CParser::InitializerContext *ctx) override {
+        if (ctx->initializerList()) {
+            this->report(ctx->getStart(), "initializer list");
+        }
+    }
+
+    // Only local arrays with a size in every dimension are supported.
+    void enterDirectDeclarator(
+        CParser::DirectDeclaratorContext *ctx) override {
+        if (!ctx->LeftBracket()) {
+            return;
+        }
+        auto bracket{ctx->LeftBracket()->getSymbol()};
+        if (!ctx->assignmentExpression()) {
+            this->report(bracket, "array without a size");
+        }
+        // The rest is checked once, at the last dimension.
+        if (dynamic_cast<CParser::DirectDeclaratorContext *>(ctx->parent)) {
+            return;
+        }
+        auto parent{ctx->parent};
+        while (parent && !dynamic_cast<CParser::InitDeclaratorContext *>(parent)) {
+            if (dynamic_cast<CParser::ParameterDeclarationContext *>(parent)) {
+                this->report(bracket, "array parameter");
+                return;
+            }
+            if (dynamic_cast<CParser::StructDeclaratorContext *>(parent)) {
+                this->report(bracket, "array member");
+                return;
+            }
+            parent = parent->parent;
+        }
+        auto init{dynamic_cast<CParser::InitDeclaratorContext *>(parent)};
+        if (init && init->initializer()) {
+            this->report(init->initializer()->getStart(), "array initializer");
+        }
+    }
+
+    // `&` is only allowed as the address-of operator.
+    void enterAndExpression(CParser::AndExpressionContext *ctx) override {
+        if (ctx->And()) {
+            this->report(ctx->And()->getSymbol(), "`&`");
+        }
+    }
+
+    void enterCastExpression(CParser::CastExpressionContext *ctx) override {
+        if (ctx->typeName()) {
+            this->report(ctx->getStart(), "cast");
+        }
+    }
+
+    void enterExpression(CParser::ExpressionContext *ctx) override {
+        if (ctx->Comma()) {
+            this->report(ctx->Comma()->getSymbol(), "comma operator");
+        }
+    }
+
+    void enterLabeledStatement(
+        CParser::LabeledStatementContext *ctx) override {
+        this->report(ctx->getStart(), "label");
+    }
+
+    void enterForDeclaration(CParser::ForDeclarationContext *ctx) override {
+        this->report(ctx->getStart(), "declaration in `for`");
+    }
+
+    void enterForExpression(CParser::ForExpressionContext *ctx) override {
+        if (ctx->Comma()) {
+            this->report(ctx->Comma()->getSymbol(), "comma operator");
+        }
+    }
+
+   private:
+    RsGetStr rsGetStr;
+    RsEmitEvent rsEmitEvent;
+
+    void report(antlr4::Token *token, const std::string &construct) {
+        std::string text{std::to_string(token->getLine())};
+        text.push_back(' ');
+        text.append(std::to_string(token->getCharPositionInLine() + 1));
+        text.push_back(' ');
+        text.append(construct);
+        auto rsTag{this->rsGetStr(std::strlen("Unsupported"))};
+        std::strcpy(rsTag, "Unsupported");
+        auto rsText{this->rsGetStr(text.size())};
+        std::strcpy(rsText, text.c_str());
+        this->rsEmitEvent(rsTag, rsText);
+    }
+};
+
+// Reports where names are declared and used, and where scopes begin and end,
+// as "line column name" events, so the Rust side can resolve every use.
+class NameListener : public CBaseListener {
+   public:
+    NameListener(RsGetStr rsGetStr, RsEmitEvent rsEmitEvent)
+        : rsGetStr(rsGetStr), rsEmitEvent(rsEmitEvent) {}
+
+    // Parameters share a scope that encloses the body.
+    void enterFunctionDefinition(
+        CParser::FunctionDefinitionContext *ctx) override {
+        auto name{functionName(ctx->declarationSpecifiers(), ctx->declarator())};
+        if (name) {
+            this->report("Function", name->getSymbol());
+        }
+        this->emitEvent("EnterScope", "");
+    }
+
+    void exitFunctionDefinition(
+        CParser::FunctionDefinitionContext *ctx) override {
+        this->emitEvent("ExitScope", "");
+    }
+
+    void enterCompoundStatement(
+        CParser::CompoundStatementContext *ctx) override {
+        this->emitEvent("EnterScope", "");
+    }
+
+    void exitCompoundStatement(
+        CParser::CompoundStatementContext *ctx) override {
+        this->emitEvent("ExitScope", "");
+    }
+
+    // A variable, an array, or a parameter. The name of a function is nested
+    // in the declarator with its parameters, and is reported as a `Function`.
+    // A name after a pointer in parentheses is declared too, which is how the
+    // only parameter of `int f(int *p)` is parsed.
+    void enterDirectDeclarator(
+        CParser::DirectDeclaratorContext *ctx) override {
+        auto parent{
+            dynamic_cast<CParser::DirectDeclaratorContext *>(ctx->parent)};
+        // Struct members are not variables.
+        if (!parent && dynamic_cast<CParser::StructDeclaratorContext *>(
+                           ctx->parent->parent)) {
+            return;
+        }
+        if (ctx->Identifier() &&
+            (!parent || parent->LeftBracket() || parent->pointer())) {
+            this->report("Declare", ctx->Identifier()->getSymbol());
+        }
+    }
+
+    void enterEnumerationConstant(
+        CParser::EnumerationConstantContext *ctx) override {
+        this->report("Declare", ctx->Identifier()->getSymbol());
+    }
+
+    void enterPrimaryExpression(
+        CParser::PrimaryExpressionContext *ctx) override {
+        if (ctx->Identifier()) {
+            this->report("Use", ctx->Identifier()->getSymbol());
+        }
+    }
+
+   private:
+    RsGetStr rsGetStr;
+    RsEmitEvent rsEmitEvent;
+
+    void report(const char *tag, antlr4::Token *token) {
+        std::string text{std::to_string(token->getLine())};
+        text.push_back(' ');
+        text.append(std::to_string(token->getCharPositionInLine() + 1));
+        text.push_back(' ');
+        text.append(token->getText());
+        this->emitEvent(tag, text.c_str());
+    }
+
+    void emitEvent(const char *tag, const char *text) {
+        auto rsTag{this->rsGetStr(std::strlen(tag))};
+        std::strcpy(rsTag, tag);
+        auto rsText{this->rsGetStr(std::strlen(text))};
+        std::strcpy(rsText, text);
+        this->rsEmitEvent(rsTag, rsText);
+    }
 };
 
 extern "C" char *_parse(char *source, RsGetStr rsGetStr,
@@ -214,6 +946,63 @@ extern "C" char *_parse(char *source, RsGetStr rsGetStr,
     CParser parser{&tokens};
     antlr4::tree::ParseTree *tree{parser.compilationUnit()};
     EventListener listener{rsGetStr, rsEmitEvent};
+    if (auto errors{parser.getNumberOfSyntaxErrors()}) {
+        listener.syntaxErrors(errors);
+    } else {
+        antlr4::tree::ParseTreeWalker::DEFAULT.walk(&listener, tree);
+    }
+    return source;
+}
+
+extern "C" char *_check_subset(char *source, RsGetStr rsGetStr,
+                               RsEmitEvent rsEmitEvent) {
+    antlr4::ANTLRInputStream input{source};
+    CLexer lexer{&input};
+    antlr4::CommonTokenStream tokens{&lexer};
+    CParser parser{&tokens};
+    antlr4::tree::ParseTree *tree{parser.compilationUnit()};
+    SubsetListener listener{rsGetStr, rsEmitEvent};
     antlr4::tree::ParseTreeWalker::DEFAULT.walk(&listener, tree);
     return source;
 }
+
+extern "C" char *_resolve_names(char *source, RsGetStr rsGetStr,
+                                RsEmitEvent rsEmitEvent) {
+    antlr4::ANTLRInputStream input{source};
+    CLexer lexer{&input};
+    antlr4::CommonTokenStream tokens{&lexer};
+    CParser parser{&tokens};
+    antlr4::tree::ParseTree *tree{parser.compilationUnit()};
+    NameListener listener{rsGetStr, rsEmitEvent};
+    antlr4::tree::ParseTreeWalker::DEFAULT.walk(&listener, tree);
+    return source;
+}
+
+// Reports every token as a "start stop name" event, with the indices of its
+// first and last characters and its symbolic name in the grammar, which is
+// empty for the literal tokens of the parser rules. Comments, whitespace, and
+// preprocessor lines are skipped by the lexer, so they are what lies between
+// the spans.
+extern "C" char *_lex(char *source, RsGetStr rsGetStr,
+                      RsEmitEvent rsEmitEvent) {
+    antlr4::ANTLRInputStream input{source};
+    CLexer lexer{&input};
+    antlr4::CommonTokenStream tokens{&lexer};
+    tokens.fill();
+    for (auto token : tokens.getTokens()) {
+        if (token->getType() == antlr4::Token::EOF) {
+            break;
+        }
+        std::string text{std::to_string(token->getStartIndex())};
+        text.push_back(' ');
+        text.append(std::to_string(token->getStopIndex()));
+        text.push_back(' ');
+        text.append(lexer.getVocabulary().getSymbolicName(token->getType()));
+        auto rsTag{rsGetStr(std::strlen("Token"))};
+        std::strcpy(rsTag, "Token");
+        auto rsText{rsGetStr(text.size())};
+        std::strcpy(rsText, text.c_str());
+        rsEmitEvent(rsTag, rsText);
+    }
+    return source;
+}
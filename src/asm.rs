@@ -1,271 +1,230 @@
-use crate::ir::{Expression, SSAFunction, SSAProgram, SSAVar, Statement, CFG};
-use crate::x64::{Register, VRegisterAllocator, X64Function, X64Program, X64};
+use crate::isel;
+use crate::lines::Lines;
+use crate::lir::{Element, Lir, LirFunction, LirProgram, Var};
+use crate::x64::{Register, X64Function, X64Program, X64RegisterAllocator, X64};
 use std::collections::HashMap;
 
-// # If without Alt (IfNoAlt)
-// <cond-start>
-// <cond>
-// <cond-end>: if false then jump to TagEnd
-// <body-start>
-// <body>
-// <body-end>: TagEnd
-//
-// # If with Alt (IfBody, IfAlt)
-// <cond-start>
-// <cond>
-// <cond-end>: if false then jump to TagStart
-// <body-start>
-// <body>
-// <body-end>: jump to TagEnd
-// <alt-start>: TagStart
-// <alt>
-// <alt-end>: TagEnd
-//
-// # While (WhileBody)
-// <cond-start>: TagStart
-// <cond>
-// <cond-end>: if false then jump to TagEnd
-// <body-start>
-// <body>
-// <body-end>: jump to TagStart
-// <body-end>: TagEnd
-enum Tag {
-    IfNoAlt(String),
-    IfBody(String),
-    IfAlt(String),
-    WhileBody(String),
-}
-
+// Instruction selection from the LIR. The integer arithmetic is covered by
+// the patterns of `isel`, and every other instruction maps to a fixed sequence
+// of x64 code over the same virtual registers, much like the naive treewalk
+// scheme. A `Cond` lives in the flags: its comparison sets them, and only
+// moves, which leave them alone, come between it and the jumps reading it.
 pub struct X64Builder {
-    allocator: VRegisterAllocator,
-    tags: HashMap<usize, Vec<Tag>>,
-    successors: Vec<usize>,
+    // The operator of every `Cond`, and whether it compares `double` values.
+    conds: HashMap<Var, (&'static str, bool)>,
 }
 
 impl X64Builder {
     pub fn new() -> Self {
         X64Builder {
-            allocator: VRegisterAllocator::new(),
-            tags: HashMap::new(),
-            successors: Vec::new(),
+            conds: HashMap::new(),
         }
     }
 
-    pub fn build(&mut self, cfg: SSAProgram) -> X64Program {
-        cfg.into_iter()
+    pub fn build(&mut self, lir: LirProgram) -> X64Program {
+        self.build_lines(lir, &Lines::new()).0
+    }
+
+    // `build`, along with the line of every instruction, given the line of
+    // every LIR instruction, which its x64 code is at.
+    pub fn build_lines(&mut self, lir: LirProgram, lines: &Lines) -> (X64Program, Lines) {
+        let mut asm_lines = Lines::new();
+        let program = lir
+            .into_iter()
             .map(
-                |SSAFunction {
+                |LirFunction {
                      name,
-                     parameters,
+                     param_cnt,
+                     arrays,
                      body,
-                     ..
-                 }| X64Function {
-                    name,
-                    param_cnt: parameters.len(),
-                    body: self.build_body(parameters, body),
+                 }| {
+                    let lir_lines = lines.get(&name).map_or(&[][..], Vec::as_slice);
+                    let (body, body_lines) = self.build_body(body, lir_lines);
+                    asm_lines.insert(name.clone(), body_lines);
+                    X64Function {
+                        name,
+                        param_cnt,
+                        arrays,
+                        body,
+                    }
                 },
             )
-            .collect()
+            .collect();
+        (program, asm_lines)
     }
 
-    fn build_body(&mut self, parameters: Vec<SSAVar>, body: CFG) -> Vec<X64> {
-        self.allocator.clear();
-        self.tags.clear();
+    fn build_body(&mut self, body: Vec<Lir>, lines: &[usize]) -> (Vec<X64>, Vec<usize>) {
+        self.conds.clear();
+        let mut covers = isel::cover(&body);
         let mut asms = Vec::new();
-        for var in parameters {
-            self.allocator.from_var(var);
-        }
-        for (index, block) in body.into_iter().enumerate() {
-            self.successors = block.successors.into_iter().collect();
-            self.successors.sort_unstable();
-            asms.extend(self.build_block(index, block.statements));
-        }
-        asms
-    }
-
-    fn build_block(&mut self, index: usize, stmts: Vec<Statement>) -> Vec<X64> {
-        let mut asms = Vec::new();
-        for stmt in stmts {
-            asms.extend(self.build_stmt(stmt));
-        }
-        for tag in self.tags.entry(index).or_default() {
-            match tag {
-                Tag::IfNoAlt(tag) => asms.push(X64::Tag(format!("{}End", tag))),
-                Tag::IfBody(tag) => asms.push(X64::Jmp(format!("{}End", tag))),
-                Tag::IfAlt(tag) => {
-                    asms.insert(0, X64::Tag(format!("{}Start", tag)));
-                    asms.push(X64::Tag(format!("{}End", tag)));
-                }
-                Tag::WhileBody(tag) => {
-                    asms.push(X64::Jmp(format!("{}Start", tag)));
-                    asms.push(X64::Tag(format!("{}End", tag)));
-                }
+        let mut asm_lines = Vec::new();
+        for (position, lir) in body.into_iter().enumerate() {
+            match covers.remove(&position) {
+                Some(covered) => asms.extend(covered),
+                None => asms.extend(self.build_lir(lir)),
             }
+            let line = lines.get(position).copied().unwrap_or_default();
+            asm_lines.resize(asms.len(), line);
         }
-        asms
+        (asms, asm_lines)
     }
 
-    fn build_stmt(&mut self, stmt: Statement) -> Vec<X64> {
-        match stmt {
-            Statement::Nop => Vec::new(),
-            Statement::Phi(_, _) => unreachable!(),
-            Statement::Declaration(var) => {
-                self.allocator.from_var(var);
-                Vec::new()
+    fn build_lir(&mut self, lir: Lir) -> Vec<X64> {
+        match lir {
+            Lir::Param(dst, index) => vec![X64::Param(register(dst), index)],
+            Lir::Const(dst, num) => vec![X64::MovNum(register(dst), num)],
+            Lir::Bits(dst, src) | Lir::Copy(dst, src) => {
+                vec![X64::MovReg(register(dst), register(src))]
             }
-            Statement::Compound(stmts) => {
-                stmts.into_iter().flat_map(|s| self.build_stmt(s)).collect()
-            }
-            Statement::Expression(expr) => self.build_expr(expr).0,
-            Statement::If {
-                condition,
-                alternative,
-                ..
-            } => {
-                let (mut asms, reg) = self.build_expr(condition);
-                // Return immediately if the body and the alternative are empty.
-                if self.successors.len() == 1 {
-                    return asms;
-                }
-                if alternative.is_none() {
-                    let body = self.successors[1] - 1;
-                    let if_no_alt = Tag::IfNoAlt(format!("{}", reg));
-                    self.tags.entry(body).or_default().push(if_no_alt);
-                    asms.extend(vec![X64::CmpNum(reg, 0), X64::Je(format!("{}End", reg))]);
-                } else {
-                    let body = self.successors[0];
-                    let if_body = Tag::IfBody(format!("{}", reg));
-                    self.tags.entry(body).or_default().push(if_body);
-                    let alt = self.successors[1];
-                    let if_alt = Tag::IfAlt(format!("{}", reg));
-                    self.tags.entry(alt).or_default().push(if_alt);
-                    asms.extend(vec![X64::CmpNum(reg, 0), X64::Je(format!("{}Start", reg))]);
-                }
+            Lir::Convert(dst, src) => match dst.is_double() {
+                true => vec![X64::Cvtsi2sd(register(dst), register(src))],
+                false => vec![X64::Cvttsd2si(register(dst), register(src))],
+            },
+            Lir::Unary(dst, _, src) => {
+                let (dst, src) = (register(dst), register(src));
+                let mut asms = move_unless_same(dst, src);
+                asms.push(X64::Neg(dst));
                 asms
             }
-            Statement::While { condition, .. } => {
-                let (mut asms, reg) = self.build_expr(condition);
-                let body = self.successors[0];
-                let while_body = Tag::WhileBody(format!("{}", reg));
-                self.tags.entry(body).or_default().push(while_body);
-                asms.insert(0, X64::Tag(format!("{}Start", reg)));
-                asms.extend(vec![X64::CmpNum(reg, 0), X64::Je(format!("{}End", reg))]);
+            Lir::Binary(dst, left, op, right) => {
+                let op = match (dst.is_double(), op) {
+                    (true, "+") => X64::Addsd,
+                    (true, "-") => X64::Subsd,
+                    (true, "*") => X64::Mulsd,
+                    (true, "/") => X64::Divsd,
+                    (false, "+") => X64::Add,
+                    (false, "-") => X64::Sub,
+                    (false, "*") => X64::Imul,
+                    (false, "/") => X64::Idiv,
+                    (false, "%") => X64::Irem,
+                    (false, "&") => X64::And,
+                    (false, "|") => X64::Or,
+                    _ => unreachable!(),
+                };
+                let (dst, left) = (register(dst), register(left));
+                let mut asms = move_unless_same(dst, left);
+                asms.push(op(dst, register(right)));
                 asms
             }
-            Statement::Return(Some(expr)) => {
-                let (mut asms, reg) = self.build_expr(expr);
-                asms.push(X64::Ret(Some(reg)));
-                asms
+            Lir::Compare(dst, left, op, right) => {
+                self.conds.insert(dst, (op, left.is_double()));
+                match left.is_double() {
+                    true => vec![X64::Ucomisd(register(left), register(right))],
+                    false => vec![X64::CmpReg(register(left), register(right))],
+                }
             }
-            Statement::Return(None) => vec![X64::Ret(None)],
-        }
-    }
-
-    fn build_expr(&mut self, expr: Expression) -> (Vec<X64>, Register) {
-        match expr {
-            Expression::Identifier(var) => (Vec::new(), self.allocator.from_var(var)),
-            Expression::Number(num) => {
-                let reg = self.allocator.create_temp();
-                (vec![X64::MovNum(reg, num)], reg)
+            Lir::Load(dst, address) => vec![X64::Load(register(dst), register(address))],
+            Lir::Store(address, src) => vec![X64::Store(register(address), register(src))],
+            Lir::LoadElement(dst, array, Element::Const(offset)) => {
+                let offset = offset * X64RegisterAllocator::ELEMENT_SIZE;
+                vec![X64::LoadField(register(dst), array, offset)]
             }
-            Expression::Call {
-                function,
-                arguments,
-            } => {
-                if let (Expression::Identifier(SSAVar { name, .. }), Expression::Arguments(exprs)) =
-                    (*function, *arguments)
-                {
-                    let mut asms = Vec::new();
-                    let mut regs = Vec::new();
-                    for expr in exprs {
-                        let (a, r) = self.build_expr(expr);
-                        asms.extend(a);
-                        regs.push(r);
-                    }
-                    let ret_reg = self.allocator.create_temp();
-                    asms.push(X64::Call(name, regs, ret_reg));
-                    (asms, ret_reg)
-                } else {
-                    unreachable!();
-                }
+            Lir::LoadElement(dst, array, Element::Var(index)) => {
+                vec![X64::LoadIndex(register(dst), array, register(index))]
             }
-            Expression::Arguments(_) => unreachable!(),
-            Expression::Prefix {
-                operator,
-                expression,
-            } => match operator {
-                "+" => self.build_expr(*expression),
-                "-" => {
-                    let (mut asms, reg) = self.build_expr(*expression);
-                    asms.push(X64::Neg(reg));
-                    (asms, reg)
-                }
-                "!" => {
-                    let (mut asms, reg) = self.build_expr(*expression);
-                    let r = self.allocator.create_temp();
-                    asms.extend(vec![
-                        X64::MovNum(r, 1),
-                        X64::CmpNum(reg, 0),
-                        X64::Je(format!("{}", r)),
-                        X64::MovNum(r, 0),
-                        X64::Tag(format!("{}", r)),
-                    ]);
-                    (asms, r)
-                }
-                _ => unreachable!(),
-            },
-            Expression::Infix {
-                left,
-                operator,
-                right,
-            } => {
-                let (mut left_asms, left_reg) = self.build_expr(*left);
-                let (right_asms, right_reg) = self.build_expr(*right);
-                let (asms, reg) = if operator == "=" {
-                    (vec![X64::MovReg(left_reg, right_reg)], left_reg)
-                } else {
-                    let reg = self.allocator.create_temp();
-                    let asms = match operator {
-                        "*" => vec![X64::MovReg(reg, left_reg), X64::Imul(reg, right_reg)],
-                        "/" => vec![X64::MovReg(reg, left_reg), X64::Idiv(reg, right_reg)],
-                        "+" => vec![X64::MovReg(reg, left_reg), X64::Add(reg, right_reg)],
-                        "-" => vec![X64::MovReg(reg, left_reg), X64::Sub(reg, right_reg)],
-                        "&&" => vec![X64::MovReg(reg, left_reg), X64::And(reg, right_reg)],
-                        "||" => vec![X64::MovReg(reg, left_reg), X64::Or(reg, right_reg)],
-                        op => {
-                            let asm = match op {
-                                "<" => X64::Jl(format!("{}", reg)),
-                                ">" => X64::Jg(format!("{}", reg)),
-                                "<=" => X64::Jle(format!("{}", reg)),
-                                ">=" => X64::Jge(format!("{}", reg)),
-                                "==" => X64::Je(format!("{}", reg)),
-                                "!=" => X64::Jne(format!("{}", reg)),
-                                _ => unreachable!(),
-                            };
-                            vec![
-                                X64::MovNum(reg, 1),
-                                X64::CmpReg(left_reg, right_reg),
-                                asm,
-                                X64::MovNum(reg, 0),
-                                X64::Tag(format!("{}", reg)),
-                            ]
-                        }
-                    };
-                    (asms, reg)
-                };
-                left_asms.extend(right_asms);
-                left_asms.extend(asms);
-                (left_asms, reg)
+            Lir::StoreElement(array, Element::Const(offset), src) => {
+                let offset = offset * X64RegisterAllocator::ELEMENT_SIZE;
+                vec![X64::StoreField(array, offset, register(src))]
             }
+            Lir::StoreElement(array, Element::Var(index), src) => {
+                vec![X64::StoreIndex(array, register(index), register(src))]
+            }
+            Lir::Address(dst, array, index) => {
+                vec![X64::LeaIndex(register(dst), array, register(index))]
+            }
+            Lir::Call(name, args, dst, variadic) => {
+                let args = args.into_iter().map(register).collect();
+                vec![X64::Call(name, args, register(dst), variadic)]
+            }
+            Lir::Label(tag) => vec![X64::Tag(tag)],
+            Lir::Jump(tag) => vec![X64::Jmp(tag)],
+            Lir::JumpIf(var @ Var::Cond(_), tag) => match self.conds[&var] {
+                (op, false) => vec![jump_if(op, tag)],
+                (op, true) => vec![jump_if_float(op, tag)],
+            },
+            Lir::JumpUnless(var @ Var::Cond(_), tag) => match self.conds[&var] {
+                (op, false) => vec![jump_unless(op, tag)],
+                (op, true) => vec![jump_unless_float(op, tag)],
+            },
+            Lir::JumpIf(var, tag) => vec![X64::CmpNum(register(var), 0), X64::Jne(tag)],
+            Lir::JumpUnless(var, tag) => vec![X64::CmpNum(register(var), 0), X64::Je(tag)],
+            Lir::Return(var) => vec![X64::Ret(var.map(register))],
         }
     }
 }
 
+// The virtual register of an integer or `double` value.
+pub fn register(var: Var) -> Register {
+    match var {
+        Var::Int(index) => Register::Virtual(index),
+        Var::Double(index) => Register::VirtualXmm(index),
+        Var::Cond(_) => unreachable!(),
+    }
+}
+
+fn move_unless_same(dst: Register, src: Register) -> Vec<X64> {
+    match dst == src {
+        true => Vec::new(),
+        false => vec![X64::MovReg(dst, src)],
+    }
+}
+
+// Jump to `tag` if the comparison `op` is true.
+fn jump_if(op: &str, tag: String) -> X64 {
+    match op {
+        "<" => X64::Jl(tag),
+        ">" => X64::Jg(tag),
+        "<=" => X64::Jle(tag),
+        ">=" => X64::Jge(tag),
+        "==" => X64::Je(tag),
+        "!=" => X64::Jne(tag),
+        _ => unreachable!(),
+    }
+}
+
+// Jump to `tag` if the comparison `op` of two `double` values is true.
+fn jump_if_float(op: &str, tag: String) -> X64 {
+    match op {
+        "<" => X64::Jb(tag),
+        ">" => X64::Ja(tag),
+        "<=" => X64::Jbe(tag),
+        ">=" => X64::Jae(tag),
+        "==" => X64::Je(tag),
+        "!=" => X64::Jne(tag),
+        _ => unreachable!(),
+    }
+}
+
+// Jump to `tag` if the comparison `op` is false.
+fn jump_unless(op: &str, tag: String) -> X64 {
+    match op {
+        "<" => X64::Jge(tag),
+        ">" => X64::Jle(tag),
+        "<=" => X64::Jg(tag),
+        ">=" => X64::Jl(tag),
+        "==" => X64::Jne(tag),
+        "!=" => X64::Je(tag),
+        _ => unreachable!(),
+    }
+}
+
+// Jump to `tag` if the comparison `op` of two `double` values is false.
+fn jump_unless_float(op: &str, tag: String) -> X64 {
+    match op {
+        "<" => X64::Jae(tag),
+        ">" => X64::Jbe(tag),
+        "<=" => X64::Ja(tag),
+        ">=" => X64::Jb(tag),
+        "==" => X64::Jne(tag),
+        "!=" => X64::Je(tag),
+        _ => unreachable!(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser;
-    use crate::ssa;
+    use crate::{lir, parser, ssa};
 
     #[test]
     fn simple_var_num() {
@@ -280,12 +239,13 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 0,
+            arrays: Vec::new(),
             body: vec![X64::MovNum(Register::Virtual(1), 1)],
         }];
         assert_eq!(asm, expected);
@@ -304,23 +264,30 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![
             X64Function {
                 name: String::from("f"),
                 param_cnt: 1,
-                body: vec![X64::Ret(Some(Register::Virtual(0)))],
+                arrays: Vec::new(),
+                body: vec![
+                    X64::Param(Register::Virtual(0), 0),
+                    X64::Ret(Some(Register::Virtual(0))),
+                ],
             },
             X64Function {
                 name: String::from("main"),
                 param_cnt: 1,
+                arrays: Vec::new(),
                 body: vec![
+                    X64::Param(Register::Virtual(0), 0),
                     X64::Call(
                         String::from("f"),
                         vec![Register::Virtual(0)],
                         Register::Virtual(1),
+                        false,
                     ),
                     X64::MovNum(Register::Virtual(2), 1),
                     X64::MovReg(Register::Virtual(3), Register::Virtual(1)),
@@ -344,25 +311,89 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 1,
+            arrays: Vec::new(),
             body: vec![
-                X64::Neg(Register::Virtual(1)),
-                X64::MovNum(Register::Virtual(2), 0),
-                X64::MovNum(Register::Virtual(3), 1),
-                X64::CmpNum(Register::Virtual(2), 0),
-                X64::Je(String::from("VR3")),
+                X64::Param(Register::Virtual(0), 0),
+                X64::MovReg(Register::Virtual(2), Register::Virtual(1)),
+                X64::Neg(Register::Virtual(2)),
                 X64::MovNum(Register::Virtual(3), 0),
-                X64::Tag(String::from("VR3")),
+                X64::MovNum(Register::Virtual(4), 1),
+                X64::CmpNum(Register::Virtual(3), 0),
+                X64::Je(String::from("main_L1")),
+                X64::MovNum(Register::Virtual(4), 0),
+                X64::Tag(String::from("main_L1")),
             ],
         }];
         assert_eq!(asm, expected);
     }
 
+    #[test]
+    fn expr_conditional() {
+        let ast = parser::parse(
+            "
+            void main(int a) {
+                int b;
+                b = a ? 1 : a;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Param(Register::Virtual(0), 0),
+                X64::CmpNum(Register::Virtual(0), 0),
+                X64::Je(String::from("main_Block3")),
+                X64::MovNum(Register::Virtual(3), 1),
+                X64::MovReg(Register::Virtual(2), Register::Virtual(3)),
+                X64::MovReg(Register::Virtual(4), Register::Virtual(2)),
+                X64::Jmp(String::from("main_Block4")),
+                X64::Tag(String::from("main_Block3")),
+                X64::MovReg(Register::Virtual(5), Register::Virtual(0)),
+                X64::MovReg(Register::Virtual(4), Register::Virtual(5)),
+                X64::Tag(String::from("main_Block4")),
+                X64::MovReg(Register::Virtual(6), Register::Virtual(4)),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn tags() {
+        let ast = parser::parse(
+            "
+            void f(int a) {
+                !a;
+            }
+            void main(int a) {
+                !a;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let tags: Vec<_> = X64Builder::new()
+            .build(lir::lower(cfg))
+            .into_iter()
+            .flat_map(|func| func.body)
+            .filter_map(|asm| match asm {
+                X64::Tag(tag) => Some(tag),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tags, vec!["f_L1", "main_L2"]);
+    }
+
     #[test]
     fn expr_infix() {
         let ast = parser::parse(
@@ -373,64 +404,157 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 2,
+            arrays: Vec::new(),
             body: vec![
-                X64::MovNum(Register::Virtual(2), 0),
-                X64::MovNum(Register::Virtual(3), 1),
-                X64::MovReg(Register::Virtual(4), Register::Virtual(2)),
-                X64::Imul(Register::Virtual(4), Register::Virtual(3)),
-                X64::MovNum(Register::Virtual(5), 2),
-                X64::MovReg(Register::Virtual(6), Register::Virtual(4)),
-                X64::Idiv(Register::Virtual(6), Register::Virtual(5)),
-                X64::MovNum(Register::Virtual(7), 3),
-                X64::MovReg(Register::Virtual(8), Register::Virtual(6)),
-                X64::Add(Register::Virtual(8), Register::Virtual(7)),
-                X64::MovNum(Register::Virtual(9), 4),
-                X64::MovReg(Register::Virtual(10), Register::Virtual(8)),
-                X64::Sub(Register::Virtual(10), Register::Virtual(9)),
-                X64::MovNum(Register::Virtual(11), 5),
-                X64::MovReg(Register::Virtual(12), Register::Virtual(10)),
-                X64::And(Register::Virtual(12), Register::Virtual(11)),
-                X64::MovNum(Register::Virtual(13), 6),
-                X64::MovReg(Register::Virtual(14), Register::Virtual(12)),
-                X64::Or(Register::Virtual(14), Register::Virtual(13)),
-                X64::MovReg(Register::Virtual(0), Register::Virtual(14)),
-                X64::MovNum(Register::Virtual(15), 1),
-                X64::CmpReg(Register::Virtual(0), Register::Virtual(0)),
-                X64::Jl(String::from("VR15")),
-                X64::MovNum(Register::Virtual(15), 0),
-                X64::Tag(String::from("VR15")),
-                X64::MovNum(Register::Virtual(16), 1),
-                X64::CmpReg(Register::Virtual(15), Register::Virtual(0)),
-                X64::Jg(String::from("VR16")),
-                X64::MovNum(Register::Virtual(16), 0),
-                X64::Tag(String::from("VR16")),
+                X64::Param(Register::Virtual(0), 0),
+                X64::Param(Register::Virtual(1), 1),
+                X64::MovNum(Register::Virtual(3), 0),
+                X64::MovNum(Register::Virtual(4), 1),
+                X64::MovReg(Register::Virtual(5), Register::Virtual(3)),
+                X64::Imul(Register::Virtual(5), Register::Virtual(4)),
+                X64::MovNum(Register::Virtual(6), 2),
+                X64::MovReg(Register::Virtual(7), Register::Virtual(5)),
+                X64::Idiv(Register::Virtual(7), Register::Virtual(6)),
+                X64::MovNum(Register::Virtual(8), 3),
+                X64::MovReg(Register::Virtual(9), Register::Virtual(7)),
+                X64::Add(Register::Virtual(9), Register::Virtual(8)),
+                X64::MovNum(Register::Virtual(10), 4),
+                X64::MovReg(Register::Virtual(11), Register::Virtual(9)),
+                X64::Sub(Register::Virtual(11), Register::Virtual(10)),
+                X64::MovNum(Register::Virtual(12), 5),
+                X64::MovReg(Register::Virtual(13), Register::Virtual(11)),
+                X64::And(Register::Virtual(13), Register::Virtual(12)),
+                X64::MovNum(Register::Virtual(14), 6),
+                X64::MovReg(Register::Virtual(15), Register::Virtual(13)),
+                X64::Or(Register::Virtual(15), Register::Virtual(14)),
+                X64::MovReg(Register::Virtual(2), Register::Virtual(15)),
                 X64::MovNum(Register::Virtual(17), 1),
-                X64::CmpReg(Register::Virtual(16), Register::Virtual(0)),
-                X64::Jle(String::from("VR17")),
+                X64::CmpReg(Register::Virtual(2), Register::Virtual(2)),
+                X64::Jl(String::from("main_L1")),
                 X64::MovNum(Register::Virtual(17), 0),
-                X64::Tag(String::from("VR17")),
+                X64::Tag(String::from("main_L1")),
                 X64::MovNum(Register::Virtual(18), 1),
-                X64::CmpReg(Register::Virtual(17), Register::Virtual(0)),
-                X64::Jge(String::from("VR18")),
+                X64::CmpReg(Register::Virtual(17), Register::Virtual(2)),
+                X64::Jg(String::from("main_L2")),
                 X64::MovNum(Register::Virtual(18), 0),
-                X64::Tag(String::from("VR18")),
+                X64::Tag(String::from("main_L2")),
                 X64::MovNum(Register::Virtual(19), 1),
-                X64::CmpReg(Register::Virtual(18), Register::Virtual(0)),
-                X64::Je(String::from("VR19")),
+                X64::CmpReg(Register::Virtual(18), Register::Virtual(2)),
+                X64::Jle(String::from("main_L3")),
                 X64::MovNum(Register::Virtual(19), 0),
-                X64::Tag(String::from("VR19")),
+                X64::Tag(String::from("main_L3")),
                 X64::MovNum(Register::Virtual(20), 1),
-                X64::CmpReg(Register::Virtual(19), Register::Virtual(0)),
-                X64::Jne(String::from("VR20")),
+                X64::CmpReg(Register::Virtual(19), Register::Virtual(2)),
+                X64::Jge(String::from("main_L4")),
                 X64::MovNum(Register::Virtual(20), 0),
-                X64::Tag(String::from("VR20")),
-                X64::MovReg(Register::Virtual(1), Register::Virtual(20)),
+                X64::Tag(String::from("main_L4")),
+                X64::MovNum(Register::Virtual(21), 1),
+                X64::CmpReg(Register::Virtual(20), Register::Virtual(2)),
+                X64::Je(String::from("main_L5")),
+                X64::MovNum(Register::Virtual(21), 0),
+                X64::Tag(String::from("main_L5")),
+                X64::MovNum(Register::Virtual(22), 1),
+                X64::CmpReg(Register::Virtual(21), Register::Virtual(2)),
+                X64::Jne(String::from("main_L6")),
+                X64::MovNum(Register::Virtual(22), 0),
+                X64::Tag(String::from("main_L6")),
+                X64::MovReg(Register::Virtual(16), Register::Virtual(22)),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn expr_index() {
+        let ast = parser::parse(
+            "
+            void main(int i) {
+                int m[3][4];
+                m[i][2] = m[1][i];
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let vreg = Register::Virtual;
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: vec![12],
+            body: vec![
+                X64::Param(Register::Virtual(0), 0),
+                // The row stride of 4 is a scale, so the position is a `lea`.
+                X64::MovNum(vreg(3), 2),
+                X64::Lea(vreg(4), vreg(3), vreg(0), 4),
+                X64::MovNum(vreg(5), 4),
+                X64::MovReg(vreg(6), vreg(0)),
+                X64::Add(vreg(6), vreg(5)),
+                X64::LoadIndex(vreg(7), 0, vreg(6)),
+                X64::StoreIndex(0, vreg(4), vreg(7)),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn expr_field() {
+        let ast = parser::parse(
+            "
+            struct pair { int a, b; };
+            void main(int i) {
+                struct pair p;
+                p.b = p.a;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let vreg = Register::Virtual;
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: vec![2],
+            body: vec![
+                X64::Param(Register::Virtual(0), 0),
+                X64::LoadField(vreg(1), 0, 0),
+                X64::StoreField(0, 8, vreg(1)),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn expr_pointer() {
+        let ast = parser::parse(
+            "
+            void main(int p) {
+                int a[2];
+                *p = *&a[1];
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let vreg = Register::Virtual;
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: vec![2],
+            body: vec![
+                X64::Param(Register::Virtual(0), 0),
+                X64::MovNum(vreg(1), 1),
+                X64::LeaIndex(vreg(2), 0, vreg(1)),
+                X64::Load(vreg(3), vreg(2)),
+                X64::Store(vreg(0), vreg(3)),
             ],
         }];
         assert_eq!(asm, expected);
@@ -454,28 +578,37 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 0,
+            arrays: Vec::new(),
             body: vec![
                 X64::MovNum(Register::Virtual(0), 0),
                 X64::CmpNum(Register::Virtual(0), 0),
-                X64::Je(String::from("VR0Start")),
+                X64::Je(String::from("main_Block2")),
                 X64::MovNum(Register::Virtual(1), 1),
-                X64::Jmp(String::from("VR0End")),
-                X64::Tag(String::from("VR0Start")),
+                X64::Jmp(String::from("main_Block3")),
+                X64::Tag(String::from("main_Block2")),
                 X64::MovNum(Register::Virtual(2), 2),
-                X64::Tag(String::from("VR0End")),
+                X64::Tag(String::from("main_Block3")),
                 X64::MovNum(Register::Virtual(3), 3),
                 X64::CmpNum(Register::Virtual(3), 0),
-                X64::Je(String::from("VR3End")),
+                X64::Je(String::from("main_Block5")),
                 X64::MovNum(Register::Virtual(4), 4),
-                X64::Tag(String::from("VR3End")),
+                X64::Tag(String::from("main_Block5")),
                 X64::MovNum(Register::Virtual(5), 5),
+                X64::CmpNum(Register::Virtual(5), 0),
+                X64::Je(String::from("main_Block7")),
+                X64::Jmp(String::from("main_Block8")),
+                X64::Tag(String::from("main_Block7")),
+                X64::Tag(String::from("main_Block8")),
                 X64::MovNum(Register::Virtual(6), 6),
+                X64::CmpNum(Register::Virtual(6), 0),
+                X64::Je(String::from("main_Block10")),
+                X64::Tag(String::from("main_Block10")),
             ],
         }];
         assert_eq!(asm, expected);
@@ -493,26 +626,141 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 0,
+            arrays: Vec::new(),
             body: vec![
-                X64::Tag(String::from("VR0Start")),
+                X64::Tag(String::from("main_Block1")),
                 X64::MovNum(Register::Virtual(0), 0),
                 X64::CmpNum(Register::Virtual(0), 0),
-                X64::Je(String::from("VR0End")),
+                X64::Je(String::from("main_Block3")),
                 X64::MovNum(Register::Virtual(1), 1),
-                X64::Jmp(String::from("VR0Start")),
-                X64::Tag(String::from("VR0End")),
-                X64::Tag(String::from("VR2Start")),
+                X64::Jmp(String::from("main_Block1")),
+                X64::Tag(String::from("main_Block3")),
                 X64::MovNum(Register::Virtual(2), 2),
                 X64::CmpNum(Register::Virtual(2), 0),
-                X64::Je(String::from("VR2End")),
-                X64::Jmp(String::from("VR2Start")),
-                X64::Tag(String::from("VR2End")),
+                X64::Je(String::from("main_Block5")),
+                X64::Jmp(String::from("main_Block3")),
+                X64::Tag(String::from("main_Block5")),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn stmt_break_continue() {
+        let ast = parser::parse(
+            "
+            void main() {
+                while (0) {
+                    if (1) break;
+                    continue;
+                }
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Tag(String::from("main_Block1")),
+                X64::MovNum(Register::Virtual(0), 0),
+                X64::CmpNum(Register::Virtual(0), 0),
+                X64::Je(String::from("main_Block5")),
+                X64::MovNum(Register::Virtual(1), 1),
+                X64::CmpNum(Register::Virtual(1), 0),
+                X64::Je(String::from("main_Block4")),
+                X64::Jmp(String::from("main_Block5")),
+                X64::Tag(String::from("main_Block4")),
+                X64::Jmp(String::from("main_Block1")),
+                X64::Tag(String::from("main_Block5")),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn stmt_do_while() {
+        let ast = parser::parse(
+            "
+            void main() {
+                do {
+                    1;
+                } while (2 < 3);
+                do {} while (4);
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Tag(String::from("main_Block1")),
+                X64::MovNum(Register::Virtual(0), 1),
+                X64::MovNum(Register::Virtual(1), 2),
+                X64::MovNum(Register::Virtual(2), 3),
+                X64::CmpReg(Register::Virtual(1), Register::Virtual(2)),
+                X64::Jge(String::from("main_Block3")),
+                X64::Jmp(String::from("main_Block1")),
+                X64::Tag(String::from("main_Block3")),
+                X64::MovNum(Register::Virtual(3), 4),
+                X64::CmpNum(Register::Virtual(3), 0),
+                X64::Je(String::from("main_Block4")),
+                X64::Jmp(String::from("main_Block3")),
+                X64::Tag(String::from("main_Block4")),
+            ],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn stmt_compare() {
+        let ast = parser::parse(
+            "
+            void main() {
+                while (0 < 1) {
+                    2;
+                }
+                if (3 != 4) {
+                    5;
+                }
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Tag(String::from("main_Block1")),
+                X64::MovNum(Register::Virtual(0), 0),
+                X64::MovNum(Register::Virtual(1), 1),
+                X64::CmpReg(Register::Virtual(0), Register::Virtual(1)),
+                X64::Jge(String::from("main_Block3")),
+                X64::MovNum(Register::Virtual(2), 2),
+                X64::Jmp(String::from("main_Block1")),
+                X64::Tag(String::from("main_Block3")),
+                X64::MovNum(Register::Virtual(3), 3),
+                X64::MovNum(Register::Virtual(4), 4),
+                X64::CmpReg(Register::Virtual(3), Register::Virtual(4)),
+                X64::Je(String::from("main_Block5")),
+                X64::MovNum(Register::Virtual(5), 5),
+                X64::Tag(String::from("main_Block5")),
             ],
         }];
         assert_eq!(asm, expected);
@@ -530,19 +778,24 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
         let expected = vec![X64Function {
             name: String::from("main"),
             param_cnt: 0,
+            arrays: Vec::new(),
             body: vec![
                 X64::MovNum(Register::Virtual(0), 0),
                 X64::CmpNum(Register::Virtual(0), 0),
-                X64::Je(String::from("VR0End")),
+                X64::Je(String::from("main_Block2")),
+                // Both returns jump to the exit block, and a function
+                // returning nothing drops the value.
                 X64::MovNum(Register::Virtual(1), 1),
-                X64::Ret(Some(Register::Virtual(1))),
-                X64::Tag(String::from("VR0End")),
+                X64::Jmp(String::from("main_Block3")),
+                X64::Tag(String::from("main_Block2")),
+                X64::Jmp(String::from("main_Block3")),
+                X64::Tag(String::from("main_Block3")),
                 X64::Ret(None),
             ],
         }];
@@ -0,0 +1,396 @@
+use crate::dataflow::{self, DataflowAnalysis, Direction};
+use crate::ir::{declared_double, Block, Expression, SSAFunction, SSAProgram, SSAVar, Statement};
+use std::collections::{HashMap, HashSet};
+
+// Available expressions over SSA. An expression is available where every path
+// to it has assigned the expression to the same variable, and neither that
+// variable nor an operand has been defined again since. Only `-`, `!`, and
+// binary operators over variables and constants are tracked, since a load, a
+// call, or an assignment may not give the same value twice. Variables are only
+// defined again around loops.
+// `None` stands for every expression, the fact of blocks not reached yet.
+type Available = Option<HashMap<Expression, SSAVar>>;
+
+// Replace every expression computed again while a variable still holds its
+// value by that variable. Returns the number of expressions replaced.
+pub fn eliminate(ssa: &mut SSAProgram) -> usize {
+    ssa.iter_mut()
+        .map(|func| eliminate_function(func).len())
+        .sum()
+}
+
+// Report every expression `eliminate` would replace, located at the line of
+// its function.
+pub fn report(ssa: &SSAProgram, lines: &HashMap<String, usize>) -> Vec<String> {
+    let mut notes = Vec::new();
+    for func in ssa {
+        for (expr, holder) in eliminate_function(&mut func.clone()) {
+            notes.push(format!(
+                "line {}: `{}` is computed again in function `{}` while `{}` still holds its value",
+                lines[&func.name],
+                show(&expr),
+                func.name,
+                name(&holder)
+            ));
+        }
+    }
+    notes
+}
+
+struct AvailableExpressions<'a> {
+    doubles: &'a HashSet<String>,
+}
+
+impl DataflowAnalysis for AvailableExpressions<'_> {
+    type Fact = Available;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Available {
+        Some(HashMap::new())
+    }
+
+    fn top(&self) -> Available {
+        None
+    }
+
+    fn meet(&self, left: &Available, right: &Available) -> Available {
+        match (left, right) {
+            (None, fact) | (fact, None) => fact.clone(),
+            (Some(left), Some(right)) => Some(
+                left.iter()
+                    .filter(|(expr, holder)| right.get(expr) == Some(holder))
+                    .map(|(expr, holder)| (expr.clone(), holder.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn transfer(&self, _: usize, block: &Block, fact: &Available) -> Available {
+        let mut fact = fact.clone();
+        if let Some(available) = &mut fact {
+            for stmt in &block.statements {
+                transfer_stmt(stmt, available, self.doubles);
+            }
+        }
+        fact
+    }
+}
+
+// Returns every expression replaced, with the variable replacing it.
+fn eliminate_function(func: &mut SSAFunction) -> Vec<(Expression, SSAVar)> {
+    let analysis = AvailableExpressions {
+        doubles: &func.doubles,
+    };
+    let ins = dataflow::solve(&analysis, &func.body).ins;
+    let mut replaced = Vec::new();
+    for (block, available) in func.body.iter_mut().zip(ins) {
+        // Unreachable blocks are left alone.
+        let mut available = match available {
+            Some(available) => available,
+            None => continue,
+        };
+        for stmt in &mut block.statements {
+            // An assignment nested in the statement may run before the
+            // expressions it would replace.
+            let mut defs = Vec::new();
+            match stmt {
+                Statement::Expression(Expression::Infix {
+                    left,
+                    operator: "=",
+                    right,
+                }) if matches!(**left, Expression::Identifier(_)) => {
+                    find_assigned(right, &mut defs)
+                }
+                _ => find_stmt_defs(stmt, &mut defs),
+            }
+            kill(&mut available, &defs);
+            replace_stmt(stmt, &available, &mut replaced);
+            transfer_stmt(stmt, &mut available, &func.doubles);
+        }
+    }
+    replaced
+}
+
+fn transfer_stmt(
+    stmt: &Statement,
+    available: &mut HashMap<Expression, SSAVar>,
+    doubles: &HashSet<String>,
+) {
+    let mut defs = Vec::new();
+    find_stmt_defs(stmt, &mut defs);
+    kill(available, &defs);
+    if let Statement::Expression(Expression::Infix {
+        left,
+        operator: "=",
+        right,
+    }) = stmt
+    {
+        if let Expression::Identifier(var) = &**left {
+            // A value only replaces an expression of the same type.
+            if is_candidate(right)
+                && !mentions(right, var)
+                && !declared_double(doubles, var)
+                && !mentions_double(right, doubles)
+            {
+                available.insert((**right).clone(), var.clone());
+            }
+        }
+    }
+}
+
+fn kill(available: &mut HashMap<Expression, SSAVar>, defs: &[SSAVar]) {
+    available.retain(|expr, holder| {
+        !defs.contains(holder) && !defs.iter().any(|def| mentions(expr, def))
+    });
+}
+
+pub fn find_stmt_defs(stmt: &Statement, defs: &mut Vec<SSAVar>) {
+    match stmt {
+        Statement::Phi(var, _) | Statement::Declaration(var) => defs.push(var.clone()),
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                find_stmt_defs(stmt, defs);
+            }
+        }
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => find_assigned(expr, defs),
+        Statement::ArrayDeclaration(..)
+        | Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Return(None) => {}
+    }
+}
+
+// Collect every variable `expr` assigns to.
+pub fn find_assigned(expr: &Expression, defs: &mut Vec<SSAVar>) {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_assigned(index, defs);
+            }
+        }
+        Expression::Call { arguments, .. } => find_assigned(arguments, defs),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                find_assigned(expr, defs);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_assigned(expression, defs),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            match (&**left, *operator) {
+                (Expression::Identifier(var), "=") => defs.push(var.clone()),
+                (left, _) => find_assigned(left, defs),
+            }
+            find_assigned(right, defs);
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn replace_stmt(
+    stmt: &mut Statement,
+    available: &HashMap<Expression, SSAVar>,
+    replaced: &mut Vec<(Expression, SSAVar)>,
+) {
+    match stmt {
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                replace_stmt(stmt, available, replaced);
+            }
+        }
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => replace_expr(expr, available, replaced),
+        Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Phi(..)
+        | Statement::Declaration(_)
+        | Statement::ArrayDeclaration(..)
+        | Statement::Return(None) => {}
+    }
+}
+
+fn replace_expr(
+    expr: &mut Expression,
+    available: &HashMap<Expression, SSAVar>,
+    replaced: &mut Vec<(Expression, SSAVar)>,
+) {
+    if let Some(holder) = available.get(expr) {
+        replaced.push((expr.clone(), holder.clone()));
+        *expr = Expression::Identifier(holder.clone());
+        return;
+    }
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                replace_expr(index, available, replaced);
+            }
+        }
+        Expression::Call { arguments, .. } => replace_expr(arguments, available, replaced),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                replace_expr(expr, available, replaced);
+            }
+        }
+        Expression::Prefix { expression, .. } => replace_expr(expression, available, replaced),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            // The variable assigned to is not a use.
+            if *operator != "=" || !matches!(**left, Expression::Identifier(_)) {
+                replace_expr(left, available, replaced);
+            }
+            replace_expr(right, available, replaced);
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn is_candidate(expr: &Expression) -> bool {
+    let is_operand = |expr: &Expression| match expr {
+        Expression::Identifier(_) | Expression::Number(_) => true,
+        expr => is_candidate(expr),
+    };
+    match expr {
+        Expression::Prefix {
+            operator: "-" | "!",
+            expression,
+        } => is_operand(expression),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => *operator != "=" && is_operand(left) && is_operand(right),
+        _ => false,
+    }
+}
+
+// Whether a `double` variable is an operand of `expr`, which is a candidate.
+pub fn mentions_double(expr: &Expression, doubles: &HashSet<String>) -> bool {
+    match expr {
+        Expression::Identifier(var) => declared_double(doubles, var),
+        Expression::Prefix { expression, .. } => mentions_double(expression, doubles),
+        Expression::Infix { left, right, .. } => {
+            mentions_double(left, doubles) || mentions_double(right, doubles)
+        }
+        _ => false,
+    }
+}
+
+// Whether `var` is an operand of `expr`, which is a candidate.
+pub fn mentions(expr: &Expression, var: &SSAVar) -> bool {
+    match expr {
+        Expression::Identifier(other) => other == var,
+        Expression::Prefix { expression, .. } => mentions(expression, var),
+        Expression::Infix { left, right, .. } => mentions(left, var) || mentions(right, var),
+        _ => false,
+    }
+}
+
+// Print a candidate the way it could be written in the source.
+fn show(expr: &Expression) -> String {
+    let operand = |expr: &Expression| match expr {
+        Expression::Infix { .. } => format!("({})", show(expr)),
+        expr => show(expr),
+    };
+    match expr {
+        Expression::Identifier(var) => name(var).to_string(),
+        Expression::Number(num) => num.to_string(),
+        Expression::Prefix {
+            operator,
+            expression,
+        } => format!("{}{}", operator, operand(expression)),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => format!("{} {} {}", operand(left), operator, operand(right)),
+        _ => unreachable!(),
+    }
+}
+
+// Drop the suffix that tells shadowed variables apart.
+fn name(var: &SSAVar) -> &str {
+    var.name.split('.').next().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::X64Builder;
+    use crate::lir;
+    use crate::x64::Spill;
+    use crate::{emulator, parser, peephole, reg_allocator, ssa};
+
+    #[test]
+    fn available() {
+        let ast = parser::parse(
+            "
+            int main(int a, int b) {
+                int x = a + b;
+                int z = a * 3;
+                int y;
+                if (a) {
+                    y = (a + b) * 2;
+                } else {
+                    y = a + b;
+                    b = 1;
+                }
+                while (y < 100) {
+                    y = y + (a + b);
+                }
+                y = y + a * 3;
+                a ? (z = 0) : 0;
+                return y + a * 3 + z;
+            }
+        ",
+        );
+        let lines = ast
+            .iter()
+            .map(|func| (func.name.clone(), func.line))
+            .collect();
+        let mut ssa = ssa::construct(ast);
+        // `b` in the loop may be 1. `z` may be 0 at the end, but in another
+        // version than the one holding `a * 3`.
+        let expected = vec![
+            "line 2: `a + b` is computed again in function `main` while `x` still holds its value",
+            "line 2: `a + b` is computed again in function `main` while `x` still holds its value",
+            "line 2: `a * 3` is computed again in function `main` while `z` still holds its value",
+            "line 2: `a * 3` is computed again in function `main` while `z` still holds its value",
+        ];
+        assert_eq!(report(&ssa, &lines), expected);
+        assert_eq!(eliminate(&mut ssa), 4);
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa)));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(emulator::run(&asm, &[3, 4]), Ok(123));
+        assert_eq!(emulator::run(&asm, &[0, 4]), Ok(100));
+    }
+}
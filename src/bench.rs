@@ -0,0 +1,95 @@
+// Every stage of the pipeline on its own, so benchmarks and profiles can time
+// one stage on IR built ahead of time. The stages run the passes of `Pipeline`,
+// as `compile` does. The IR types stay private: each stage returns an opaque
+// value that only the next stage accepts. Stages consume their input, so a
+// benchmark clones it in the setup of every iteration.
+use crate::cancel::CancellationToken;
+use crate::ir::{Program, SSAProgram};
+use crate::parser::Error;
+use crate::serializer::Toolchain;
+use crate::x64::{Spill, X64Program};
+use crate::{parse_units, try_parse_units, Context, Opt, Pipeline};
+use std::path::Path;
+
+// Every stage carries the context of the program along, so that the later
+// stages know the functions called and the messages of the assertions.
+#[derive(Debug, Clone)]
+pub struct Ast(Program, Context);
+
+#[derive(Debug, Clone)]
+pub struct Ssa(SSAProgram, Context);
+
+#[derive(Debug, Clone)]
+pub struct Vasm(X64Program, Context);
+
+#[derive(Debug, Clone)]
+pub struct Asm(X64Program, Context);
+
+const PATH: &str = "<source>";
+
+pub fn parse(source: &str) -> Ast {
+    let (ast, context) = parse_units(&[(Path::new(PATH), source)], false);
+    Ast(ast, context)
+}
+
+// `parse`, with a malformed event stream from the C++ parser as an error.
+pub fn try_parse(source: &str) -> Result<Ast, Error> {
+    let (ast, context) = try_parse_units(&[(Path::new(PATH), source)], false)?;
+    Ok(Ast(ast, context))
+}
+
+// Construct SSA, fold the comparisons its ranges decide, and replace the
+// expressions already available.
+pub fn ssa(Ast(ast, context): Ast) -> Ssa {
+    let opt = Opt::default();
+    let mut pipeline = Pipeline::new(&opt, &context);
+    let (mut ssa, _, _) = pipeline.construct(ast, &CancellationToken::new()).unwrap();
+    pipeline.optimize(&mut ssa);
+    Ssa(ssa, context)
+}
+
+// Destruct SSA, move the partially redundant computations, lower to the LIR,
+// select instructions over virtual registers, number the values in every
+// block, and schedule them.
+pub fn codegen(Ssa(ssa, context): Ssa) -> Vasm {
+    let opt = Opt::default();
+    let mut pipeline = Pipeline::new(&opt, &context);
+    let cfg = pipeline.destruct(ssa);
+    let lir = pipeline.lower(cfg);
+    let vasm = pipeline.select(lir);
+    Vasm(vasm, context)
+}
+
+pub fn regalloc(Vasm(vasm, context): Vasm, spill: Spill) -> Asm {
+    let opt = Opt {
+        spill,
+        ..Opt::default()
+    };
+    let asm = Pipeline::new(&opt, &context).allocate(vasm);
+    Asm(asm, context)
+}
+
+// Clean up the assembly and serialize it for `toolchain`.
+pub fn emit(Asm(asm, context): Asm, toolchain: Toolchain) -> String {
+    let opt = Opt {
+        toolchain,
+        ..Opt::default()
+    };
+    let mut pipeline = Pipeline::new(&opt, &context);
+    let asm = pipeline.cleanup(asm);
+    pipeline.emit(asm).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, stress, Opt};
+
+    #[test]
+    fn stages() {
+        let source = stress::straight_line(3, 50);
+        let vasm = codegen(ssa(parse(&source)));
+        let asm = emit(regalloc(vasm, Spill::default()), Toolchain::Masm);
+        assert_eq!(Some(asm), compile(&source, Opt::default()));
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// A handle an embedding application keeps to stop a compilation early. The
+// compiler checks it between passes and on every iteration of the data-flow
+// fixpoint, so a cancelled compilation stops at the next check.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+// The error of a compilation stopped through its `CancellationToken`.
+#[derive(Debug, PartialEq)]
+pub struct Cancelled;
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    // A token that also cancels itself once `timeout` has passed.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CancellationToken {
+            cancelled: Arc::default(),
+            deadline: Some(Instant::now() + timeout),
+        }
+    }
+
+    // Cancel every compilation using this token or one of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Cancelled> {
+        match self.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_cancellable, CompileError, Opt};
+    use std::path::Path;
+
+    #[test]
+    fn cancellation() {
+        let source = (Path::new("main.c"), "int main() { return 1; }");
+        let token = CancellationToken::new();
+        let result = compile_cancellable(&[source], Opt::default(), &token);
+        assert!(matches!(result, Ok(Some(_))));
+        token.clone().cancel();
+        let result = compile_cancellable(&[source], Opt::default(), &token);
+        assert_eq!(result, Err(CompileError::Cancelled));
+        let token = CancellationToken::with_timeout(Duration::from_secs(0));
+        let result = compile_cancellable(&[source], Opt::default(), &token);
+        assert_eq!(result, Err(CompileError::Cancelled));
+    }
+}
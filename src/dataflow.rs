@@ -0,0 +1,231 @@
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::ir::Block;
+use crate::traversal;
+use std::collections::HashSet;
+
+// Which way facts flow: from a block to its successors, or to its
+// predecessors.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+// A data-flow problem over the blocks of a CFG, whose entry is block 0. Facts
+// start at `top`, the identity of `meet`, and only move down as they are
+// combined, so `solve` reaches the maximal fixpoint.
+pub trait DataflowAnalysis {
+    type Fact: Clone + PartialEq;
+
+    const DIRECTION: Direction;
+
+    // The fact entering the entry block, or leaving the blocks without
+    // successors when going backward.
+    fn boundary(&self) -> Self::Fact;
+
+    fn top(&self) -> Self::Fact;
+
+    fn meet(&self, left: &Self::Fact, right: &Self::Fact) -> Self::Fact;
+
+    // The fact on the other side of block `index`, given the one flowing into it.
+    fn transfer(&self, index: usize, block: &Block, fact: &Self::Fact) -> Self::Fact;
+}
+
+// The facts at the start and at the end of every block, whatever the
+// direction of the analysis.
+#[derive(Debug, PartialEq)]
+pub struct Solution<F> {
+    pub ins: Vec<F>,
+    pub outs: Vec<F>,
+}
+
+// Iterate the transfer functions to a fixpoint. Blocks are visited in reverse
+// postorder going forward and in postorder going backward, so most facts are
+// final after one round outside loops. Unreachable blocks keep `top`.
+pub fn solve<A: DataflowAnalysis>(analysis: &A, cfg: &[Block]) -> Solution<A::Fact> {
+    solve_cancellable(analysis, cfg, &CancellationToken::new()).unwrap()
+}
+
+// Like `solve`, but gives up with `Cancelled` once `cancel` is cancelled,
+// which is checked on every iteration.
+pub fn solve_cancellable<A: DataflowAnalysis>(
+    analysis: &A,
+    cfg: &[Block],
+    cancel: &CancellationToken,
+) -> Result<Solution<A::Fact>, Cancelled> {
+    let forward = A::DIRECTION == Direction::Forward;
+    let mut order: Vec<_> = traversal::reverse_postorder(cfg, 0).collect();
+    if !forward {
+        order.reverse();
+    }
+    // Facts flowing into and out of every block, in the direction of flow.
+    let mut befores = vec![analysis.top(); cfg.len()];
+    let mut afters = vec![analysis.top(); cfg.len()];
+    let mut changed = true;
+    while changed {
+        cancel.check()?;
+        changed = false;
+        for &i in &order {
+            let sources = match forward {
+                true => &cfg[i].predecessors,
+                false => &cfg[i].successors,
+            };
+            let boundary = match forward {
+                true => i == 0,
+                false => sources.is_empty(),
+            };
+            let mut before = match boundary {
+                true => analysis.boundary(),
+                false => analysis.top(),
+            };
+            for &source in sources {
+                before = analysis.meet(&before, &afters[source]);
+            }
+            let after = analysis.transfer(i, &cfg[i], &before);
+            befores[i] = before;
+            if after != afters[i] {
+                afters[i] = after;
+                changed = true;
+            }
+        }
+    }
+    Ok(match forward {
+        true => Solution {
+            ins: befores,
+            outs: afters,
+        },
+        false => Solution {
+            ins: afters,
+            outs: befores,
+        },
+    })
+}
+
+// The blocks dominating a block: those on every path to it from the entry.
+struct Dominance {
+    len: usize,
+}
+
+impl DataflowAnalysis for Dominance {
+    type Fact = HashSet<usize>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn top(&self) -> Self::Fact {
+        (0..self.len).collect()
+    }
+
+    fn meet(&self, left: &Self::Fact, right: &Self::Fact) -> Self::Fact {
+        left.intersection(right).copied().collect()
+    }
+
+    fn transfer(&self, index: usize, _: &Block, fact: &Self::Fact) -> Self::Fact {
+        let mut fact = fact.clone();
+        fact.insert(index);
+        fact
+    }
+}
+
+// The immediate dominator of every block reachable from the entry: the one of
+// its strict dominators that all the others dominate. The entry block and
+// unreachable blocks have none.
+pub fn dominators(cfg: &[Block]) -> Vec<Option<usize>> {
+    dominators_cancellable(cfg, &CancellationToken::new()).unwrap()
+}
+
+// Like `dominators`, but gives up with `Cancelled` once `cancel` is cancelled.
+pub fn dominators_cancellable(
+    cfg: &[Block],
+    cancel: &CancellationToken,
+) -> Result<Vec<Option<usize>>, Cancelled> {
+    let doms = solve_cancellable(&Dominance { len: cfg.len() }, cfg, cancel)?.outs;
+    let mut idoms = vec![None; cfg.len()];
+    for i in traversal::postorder(cfg, 0) {
+        idoms[i] = doms[i]
+            .iter()
+            .copied()
+            .find(|&dom| dom != i && doms[dom].len() == doms[i].len() - 1);
+    }
+    Ok(idoms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Expression, SSAVar, Statement, CFG};
+
+    // 0 -> 1 -> 2 -> 4
+    //      ^    |
+    //      +----3
+    // Block 2 reads `x` and block 3 assigns it; block 5 is unreachable.
+    fn loop_cfg() -> CFG {
+        let mut cfg: CFG = (0..6).map(|_| Block::default()).collect();
+        for (pred, succ) in [(0, 1), (1, 2), (2, 3), (3, 1), (2, 4)] {
+            cfg[pred].successors.insert(succ);
+            cfg[succ].predecessors.insert(pred);
+        }
+        let x = || Box::new(Expression::Identifier(SSAVar::new("x")));
+        cfg[2].statements.push(Statement::Expression(*x()));
+        cfg[3]
+            .statements
+            .push(Statement::Expression(Expression::Infix {
+                left: x(),
+                operator: "=",
+                right: Box::new(Expression::Number(0)),
+            }));
+        cfg
+    }
+
+    // Whether a block may read `x` before assigning it, or on a path from it.
+    struct Liveness;
+
+    impl DataflowAnalysis for Liveness {
+        type Fact = bool;
+
+        const DIRECTION: Direction = Direction::Backward;
+
+        fn boundary(&self) -> bool {
+            false
+        }
+
+        fn top(&self) -> bool {
+            false
+        }
+
+        fn meet(&self, left: &bool, right: &bool) -> bool {
+            *left || *right
+        }
+
+        fn transfer(&self, _: usize, block: &Block, live: &bool) -> bool {
+            match block.statements.first() {
+                Some(Statement::Expression(Expression::Infix { .. })) => false,
+                Some(_) => true,
+                None => *live,
+            }
+        }
+    }
+
+    #[test]
+    fn liveness() {
+        let solution = solve(&Liveness, &loop_cfg());
+        let expected = Solution {
+            ins: vec![true, true, true, false, false, false],
+            outs: vec![true, true, false, true, false, false],
+        };
+        assert_eq!(solution, expected);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let solution = solve_cancellable(&Liveness, &loop_cfg(), &cancel);
+        assert_eq!(solution, Err(Cancelled));
+    }
+
+    #[test]
+    fn dominance() {
+        let expected = vec![None, Some(0), Some(1), Some(2), Some(2), None];
+        assert_eq!(dominators(&loop_cfg()), expected);
+    }
+}
@@ -0,0 +1,135 @@
+// A line-based unified diff computed from the longest common subsequence.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Edit {
+    Keep(usize, usize), // Keep(before_index, after_index)
+    Delete(usize),
+    Insert(usize),
+}
+
+pub fn unified(before: &str, after: &str, context: usize) -> Option<String> {
+    if before == after {
+        return None;
+    }
+    let before: Vec<_> = before.lines().collect();
+    let after: Vec<_> = after.lines().collect();
+    let edits = find_edits(&before, &after);
+    let mut diff = String::from("--- before\n+++ after\n");
+    for hunk in group_hunks(&edits, context) {
+        let (mut before_start, mut before_len) = (None, 0);
+        let (mut after_start, mut after_len) = (None, 0);
+        let mut lines = String::new();
+        for edit in hunk {
+            match *edit {
+                Edit::Keep(i, j) => {
+                    before_start.get_or_insert(i);
+                    after_start.get_or_insert(j);
+                    before_len += 1;
+                    after_len += 1;
+                    lines += &format!(" {}\n", before[i]);
+                }
+                Edit::Delete(i) => {
+                    before_start.get_or_insert(i);
+                    before_len += 1;
+                    lines += &format!("-{}\n", before[i]);
+                }
+                Edit::Insert(j) => {
+                    after_start.get_or_insert(j);
+                    after_len += 1;
+                    lines += &format!("+{}\n", after[j]);
+                }
+            }
+        }
+        diff += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            before_start.map_or(0, |i| i + 1),
+            before_len,
+            after_start.map_or(0, |j| j + 1),
+            after_len,
+        );
+        diff += &lines;
+    }
+    Some(diff)
+}
+
+// The edits turning `before` into `after`, which need not be lines.
+pub(crate) fn find_edits<T: PartialEq>(before: &[T], after: &[T]) -> Vec<Edit> {
+    // `lcs[i][j]` is the LCS length of `before[i..]` and `after[j..]`.
+    let mut lcs = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            lcs[i][j] = if before[i] == after[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            edits.push(Edit::Keep(i, j));
+            i += 1;
+            j += 1;
+        } else if j == after.len() || (i < before.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        }
+    }
+    edits
+}
+
+// Split edits into hunks, keeping at most `context` unchanged lines around each change.
+fn group_hunks(edits: &[Edit], context: usize) -> Vec<&[Edit]> {
+    let changes: Vec<_> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep(..)))
+        .map(|(index, _)| index)
+        .collect();
+    let mut hunks = Vec::new();
+    let mut index = 0;
+    while index < changes.len() {
+        let start = changes[index].saturating_sub(context);
+        let mut end = changes[index];
+        while index + 1 < changes.len() && changes[index + 1] - end <= 2 * context + 1 {
+            index += 1;
+            end = changes[index];
+        }
+        let end = (end + context + 1).min(edits.len());
+        hunks.push(&edits[start..end]);
+        index += 1;
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", 3), None);
+    }
+
+    #[test]
+    fn hunks() {
+        let before = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let after = "1\nx\n3\n4\n5\n6\n7\n8\n9\ny\n";
+        let expected = "--- before
++++ after
+@@ -1,3 +1,3 @@
+ 1
+-2
++x
+ 3
+@@ -9,1 +9,2 @@
+ 9
++y
+";
+        assert_eq!(unified(before, after, 1), Some(String::from(expected)));
+    }
+}
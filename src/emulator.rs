@@ -0,0 +1,465 @@
+use crate::ir::Int;
+use crate::x64::{Register, X64Program, X64RegisterAllocator, X64};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+
+const MEMORY_SIZE: usize = 1 << 23;
+const STEP_LIMIT: usize = 100_000_000;
+
+// Run `main` of an allocated program with `args`, the way the driver calls
+// it, and return what it returns. Stack slots, arrays, and pointers all live
+// in one flat memory, where an address is an offset into it. The program
+// fails on a call to a function it does not define, except for an assertion
+// that holds.
+pub fn run(asm: &X64Program, args: &[Int]) -> Result<Int, String> {
+    let mut machine = Machine {
+        regs: [0; 22],
+        memory: vec![0; MEMORY_SIZE],
+        flags: (0, 0),
+    };
+    let tags: Vec<HashMap<_, _>> = asm
+        .iter()
+        .map(|func| {
+            let tags = func.body.iter().enumerate();
+            tags.filter_map(|(i, asm)| match asm {
+                X64::Tag(tag) => Some((tag.as_str(), i)),
+                _ => None,
+            })
+            .collect()
+        })
+        .collect();
+    let function = |name: &str| asm.iter().position(|func| func.name == name);
+    // The driver points RBP to the frame of `main` at the top of the stack.
+    let rsp = MEMORY_SIZE - X64RegisterAllocator::FRAME_SIZE;
+    machine.set(X64RegisterAllocator::RSP, rsp as Int);
+    machine.set(X64RegisterAllocator::RBP, rsp as Int);
+    for (i, arg) in args.iter().enumerate() {
+        match [
+            X64RegisterAllocator::RCX,
+            X64RegisterAllocator::RDX,
+            X64RegisterAllocator::R8,
+            X64RegisterAllocator::R9,
+        ]
+        .get(i)
+        {
+            Some(reg) => machine.set(*reg, *arg),
+            None => machine.store(rsp + i * X64RegisterAllocator::WORD_SIZE, *arg)?,
+        }
+    }
+    let main = function("main").ok_or("The program has no `main`.")?;
+    machine.push(0)?;
+    // The function and the position of every call in progress.
+    let mut frames = vec![(main, 0)];
+    for _ in 0..STEP_LIMIT {
+        let (func, pc) = frames.last_mut().unwrap();
+        let (func, asm) = (*func, &asm[*func].body[*pc]);
+        *pc += 1;
+        if let Some(tag) = asm.jump_target() {
+            let (left, right) = machine.flags;
+            let taken = match asm {
+                X64::Jl(_) => left < right,
+                X64::Jg(_) => left > right,
+                X64::Jle(_) => left <= right,
+                X64::Jge(_) => left >= right,
+                X64::Je(_) => left == right,
+                X64::Jne(_) => left != right,
+                X64::Jb(_) => (left as u64) < (right as u64),
+                X64::Ja(_) => (left as u64) > (right as u64),
+                X64::Jbe(_) => (left as u64) <= (right as u64),
+                X64::Jae(_) => (left as u64) >= (right as u64),
+                _ => true,
+            };
+            if taken {
+                *pc = tags[func][tag.as_str()];
+            }
+            continue;
+        }
+        match asm {
+            X64::Call(name, ..) => match function(name) {
+                Some(callee) => {
+                    machine.push(0)?;
+                    frames.push((callee, 0));
+                }
+                None if name.starts_with("eac_assert_") => {
+                    if machine.get(X64RegisterAllocator::RCX) == 0 {
+                        return Err(format!("Assertion `{}` failed.", name));
+                    }
+                }
+                None => return Err(format!("Function `{}` is not defined.", name)),
+            },
+            X64::Ret(_) => {
+                machine.pop()?;
+                frames.pop();
+                if frames.is_empty() {
+                    return Ok(machine.get(X64RegisterAllocator::RAX));
+                }
+            }
+            asm => machine.execute(asm)?,
+        }
+    }
+    Err(format!("`main` did not return in {} steps.", STEP_LIMIT))
+}
+
+struct Machine {
+    regs: [Int; 22], // An XMM register holds the bits of a `double`.
+    memory: Vec<u8>,
+    flags: (Int, Int), // The operands of the last comparison.
+}
+
+impl Machine {
+    fn execute(&mut self, asm: &X64) -> Result<(), String> {
+        let rsp = self.get(X64RegisterAllocator::RSP) as usize;
+        let rbp = self.get(X64RegisterAllocator::RBP) as usize;
+        let element = |array: usize, index: Int| {
+            (rsp + array) as Int + index * X64RegisterAllocator::ELEMENT_SIZE as Int
+        };
+        match *asm {
+            X64::MovNum(reg, num) => self.set(reg, num),
+            X64::MovReg(left, right) => self.set(left, self.get(right)),
+            X64::MovToStack(offset, reg) => self.store(rbp + offset, self.get(reg))?,
+            X64::MovFromStack(reg, offset) => self.set(reg, self.load(rbp + offset)?),
+            X64::LoadIndex(reg, array, index) => {
+                let address = self.address(element(array, self.get(index)))?;
+                self.set(reg, self.load(address)?);
+            }
+            X64::StoreIndex(array, index, reg) => {
+                let address = self.address(element(array, self.get(index)))?;
+                self.store(address, self.get(reg))?;
+            }
+            X64::LeaIndex(reg, array, index) => self.set(reg, element(array, self.get(index))),
+            X64::Lea(reg, base, index, scale) => {
+                let offset = self.get(index).wrapping_mul(scale as Int);
+                self.set(reg, self.get(base).wrapping_add(offset))
+            }
+            X64::LoadField(reg, array, offset) => self.set(reg, self.load(rsp + array + offset)?),
+            X64::StoreField(array, offset, reg) => {
+                self.store(rsp + array + offset, self.get(reg))?
+            }
+            X64::Load(reg, address) => {
+                let address = self.address(self.get(address))?;
+                self.set(reg, self.load(address)?);
+            }
+            X64::Store(address, reg) => {
+                let address = self.address(self.get(address))?;
+                self.store(address, self.get(reg))?;
+            }
+            X64::Neg(reg) => self.set(reg, self.get(reg).wrapping_neg()),
+            X64::CmpNum(reg, num) => self.flags = (self.get(reg), num as Int),
+            X64::CmpReg(left, right) => self.flags = (self.get(left), self.get(right)),
+            X64::Test(left, right) => self.flags = (self.get(left) & self.get(right), 0),
+            X64::Imul(left, right) => self.set(left, self.get(left).wrapping_mul(self.get(right))),
+            X64::Idiv(_, right) => {
+                let rax = X64RegisterAllocator::RAX;
+                let rdx = X64RegisterAllocator::RDX;
+                let dividend = ((self.get(rdx) as i128) << 64) | (self.get(rax) as u64 as i128);
+                let divisor = self.get(right) as i128;
+                if divisor == 0 {
+                    return Err(String::from("Division by zero."));
+                }
+                let quotient = Int::try_from(dividend / divisor)
+                    .map_err(|_| String::from("The quotient of a division overflows."))?;
+                self.set(rax, quotient);
+                self.set(rdx, (dividend % divisor) as Int);
+            }
+            X64::Cqo => {
+                let sign = self.get(X64RegisterAllocator::RAX) >> 63;
+                self.set(X64RegisterAllocator::RDX, sign);
+            }
+            X64::Add(left, right) => self.set(left, self.get(left).wrapping_add(self.get(right))),
+            X64::AddNum(reg, num) => self.set(reg, self.get(reg).wrapping_add(num as Int)),
+            X64::Sub(left, right) => self.set(left, self.get(left).wrapping_sub(self.get(right))),
+            X64::SubNum(reg, num) => self.set(reg, self.get(reg).wrapping_sub(num as Int)),
+            X64::And(left, right) => self.set(left, self.get(left) & self.get(right)),
+            X64::Or(left, right) => self.set(left, self.get(left) | self.get(right)),
+            X64::Xor(left, right) => self.set(left, self.get(left) ^ self.get(right)),
+            X64::Cvtsi2sd(left, right) => self.set(left, (self.get(right) as f64).to_bits() as Int),
+            X64::Cvttsd2si(left, right) => {
+                // Out of range, the result is the "integer indefinite" value.
+                let value = f64::from_bits(self.get(right) as u64).trunc();
+                let fits = (Int::MIN as f64..Int::MAX as f64).contains(&value);
+                self.set(left, if fits { value as Int } else { Int::MIN });
+            }
+            X64::Addsd(left, right) => self.float(left, right, |l, r| l + r),
+            X64::Subsd(left, right) => self.float(left, right, |l, r| l - r),
+            X64::Mulsd(left, right) => self.float(left, right, |l, r| l * r),
+            X64::Divsd(left, right) => self.float(left, right, |l, r| l / r),
+            // Recorded as an unsigned comparison of 0 and 1. An unordered
+            // result counts as below, though `je` would also be taken on it.
+            X64::Ucomisd(left, right) => {
+                let left = f64::from_bits(self.get(left) as u64);
+                let right = f64::from_bits(self.get(right) as u64);
+                self.flags = match left.partial_cmp(&right) {
+                    Some(Ordering::Greater) => (1, 0),
+                    Some(Ordering::Equal) => (0, 0),
+                    Some(Ordering::Less) | None => (0, 1),
+                };
+            }
+            X64::Push(reg) => self.push(self.get(reg))?,
+            X64::Pop(reg) => {
+                let value = self.pop()?;
+                self.set(reg, value);
+            }
+            X64::Tag(_) => {}
+            X64::Irem(..) => unreachable!(),
+            ref asm => panic!("`{}` is left for the emulator to run.", asm),
+        }
+        Ok(())
+    }
+
+    fn get(&self, reg: Register) -> Int {
+        match reg {
+            Register::X64(reg) => self.regs[reg as usize],
+            Register::Virtual(_) | Register::VirtualXmm(_) => {
+                panic!("Register `{}` is not allocated.", reg)
+            }
+        }
+    }
+
+    fn set(&mut self, reg: Register, value: Int) {
+        match reg {
+            Register::X64(reg) => self.regs[reg as usize] = value,
+            Register::Virtual(_) | Register::VirtualXmm(_) => {
+                panic!("Register `{}` is not allocated.", reg)
+            }
+        }
+    }
+
+    // Apply `op` to the `double` values in two XMM registers.
+    fn float(&mut self, left: Register, right: Register, op: fn(f64, f64) -> f64) {
+        let value = op(
+            f64::from_bits(self.get(left) as u64),
+            f64::from_bits(self.get(right) as u64),
+        );
+        self.set(left, value.to_bits() as Int);
+    }
+
+    fn address(&self, address: Int) -> Result<usize, String> {
+        usize::try_from(address).map_err(|_| format!("Invalid address {}.", address))
+    }
+
+    fn load(&self, address: usize) -> Result<Int, String> {
+        match self.memory.get(address..address + 8) {
+            Some(bytes) => Ok(Int::from_le_bytes(bytes.try_into().unwrap())),
+            None => Err(format!("Invalid address {}.", address)),
+        }
+    }
+
+    fn store(&mut self, address: usize, value: Int) -> Result<(), String> {
+        match self.memory.get_mut(address..address + 8) {
+            Some(bytes) => {
+                bytes.copy_from_slice(&value.to_le_bytes());
+                Ok(())
+            }
+            None => Err(format!("Invalid address {}.", address)),
+        }
+    }
+
+    fn push(&mut self, value: Int) -> Result<(), String> {
+        let rsp = self.get(X64RegisterAllocator::RSP) - 8;
+        if rsp < 0 {
+            return Err(String::from("Stack overflow."));
+        }
+        self.set(X64RegisterAllocator::RSP, rsp);
+        self.store(rsp as usize, value)
+    }
+
+    fn pop(&mut self) -> Result<Int, String> {
+        let rsp = self.get(X64RegisterAllocator::RSP);
+        let value = self.load(rsp as usize)?;
+        self.set(X64RegisterAllocator::RSP, rsp + 8);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::X64Builder;
+    use crate::lir;
+    use crate::x64::Spill;
+    use crate::{parser, peephole, reg_allocator, ssa};
+
+    #[test]
+    fn programs() {
+        let ast = parser::parse(
+            "
+            int f(int n) {
+                if (n < 2) { return n; }
+                return f(n - 1) + f(n - 2);
+            }
+            int main(int n, int d) {
+                int a[4];
+                int i = 0;
+                while (i < 4) { a[i] = f(n + i) % d; i = i + 1; }
+                return a[0] * 1000 + a[1] * 100 + a[2] * 10 + a[3] - 7 / d;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let vasm = X64Builder::new().build(lir::lower(cfg));
+        for spill in [Spill::Cost, Spill::NextUse] {
+            let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm.clone(), spill));
+            // `f(10..=13)` is 55, 89, 144, and 233.
+            assert_eq!(run(&asm, &[10, 10]), Ok(5943));
+            assert_eq!(run(&asm, &[10, 0]), Err(String::from("Division by zero.")));
+        }
+    }
+
+    #[test]
+    fn long_arguments() {
+        // Arguments past the fourth live in 8-byte stack slots, so neighbors
+        // wider than 32 bits must not overlap.
+        let ast = parser::parse(
+            "
+            long sum(long a, long b, long c, long d, long e, long f) {
+                return a + b + c + d + e + f;
+            }
+            long main(long a, long b, long c, long d, long e, long f) {
+                return sum(f, e, d, c, b, 5000000000 + a) / 1000000000;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        let args = [1, 2, 3, 4, 6000000000, 7000000000];
+        assert_eq!(run(&asm, &args), Ok(18));
+    }
+
+    #[test]
+    fn negation() {
+        // Negating a variable leaves the variable as it was.
+        let ast = parser::parse(
+            "
+            int main(int a) {
+                int b = -a;
+                return a * 10 + b;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(run(&asm, &[3]), Ok(27));
+    }
+
+    #[test]
+    fn pointer_parameter() {
+        // A pointer as the only parameter is parsed like a function pointer
+        // declarator, and the function keeps its name and its parameter.
+        let ast = parser::parse(
+            "
+            int g(int *p) {
+                *p = *p + 1;
+                return *p * 10;
+            }
+            int main(int a) {
+                int x = a;
+                int y = g(&x);
+                return y + x;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(run(&asm, &[4]), Ok(55));
+    }
+
+    #[test]
+    fn smallest_integer() {
+        // The smallest integer is its own negation, and negating the value
+        // right above it leaves that value in place.
+        let ast = parser::parse(
+            "
+            int main() {
+                int m = -9223372036854775808;
+                int k = m + 1;
+                int n = -k;
+                return (k < 0) + (n > 0) * 2 + (-m == m) * 4 + (n + k == 0) * 8;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(run(&asm, &[]), Ok(15));
+    }
+
+    #[test]
+    fn short_circuit_conditional() {
+        // A `?:` in the right operand of `&&` or `||` only runs when C
+        // evaluates that operand.
+        let ast = parser::parse(
+            "
+            int main(int a) {
+                int r = a && (a / a ? 1 : 2);
+                int s = a == 0 || (10 / a > 1 ? 1 : 0);
+                return r * 10 + s;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(run(&asm, &[0]), Ok(1));
+        assert_eq!(run(&asm, &[4]), Ok(11));
+        assert_eq!(run(&asm, &[20]), Ok(10));
+    }
+
+    #[test]
+    fn short_circuit_assignment() {
+        // An assignment in the right operand of `&&` or `||` only happens
+        // when C evaluates that operand, and the value before it survives
+        // otherwise.
+        let ast = parser::parse(
+            "
+            int main(int a) {
+                int b = 0;
+                int c = 0;
+                if (a == 0 || (b = 5)) { a = 1; }
+                int r = b > 0 && (c = 7);
+                return a * 100 + b * 10 + c + r;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(run(&asm, &[0]), Ok(100));
+        assert_eq!(run(&asm, &[4]), Ok(158));
+    }
+
+    #[test]
+    fn doubles() {
+        // Integers convert to `double` in mixed operations and back when
+        // returned, and `double` values live on the stack across calls.
+        let ast = parser::parse(
+            "
+            double half(double x) {
+                return x / 2;
+            }
+            double poly(double a, int b, double c, int d, double e) {
+                return a * b + c * d - e;
+            }
+            int main(int n) {
+                double x = 3.5;
+                double y = half(x) + 1;
+                double s = 0.0;
+                int i = 0;
+                while (i < n) { s = s + 0.5; i = i + 1; }
+                int t = 0;
+                if (s > 4.9) { t = t + 1; }
+                if (x <= 3.5) { t = t + 2; }
+                if (-x < 0.0) { t = t + 4; }
+                if (!(s == 5.0)) { t = t + 8; }
+                double z = i > 3 ? 2.25 : 1;
+                int k = poly(1.5, 2, 0.25, 4, 1e1);
+                return y * 10 + s + t + z * 4 + k;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        for spill in [Spill::Cost, Spill::NextUse] {
+            let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm.clone(), spill));
+            // 27.5 + 5 + 7 + 9 - 6, truncated.
+            assert_eq!(run(&asm, &[10]), Ok(42));
+        }
+    }
+}
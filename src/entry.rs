@@ -0,0 +1,160 @@
+use crate::x64::{X64Function, X64Program, X64RegisterAllocator as X64R, X64};
+use std::str::FromStr;
+
+// The entry point of the driver, which prints what `main` returns.
+pub const DRIVER_ENTRY: &str = "drive";
+
+// What the program's `main` is renamed to when linking with the C runtime.
+pub const CRT_MAIN: &str = "eac_main";
+
+// The Windows subsystem the executable is linked for.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Subsystem {
+    #[default]
+    Console,
+    Windows, // No console is allocated, so nothing is printed.
+}
+
+impl Subsystem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Console => "console",
+            Subsystem::Windows => "windows",
+        }
+    }
+
+    pub fn default_entry(&self) -> &'static str {
+        match self {
+            Subsystem::Console => DRIVER_ENTRY,
+            Subsystem::Windows => "WinMainCRTStartup",
+        }
+    }
+}
+
+impl FromStr for Subsystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "console" => Ok(Subsystem::Console),
+            "windows" => Ok(Subsystem::Windows),
+            s => Err(format!("Unknown subsystem `{}`.", s)),
+        }
+    }
+}
+
+// Add a stub named `entry` that calls `main` and exits the process with its
+// return value, unless `entry` is the driver's entry point or a function of
+// the program. The stack is kept 16-byte aligned at both calls.
+pub fn add_entry_stub(asm: &mut X64Program, entry: &str) {
+    if entry == DRIVER_ENTRY || asm.iter().any(|func| func.name == entry) {
+        return;
+    }
+    let frame = X64R::FRAME_SIZE + 8;
+    asm.push(X64Function {
+        name: entry.to_string(),
+        param_cnt: 0,
+        arrays: Vec::new(),
+        body: vec![
+            X64::SubNum(X64R::RSP, frame),
+            X64::MovReg(X64R::RBP, X64R::RSP),
+            X64::Call(String::from("main"), Vec::new(), X64R::RAX, false),
+            X64::MovReg(X64R::RCX, X64R::RAX),
+            X64::Call(String::from("ExitProcess"), Vec::new(), X64R::RAX, false),
+        ],
+    });
+}
+
+// Make `main` callable by the C runtime, which neither provides the frame
+// nor points RBP to it as callers in the program do. The program's `main` is
+// renamed to `CRT_MAIN`, and a new `main` allocates its frame and calls it,
+// passing on the registers of `argc` and `argv` untouched. The push keeps RSP
+// 16-byte aligned at the call.
+pub fn add_crt_main(asm: &mut X64Program) {
+    for func in asm.iter_mut() {
+        if func.name == "main" {
+            func.name = CRT_MAIN.to_string();
+        }
+        for asm in &mut func.body {
+            if let X64::Call(name, ..) = asm {
+                if name == "main" {
+                    *name = CRT_MAIN.to_string();
+                }
+            }
+        }
+    }
+    asm.push(X64Function {
+        name: String::from("main"),
+        param_cnt: 0,
+        arrays: Vec::new(),
+        body: vec![
+            X64::Push(X64R::RBP),
+            X64::SubNum(X64R::RSP, X64R::FRAME_SIZE),
+            X64::MovReg(X64R::RBP, X64R::RSP),
+            X64::Call(String::from(CRT_MAIN), Vec::new(), X64R::RAX, false),
+            X64::AddNum(X64R::RSP, X64R::FRAME_SIZE),
+            X64::Pop(X64R::RBP),
+            X64::Ret(None),
+        ],
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_stub() {
+        let main = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![X64::Ret(None)],
+        };
+        let mut asm = vec![main.clone()];
+        add_entry_stub(&mut asm, DRIVER_ENTRY);
+        add_entry_stub(&mut asm, "main");
+        assert_eq!(asm, vec![main]);
+        add_entry_stub(&mut asm, "WinMainCRTStartup");
+        let expected = vec![
+            X64::SubNum(X64R::RSP, 1032),
+            X64::MovReg(X64R::RBP, X64R::RSP),
+            X64::Call(String::from("main"), Vec::new(), X64R::RAX, false),
+            X64::MovReg(X64R::RCX, X64R::RAX),
+            X64::Call(String::from("ExitProcess"), Vec::new(), X64R::RAX, false),
+        ];
+        assert_eq!(asm[1].name, "WinMainCRTStartup");
+        assert_eq!(asm[1].body, expected);
+    }
+
+    #[test]
+    fn crt_main() {
+        let main = X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Call(String::from("main"), Vec::new(), X64R::RAX, false),
+                X64::Ret(None),
+            ],
+        };
+        let mut asm = vec![main];
+        add_crt_main(&mut asm);
+        assert_eq!(asm[0].name, "eac_main");
+        assert_eq!(
+            asm[0].body[0],
+            X64::Call(String::from("eac_main"), Vec::new(), X64R::RAX, false)
+        );
+        let expected = vec![
+            X64::Push(X64R::RBP),
+            X64::SubNum(X64R::RSP, 1024),
+            X64::MovReg(X64R::RBP, X64R::RSP),
+            X64::Call(String::from("eac_main"), Vec::new(), X64R::RAX, false),
+            X64::AddNum(X64R::RSP, 1024),
+            X64::Pop(X64R::RBP),
+            X64::Ret(None),
+        ];
+        assert_eq!(asm[1].name, "main");
+        assert_eq!(asm[1].body, expected);
+    }
+}
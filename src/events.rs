@@ -0,0 +1,122 @@
+// Recordings of the events the C++ parser sends, so a bug in the front end
+// can be reproduced by building the AST again on a machine without ANTLR. A
+// recording has a line `unit <path>` before the events of every translation
+// unit, then a line for every event: its tag, a tab, and its text, with `\`,
+// tabs, and line breaks escaped.
+use crate::parser::{self, Error, Events};
+use std::path::{Path, PathBuf};
+
+pub const EXTENSION: &str = "events";
+
+// Parse every source and record its events.
+pub fn record(sources: &[(&Path, &str)]) -> String {
+    let mut recording = String::new();
+    for (path, source) in sources {
+        recording += &format!("unit {}\n", path.display());
+        for (tag, text) in parser::events(source) {
+            recording += &format!("{}\t{}\n", tag, escape(&text));
+        }
+    }
+    recording
+}
+
+// The path and the events of every unit in `recording`, in order.
+pub fn units(recording: &str) -> Result<Vec<(PathBuf, Events)>, Error> {
+    let mut units: Vec<(PathBuf, Events)> = Vec::new();
+    for (i, line) in recording.lines().enumerate() {
+        let malformed = || Error::Parsing(format!("Line {} of the recording is malformed.", i + 1));
+        if let Some(path) = line.strip_prefix("unit ") {
+            units.push((PathBuf::from(path), Vec::new()));
+            continue;
+        }
+        let (tag, text) = line.split_once('\t').ok_or_else(malformed)?;
+        let text = unescape(text).ok_or_else(malformed)?;
+        match units.last_mut() {
+            Some((_, events)) => events.push((tag.to_string(), text)),
+            None => return Err(malformed()),
+        }
+    }
+    Ok(units)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'r' => '\r',
+                'n' => '\n',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Lines;
+
+    #[test]
+    fn replay() {
+        let source = "
+            int f(int a);
+            int main() {
+                assert(f(1) <
+                    2);
+                return f(1);
+            }
+        ";
+        let path = Path::new("main.c");
+        let recording = record(&[(path, source)]);
+        let mut units = units(&recording).unwrap();
+        assert_eq!(units.len(), 1);
+        let (path, events) = units.pop().unwrap();
+        assert_eq!(path, PathBuf::from("main.c"));
+        let (mut assertions, mut prototypes) = (Vec::new(), Vec::new());
+        let mut lines = Lines::new();
+        let replayed = parser::replay_unit(
+            events,
+            "main.c",
+            &mut assertions,
+            &mut prototypes,
+            &mut lines,
+        );
+        let (mut expected_assertions, mut expected_prototypes) = (Vec::new(), Vec::new());
+        let expected = parser::parse_unit(
+            source,
+            "main.c",
+            &mut expected_assertions,
+            &mut expected_prototypes,
+        );
+        assert_eq!(replayed, Ok(expected));
+        assert_eq!(assertions, expected_assertions);
+        assert_eq!(prototypes, expected_prototypes);
+        assert_eq!(lines["main"], [3, 4, 6]);
+    }
+
+    #[test]
+    fn malformed() {
+        let error = |line| {
+            Err(Error::Parsing(format!(
+                "Line {} of the recording is malformed.",
+                line
+            )))
+        };
+        assert_eq!(units("ExitJumpStatement\t"), error(1));
+        assert_eq!(units("unit main.c\nExitJumpStatement"), error(2));
+        assert_eq!(units("unit main.c\nExitJumpStatement\t\\x"), error(2));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// Where `build` reads its inputs and writes its artifacts.
+pub trait FileProvider {
+    fn read(&self, path: &Path) -> Result<String>;
+    fn write(&mut self, path: &Path, contents: &str) -> Result<()>;
+    fn is_file(&self, path: &Path) -> bool;
+}
+
+// The real file system.
+pub struct DiskFiles;
+
+impl FileProvider for DiskFiles {
+    fn read(&self, path: &Path) -> Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&mut self, path: &Path, contents: &str) -> Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+}
+
+// Files kept in memory, for embedders without a disk and for tests.
+#[derive(Debug, Default)]
+pub struct MemoryFiles {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> Self {
+        MemoryFiles::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<PathBuf>, contents: impl Into<String>) {
+        self.files.insert(path.into(), contents.into());
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&str> {
+        self.files.get(path.as_ref()).map(String::as_str)
+    }
+}
+
+impl FileProvider for MemoryFiles {
+    fn read(&self, path: &Path) -> Result<String> {
+        match self.files.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => Err(Error::new(ErrorKind::NotFound, path.display().to_string())),
+        }
+    }
+
+    fn write(&mut self, path: &Path, contents: &str) -> Result<()> {
+        self.insert(path, contents);
+        Ok(())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build, Build, Lint, Opt, Toolchain};
+
+    #[test]
+    fn memory() {
+        let mut files = MemoryFiles::new();
+        files.insert(
+            "app/fib.toml",
+            "sources = [\"main.c\", \"fib.c\"]\noutput = \"fib\"",
+        );
+        files.insert("app/main.c", "int main() { return fib(10); }");
+        files.insert("app/fib.c", "int fib(int n) { return n; }");
+        let opt = Opt {
+            input: PathBuf::from("app/fib.toml"),
+            map: true,
+            ..Opt::default()
+        };
+        let result = build(opt, &mut files);
+        let expected = Build::Assemble {
+            asm: PathBuf::from("fib.asm"),
+            output: String::from("fib"),
+            libs: Vec::new(),
+        };
+        assert_eq!(result, expected);
+        assert!(files.get("fib.asm").unwrap().contains("call fib"));
+        let map = files.get("fib.map").unwrap();
+        assert!(map.lines().any(|line| line.ends_with(" fib")));
+        // A denied lint stops the build before it writes anything.
+        let mut files = MemoryFiles::new();
+        files.insert("main.c", "int main() { while (1) {} return 0; }");
+        let opt = Opt {
+            input: PathBuf::from("main.c"),
+            deny: vec![Lint::EmptyLoopBody],
+            ..Opt::default()
+        };
+        assert_eq!(build(opt, &mut files), Build::Denied);
+        assert!(files.get("main.asm").is_none());
+        // So does a recording the front end cannot build an AST from.
+        let mut files = MemoryFiles::new();
+        files.insert("main.events", "unit main.c\nExitJumpStatement");
+        let opt = Opt {
+            input: PathBuf::from("main.events"),
+            ..Opt::default()
+        };
+        assert_eq!(build(opt, &mut files), Build::Failed);
+        assert!(files.get("main.asm").is_none());
+        // And a function defined in two sources of a project.
+        let mut files = MemoryFiles::new();
+        files.insert("app/fib.toml", "sources = [\"main.c\", \"fib.c\"]");
+        files.insert(
+            "app/main.c",
+            "int fib(int n) { return 0; } int main() { return fib(10); }",
+        );
+        files.insert("app/fib.c", "int fib(int n) { return n; }");
+        let opt = Opt {
+            input: PathBuf::from("app/fib.toml"),
+            ..Opt::default()
+        };
+        assert_eq!(build(opt, &mut files), Build::Failed);
+        assert!(files.get("main.asm").is_none());
+        // And a call to an external function for macOS, whose C libraries
+        // expect another calling convention.
+        let mut files = MemoryFiles::new();
+        files.insert("main.c", "int abs(int); int main() { return abs(-1); }");
+        let opt = Opt {
+            input: PathBuf::from("main.c"),
+            toolchain: Toolchain::Macos,
+            ..Opt::default()
+        };
+        assert_eq!(build(opt, &mut files), Build::Failed);
+        assert!(files.get("main.s").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only `drive` sets the limit `--stack-guard` checks")]
+    fn stack_guard_crt() {
+        let mut files = MemoryFiles::new();
+        files.insert("main.c", "int main() { return 0; }");
+        let opt = Opt {
+            input: PathBuf::from("main.c"),
+            stack_guard: true,
+            crt: true,
+            ..Opt::default()
+        };
+        build(opt, &mut files);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only `drive` sets the limit `--stack-guard` checks")]
+    fn stack_guard_entry() {
+        let mut files = MemoryFiles::new();
+        files.insert("main.c", "int main() { return 0; }");
+        let opt = Opt {
+            input: PathBuf::from("main.c"),
+            stack_guard: true,
+            entry: Some(String::from("start")),
+            ..Opt::default()
+        };
+        build(opt, &mut files);
+    }
+}
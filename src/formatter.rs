@@ -0,0 +1,252 @@
+use crate::lexer::{self, TokenKind};
+
+const INDENT: &str = "    ";
+
+// Re-emit `source` with one statement per line, four spaces of indentation per
+// brace, and single spaces around binary operators and after commas and
+// keywords. Comments and preprocessor lines are kept where they were: on a
+// line of their own, or after the code on the same line. A run of blank lines
+// is kept as one blank line.
+pub fn format(source: &str) -> String {
+    let mut printer = Printer::default();
+    let mut trivia = Vec::new();
+    let mut tokens = lexer::lex(source).into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let text = &source[token.span];
+        if token.kind.is_trivia() {
+            trivia.push((token.kind, text));
+            continue;
+        }
+        printer.trivia(&trivia);
+        trivia.clear();
+        let next = tokens
+            .clone()
+            .find(|next| !next.kind.is_trivia())
+            .map(|next| &source[next.span]);
+        printer.token(text, next);
+    }
+    printer.trivia(&trivia);
+    printer.newline();
+    printer.out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    line_start: bool,
+    space: bool,            // Whether the next token on this line is spaced.
+    prev: Option<String>,   // The previous token.
+    parens: usize,          // The nesting depth of parentheses.
+    for_header: Vec<usize>, // The depths of open `for (...)` headers.
+    do_bodies: Vec<bool>,   // Whether each open brace is the body of a `do`.
+    struct_tag: bool,       // Whether the previous token names a struct or a union.
+}
+
+impl Printer {
+    fn token(&mut self, token: &str, next: Option<&str>) {
+        let prev = self.prev.take();
+        let prev = prev.as_deref();
+        match token {
+            "{" => {
+                self.do_bodies.push(prev == Some("do"));
+                self.write(token, true);
+                self.indent += 1;
+                self.newline();
+            }
+            "}" => {
+                self.newline();
+                self.indent = self.indent.saturating_sub(1);
+                self.write(token, false);
+                let do_body = self.do_bodies.pop().unwrap_or(false);
+                match next {
+                    Some("else") => self.space = true,
+                    Some("while") if do_body => self.space = true,
+                    _ => self.newline(),
+                }
+            }
+            ";" => {
+                self.write(token, false);
+                match self.for_header.last() {
+                    Some(depth) if *depth == self.parens => self.space = true,
+                    _ => self.newline(),
+                }
+            }
+            "," => {
+                self.write(token, false);
+                self.space = true;
+            }
+            "(" => {
+                // A call or a function declarator hugs its parentheses.
+                let spaced = !matches!(prev, Some(prev) if is_name(prev) || prev == ")");
+                self.write(token, spaced);
+                self.parens += 1;
+                if prev == Some("for") {
+                    self.for_header.push(self.parens);
+                }
+                self.space = false;
+            }
+            ")" => {
+                if self.for_header.last() == Some(&self.parens) {
+                    self.for_header.pop();
+                }
+                self.parens = self.parens.saturating_sub(1);
+                self.write(token, false);
+                self.space = true;
+            }
+            // A member hugs its struct.
+            "." => {
+                self.write(token, false);
+                self.space = false;
+            }
+            // An index hugs its array and its brackets.
+            "[" => {
+                self.write(token, false);
+                self.space = false;
+            }
+            "]" => {
+                self.write(token, false);
+                self.space = true;
+            }
+            "*" if self.struct_tag => {
+                self.write(token, true);
+                self.space = false;
+            }
+            "!" | "+" | "-" | "*" | "&" if is_unary(prev) => {
+                self.write(token, true);
+                self.space = false;
+            }
+            _ => {
+                self.write(token, true);
+                self.space = true;
+            }
+        }
+        self.struct_tag = matches!(prev, Some("struct") | Some("union"));
+        self.prev = Some(token.to_string());
+    }
+
+    // Keep the comments and preprocessor lines among the trivia between two tokens.
+    fn trivia(&mut self, trivia: &[(TokenKind, &str)]) {
+        let mut newlines = 0;
+        for (i, (kind, text)) in trivia.iter().enumerate() {
+            if *kind == TokenKind::Whitespace {
+                newlines += text.matches('\n').count();
+                continue;
+            }
+            let own_line = newlines > 0 || self.prev.is_none();
+            self.blank_lines(newlines);
+            if own_line {
+                self.newline();
+                self.write(text.trim_end(), false);
+            } else {
+                // Move a trailing comment back to the line it followed.
+                if self.line_start && self.out.ends_with('\n') {
+                    self.out.pop();
+                    self.line_start = false;
+                }
+                self.write(text.trim_end(), true);
+            }
+            let block = text.starts_with("/*");
+            if !block || trivia[i + 1..].iter().any(|(_, text)| text.contains('\n')) {
+                self.newline();
+            } else {
+                self.space = true;
+            }
+            newlines = 0;
+        }
+        self.blank_lines(newlines);
+    }
+
+    // Keep one blank line for a run of them between statements.
+    fn blank_lines(&mut self, newlines: usize) {
+        let after_statement = self.line_start && !self.out.is_empty() && !self.out.ends_with("{\n");
+        if newlines > 1 && after_statement && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn write(&mut self, text: &str, spaced: bool) {
+        if self.line_start {
+            self.out += &INDENT.repeat(self.indent);
+        } else if spaced && self.space && !self.out.is_empty() {
+            self.out.push(' ');
+        }
+        self.out += text;
+        self.line_start = false;
+        self.space = true;
+    }
+
+    fn newline(&mut self) {
+        if !self.line_start && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.line_start = true;
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    let keyword = matches!(token, "if" | "while" | "for" | "switch" | "return");
+    !keyword && token.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+fn is_operator(token: &str) -> bool {
+    token.starts_with(|c| "=!<>+-*/%&|?:^~".contains(c))
+}
+
+// Whether `+`, `-`, `!`, `*`, or `&` after `prev` is a prefix operator. After
+// a type, `*` declares a pointer.
+fn is_unary(prev: Option<&str>) -> bool {
+    match prev {
+        None => true,
+        Some(prev) => {
+            is_operator(prev)
+                || matches!(
+                    prev,
+                    "(" | "[" | "," | ";" | "{" | "}" | "return" | "int" | "long" | "void"
+                )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let source = "
+// Count down.
+int   f(int n,int m){int a=-n,b [2],* p=& a;// The start.
+  if(a<0){a=a*-1;}else if(!a) {return m?1:2;}
+
+
+  for(a=0;a<n;a=a+1)m=f(a-1, m);
+  do { a = a - 1; } while (a);
+  struct point * q;q . x=a;
+  b [ a ]=* p*2;
+  /* Done. */ return a;
+}
+";
+        let expected = "// Count down.
+int f(int n, int m) {
+    int a = -n, b[2], *p = &a; // The start.
+    if (a < 0) {
+        a = a * -1;
+    } else if (!a) {
+        return m ? 1 : 2;
+    }
+
+    for (a = 0; a < n; a = a + 1) m = f(a - 1, m);
+    do {
+        a = a - 1;
+    } while (a);
+    struct point *q;
+    q.x = a;
+    b[a] = *p * 2;
+    /* Done. */ return a;
+}
+";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+    }
+}
@@ -0,0 +1,165 @@
+// Normalize generated assembly before comparing it with a golden file, so the
+// comparison only breaks on changes to the code. Comments go, whitespace
+// collapses to single spaces without blank lines, and local labels, the ones
+// only ever jumped to, are renamed `L0`, `L1`, ... in the order they first
+// appear. Function names stay, so a call to the wrong function still shows.
+// Quoted strings, such as assertion messages, are kept as they are.
+pub fn normalize(asm: &str) -> String {
+    let lines: Vec<_> = asm
+        .lines()
+        .map(collapse)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let locals = local_labels(&lines);
+    let mut names: Vec<String> = Vec::new();
+    let mut normalized = String::new();
+    for line in &lines {
+        for (i, (word, quoted)) in split_words(line).into_iter().enumerate() {
+            if i > 0 {
+                normalized.push(' ');
+            }
+            let (name, rest) = split_name(&word);
+            if quoted || !locals.contains(&name) {
+                normalized += &word;
+                continue;
+            }
+            let index = match names.iter().position(|other| *other == name) {
+                Some(index) => index,
+                None => {
+                    names.push(name);
+                    names.len() - 1
+                }
+            };
+            normalized += &format!("L{}{}", index, rest);
+        }
+        normalized.push('\n');
+    }
+    normalized
+}
+
+// The line without its comment, with every run of whitespace outside quotes
+// turned into one space, and a comma followed by exactly one.
+fn collapse(line: &str) -> String {
+    let mut collapsed = String::new();
+    let mut quoted = false;
+    let mut space = false;
+    for c in line.chars() {
+        if quoted {
+            collapsed.push(c);
+            quoted = c != '"';
+            continue;
+        }
+        match c {
+            ';' | '#' => break,
+            c if c.is_whitespace() => space = true,
+            ',' => {
+                collapsed.push(',');
+                space = true;
+            }
+            c => {
+                if space && !collapsed.is_empty() {
+                    collapsed.push(' ');
+                }
+                space = false;
+                quoted = c == '"';
+                collapsed.push(c);
+            }
+        }
+    }
+    collapsed
+}
+
+// The words of a collapsed line, and whether each is a quoted string.
+fn split_words(line: &str) -> Vec<(String, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Whether the word is a string, and whether it is still open.
+    let (mut string, mut open) = (false, false);
+    for c in line.chars() {
+        match c {
+            ' ' if !open => {
+                words.push((std::mem::take(&mut word), string));
+                string = false;
+            }
+            c => {
+                if c == '"' {
+                    string |= word.is_empty();
+                    open = !open;
+                }
+                word.push(c);
+            }
+        }
+    }
+    words.push((word, string));
+    words
+}
+
+// A word as the name it starts with and the punctuation after it, such as the
+// `:` of a label or the `,` of an operand.
+fn split_name(word: &str) -> (String, &str) {
+    let end = word
+        .find(|c: char| !(c.is_alphanumeric() || "_.$@?".contains(c)))
+        .unwrap_or(word.len());
+    (word[..end].to_string(), &word[end..])
+}
+
+// The labels defined with `:` that are only named again by jumps.
+fn local_labels(lines: &[String]) -> Vec<String> {
+    let mut labels = Vec::new();
+    for line in lines {
+        if let Some(label) = line.strip_suffix(':') {
+            if !label.contains(' ') {
+                labels.push(label.to_string());
+            }
+        }
+    }
+    for line in lines {
+        let words = split_words(line);
+        if words.len() == 1 || words[0].0.starts_with('j') {
+            continue;
+        }
+        for (word, _) in words {
+            let (name, _) = split_name(&word);
+            labels.retain(|label| *label != name);
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosmetic_changes() {
+        let before = "
+main proc ; The entry.
+    mov  RAX,RBX
+    jmp main_Block3
+
+main_Block3:
+    call fib
+    ret
+";
+        let after = "main proc\n\tmov RAX, RBX\n\tjmp main_Tag7\nmain_Tag7:\n\tcall fib\n\tret\n";
+        let expected = "main proc\nmov RAX, RBX\njmp L0\nL0:\ncall fib\nret\n";
+        assert_eq!(normalize(before), expected);
+        assert_eq!(normalize(after), expected);
+    }
+
+    #[test]
+    fn functions_and_strings() {
+        let asm = "
+    .globl main
+main:
+    call fib # Recurse.
+    jne fib
+fib:
+    ret
+message: .ascii \"a;  b # c\"
+";
+        let expected =
+            ".globl main\nmain:\ncall fib\njne fib\nfib:\nret\nmessage: .ascii \"a;  b # c\"\n";
+        assert_eq!(normalize(asm), expected);
+    }
+}
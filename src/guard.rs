@@ -0,0 +1,47 @@
+use crate::x64::{X64Program, X64RegisterAllocator, X64};
+
+// The driver routine that checks the stack pointer against the limit it set
+// at startup. It only clobbers RAX, which holds nothing on function entry.
+pub const STACK_CHECK: &str = "eac_stack_check";
+
+// Call `STACK_CHECK` before anything else in every function, so runaway
+// recursion stops with a message instead of crashing past the end of the stack.
+pub fn insert_stack_checks(asm: &mut X64Program) {
+    for func in asm {
+        let check = X64::Call(
+            String::from(STACK_CHECK),
+            Vec::new(),
+            X64RegisterAllocator::RAX,
+            false,
+        );
+        func.body.insert(0, check);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x64::{X64Function, X64RegisterAllocator as X64R};
+
+    #[test]
+    fn stack_checks() {
+        let mut asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![X64::Push(X64R::RBX), X64::Ret(None)],
+        }];
+        insert_stack_checks(&mut asm);
+        let expected = vec![
+            X64::Call(
+                String::from("eac_stack_check"),
+                Vec::new(),
+                X64R::RAX,
+                false,
+            ),
+            X64::Push(X64R::RBX),
+            X64::Ret(None),
+        ];
+        assert_eq!(asm[0].body, expected);
+    }
+}
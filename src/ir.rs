@@ -1,116 +1,51 @@
-use std::collections::HashSet;
-
-// IR used in the parser.
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
-pub struct SSAVar {
-    pub name: String,
-    pub subscript: Option<usize>,
-}
-
-impl SSAVar {
-    pub fn new(name: &str) -> Self {
-        SSAVar {
-            name: name.to_string(),
-            subscript: None,
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Expression {
-    Identifier(SSAVar),
-    Number(i32),
-    Call {
-        function: Box<Expression>,
-        arguments: Box<Expression>,
-    },
-    Arguments(Vec<Expression>),
-    Prefix {
-        operator: &'static str,
-        expression: Box<Expression>,
-    },
-    Infix {
-        left: Box<Expression>,
-        operator: &'static str,
-        right: Box<Expression>,
-    },
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Statement {
-    Nop,                          // For CFG use only.
-    Phi(SSAVar, HashSet<SSAVar>), // For SSA use only.
-    Declaration(SSAVar),
-    Compound(Vec<Statement>),
-    Expression(Expression),
-    If {
-        condition: Expression,
-        body: Box<Statement>,
-        alternative: Option<Box<Statement>>,
-    },
-    While {
-        condition: Expression,
-        body: Box<Statement>,
-    },
-    Return(Option<Expression>),
-}
+// The IR types live in the `eac-ir` crate; the builder turning function
+// bodies into CFGs stays with the passes using it.
+pub use eac_ir::ir::*;
 
+// A supporting builder used in the data-flow analysis. Statements nest, so the
+// blocks of every `if` and loop still being built are kept on stacks. Blocks
+// are only ever appended, so the current block is always the last one. Every
+// statement pushed is at the source line set last.
 #[derive(Debug, PartialEq)]
-pub struct Function {
-    pub void: bool,
-    pub name: String,
-    pub parameters: Vec<SSAVar>,
-    pub body: Statement,
-}
-
-pub type Program = Vec<Function>;
-
-// IR used in the data-flow analysis.
-#[derive(Debug, PartialEq, Default)]
-pub struct Block {
-    pub statements: Vec<Statement>,
-    pub predecessors: HashSet<usize>,
-    pub successors: HashSet<usize>,
+pub struct CFGBuilder {
+    blocks: Vec<Block>,
+    lines: Vec<Vec<usize>>,
+    line: usize,
+    current: usize,
+    ifs: Vec<IfBlocks>,
+    loops: Vec<LoopBlocks>,
 }
 
-pub type CFG = Vec<Block>;
-
+// The blocks of an `if` statement under construction.
 #[derive(Debug, PartialEq)]
-pub struct SSAFunction {
-    pub void: bool,
-    pub name: String,
-    pub parameters: Vec<SSAVar>,
-    pub body: CFG,
+struct IfBlocks {
+    cond: usize,
+    exit_body: usize,
+    alt: bool,
 }
 
-pub type SSAProgram = Vec<SSAFunction>;
-
-// A supporting builder used in the data-flow analysis.
-#[derive(Debug, PartialEq)]
-pub struct CFGBuilder {
-    blocks: Vec<Block>,
-    current: usize,
-    while_cond: usize,
-    if_cond: usize,
-    if_enter_body: usize,
-    if_exit_body: usize,
-    if_alt: bool,
-    if_enter_alt: usize,
-    if_exit_alt: usize,
+// The blocks of a loop under construction: where it starts again (the
+// condition of a `while`, the body of a `do`), the block ending with the
+// condition of a `while`, which may take more blocks than the first, and the
+// blocks ending with a `break` or a `continue` that still wait for their
+// target.
+#[derive(Debug, PartialEq, Default)]
+struct LoopBlocks {
+    start: usize,
+    cond: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
 }
 
 impl CFGBuilder {
     pub fn new() -> Self {
         CFGBuilder {
             blocks: vec![Block::default()],
+            lines: vec![Vec::new()],
+            line: 0,
             current: 0,
-            while_cond: 0,
-            if_cond: 0,
-            if_enter_body: 0,
-            if_exit_body: 0,
-            if_alt: false,
-            if_enter_alt: 0,
-            if_exit_alt: 0,
+            ifs: Vec::new(),
+            loops: Vec::new(),
         }
     }
 
@@ -118,30 +53,79 @@ impl CFGBuilder {
         self.blocks
     }
 
+    // The line of every statement of every block, taken out of the builder.
+    pub fn take_lines(&mut self) -> Vec<Vec<usize>> {
+        std::mem::take(&mut self.lines)
+    }
+
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+
     pub fn push(&mut self, stmt: Statement) {
         self.blocks[self.current].statements.push(stmt);
+        self.lines[self.current].push(self.line);
+    }
+
+    // Whether control can reach the end of the current block. Statements
+    // after a `return`, `break`, or `continue` never run.
+    pub fn reachable(&self) -> bool {
+        self.is_reachable(self.current) && !self.is_terminated(self.current)
     }
 
     pub fn enter_new_block(&mut self) {
         if !self.blocks[self.current].statements.is_empty() {
-            self.blocks[self.current]
-                .successors
-                .insert(self.current + 1);
-            let mut block = Block::default();
-            block.predecessors.insert(self.current);
-            self.blocks.push(block);
-            self.current += 1;
+            let pred = self.current;
+            self.start_block();
+            self.fall_through(pred, self.current, Edge::Next);
+        }
+    }
+
+    // Start the block a loop returns to. The entry block has no predecessors,
+    // so a loop never starts in it.
+    fn enter_loop(&mut self) {
+        if self.current == 0 && self.blocks[0].statements.is_empty() {
+            self.start_block();
+            self.connect(0, self.current, Edge::Next);
+        } else {
+            self.enter_new_block();
+        }
+    }
+
+    // Append a block that no edge leads to yet.
+    fn start_block(&mut self) {
+        self.blocks.push(Block::default());
+        self.lines.push(Vec::new());
+        self.current = self.blocks.len() - 1;
+    }
+
+    // Connect `pred` to `succ`, unless `pred` ends with a jump of its own.
+    fn fall_through(&mut self, pred: usize, succ: usize, edge: Edge) {
+        if !self.is_terminated(pred) {
+            self.connect(pred, succ, edge);
         }
     }
 
-    fn connect(&mut self, pred: usize, succ: usize) {
-        self.blocks[pred].successors.insert(succ);
-        self.blocks[succ].predecessors.insert(pred);
+    // Only the entry block is reachable before anything leads to it: loop
+    // bodies and conditions are entered before the edges back to them exist.
+    fn is_reachable(&self, block: usize) -> bool {
+        block == 0 || !self.blocks[block].predecessors.is_empty()
     }
 
-    fn disconnect(&mut self, pred: usize, succ: usize) {
-        self.blocks[pred].successors.remove(&succ);
-        self.blocks[succ].predecessors.remove(&pred);
+    fn is_terminated(&self, block: usize) -> bool {
+        matches!(
+            self.blocks[block].statements.last(),
+            Some(Statement::Return(_)) | Some(Statement::Break) | Some(Statement::Continue)
+        )
+    }
+
+    // Unreachable blocks lead nowhere, so successors only reflect real control flow.
+    fn connect(&mut self, pred: usize, succ: usize, edge: Edge) {
+        if self.is_reachable(pred) {
+            self.blocks[pred].successors.insert(succ);
+            self.blocks[pred].edges.insert(succ, edge);
+            self.blocks[succ].predecessors.insert(pred);
+        }
     }
 
     pub fn enter_if(&mut self, condition: Expression, alt: bool) {
@@ -156,59 +140,133 @@ impl CFGBuilder {
             alternative,
         };
         self.push(stmt);
-        self.if_cond = self.current;
-        self.if_alt = alt;
-        self.enter_new_block();
-    }
-
-    pub fn enter_if_body(&mut self) {
-        self.if_enter_body = self.current;
+        let cond = self.current;
+        self.ifs.push(IfBlocks {
+            cond,
+            exit_body: cond,
+            alt,
+        });
+        self.start_block();
+        self.connect(cond, self.current, Edge::Then);
     }
 
     pub fn exit_if_body(&mut self) {
-        self.enter_new_block();
-        self.if_exit_body = self.current - 1;
-        self.disconnect(self.if_exit_body, self.current);
+        self.ifs.last_mut().unwrap().exit_body = self.current;
     }
 
     pub fn enter_if_alt(&mut self) {
-        self.if_enter_alt = self.current;
-    }
-
-    pub fn exit_if_alt(&mut self) {
-        self.enter_new_block();
-        self.if_exit_alt = self.current - 1;
+        let cond = self.ifs.last().unwrap().cond;
+        self.start_block();
+        self.connect(cond, self.current, Edge::Else);
     }
 
     pub fn exit_if(&mut self) {
-        self.enter_new_block();
-        self.connect(self.if_exit_body, self.current);
-        if self.if_alt {
-            self.connect(self.if_cond, self.if_enter_alt);
+        let blocks = self.ifs.pop().unwrap();
+        let exit = self.current;
+        self.start_block();
+        if blocks.alt {
+            self.fall_through(blocks.exit_body, self.current, Edge::Skip);
+            self.fall_through(exit, self.current, Edge::Next);
         } else {
-            self.connect(self.if_cond, self.current);
+            self.fall_through(blocks.exit_body, self.current, Edge::Next);
+            self.connect(blocks.cond, self.current, Edge::Else);
         }
-        self.enter_new_block();
     }
 
-    pub fn enter_while(&mut self, condition: Expression) {
-        self.enter_new_block();
+    // The condition of a `while` is computed from here on.
+    pub fn enter_while(&mut self) {
+        self.enter_loop();
+        self.loops.push(LoopBlocks {
+            start: self.current,
+            ..LoopBlocks::default()
+        });
+    }
+
+    pub fn enter_while_body(&mut self, condition: Expression) {
         let stmt = Statement::While {
             condition,
             body: Box::new(Statement::Nop),
         };
         self.push(stmt);
-        self.while_cond = self.current;
-        self.enter_new_block();
+        let cond = self.current;
+        self.loops.last_mut().unwrap().cond = cond;
+        self.start_block();
+        self.connect(cond, self.current, Edge::Body);
     }
 
-    pub fn exit_while(&mut self, body_return: bool) {
-        self.enter_new_block();
-        let while_exit_body = self.current - 1;
-        if !body_return {
-            self.connect(while_exit_body, self.while_cond);
+    pub fn exit_while(&mut self) {
+        let blocks = self.loops.pop().unwrap();
+        self.fall_through(self.current, blocks.start, Edge::Back);
+        self.connect_continues(blocks.start, &blocks);
+        self.start_block();
+        self.connect(blocks.cond, self.current, Edge::Exit);
+        self.connect_breaks(&blocks);
+    }
+
+    pub fn enter_do(&mut self) {
+        self.enter_loop();
+        self.loops.push(LoopBlocks {
+            start: self.current,
+            ..LoopBlocks::default()
+        });
+    }
+
+    // The condition ends the loop, in blocks of its own after the body, which
+    // are computed from here on.
+    pub fn enter_do_condition(&mut self) {
+        if !self.blocks[self.current].statements.is_empty() {
+            let exit_body = self.current;
+            self.start_block();
+            self.fall_through(exit_body, self.current, Edge::Next);
+        }
+        let blocks = self.loops.pop().unwrap();
+        self.connect_continues(self.current, &blocks);
+        self.loops.push(blocks);
+    }
+
+    pub fn exit_do(&mut self, condition: Expression) {
+        let blocks = self.loops.pop().unwrap();
+        let cond = self.current;
+        // If nothing reaches the condition, the body never repeats and the
+        // block after the loop takes its place.
+        if self.is_reachable(cond) {
+            let stmt = Statement::DoWhile {
+                body: Box::new(Statement::Nop),
+                condition,
+            };
+            self.push(stmt);
+            self.connect(cond, blocks.start, Edge::Back);
+            self.start_block();
+            self.connect(cond, self.current, Edge::Exit);
+        }
+        self.connect_breaks(&blocks);
+    }
+
+    pub fn push_break(&mut self) {
+        self.push(Statement::Break);
+        let current = self.current;
+        let blocks = self.loops.last_mut().expect("`break` is not in a loop.");
+        blocks.breaks.push(current);
+    }
+
+    pub fn push_continue(&mut self) {
+        self.push(Statement::Continue);
+        let current = self.current;
+        let blocks = self.loops.last_mut().expect("`continue` is not in a loop.");
+        blocks.continues.push(current);
+    }
+
+    // Once a loop is built, its `continue`s jump to `cond`.
+    fn connect_continues(&mut self, cond: usize, blocks: &LoopBlocks) {
+        for &pred in &blocks.continues {
+            self.connect(pred, cond, Edge::Continue);
+        }
+    }
+
+    // Its `break`s jump to the current block, which follows the loop.
+    fn connect_breaks(&mut self, blocks: &LoopBlocks) {
+        for &pred in &blocks.breaks {
+            self.connect(pred, self.current, Edge::Break);
         }
-        self.disconnect(while_exit_body, self.current);
-        self.connect(self.while_cond, self.current);
     }
 }
@@ -0,0 +1,302 @@
+use crate::asm::register;
+use crate::ir::Int;
+use crate::lir::{Lir, Var};
+use crate::x64::{Register, X64};
+use std::collections::HashMap;
+
+// Instruction selection via tree-pattern matching (Chapter 11.4). The integer
+// `+`, `-`, and `*` of the LIR, along with their constants, form trees
+// wherever a temporary is read once by the operation above it. Every rule of
+// `RULES` whose pattern matches a node is a way to cover it, and the cover of
+// least total cost is chosen. Every node is one LIR instruction, which is
+// replaced in place by what covers it, so a node folded into the rule above
+// it emits nothing. Whatever the trees do not model, such as calls and loads,
+// is a leaf computed on its own.
+#[derive(Debug, Clone)]
+enum Tree {
+    Leaf(Register),
+    Const(Int, Register),
+    Op(&'static str, Box<Tree>, Box<Tree>, Register),
+}
+
+enum Pattern {
+    Any,   // Any tree, covered on its own into a register.
+    Scale, // A constant 2, 4, or 8, which addressing can multiply by.
+    Op(&'static str, &'static Pattern, &'static Pattern),
+}
+
+// How to emit a rule from the registers of its `Any` operands, in order.
+enum Emit {
+    // `mov reg, left; op reg, right`.
+    Binary(fn(Register, Register) -> X64),
+    // `lea reg, [base + index * scale]`, with the operands at the positions.
+    Lea { base: usize, index: usize },
+}
+
+struct Rule {
+    pattern: Pattern,
+    cost: usize,
+    emit: Emit,
+}
+
+use Pattern::{Any, Scale};
+
+const SCALED: Pattern = Pattern::Op("*", &Any, &Scale);
+const SCALED_LEFT: Pattern = Pattern::Op("*", &Scale, &Any);
+
+const RULES: &[Rule] = &[
+    Rule {
+        pattern: Pattern::Op("+", &SCALED, &Any),
+        cost: 1,
+        emit: Emit::Lea { base: 1, index: 0 },
+    },
+    Rule {
+        pattern: Pattern::Op("+", &SCALED_LEFT, &Any),
+        cost: 1,
+        emit: Emit::Lea { base: 1, index: 0 },
+    },
+    Rule {
+        pattern: Pattern::Op("+", &Any, &SCALED),
+        cost: 1,
+        emit: Emit::Lea { base: 0, index: 1 },
+    },
+    Rule {
+        pattern: Pattern::Op("+", &Any, &SCALED_LEFT),
+        cost: 1,
+        emit: Emit::Lea { base: 0, index: 1 },
+    },
+    Rule {
+        pattern: Pattern::Op("+", &Any, &Any),
+        cost: 2,
+        emit: Emit::Binary(X64::Add),
+    },
+    Rule {
+        pattern: Pattern::Op("-", &Any, &Any),
+        cost: 2,
+        emit: Emit::Binary(X64::Sub),
+    },
+    Rule {
+        pattern: Pattern::Op("*", &Any, &Any),
+        cost: 4,
+        emit: Emit::Binary(X64::Imul),
+    },
+];
+
+// The instructions covering every LIR instruction of `body` in a tree, by
+// position, with nothing for those folded into a rule above them. A
+// temporary is folded into the operation reading it only if nothing in
+// between writes what it reads, since a rule may move the read up to the root.
+pub fn cover(body: &[Lir]) -> HashMap<usize, Vec<X64>> {
+    let mut defs: HashMap<Var, Vec<usize>> = HashMap::new();
+    let mut uses: HashMap<Var, Vec<usize>> = HashMap::new();
+    for (position, lir) in body.iter().enumerate() {
+        if let Some(var) = lir.writes() {
+            defs.entry(var).or_default().push(position);
+        }
+        for var in lir.reads() {
+            uses.entry(var).or_default().push(position);
+        }
+    }
+    // The register a node computes, which nothing else writes, and what it reads.
+    let node = |position: usize| {
+        let (var, reads) = match &body[position] {
+            Lir::Const(var @ Var::Int(_), _) => (*var, Vec::new()),
+            Lir::Binary(var @ Var::Int(_), left, "+" | "-" | "*", right) => {
+                (*var, vec![*left, *right])
+            }
+            _ => return None,
+        };
+        (defs[&var].len() == 1).then_some((var, reads))
+    };
+    let folded = |var: &Var| {
+        let def = match defs.get(var).map(Vec::as_slice) {
+            Some(&[def]) => def,
+            _ => return None,
+        };
+        let reads = node(def)?.1;
+        let parent = match uses.get(var).map(Vec::as_slice) {
+            Some(&[parent]) if parent > def => parent,
+            _ => return None,
+        };
+        node(parent)?;
+        let clobbered = body[def + 1..parent]
+            .iter()
+            .any(|lir| lir.writes().is_some_and(|var| reads.contains(&var)));
+        (!clobbered).then_some(def)
+    };
+    fn build(position: usize, body: &[Lir], folded: &dyn Fn(&Var) -> Option<usize>) -> Tree {
+        let operand = |var: &Var| match folded(var) {
+            Some(def) => build(def, body, folded),
+            None => Tree::Leaf(register(*var)),
+        };
+        match &body[position] {
+            Lir::Const(var, num) => Tree::Const(*num, register(*var)),
+            Lir::Binary(var, left, op, right) => Tree::Op(
+                op,
+                Box::new(operand(left)),
+                Box::new(operand(right)),
+                register(*var),
+            ),
+            _ => unreachable!(),
+        }
+    }
+    let mut covers = HashMap::new();
+    for position in 0..body.len() {
+        if node(position).is_some_and(|(var, _)| folded(&var).is_none()) {
+            covers.extend(select(&build(position, body, &folded)));
+        }
+    }
+    (0..body.len())
+        .filter_map(|position| {
+            let (var, _) = node(position)?;
+            Some((position, covers.remove(&register(var)).unwrap_or_default()))
+        })
+        .collect()
+}
+
+// The cheapest cover of `tree`, by the register of every node it emits.
+fn select(tree: &Tree) -> HashMap<Register, Vec<X64>> {
+    let mut costs = HashMap::new();
+    let mut covers = HashMap::new();
+    emit(tree, &mut costs, &mut covers);
+    covers
+}
+
+// The least cost of covering `tree`, and the rule of the cover at its root.
+type Costs = HashMap<*const Tree, (usize, Option<usize>)>;
+
+fn cost(tree: &Tree, costs: &mut Costs) -> (usize, Option<usize>) {
+    if let Some(cost) = costs.get(&(tree as *const Tree)) {
+        return *cost;
+    }
+    let cost = match tree {
+        Tree::Leaf(_) => (0, None),
+        Tree::Const(..) => (1, None),
+        Tree::Op(..) => RULES
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| {
+                let mut operands = Vec::new();
+                if !matches(&rule.pattern, tree, &mut operands, &mut None) {
+                    return None;
+                }
+                let operands: usize = operands.iter().map(|tree| cost(tree, costs).0).sum();
+                Some((rule.cost + operands, Some(index)))
+            })
+            .min_by_key(|(cost, _)| *cost)
+            .unwrap_or_else(|| panic!("No rule covers `{:?}`.", tree)),
+    };
+    costs.insert(tree, cost);
+    cost
+}
+
+// Whether `pattern` matches `tree`, pushing the trees its `Any` operands
+// match and setting the constant its `Scale` matches.
+fn matches<'a>(
+    pattern: &Pattern,
+    tree: &'a Tree,
+    operands: &mut Vec<&'a Tree>,
+    scale: &mut Option<u8>,
+) -> bool {
+    match (pattern, tree) {
+        (Pattern::Any, tree) => {
+            operands.push(tree);
+            true
+        }
+        (Pattern::Scale, Tree::Const(num @ (2 | 4 | 8), _)) => {
+            *scale = Some(*num as u8);
+            true
+        }
+        (Pattern::Op(op, left, right), Tree::Op(operator, left_tree, right_tree, _)) => {
+            op == operator
+                && matches(left, left_tree, operands, scale)
+                && matches(right, right_tree, operands, scale)
+        }
+        _ => false,
+    }
+}
+
+fn emit(tree: &Tree, costs: &mut Costs, covers: &mut HashMap<Register, Vec<X64>>) -> Register {
+    let reg = match tree {
+        Tree::Leaf(reg) => return *reg,
+        Tree::Const(num, reg) => {
+            covers.insert(*reg, vec![X64::MovNum(*reg, *num)]);
+            return *reg;
+        }
+        Tree::Op(.., reg) => *reg,
+    };
+    let rule = &RULES[cost(tree, costs).1.unwrap()];
+    let (mut operands, mut scale) = (Vec::new(), None);
+    matches(&rule.pattern, tree, &mut operands, &mut scale);
+    let regs: Vec<_> = operands
+        .into_iter()
+        .map(|operand| emit(operand, costs, covers))
+        .collect();
+    let asms = match rule.emit {
+        Emit::Binary(op) => vec![X64::MovReg(reg, regs[0]), op(reg, regs[1])],
+        Emit::Lea { base, index } => vec![X64::Lea(reg, regs[base], regs[index], scale.unwrap())],
+    };
+    covers.insert(reg, asms);
+    reg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Emit the covers of `body` in place of its instructions.
+    fn emit(body: &[Lir]) -> Vec<X64> {
+        let mut covers = cover(body);
+        (0..body.len())
+            .flat_map(|position| covers.remove(&position).unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn lea() {
+        // y + x * 4 - z * 3, with the variables in 0 to 2.
+        let v = Var::Int;
+        let body = vec![
+            Lir::Const(v(3), 4),
+            Lir::Binary(v(4), v(1), "*", v(3)),
+            Lir::Binary(v(5), v(0), "+", v(4)),
+            Lir::Const(v(6), 3),
+            Lir::Binary(v(7), v(2), "*", v(6)),
+            Lir::Binary(v(8), v(5), "-", v(7)),
+        ];
+        let r = Register::Virtual;
+        let expected = vec![
+            X64::Lea(r(5), r(0), r(1), 4),
+            X64::MovNum(r(6), 3),
+            X64::MovReg(r(7), r(2)),
+            X64::Imul(r(7), r(6)),
+            X64::MovReg(r(8), r(5)),
+            X64::Sub(r(8), r(7)),
+        ];
+        assert_eq!(emit(&body), expected);
+    }
+
+    #[test]
+    fn clobbered_operand() {
+        // x * 4 + (x = 5): folding the product into a `lea` would read the
+        // new `x`.
+        let v = Var::Int;
+        let body = vec![
+            Lir::Const(v(1), 4),
+            Lir::Binary(v(2), v(0), "*", v(1)),
+            Lir::Const(v(3), 5),
+            Lir::Copy(v(0), v(3)),
+            Lir::Binary(v(4), v(2), "+", v(0)),
+        ];
+        let r = Register::Virtual;
+        let expected = vec![
+            X64::MovNum(r(1), 4),
+            X64::MovReg(r(2), r(0)),
+            X64::Imul(r(2), r(1)),
+            X64::MovNum(r(3), 5),
+            X64::MovReg(r(4), r(2)),
+            X64::Add(r(4), r(0)),
+        ];
+        assert_eq!(emit(&body), expected);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+// The type of a struct member: an `int` or a pointer, which both fill a
+// 64-bit word, or a struct or a union defined earlier, which is laid out in
+// place.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MemberType {
+    Scalar,
+    Struct(String),
+}
+
+// A struct or union definition, with its members in declaration order.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Struct {
+    pub name: String,
+    pub union: bool,
+    pub members: Vec<(String, MemberType)>,
+}
+
+// Where the members of a struct lie, in bytes from its start. Every member is
+// a multiple of 8 bytes long, so members follow each other without padding.
+// The members of a union all start at its start and overlap.
+#[derive(Debug, PartialEq)]
+struct Layout {
+    size: usize,
+    members: HashMap<String, (usize, MemberType)>,
+}
+
+// The layouts of the structs and unions defined so far in a translation
+// unit, which share one namespace of tags.
+#[derive(Debug, Default)]
+pub struct Layouts {
+    structs: HashMap<String, Layout>,
+}
+
+impl Layouts {
+    pub const WORD_SIZE: usize = 8;
+
+    pub fn new() -> Self {
+        Layouts::default()
+    }
+
+    pub fn define(&mut self, def: Struct) {
+        if self.structs.contains_key(&def.name) {
+            panic!("Struct `{}` is defined twice.", def.name);
+        }
+        let mut size = 0;
+        let mut members = HashMap::new();
+        for (name, ty) in def.members {
+            let len = match &ty {
+                MemberType::Scalar => Self::WORD_SIZE,
+                MemberType::Struct(inner) => self.size(inner),
+            };
+            let offset = if def.union { 0 } else { size };
+            if members.insert(name.clone(), (offset, ty)).is_some() {
+                panic!("Struct `{}` has two members named `{}`.", def.name, name);
+            }
+            size = if def.union { size.max(len) } else { size + len };
+        }
+        self.structs.insert(def.name, Layout { size, members });
+    }
+
+    pub fn size(&self, name: &str) -> usize {
+        self.layout(name).size
+    }
+
+    // The offset and the type of `member` in the struct `name`.
+    pub fn member(&self, name: &str, member: &str) -> (usize, &MemberType) {
+        match self.layout(name).members.get(member) {
+            Some((offset, ty)) => (*offset, ty),
+            None => panic!("Struct `{}` has no member `{}`.", name, member),
+        }
+    }
+
+    fn layout(&self, name: &str) -> &Layout {
+        self.structs
+            .get(name)
+            .unwrap_or_else(|| panic!("Struct `{}` is not defined.", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        let mut layouts = Layouts::new();
+        layouts.define(Struct {
+            name: String::from("point"),
+            union: false,
+            members: vec![
+                (String::from("x"), MemberType::Scalar),
+                (String::from("y"), MemberType::Scalar),
+            ],
+        });
+        layouts.define(Struct {
+            name: String::from("line"),
+            union: false,
+            members: vec![
+                (
+                    String::from("from"),
+                    MemberType::Struct(String::from("point")),
+                ),
+                (
+                    String::from("to"),
+                    MemberType::Struct(String::from("point")),
+                ),
+                (String::from("next"), MemberType::Scalar),
+            ],
+        });
+        assert_eq!(layouts.size("point"), 16);
+        assert_eq!(layouts.size("line"), 40);
+        assert_eq!(layouts.member("point", "y"), (8, &MemberType::Scalar));
+        let point = MemberType::Struct(String::from("point"));
+        assert_eq!(layouts.member("line", "to"), (16, &point));
+        assert_eq!(layouts.member("line", "next"), (32, &MemberType::Scalar));
+        layouts.define(Struct {
+            name: String::from("shape"),
+            union: true,
+            members: vec![
+                (String::from("radius"), MemberType::Scalar),
+                (
+                    String::from("line"),
+                    MemberType::Struct(String::from("line")),
+                ),
+            ],
+        });
+        assert_eq!(layouts.size("shape"), 40);
+        let line = MemberType::Struct(String::from("line"));
+        assert_eq!(layouts.member("shape", "line"), (0, &line));
+        assert_eq!(layouts.member("shape", "radius"), (0, &MemberType::Scalar));
+    }
+}
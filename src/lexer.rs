@@ -0,0 +1,138 @@
+use crate::parser;
+use std::iter;
+use std::ops::Range;
+
+// What a token is, for tools such as highlighters and linters. The last three
+// kinds are trivia: text the parser skips, which the lexer output still covers
+// so that the tokens put together give back the source.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Keyword,
+    Identifier,
+    Constant, // An integer or character constant.
+    StringLiteral,
+    Punctuator,
+    Whitespace,
+    Comment,
+    Directive, // A preprocessor line, or anything else the lexer skipped.
+}
+
+impl TokenKind {
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::Whitespace | TokenKind::Comment | TokenKind::Directive
+        )
+    }
+}
+
+// A token and the byte range of its text in the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+// Split `source` into tokens, trivia included, so that the spans are in order
+// and leave no gaps.
+pub fn lex(source: &str) -> Vec<Token> {
+    // The lexer counts characters, not bytes.
+    let offsets: Vec<usize> = source
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(iter::once(source.len()))
+        .collect();
+    let mut tokens = Vec::new();
+    let mut end = 0;
+    for (span, name) in parser::tokens(source) {
+        let span = offsets[span.start]..offsets[span.end];
+        lex_trivia(source, end..span.start, &mut tokens);
+        let kind = kind(&name, &source[span.clone()]);
+        end = span.end;
+        tokens.push(Token { kind, span });
+    }
+    lex_trivia(source, end..source.len(), &mut tokens);
+    tokens
+}
+
+fn kind(name: &str, text: &str) -> TokenKind {
+    match name {
+        "Identifier" => TokenKind::Identifier,
+        "Constant" | "DigitSequence" => TokenKind::Constant,
+        "StringLiteral" => TokenKind::StringLiteral,
+        _ if text.starts_with(|c: char| c.is_alphabetic() || c == '_') => TokenKind::Keyword,
+        _ => TokenKind::Punctuator,
+    }
+}
+
+fn lex_trivia(source: &str, gap: Range<usize>, tokens: &mut Vec<Token>) {
+    let mut start = gap.start;
+    while start < gap.end {
+        let rest = &source[start..gap.end];
+        let (kind, len) = if rest.starts_with(char::is_whitespace) {
+            let len = rest.find(|c: char| !c.is_whitespace());
+            (TokenKind::Whitespace, len.unwrap_or(rest.len()))
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let len = comment.find("*/").map(|len| len + 4);
+            (TokenKind::Comment, len.unwrap_or(rest.len()))
+        } else {
+            let kind = if rest.starts_with("//") {
+                TokenKind::Comment
+            } else {
+                TokenKind::Directive
+            };
+            (kind, rest.find(['\r', '\n']).unwrap_or(rest.len()))
+        };
+        tokens.push(Token {
+            kind,
+            span: start..start + len,
+        });
+        start += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trivia() {
+        let source = "#include <stdio.h>\nint main() { /* é */ return 'a' + x1; } // end";
+        let tokens = lex(source);
+        let text: String = tokens.iter().map(|t| &source[t.span.clone()]).collect();
+        assert_eq!(text, source);
+        use TokenKind::*;
+        let expected = vec![
+            (Directive, "#include <stdio.h>"),
+            (Whitespace, "\n"),
+            (Keyword, "int"),
+            (Whitespace, " "),
+            (Identifier, "main"),
+            (Punctuator, "("),
+            (Punctuator, ")"),
+            (Whitespace, " "),
+            (Punctuator, "{"),
+            (Whitespace, " "),
+            (Comment, "/* é */"),
+            (Whitespace, " "),
+            (Keyword, "return"),
+            (Whitespace, " "),
+            (Constant, "'a'"),
+            (Whitespace, " "),
+            (Punctuator, "+"),
+            (Whitespace, " "),
+            (Identifier, "x1"),
+            (Punctuator, ";"),
+            (Whitespace, " "),
+            (Punctuator, "}"),
+            (Whitespace, " "),
+            (Comment, "// end"),
+        ];
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.kind, &source[t.span.clone()]))
+            .collect();
+        assert_eq!(tokens, expected);
+        assert!(Comment.is_trivia() && !Keyword.is_trivia());
+    }
+}
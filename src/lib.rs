@@ -1,16 +1,75 @@
 mod asm;
+mod available;
+pub mod bench;
+mod cancel;
+mod dataflow;
+mod diff;
+mod emulator;
+mod entry;
+mod events;
+mod files;
+mod formatter;
+pub mod golden;
+mod guard;
 mod ir;
+mod isel;
+mod layout;
+mod lexer;
+mod lines;
+mod lint;
+mod lir;
+mod loops;
+mod lvn;
+mod manifest;
 mod parser;
+mod peephole;
+mod pre;
+mod preprocessor;
+mod pressure;
+mod project;
+mod range;
+pub mod reduce;
 mod reg_allocator;
+mod rename;
+mod resolver;
+mod response;
+mod scheduler;
 mod serializer;
+mod session;
 mod ssa;
+mod stats;
+pub mod stress;
+mod traversal;
+mod uninit;
+mod verifier;
 mod x64;
 
 use asm::X64Builder;
-use std::path::PathBuf;
+pub use cancel::{CancellationToken, Cancelled};
+// The IR types, for passes and tools outside the compiler.
+pub use eac_ir;
+pub use entry::Subsystem;
+pub use files::{DiskFiles, FileProvider, MemoryFiles};
+use ir::{Optimization, Pragmas, Program, Prototype, SSAProgram};
+pub use lexer::{Token, TokenKind};
+use lines::{LineMap, Lines, Texts};
+pub use lint::Lint;
+use lir::{LirBuilder, LirProgram};
+pub use manifest::{manifest, Recorder};
+pub use parser::{Error, Reference};
+pub use range::Overflow;
+pub use rename::{rename, rename_ssa, Names};
+pub use response::{command_line_len, response_file, Quoting, COMMAND_LINE_LIMIT};
+pub use serializer::Toolchain;
+pub use session::{CompilationSession, FunctionArtifacts};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
+pub use x64::Spill;
+use x64::X64Program;
 
-#[derive(StructOpt)]
+#[derive(StructOpt, Default)]
 #[structopt(name = "parser")]
 pub struct Opt {
     #[structopt(parse(from_os_str))]
@@ -25,38 +84,852 @@ pub struct Opt {
     #[structopt(long)]
     pub cfg: bool,
 
+    #[structopt(long)]
+    pub lir: bool,
+
     #[structopt(long)]
     pub vasm: bool,
 
     #[structopt(long)]
     pub asm: bool,
+
+    /// Emit Compiler Explorer-style assembly plus a JSON source map instead of linking.
+    #[structopt(long)]
+    pub explorer: bool,
+
+    /// Validate the IR after every pass and report the pass that broke it.
+    #[structopt(long)]
+    pub verify_all: bool,
+
+    /// Print a diff of every function changed by SSA destruction, register allocation, or cleanup.
+    #[structopt(long)]
+    pub print_changed: bool,
+
+    /// Spill heuristic of the register allocator: `cost` or `next-use`.
+    #[structopt(long, default_value = "cost")]
+    pub spill: Spill,
+
+    /// Semantics of signed overflow: `wrap` around, or `undefined` so range folding may assume it never happens.
+    #[structopt(long, default_value = "wrap")]
+    pub overflow: Overflow,
+
+    /// Print what every pass changed, the loops of every function, and the size and instruction mix of every function.
+    #[structopt(long)]
+    pub stats: bool,
+
+    /// Write a map of the emitted functions with their estimated offsets and sizes, stack frames, and spill stores.
+    #[structopt(long)]
+    pub map: bool,
+
+    /// Run `main` allocated with each spill heuristic of the register allocator and report if they return different results.
+    #[structopt(long)]
+    pub compare_regalloc: bool,
+
+    /// Argument to pass to `main` with `--compare-regalloc`. May be repeated.
+    #[structopt(long = "main-arg", number_of_values = 1, allow_hyphen_values = true)]
+    pub main_args: Vec<i64>,
+
+    /// Assembler and linker to build with: `masm`, `mingw`, or `macos`.
+    #[structopt(long, default_value = "masm")]
+    pub toolchain: Toolchain,
+
+    /// Assembler and linker to call instead of the toolchain's `ml64`, `gcc`, or `clang`.
+    #[structopt(long, env = "EAC_ASM_PATH", parse(from_os_str))]
+    pub asm_path: Option<PathBuf>,
+
+    /// Extra argument to append to the assembler and linker command line. May be repeated.
+    #[structopt(long = "link-arg", number_of_values = 1, allow_hyphen_values = true)]
+    pub link_args: Vec<String>,
+
+    /// Library to link against, as `user32`, `user32.lib`, or `-luser32`. May be repeated.
+    #[structopt(long = "link-lib", number_of_values = 1, allow_hyphen_values = true)]
+    pub link_libs: Vec<String>,
+
+    /// Write a JSON manifest of the produced artifacts, with their hashes and the arguments used.
+    #[structopt(long, parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// List every construct outside the supported subset of C instead of compiling.
+    #[structopt(long)]
+    pub check_subset: bool,
+
+    /// Rewrite the sources with normalized indentation and spacing instead of compiling.
+    #[structopt(long)]
+    pub fmt: bool,
+
+    /// Check the stack in every function prologue and exit with an error once recursion goes too deep.
+    #[structopt(long)]
+    pub stack_guard: bool,
+
+    /// Lint to turn off: `dead-assignment`, `constant-condition`, `empty-loop-body`, or `assignment-in-condition`. May be repeated.
+    #[structopt(long, number_of_values = 1)]
+    pub allow: Vec<Lint>,
+
+    /// Lint to report as an error that stops the build. May be repeated.
+    #[structopt(long, number_of_values = 1)]
+    pub deny: Vec<Lint>,
+
+    /// Report every expression computed again while a variable still holds its value.
+    #[structopt(long)]
+    pub report_redundant: bool,
+
+    /// Windows subsystem to link for: `console` or `windows`.
+    #[structopt(long, default_value = "console")]
+    pub subsystem: Subsystem,
+
+    /// Entry symbol of the executable, `drive` or `WinMainCRTStartup` by default.
+    #[structopt(long)]
+    pub entry: Option<String>,
+
+    /// Link against the C runtime, which initializes itself and calls `main`, instead of the driver.
+    #[structopt(long)]
+    pub crt: bool,
+
+    /// Write the events the C++ parser sends for every source to `<output>.events`, which compiles in place of the sources without the parser.
+    #[structopt(long)]
+    pub record_events: bool,
+}
+
+impl Opt {
+    // Whether the input is a recording of parser events rather than sources.
+    pub fn replay(&self) -> bool {
+        self.input
+            .extension()
+            .is_some_and(|ext| ext == events::EXTENSION)
+    }
+
+    // The entry symbol to link with. Any but the driver's own gets a stub that
+    // calls `main` and exits with its return value.
+    pub fn entry(&self) -> String {
+        match &self.entry {
+            Some(entry) => entry.clone(),
+            None => self.subsystem.default_entry().to_string(),
+        }
+    }
+
+    // The entry symbol to pass to the linker, or `None` for the C runtime's own.
+    pub fn link_entry(&self) -> Option<String> {
+        (!self.crt).then(|| self.entry())
+    }
+}
+
+// What is left for the driver after `build`.
+#[derive(Debug, PartialEq)]
+pub enum Build {
+    Done,
+    Unsupported, // `--check-subset` listed some constructs.
+    Denied,      // Lints turned into errors by `--deny` stopped the compilation.
+    Failed,      // The front end could not build an AST from the input.
+    // Assemble and link `asm` into `output`, with the libraries `libs`.
+    Assemble {
+        asm: PathBuf,
+        output: String,
+        libs: Vec<String>,
+    },
+}
+
+// Why a compilation stopped before its output.
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    Cancelled,           // Through its `CancellationToken`.
+    Denied(Vec<String>), // The lints `--deny` turned into errors, one message each.
+    Parsing(Error),      // An input the front end could not build an AST from.
+}
+
+impl From<Cancelled> for CompileError {
+    fn from(_: Cancelled) -> Self {
+        CompileError::Cancelled
+    }
+}
+
+impl From<Error> for CompileError {
+    fn from(error: Error) -> Self {
+        CompileError::Parsing(error)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Cancelled => write!(f, "The compilation was cancelled."),
+            CompileError::Denied(errors) => {
+                write!(f, "Aborting because of {} denied lint(s).", errors.len())
+            }
+            CompileError::Parsing(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// Compile `opt.input`, a source file or a project file, reading the sources
+// and writing the artifacts through `files`.
+pub fn build(opt: Opt, files: &mut dyn FileProvider) -> Build {
+    // A `.toml` input is a project file listing the sources to compile together.
+    let (paths, output, mut libs) = match opt.input.extension() {
+        Some(ext) if ext == "toml" => {
+            let project = project::load(&opt.input, files);
+            (project.sources, project.output, project.libs)
+        }
+        _ => (vec![opt.input.clone()], String::from("main"), Vec::new()),
+    };
+    libs.extend(opt.link_libs.iter().cloned());
+    let sources: Vec<_> = paths
+        .iter()
+        .map(|path| files.read(path).expect("Invalid input file path."))
+        .collect();
+    let sources: Vec<_> = paths
+        .iter()
+        .map(PathBuf::as_path)
+        .zip(sources.iter().map(String::as_str))
+        .collect();
+    if opt.replay() && (opt.check_subset || opt.fmt || opt.explorer || opt.record_events) {
+        panic!("`--check-subset`, `--fmt`, `--explorer`, and `--record-events` need the sources, not a recording.");
+    }
+    if opt.check_subset {
+        let mut result = Build::Done;
+        for (path, source) in &sources {
+            for construct in check_subset(source) {
+                println!("{}:{}", path.display(), construct);
+                result = Build::Unsupported;
+            }
+        }
+        return result;
+    }
+    if opt.fmt {
+        for (path, source) in &sources {
+            files
+                .write(path, &format(source))
+                .expect("Fail to write the formatted source file.");
+        }
+        return Build::Done;
+    }
+    if opt.toolchain == Toolchain::Macos && (opt.crt || opt.entry() != entry::DRIVER_ENTRY) {
+        panic!("`--subsystem`, `--entry`, and `--crt` need a Windows toolchain.");
+    }
+    if opt.crt && opt.entry.is_some() {
+        panic!("`--crt` links with the entry point of the C runtime, so it takes no `--entry`.");
+    }
+    if opt.stack_guard && (opt.crt || opt.entry() != entry::DRIVER_ENTRY) {
+        panic!("Only `drive` sets the limit `--stack-guard` checks, so it takes no `--crt`, `--entry`, or `--subsystem`.");
+    }
+    let file = |ext| PathBuf::from(&output).with_extension(ext);
+    if opt.record_events {
+        files
+            .write(&file(events::EXTENSION), &events::record(&sources))
+            .expect("Fail to write the events file.");
+    }
+    let asm_file = file(opt.toolchain.extension());
+    let (explorer, map) = (opt.explorer, opt.map);
+    let (asm, symbols) = match compile_program(&sources, opt, &CancellationToken::new()) {
+        Ok(Some(output)) => output,
+        Ok(None) => return Build::Done,
+        Err(error) => {
+            if let CompileError::Denied(errors) = &error {
+                for error in errors {
+                    eprintln!("error: {}", error);
+                }
+            }
+            eprintln!("{}", error);
+            return match error {
+                CompileError::Parsing(_) => Build::Failed,
+                _ => Build::Denied,
+            };
+        }
+    };
+    // For `--explorer`, the assembly comes with its source map.
+    if explorer {
+        files
+            .write(&file("asm"), &asm)
+            .expect("Fail to write the output assembly file.");
+        files
+            .write(&file("json"), &symbols)
+            .expect("Fail to write the source map file.");
+        return Build::Done;
+    }
+    files
+        .write(&asm_file, &asm)
+        .expect("Fail to write the output assembly file.");
+    if map {
+        files
+            .write(&file("map"), &symbols)
+            .expect("Fail to write the map file.");
+    }
+    Build::Assemble {
+        asm: asm_file,
+        output,
+        libs,
+    }
 }
 
 pub fn compile(source: &str, opt: Opt) -> Option<String> {
-    let ast = parser::parse(source);
+    let path = opt.input.clone();
+    compile_units(&[(&path, source)], opt)
+}
+
+// Compile several translation units, each given with its path, into one
+// program, so functions in one unit may call those defined in another.
+// Panics on the lints `opt.deny` turns into errors and on inputs the front
+// end cannot build an AST from.
+pub fn compile_units(sources: &[(&Path, &str)], opt: Opt) -> Option<String> {
+    compile_cancellable(sources, opt, &CancellationToken::new())
+        .unwrap_or_else(|error| panic!("{}", error))
+}
+
+// Like `compile_units`, but gives up with `CompileError::Cancelled` once
+// `cancel` is cancelled, and returns the denied lints and the errors of the
+// front end instead of panicking.
+pub fn compile_cancellable(
+    sources: &[(&Path, &str)],
+    opt: Opt,
+    cancel: &CancellationToken,
+) -> Result<Option<String>, CompileError> {
+    Ok(compile_program(sources, opt, cancel)?.map(|(asm, _)| asm))
+}
+
+// Compile into the assembly and the map of the functions it emits, or with
+// `opt.explorer`, into Compiler Explorer-style assembly and its source map.
+fn compile_program(
+    sources: &[(&Path, &str)],
+    opt: Opt,
+    cancel: &CancellationToken,
+) -> Result<Option<(String, String)>, CompileError> {
+    let (ast, context) = try_parse_units(sources, opt.replay())?;
+    // Calls follow the Windows x64 convention, which the C libraries on macOS
+    // do not expect.
+    if opt.toolchain == Toolchain::Macos {
+        if let Some(name) = resolver::externs(&ast).into_iter().next() {
+            return Err(CompileError::Parsing(Error::Frontend(format!(
+                "Function `{}` is not defined in the program, and `--toolchain macos` cannot call external functions.",
+                name
+            ))));
+        }
+    }
     if opt.ast {
         println!("{:#?}", ast);
-        return None;
+        return Ok(None);
+    }
+    let mut pipeline = Pipeline::new(&opt, &context);
+    let (mut ssa, warnings, errors) = pipeline.construct(ast, cancel)?;
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if opt.report_redundant {
+        for note in available::report(&ssa, &pipeline.lines) {
+            eprintln!("note: {}", note);
+        }
     }
-    let (ssa, prog_leaves) = ssa::construct(ast);
+    if !errors.is_empty() {
+        return Err(CompileError::Denied(errors));
+    }
+    pipeline.optimize(&mut ssa);
     if opt.ssa {
         println!("{:#?}", ssa);
-        return None;
+        print!("{}", pipeline.nests.unwrap().report());
+        return Ok(None);
     }
-    let cfg = ssa::destruct(ssa, prog_leaves);
+    cancel.check()?;
+    let cfg = pipeline.destruct(ssa);
     if opt.cfg {
         println!("{:#?}", cfg);
-        return None;
+        print!("{}", pipeline.nests.unwrap().report());
+        return Ok(None);
+    }
+    cancel.check()?;
+    let lir = pipeline.lower(cfg);
+    if opt.lir {
+        println!("{:#?}", lir);
+        return Ok(None);
     }
-    let vasm = X64Builder::new().build(cfg);
+    let vasm = pipeline.select(lir);
     if opt.vasm {
         println!("{:#?}", vasm);
-        return None;
+        return Ok(None);
+    }
+    if opt.compare_regalloc {
+        compare_regalloc(&vasm, &opt.main_args);
+        return Ok(None);
     }
-    let asm = reg_allocator::alloc(vasm);
+    cancel.check()?;
+    let asm = pipeline.allocate(vasm);
+    cancel.check()?;
+    let asm = pipeline.cleanup(asm);
     if opt.asm {
         println!("{:#?}", asm);
-        return None;
+        return Ok(None);
+    }
+    cancel.check()?;
+    Ok(Some(pipeline.emit(asm)))
+}
+
+// What the passes need to know of a program besides its functions: the
+// failure messages of its assertions, the prototype of every function it
+// calls, the pragmas of its functions, and the lines of their statements.
+#[derive(Debug, Clone, Default)]
+struct Context {
+    assertions: Vec<String>,
+    signatures: HashMap<String, Prototype>,
+    pragmas: Pragmas,
+    lines: Lines,
+}
+
+// The passes of the pipeline, stage by stage, which `compile_program`,
+// `bench`, and `CompilationSession` all run. Around every pass, it does what
+// `opt` asks for: verifying the IR, counting for `--stats`, printing what
+// changed, noting the loops changed, and following the lines for `--explorer`.
+struct Pipeline<'a> {
+    opt: &'a Opt,
+    context: &'a Context,
+    lines: HashMap<String, usize>, // The line of every function.
+    // For `--explorer`, the lines of the statements, and then of the
+    // instructions, follow them through every pass.
+    map: Option<LineMap>,
+    nests: Option<loops::Nests>,
+}
+
+impl<'a> Pipeline<'a> {
+    fn new(opt: &'a Opt, context: &'a Context) -> Self {
+        Pipeline {
+            opt,
+            context,
+            lines: HashMap::new(),
+            map: None,
+            nests: None,
+        }
+    }
+
+    // Construct SSA, checking the program for uninitialized variables and
+    // lints on the way. Returns the warnings and the denied lints apart.
+    fn construct(
+        &mut self,
+        ast: Program,
+        cancel: &CancellationToken,
+    ) -> Result<(SSAProgram, Vec<String>, Vec<String>), Cancelled> {
+        let opt = self.opt;
+        let context = self.context;
+        self.lines = ast
+            .iter()
+            .map(|func| (func.name.clone(), func.line))
+            .collect();
+        cancel.check()?;
+        let mut findings = lint::check_ast(&ast, &context.lines);
+        let mut warnings = Vec::new();
+        let (ssa, block_lines) = ssa::construct_cancellable(
+            ast,
+            &context.signatures,
+            &context.lines,
+            &mut warnings,
+            cancel,
+        )?;
+        if opt.verify_all {
+            verify("ssa::construct", verifier::verify_ssa(&ssa));
+        }
+        warnings.extend(uninit::check(&ssa, &self.lines, &block_lines));
+        findings.extend(lint::check_ssa(&ssa, &self.lines, &block_lines));
+        let (lint_warnings, errors) = lint::apply_levels(findings, &opt.allow, &opt.deny);
+        warnings.extend(lint_warnings);
+        self.map = opt
+            .explorer
+            .then(|| LineMap::new(self.lines.clone(), block_lines));
+        self.nests = (opt.stats || opt.ssa || opt.cfg).then(|| loops::Nests::find(&ssa));
+        Ok((ssa, warnings, errors))
+    }
+
+    // Fold the comparisons the ranges decide, and replace the expressions
+    // already available.
+    fn optimize(&mut self, ssa: &mut SSAProgram) {
+        let opt = self.opt;
+        let pragmas = &self.context.pragmas;
+        save_lines(&mut self.map, ssa);
+        let folded = unless(
+            ssa,
+            pragmas,
+            Optimization::Fold,
+            |func| &func.name,
+            |ssa| range::fold_comparisons(ssa, opt.overflow),
+        );
+        follow_lines(&mut self.map, ssa);
+        if opt.stats {
+            println!("range::fold_comparisons removed {} comparisons", folded);
+        }
+        if let Some(nests) = &mut self.nests {
+            nests.note("range::fold_comparisons", ssa);
+        }
+        if opt.verify_all {
+            verify("range::fold_comparisons", verifier::verify_ssa(ssa));
+        }
+        save_lines(&mut self.map, ssa);
+        let replaced = unless(
+            ssa,
+            pragmas,
+            Optimization::Available,
+            |func| &func.name,
+            available::eliminate,
+        );
+        follow_lines(&mut self.map, ssa);
+        if opt.stats {
+            println!("available::eliminate replaced {} expressions", replaced);
+        }
+        if let Some(nests) = &mut self.nests {
+            nests.note("available::eliminate", ssa);
+        }
+        if opt.verify_all {
+            verify("available::eliminate", verifier::verify_ssa(ssa));
+        }
+    }
+
+    // Destruct SSA and move the partially redundant computations.
+    fn destruct(&mut self, ssa: SSAProgram) -> SSAProgram {
+        let opt = self.opt;
+        let before = opt.print_changed.then(|| dump(&ssa, |func| &func.name));
+        save_lines(&mut self.map, &ssa);
+        let mut cfg = ssa::destruct(ssa);
+        follow_lines(&mut self.map, &cfg);
+        if let Some(before) = before {
+            print_changed("ssa::destruct", before, dump(&cfg, |func| &func.name));
+        }
+        if let Some(nests) = &mut self.nests {
+            nests.sync(&cfg);
+        }
+        if opt.verify_all {
+            verify("ssa::destruct", verifier::verify_destructed(&cfg));
+        }
+        save_lines(&mut self.map, &cfg);
+        let moved = unless(
+            &mut cfg,
+            &self.context.pragmas,
+            Optimization::Pre,
+            |func| &func.name,
+            pre::eliminate,
+        );
+        follow_lines(&mut self.map, &cfg);
+        if opt.stats {
+            println!(
+                "pre::eliminate removed {} partially redundant computations",
+                moved
+            );
+        }
+        if let Some(nests) = &mut self.nests {
+            nests.note("pre::eliminate", &cfg);
+            if opt.stats {
+                print!("{}", nests.report());
+            }
+        }
+        if opt.verify_all {
+            verify("pre::eliminate", verifier::verify_destructed(&cfg));
+        }
+        cfg
+    }
+
+    // Lower to the LIR, with the types of every function called.
+    fn lower(&mut self, cfg: SSAProgram) -> LirProgram {
+        let mut builder = LirBuilder::new();
+        builder.declare(&self.context.signatures);
+        match &mut self.map {
+            Some(map) => {
+                let (lir, lines) = builder.build_lines(cfg, map.blocks());
+                map.lower(lines);
+                lir
+            }
+            None => builder.build(cfg),
+        }
     }
-    Some(serializer::run(asm))
+
+    // Select instructions over virtual registers, number the values in every
+    // block, and schedule them.
+    fn select(&mut self, lir: LirProgram) -> X64Program {
+        let opt = self.opt;
+        let pragmas = &self.context.pragmas;
+        let mut vasm = match &mut self.map {
+            Some(map) => {
+                let (vasm, lines) = X64Builder::new().build_lines(lir, &map.instructions());
+                map.lower(lines);
+                vasm
+            }
+            None => X64Builder::new().build(lir),
+        };
+        if opt.verify_all {
+            verify("X64Builder::build", verifier::verify_vasm(&vasm));
+        }
+        save_lines(&mut self.map, &vasm);
+        let numbered = unless(
+            &mut vasm,
+            pragmas,
+            Optimization::Lvn,
+            |func| &func.name,
+            lvn::number,
+        );
+        follow_lines(&mut self.map, &vasm);
+        if opt.stats {
+            println!("lvn::number removed {} instructions", numbered);
+        }
+        if opt.verify_all {
+            verify("lvn::number", verifier::verify_vasm(&vasm));
+        }
+        save_lines(&mut self.map, &vasm);
+        let moved = unless(
+            &mut vasm,
+            pragmas,
+            Optimization::Schedule,
+            |func| &func.name,
+            scheduler::schedule,
+        );
+        follow_lines(&mut self.map, &vasm);
+        if opt.stats {
+            println!("scheduler::schedule moved {} instructions", moved);
+            print!("{}", pressure::report(&vasm));
+        }
+        if opt.verify_all {
+            verify("scheduler::schedule", verifier::verify_vasm(&vasm));
+        }
+        vasm
+    }
+
+    // Allocate registers, and check the stack in every prologue for
+    // `--stack-guard`.
+    fn allocate(&mut self, vasm: X64Program) -> X64Program {
+        let opt = self.opt;
+        let before = opt.print_changed.then(|| dump(&vasm, |func| &func.name));
+        save_lines(&mut self.map, &vasm);
+        let mut asm = reg_allocator::alloc(vasm, opt.spill);
+        if opt.stack_guard {
+            guard::insert_stack_checks(&mut asm);
+        }
+        follow_lines(&mut self.map, &asm);
+        if let Some(before) = before {
+            print_changed(
+                "reg_allocator::alloc",
+                before,
+                dump(&asm, |func| &func.name),
+            );
+        }
+        if opt.verify_all {
+            verify("reg_allocator::alloc", verifier::verify_asm(&asm));
+        }
+        asm
+    }
+
+    fn cleanup(&mut self, asm: X64Program) -> X64Program {
+        let opt = self.opt;
+        let before = opt.print_changed.then(|| dump(&asm, |func| &func.name));
+        save_lines(&mut self.map, &asm);
+        let (asm, stats) = peephole::cleanup(asm);
+        follow_lines(&mut self.map, &asm);
+        if opt.stats {
+            println!(
+                "peephole::cleanup removed {} self-moves, {} stack moves, {} jumps, and {} tags, and rewrote {} idioms",
+                stats.self_moves, stats.stack_moves, stats.jumps, stats.tags, stats.idioms
+            );
+        }
+        if let Some(before) = before {
+            print_changed("peephole::cleanup", before, dump(&asm, |func| &func.name));
+        }
+        if opt.verify_all {
+            verify("peephole::cleanup", verifier::verify_asm(&asm));
+        }
+        if opt.stats {
+            print!("{}", stats::report(&asm));
+        }
+        asm
+    }
+
+    // Serialize for `opt.toolchain` into the assembly and the map of the
+    // functions it emits, or for `--explorer`, into Compiler Explorer-style
+    // assembly and its source map.
+    fn emit(self, mut asm: X64Program) -> (String, String) {
+        let opt = self.opt;
+        if let Some(map) = self.map {
+            return serializer::run_explorer(asm, &self.lines, &map.instructions());
+        }
+        if opt.crt {
+            entry::add_crt_main(&mut asm);
+        } else {
+            entry::add_entry_stub(&mut asm, &opt.entry());
+        }
+        let map = stats::map(&asm);
+        let assertions = &self.context.assertions;
+        let asm = match opt.toolchain {
+            Toolchain::Masm => serializer::run(asm, assertions),
+            Toolchain::Mingw => serializer::run_gas(asm, false, assertions),
+            Toolchain::Macos => serializer::run_gas(asm, true, assertions),
+        };
+        (asm, map)
+    }
+}
+
+// Allocate registers with every spill heuristic, run the results in the
+// emulator, and fail if they do not all return the same.
+fn compare_regalloc(vasm: &X64Program, args: &[i64]) {
+    let mut results = Vec::new();
+    for (name, spill) in [("cost", Spill::Cost), ("next-use", Spill::NextUse)] {
+        let asm = reg_allocator::alloc(vasm.clone(), spill);
+        verify("reg_allocator::alloc", verifier::verify_asm(&asm));
+        let (asm, _) = peephole::cleanup(asm);
+        let result = emulator::run(&asm, args);
+        match &result {
+            Ok(value) => println!("{}: `main` returned {}", name, value),
+            Err(err) => println!("{}: {}", name, err),
+        }
+        results.push((name, result));
+    }
+    let (first, expected) = &results[0];
+    for (name, result) in &results[1..] {
+        if result != expected {
+            panic!("The `{}` and `{}` spill heuristics diverge.", first, name);
+        }
+    }
+}
+
+fn verify(pass: &str, result: Result<(), String>) {
+    if let Err(err) = result {
+        panic!("Pass `{}` broke an invariant: {}", pass, err);
+    }
+}
+
+// Take down the items of `program` before a pass, for `--explorer`.
+fn save_lines<F: Texts>(map: &mut Option<LineMap>, program: &[F]) {
+    if let Some(map) = map {
+        map.save(program);
+    }
+}
+
+// Carry the lines of the items taken down over to `program` after the pass.
+fn follow_lines<F: Texts>(map: &mut Option<LineMap>, program: &[F]) {
+    if let Some(map) = map {
+        map.follow(program);
+    }
+}
+
+fn dump<T: Debug>(funcs: &[T], name: impl Fn(&T) -> &String) -> Vec<(String, String)> {
+    funcs
+        .iter()
+        .map(|func| (name(func).clone(), format!("{:#?}", func)))
+        .collect()
+}
+
+fn print_changed(pass: &str, before: Vec<(String, String)>, after: Vec<(String, String)>) {
+    for ((name, before), (_, after)) in before.into_iter().zip(after) {
+        if let Some(diff) = diff::unified(&before, &after, 3) {
+            println!("*** IR Dump After {} on {} ***", pass, name);
+            print!("{}", diff);
+        }
+    }
+}
+
+// Parse every unit, along with the context of the program, and resolve every
+// call to the prototype of the function it calls. With `replay`, every source
+// is a recording of the events of its units instead, which keeps no comments
+// and so no pragmas.
+fn parse_units(sources: &[(&Path, &str)], replay: bool) -> (Program, Context) {
+    try_parse_units(sources, replay).unwrap_or_else(|error| panic!("{}", error))
+}
+
+// `parse_units`, returning an event stream it cannot build an AST from as an
+// error.
+fn try_parse_units(sources: &[(&Path, &str)], replay: bool) -> Result<(Program, Context), Error> {
+    let mut ast = Program::new();
+    let mut context = Context::default();
+    let mut prototypes = Vec::new();
+    let mut units = Vec::new();
+    for (path, source) in sources {
+        if !replay {
+            let path = path.display().to_string();
+            let events = parser::try_events(source)?;
+            let unit = parser::replay_unit(
+                events,
+                &path,
+                &mut context.assertions,
+                &mut prototypes,
+                &mut context.lines,
+            )?;
+            context.pragmas.extend(parser::find_pragmas(source, &unit)?);
+            units.push((path, unit));
+            continue;
+        }
+        for (path, events) in events::units(source)? {
+            let path = path.display().to_string();
+            let unit = parser::replay_unit(
+                events,
+                &path,
+                &mut context.assertions,
+                &mut prototypes,
+                &mut context.lines,
+            )?;
+            units.push((path, unit));
+        }
+    }
+    // The source every function is defined in.
+    let mut defined: HashMap<String, String> = HashMap::new();
+    for (path, unit) in units {
+        for func in unit {
+            if let Some(other) = defined.insert(func.name.clone(), path.clone()) {
+                let message = if other == path {
+                    format!(
+                        "Function `{}` is defined more than once in `{}`.",
+                        func.name, path
+                    )
+                } else {
+                    format!(
+                        "Function `{}` is defined in both `{}` and `{}`.",
+                        func.name, other, path
+                    )
+                };
+                return Err(Error::Frontend(message));
+            }
+            ast.push(func);
+        }
+    }
+    context.signatures = resolver::resolve(&ast, &prototypes);
+    Ok((ast, context))
+}
+
+// Run a whole-program pass over the functions that do not turn `optimization`
+// off, keeping the order of the functions. Returns what the pass counted.
+fn unless<F>(
+    program: &mut Vec<F>,
+    pragmas: &Pragmas,
+    optimization: Optimization,
+    name: impl Fn(&F) -> &String,
+    pass: impl FnOnce(&mut Vec<F>) -> usize,
+) -> usize {
+    let off = |func: &F| {
+        pragmas
+            .get(name(func))
+            .is_some_and(|set| set.contains(&optimization))
+    };
+    let (skipped, kept): (Vec<_>, Vec<_>) = program
+        .drain(..)
+        .enumerate()
+        .partition(|(_, func)| off(func));
+    let (indices, mut funcs): (Vec<_>, Vec<_>) = kept.into_iter().unzip();
+    let cnt = pass(&mut funcs);
+    let mut all: Vec<_> = indices.into_iter().zip(funcs).chain(skipped).collect();
+    all.sort_by_key(|(i, _)| *i);
+    program.extend(all.into_iter().map(|(_, func)| func));
+    cnt
+}
+
+// Compile into Compiler Explorer-style assembly and its source map, through
+// the same passes as `compile_units`.
+pub fn compile_explorer(sources: &[(&Path, &str)], opt: Opt) -> Option<(String, String)> {
+    let opt = Opt {
+        explorer: true,
+        ..opt
+    };
+    compile_program(sources, opt, &CancellationToken::new())
+        .unwrap_or_else(|error| panic!("{}", error))
+}
+
+pub fn check_subset(source: &str) -> Vec<String> {
+    parser::check_subset(source)
+}
+
+pub fn lex(source: &str) -> Vec<Token> {
+    lexer::lex(source)
+}
+
+pub fn format(source: &str) -> String {
+    formatter::format(source)
+}
+
+pub fn resolve_names(source: &str) -> Vec<Reference> {
+    parser::resolve_names(source)
 }
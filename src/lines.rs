@@ -0,0 +1,234 @@
+use crate::diff::{self, Edit};
+use crate::ir::{SSAFunction, Statement};
+use crate::x64::X64Function;
+use std::collections::HashMap;
+
+// Source lines live in side tables next to the IR, so the passes never see
+// them. `Lines` holds the lines of every function under its name: in the AST,
+// one for every statement of its body in pre-order, and in the LIR and the x64
+// code, one for every instruction. `BlockLines` holds one for every statement
+// of every block of a CFG.
+pub type Lines = HashMap<String, Vec<usize>>;
+pub type BlockLines = HashMap<String, Vec<Vec<usize>>>;
+
+// The number of lines `stmt` takes in the lines of an AST: one for itself and
+// one for every statement nested in it.
+pub fn span(stmt: &Statement) -> usize {
+    match stmt {
+        Statement::Compound(stmts) => 1 + stmts.iter().map(span).sum::<usize>(),
+        Statement::If {
+            body, alternative, ..
+        } => 1 + span(body) + alternative.as_deref().map_or(0, span),
+        Statement::While { body, .. } | Statement::DoWhile { body, .. } => 1 + span(body),
+        _ => 1,
+    }
+}
+
+// A function whose statements or instructions have lines, block by block.
+pub trait Texts {
+    fn name(&self) -> &String;
+
+    // The text of every item, which tells it apart from the others.
+    fn texts(&self) -> Vec<Vec<String>>;
+}
+
+impl Texts for SSAFunction {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn texts(&self) -> Vec<Vec<String>> {
+        self.body
+            .iter()
+            .map(|block| {
+                let stmts = block.statements.iter();
+                stmts.map(|stmt| format!("{:?}", stmt)).collect()
+            })
+            .collect()
+    }
+}
+
+impl Texts for X64Function {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn texts(&self) -> Vec<Vec<String>> {
+        vec![self.body.iter().map(|asm| format!("{:?}", asm)).collect()]
+    }
+}
+
+// The lines of a program that passes keep rewriting. Before a pass, `save`
+// takes down the text of every item, and after it, `follow` finds the items
+// again to carry their lines over. A program without blocks has a single one.
+pub struct LineMap {
+    functions: HashMap<String, usize>, // The line of every function, for items with nothing to follow.
+    lines: BlockLines,
+    saved: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl LineMap {
+    pub fn new(functions: HashMap<String, usize>, lines: BlockLines) -> Self {
+        LineMap {
+            functions,
+            lines,
+            saved: HashMap::new(),
+        }
+    }
+
+    pub fn blocks(&self) -> &BlockLines {
+        &self.lines
+    }
+
+    pub fn instructions(&self) -> Lines {
+        let lines = self.lines.iter();
+        lines
+            .map(|(name, lines)| (name.clone(), lines.concat()))
+            .collect()
+    }
+
+    // Start over with the lines of the instructions of a lowered program.
+    pub fn lower(&mut self, lines: Lines) {
+        let lines = lines.into_iter();
+        self.lines = lines.map(|(name, lines)| (name, vec![lines])).collect();
+    }
+
+    pub fn save<F: Texts>(&mut self, program: &[F]) {
+        let texts = program
+            .iter()
+            .map(|func| (func.name().clone(), func.texts()));
+        self.saved = texts.collect();
+    }
+
+    pub fn follow<F: Texts>(&mut self, program: &[F]) {
+        let saved = std::mem::take(&mut self.saved);
+        for func in program {
+            let name = func.name();
+            let (before, lines) = match (saved.get(name), self.lines.get(name)) {
+                (Some(before), Some(lines)) => (before.concat(), lines.concat()),
+                _ => (Vec::new(), Vec::new()),
+            };
+            let after = func.texts();
+            let default = self.functions.get(name).copied().unwrap_or_default();
+            let mut found = follow(&before, &lines, &after.concat(), default).into_iter();
+            let blocks = after
+                .iter()
+                .map(|block| found.by_ref().take(block.len()).collect())
+                .collect();
+            self.lines.insert(name.clone(), blocks);
+        }
+    }
+}
+
+// The lines of `after`, the items a pass made of `before`, which were at
+// `lines`. An item the pass kept, or moved without changing it, keeps its
+// line. An item replacing others takes the line of the first of them of the
+// same kind, such as a jump to another target. An item the pass added takes
+// the line of the item before it, or after it at the start, or `default`.
+fn follow(before: &[String], lines: &[usize], after: &[String], default: usize) -> Vec<usize> {
+    // Only where the two differ needs a diff.
+    let prefix = before.iter().zip(after).take_while(|(b, a)| b == a).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(b, a)| b == a)
+        .count();
+    let middle = diff::find_edits(
+        &before[prefix..before.len() - suffix],
+        &after[prefix..after.len() - suffix],
+    );
+    let edits: Vec<_> = (0..prefix)
+        .map(|i| Edit::Keep(i, i))
+        .chain(middle.into_iter().map(|edit| match edit {
+            Edit::Keep(i, j) => Edit::Keep(prefix + i, prefix + j),
+            Edit::Delete(i) => Edit::Delete(prefix + i),
+            Edit::Insert(j) => Edit::Insert(prefix + j),
+        }))
+        .chain((1..=suffix).map(|k| Edit::Keep(before.len() - k, after.len() - k)))
+        .collect();
+    let mut found = vec![None; after.len()];
+    let mut taken = vec![false; before.len()];
+    for edit in &edits {
+        if let Edit::Keep(i, j) = *edit {
+            found[j] = Some(lines[i]);
+            taken[i] = true;
+        }
+    }
+    let mut moved: HashMap<_, Vec<_>> = HashMap::new();
+    for i in (0..before.len()).rev().filter(|&i| !taken[i]) {
+        moved.entry(&before[i]).or_default().push(i);
+    }
+    for (j, text) in after.iter().enumerate() {
+        if found[j].is_some() {
+            continue;
+        }
+        if let Some(i) = moved.get_mut(text).and_then(Vec::pop) {
+            found[j] = Some(lines[i]);
+            taken[i] = true;
+        }
+    }
+    // Replaced items are paired in order between two kept ones.
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    for edit in edits.iter().chain([&Edit::Keep(0, 0)]) {
+        match *edit {
+            Edit::Delete(i) if !taken[i] => deleted.push(i),
+            Edit::Insert(j) if found[j].is_none() => inserted.push(j),
+            Edit::Keep(..) => {
+                let mut next = 0;
+                for &j in &inserted {
+                    let same = |&i: &usize| kind(&before[i]) == kind(&after[j]);
+                    if let Some(k) = deleted[next..].iter().position(same) {
+                        found[j] = Some(lines[deleted[next + k]]);
+                        next += k + 1;
+                    }
+                }
+                deleted.clear();
+                inserted.clear();
+            }
+            _ => {}
+        }
+    }
+    let mut previous = None;
+    for line in found.iter_mut() {
+        match line {
+            Some(line) => previous = Some(*line),
+            None => *line = previous,
+        }
+    }
+    let mut next = found.iter().flatten().next().copied().unwrap_or(default);
+    found
+        .into_iter()
+        .map(|line| {
+            next = line.unwrap_or(next);
+            next
+        })
+        .collect()
+}
+
+// The variant of the item `text`, such as `MovReg` or `Expression`.
+fn kind(text: &str) -> &str {
+    text.split(|c: char| !c.is_alphanumeric()).next().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_items() {
+        let texts = |texts: &[&str]| {
+            texts
+                .iter()
+                .map(|text| text.to_string())
+                .collect::<Vec<_>>()
+        };
+        let before = texts(&["A(1)", "B(2)", "C(3)", "Jmp(x)", "D(4)"]);
+        let after = texts(&["New", "C(3)", "A(1)", "B(2)", "Jmp(y)", "Ret"]);
+        let lines = follow(&before, &[1, 2, 3, 4, 5], &after, 9);
+        assert_eq!(lines, [3, 3, 1, 2, 4, 4]);
+        let after = texts(&["A(1)", "Mov", "B(2)"]);
+        assert_eq!(follow(&before[..2], &[1, 2], &after, 9), [1, 1, 2]);
+        assert_eq!(follow(&[], &[], &texts(&["Ret"]), 9), [9]);
+    }
+}
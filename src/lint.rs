@@ -0,0 +1,308 @@
+use crate::ir::{Expression, Function, Program, SSAProgram, SSAVar, Statement};
+use crate::lines::{self, BlockLines, Lines};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+// Checks for code that compiles but is likely a mistake. Every lint warns by
+// default, and `--allow` or `--deny` turns it off or into an error.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Lint {
+    DeadAssignment,        // A value assigned to a variable is never read.
+    ConstantCondition,     // An `if` or loop condition does not depend on any variable.
+    EmptyLoopBody,         // A loop body does nothing.
+    AssignmentInCondition, // A condition is an assignment, where `==` was likely intended.
+}
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::DeadAssignment => "dead-assignment",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::EmptyLoopBody => "empty-loop-body",
+            Lint::AssignmentInCondition => "assignment-in-condition",
+        }
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dead-assignment" => Ok(Lint::DeadAssignment),
+            "constant-condition" => Ok(Lint::ConstantCondition),
+            "empty-loop-body" => Ok(Lint::EmptyLoopBody),
+            "assignment-in-condition" => Ok(Lint::AssignmentInCondition),
+            s => Err(format!("Unknown lint `{}`.", s)),
+        }
+    }
+}
+
+// A lint that fired, with its message located at the line of the statement it
+// is about, or of its function if that is not known.
+pub type Finding = (Lint, String);
+
+// Run the lints that look at the syntax of every function, whose statements
+// are at `lines`.
+pub fn check_ast(ast: &Program, lines: &Lines) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for func in ast {
+        let lines = lines.get(&func.name).map_or(&[][..], Vec::as_slice);
+        check_stmt(&func.body, 0, lines, func, &mut findings);
+    }
+    findings
+}
+
+// Run the lints that need the CFG in SSA form, whose statements are at
+// `blocks`: an assignment is dead if no statement or phi uses the SSA variable
+// it defines.
+pub fn check_ssa(
+    ssa: &SSAProgram,
+    lines: &HashMap<String, usize>,
+    blocks: &BlockLines,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for func in ssa {
+        let blocks = blocks.get(&func.name).map_or(&[][..], Vec::as_slice);
+        let mut defs = Vec::new();
+        let mut uses = HashSet::new();
+        for (i, block) in func.body.iter().enumerate() {
+            for (j, stmt) in block.statements.iter().enumerate() {
+                let line = blocks.get(i).and_then(|lines| lines.get(j)).copied();
+                let mut stmt_defs = Vec::new();
+                check_defs_uses(stmt, &mut stmt_defs, &mut uses);
+                defs.extend(stmt_defs.into_iter().map(|var| (var, line)));
+            }
+        }
+        // Variables declared again in a nested block are renamed `name.n`.
+        let mut names = Vec::new();
+        for (var, line) in defs.iter().filter(|(var, _)| !uses.contains(var)) {
+            let name = var.name.split('.').next().unwrap();
+            if names.iter().all(|(named, _)| *named != name) {
+                names.push((name, line.unwrap_or(lines[&func.name])));
+            }
+        }
+        for (name, line) in names {
+            let message = format!(
+                "line {}: the value assigned to `{}` is never read in function `{}`",
+                line, name, func.name
+            );
+            findings.push((Lint::DeadAssignment, message));
+        }
+    }
+    findings
+}
+
+fn check_defs_uses(stmt: &Statement, defs: &mut Vec<SSAVar>, uses: &mut HashSet<SSAVar>) {
+    match stmt {
+        Statement::Phi(_, operands) => uses.extend(operands.iter().map(|(_, var)| var.clone())),
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => find_defs_uses(expr, defs, uses),
+        _ => {}
+    }
+}
+
+// Drop the findings of the lints in `allow` and split the others into
+// warnings and, for the lints in `deny`, errors. `deny` wins over `allow`.
+pub fn apply_levels(
+    findings: Vec<Finding>,
+    allow: &[Lint],
+    deny: &[Lint],
+) -> (Vec<String>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut errors = Vec::new();
+    for (lint, message) in findings {
+        let message = format!("{} [{}]", message, lint.name());
+        if deny.contains(&lint) {
+            errors.push(message);
+        } else if !allow.contains(&lint) {
+            warnings.push(message);
+        }
+    }
+    (warnings, errors)
+}
+
+// Check `stmt`, whose line is the `index`th of `lines` as they go in pre-order.
+fn check_stmt(
+    stmt: &Statement,
+    index: usize,
+    lines: &[usize],
+    func: &Function,
+    findings: &mut Vec<Finding>,
+) {
+    let (keyword, condition, body) = match stmt {
+        Statement::Compound(stmts) => {
+            let mut index = index + 1;
+            for stmt in stmts {
+                check_stmt(stmt, index, lines, func, findings);
+                index += lines::span(stmt);
+            }
+            return;
+        }
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => {
+            if let Some(alternative) = alternative {
+                let index = index + 1 + lines::span(body);
+                check_stmt(alternative, index, lines, func, findings);
+            }
+            ("if", condition, body)
+        }
+        Statement::While { condition, body } => ("while", condition, body),
+        Statement::DoWhile { body, condition } => ("do", condition, body),
+        _ => return,
+    };
+    let line = lines.get(index).copied().unwrap_or(func.line);
+    let mut report = |lint, what: String| {
+        let message = format!("line {}: {} in function `{}`", line, what, func.name);
+        findings.push((lint, message));
+    };
+    if let Expression::Infix { operator: "=", .. } = condition {
+        let what = format!("the condition of `{}` is an assignment", keyword);
+        report(Lint::AssignmentInCondition, what);
+    }
+    // `while (1)` and `for (;;)` are how infinite loops are written.
+    let infinite = keyword != "if" && matches!(condition, Expression::Number(n) if *n != 0);
+    if is_constant(condition) && !infinite {
+        let what = format!("the condition of `{}` is constant", keyword);
+        report(Lint::ConstantCondition, what);
+    }
+    if keyword != "if" && is_empty(body) {
+        let what = format!("the body of `{}` is empty", keyword);
+        report(Lint::EmptyLoopBody, what);
+    }
+    check_stmt(body, index + 1, lines, func, findings);
+}
+
+fn is_constant(expr: &Expression) -> bool {
+    match expr {
+        Expression::Number(_) | Expression::Float(_) => true,
+        Expression::Prefix { expression, .. } => is_constant(expression),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => *operator != "=" && is_constant(left) && is_constant(right),
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => is_constant(condition) && is_constant(consequent) && is_constant(alternative),
+        _ => false,
+    }
+}
+
+fn is_empty(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Nop => true,
+        Statement::Compound(stmts) => stmts.iter().all(is_empty),
+        _ => false,
+    }
+}
+
+fn find_defs_uses(expr: &Expression, defs: &mut Vec<SSAVar>, uses: &mut HashSet<SSAVar>) {
+    match expr {
+        Expression::Identifier(var) => {
+            uses.insert(var.clone());
+        }
+        Expression::Number(_) | Expression::Float(_) => {}
+        // Stores to arrays are not tracked.
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_defs_uses(index, defs, uses);
+            }
+        }
+        Expression::Call { arguments, .. } => find_defs_uses(arguments, defs, uses),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                find_defs_uses(expr, defs, uses);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_defs_uses(expression, defs, uses),
+        Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        } => {
+            find_defs_uses(right, defs, uses);
+            match &**left {
+                Expression::Identifier(var) => defs.push(var.clone()),
+                left => find_defs_uses(left, defs, uses),
+            }
+        }
+        Expression::Infix { left, right, .. } => {
+            find_defs_uses(left, defs, uses);
+            find_defs_uses(right, defs, uses);
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::{parser, ssa};
+
+    #[test]
+    fn lints() {
+        let source = "
+            int main() {
+                int a = 1;
+                int b;
+                if (b = 2) {}
+                while (a) {}
+                do { a = 3; } while (0);
+                if (a) { int b = 4; }
+                while (1) { return a; }
+            }
+        ";
+        let mut stmt_lines = Lines::new();
+        let events = parser::events(source);
+        let ast = parser::replay_unit(
+            events,
+            "main.c",
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut stmt_lines,
+        )
+        .unwrap();
+        let findings = check_ast(&ast, &stmt_lines);
+        let lines = vec![(String::from("main"), 2)].into_iter().collect();
+        let cancel = CancellationToken::new();
+        let (ssa, blocks) =
+            ssa::construct_cancellable(ast, &HashMap::new(), &stmt_lines, &mut Vec::new(), &cancel)
+                .unwrap();
+        let findings = findings
+            .into_iter()
+            .chain(check_ssa(&ssa, &lines, &blocks))
+            .collect();
+        let (warnings, errors) = apply_levels(
+            findings,
+            &[Lint::EmptyLoopBody],
+            &[Lint::AssignmentInCondition, Lint::EmptyLoopBody],
+        );
+        let expected = vec![
+            "line 7: the condition of `do` is constant in function `main` [constant-condition]",
+            "line 5: the value assigned to `b` is never read in function `main` [dead-assignment]",
+        ];
+        assert_eq!(warnings, expected);
+        let expected = vec![
+            "line 5: the condition of `if` is an assignment in function `main` [assignment-in-condition]",
+            "line 6: the body of `while` is empty in function `main` [empty-loop-body]",
+        ];
+        assert_eq!(errors, expected);
+        assert_eq!("dead-assignment".parse(), Ok(Lint::DeadAssignment));
+    }
+}
@@ -0,0 +1,764 @@
+// The LIR types live in the `eac-ir` crate; the builder lowering CFG
+// functions into them stays here.
+pub use eac_ir::lir::*;
+
+use crate::ir::{
+    declared_double, Block, Edge, Expression, Int, Prototype, SSAFunction, SSAProgram, SSAVar,
+    Statement, CFG,
+};
+use crate::lines::{BlockLines, Lines};
+use std::collections::{HashMap, HashSet};
+
+// SSA destruction leaves blocks of statements, which are lowered here into
+// the LIR that instruction selection consumes.
+//
+// Blocks are laid out in index order, and the control flow between them follows
+// the roles of their outgoing edges:
+//
+// - `Next`, `Then`, and `Body` edges fall through to the next block.
+// - `Else` and `Exit` edges leave a condition block: if the condition is false,
+//   jump to the successor.
+// - `Skip` and `Back` edges always jump to the successor.
+//
+// Every block that is the target of a jump starts with the label
+// `<function>_Block<index>`. Other labels are `<function>_L<count>`, numbered
+// across the whole program, so no two functions ever share a label.
+//
+// A `double` value lives in a `Double` register. An operation with a `double`
+// operand converts the other one and yields a `double`, and a value is
+// converted to the type of the variable, parameter, or return value it goes
+// to. Array elements and pointer targets are integers.
+pub struct LirBuilder {
+    allocator: VarAllocator,
+    arrays: Vec<(String, Vec<usize>)>, // The name and dimensions of every array, numbered by position.
+    condition: Option<Var>,            // What the branch at the end of the current block tests.
+    function: String,
+    tag_cnt: usize,
+    double: bool,             // Whether the function returns a `double`.
+    doubles: HashSet<String>, // The `double` names of the function.
+    // Whether every function returns a `double`, and which of its parameters are.
+    signatures: HashMap<String, (bool, Vec<bool>)>,
+    variadic: HashSet<String>, // The functions declared with `...`.
+}
+
+// Integers and `double` values share one numbering, and every variable keeps
+// the register it first got.
+struct VarAllocator {
+    count: usize,
+    conds: usize,
+    var_map: HashMap<SSAVar, Var>,
+}
+
+impl VarAllocator {
+    fn new() -> Self {
+        VarAllocator {
+            count: 0,
+            conds: 0,
+            var_map: HashMap::new(),
+        }
+    }
+
+    fn var(&mut self, var: SSAVar, double: bool) -> Var {
+        match self.var_map.get(&var) {
+            Some(reg) => *reg,
+            None => {
+                let reg = self.create(double);
+                self.var_map.insert(var, reg);
+                reg
+            }
+        }
+    }
+
+    fn create_temp(&mut self) -> Var {
+        self.create(false)
+    }
+
+    fn create_double_temp(&mut self) -> Var {
+        self.create(true)
+    }
+
+    fn create_cond(&mut self) -> Var {
+        self.conds += 1;
+        Var::Cond(self.conds - 1)
+    }
+
+    fn create(&mut self, double: bool) -> Var {
+        let reg = match double {
+            true => Var::Double(self.count),
+            false => Var::Int(self.count),
+        };
+        self.count += 1;
+        reg
+    }
+
+    fn clear(&mut self) {
+        self.count = 0;
+        self.conds = 0;
+        self.var_map.clear();
+    }
+}
+
+// Lower every function of `cfg`, with the types of only the functions it defines.
+#[cfg(test)]
+pub fn lower(cfg: SSAProgram) -> LirProgram {
+    LirBuilder::new().build(cfg)
+}
+
+impl LirBuilder {
+    pub fn new() -> Self {
+        LirBuilder {
+            allocator: VarAllocator::new(),
+            arrays: Vec::new(),
+            condition: None,
+            function: String::new(),
+            tag_cnt: 0,
+            double: false,
+            doubles: HashSet::new(),
+            signatures: HashMap::new(),
+            variadic: HashSet::new(),
+        }
+    }
+
+    // Declare the types of functions that `build` may not see defined.
+    pub fn declare(&mut self, prototypes: &HashMap<String, Prototype>) {
+        for (name, proto) in prototypes {
+            let signature = (proto.double, proto.parameters.clone());
+            self.signatures.insert(name.clone(), signature);
+            if proto.variadic {
+                self.variadic.insert(name.clone());
+            }
+        }
+    }
+
+    pub fn build(&mut self, cfg: SSAProgram) -> LirProgram {
+        self.build_lines(cfg, &BlockLines::new()).0
+    }
+
+    // `build`, along with the line of every instruction, given the line of
+    // every statement of `cfg`. A label is at the line of the statement after
+    // it, a branch at that of its condition, and any other jump ending a block
+    // at that of its last statement.
+    pub fn build_lines(&mut self, cfg: SSAProgram, lines: &BlockLines) -> (LirProgram, Lines) {
+        self.signatures.extend(cfg.iter().map(|func| {
+            let params = func.parameters.iter().map(|p| func.is_double(p)).collect();
+            (func.name.clone(), (func.double, params))
+        }));
+        let mut lir_lines = Lines::new();
+        let program = cfg
+            .into_iter()
+            .map(
+                |SSAFunction {
+                     name,
+                     parameters,
+                     body,
+                     double,
+                     doubles,
+                     ..
+                 }| {
+                    self.function = name.clone();
+                    self.double = double;
+                    self.doubles = doubles;
+                    let param_cnt = parameters.len();
+                    let block_lines = lines.get(&name).map_or(&[][..], Vec::as_slice);
+                    let (body, body_lines) = self.build_body(parameters, body, block_lines);
+                    lir_lines.insert(name.clone(), body_lines);
+                    LirFunction {
+                        name,
+                        param_cnt,
+                        arrays: self
+                            .arrays
+                            .drain(..)
+                            .map(|(_, dims)| dims.iter().product())
+                            .collect(),
+                        body,
+                    }
+                },
+            )
+            .collect();
+        (program, lir_lines)
+    }
+
+    fn build_body(
+        &mut self,
+        parameters: Vec<SSAVar>,
+        body: CFG,
+        lines: &[Vec<usize>],
+    ) -> (Vec<Lir>, Vec<usize>) {
+        self.allocator.clear();
+        // The entry defines every parameter explicitly, so nothing after it
+        // depends on which virtual register a parameter gets.
+        let mut lirs = Vec::new();
+        for (index, var) in parameters.into_iter().enumerate() {
+            let double = self.is_double(&var);
+            lirs.push(Lir::Param(self.allocator.var(var, double), index));
+        }
+        let entry = lines.iter().flatten().next().copied().unwrap_or_default();
+        let mut lir_lines = vec![entry; lirs.len()];
+        let mut targets = HashSet::new();
+        let blocks: Vec<_> = body
+            .into_iter()
+            .enumerate()
+            .map(|(index, block)| {
+                let block_lines = lines.get(index).map_or(&[][..], Vec::as_slice);
+                self.build_block(block, block_lines, &mut targets)
+            })
+            .collect();
+        for (index, (block, block_lines)) in blocks.into_iter().enumerate() {
+            if targets.contains(&index) {
+                lirs.push(Lir::Label(self.block_tag(index)));
+                let line = block_lines.first().or(lir_lines.last()).copied();
+                lir_lines.push(line.unwrap_or(entry));
+            }
+            lirs.extend(block);
+            lir_lines.extend(block_lines);
+        }
+        (lirs, lir_lines)
+    }
+
+    fn build_block(
+        &mut self,
+        block: Block,
+        lines: &[usize],
+        targets: &mut HashSet<usize>,
+    ) -> (Vec<Lir>, Vec<usize>) {
+        self.condition = None;
+        let line = |index: usize| lines.get(index).copied().unwrap_or_default();
+        let last = line(block.statements.len().saturating_sub(1));
+        let mut condition_line = last;
+        let mut lirs = Vec::new();
+        let mut lir_lines = Vec::new();
+        for (index, stmt) in block.statements.into_iter().enumerate() {
+            let condition = self.condition;
+            lirs.extend(self.build_stmt(stmt));
+            lir_lines.resize(lirs.len(), line(index));
+            if self.condition != condition {
+                condition_line = line(index);
+            }
+        }
+        let mut edges: Vec<_> = block.edges.into_iter().collect();
+        edges.sort_unstable_by_key(|(succ, _)| *succ);
+        // Branch only if the condition actually leads to different blocks.
+        if let (Some(condition), true) = (self.condition, block.successors.len() > 1) {
+            for (succ, edge) in &edges {
+                if let Edge::Else | Edge::Exit = edge {
+                    targets.insert(*succ);
+                    lirs.push(Lir::JumpUnless(condition, self.block_tag(*succ)));
+                    lir_lines.push(condition_line);
+                }
+            }
+        }
+        for (succ, edge) in edges {
+            if let Edge::Skip | Edge::Back | Edge::Break | Edge::Continue | Edge::Return = edge {
+                targets.insert(succ);
+                lirs.push(Lir::Jump(self.block_tag(succ)));
+            }
+        }
+        lir_lines.resize(lirs.len(), last);
+        (lirs, lir_lines)
+    }
+
+    fn build_stmt(&mut self, stmt: Statement) -> Vec<Lir> {
+        match stmt {
+            // The edge leaving the block makes the jump.
+            Statement::Nop | Statement::Break | Statement::Continue => Vec::new(),
+            Statement::Phi(_, _) => unreachable!(),
+            Statement::Declaration(var) => {
+                let double = self.is_double(&var);
+                self.allocator.var(var, double);
+                Vec::new()
+            }
+            Statement::ArrayDeclaration(var, dims) => {
+                self.arrays.push((var.name, dims));
+                Vec::new()
+            }
+            Statement::Compound(stmts) => {
+                stmts.into_iter().flat_map(|s| self.build_stmt(s)).collect()
+            }
+            Statement::Expression(expr) => self.build_expr(expr).0,
+            Statement::If { condition, .. }
+            | Statement::While { condition, .. }
+            | Statement::DoWhile { condition, .. } => {
+                // The jump is emitted at the end of the block. Only copies may
+                // follow the condition (phi copies), so a comparison is a
+                // `Cond` that a target may keep in its flags until then.
+                match condition {
+                    Expression::Infix {
+                        left,
+                        operator: op @ ("<" | ">" | "<=" | ">=" | "==" | "!="),
+                        right,
+                    } => {
+                        let (mut lirs, left) = self.build_expr(*left);
+                        let (right_lirs, right) = self.build_expr(*right);
+                        lirs.extend(right_lirs);
+                        let (left, right) = match is_float(left, right) {
+                            true => (
+                                self.convert(&mut lirs, left, true),
+                                self.convert(&mut lirs, right, true),
+                            ),
+                            false => (left, right),
+                        };
+                        let cond = self.allocator.create_cond();
+                        lirs.push(Lir::Compare(cond, left, op, right));
+                        self.condition = Some(cond);
+                        lirs
+                    }
+                    condition => {
+                        let (mut lirs, reg) = self.build_expr(condition);
+                        if reg.is_double() {
+                            let zero = self.float_zero(&mut lirs);
+                            let cond = self.allocator.create_cond();
+                            lirs.push(Lir::Compare(cond, reg, "!=", zero));
+                            self.condition = Some(cond);
+                        } else {
+                            self.condition = Some(reg);
+                        }
+                        lirs
+                    }
+                }
+            }
+            Statement::Return(Some(expr)) => {
+                let (mut lirs, reg) = self.build_expr(expr);
+                let reg = self.convert(&mut lirs, reg, self.double);
+                lirs.push(Lir::Return(Some(reg)));
+                lirs
+            }
+            Statement::Return(None) => vec![Lir::Return(None)],
+        }
+    }
+
+    fn build_expr(&mut self, expr: Expression) -> (Vec<Lir>, Var) {
+        match expr {
+            Expression::Identifier(var) => {
+                let double = self.is_double(&var);
+                (Vec::new(), self.allocator.var(var, double))
+            }
+            Expression::Number(num) => {
+                let reg = self.allocator.create_temp();
+                (vec![Lir::Const(reg, num)], reg)
+            }
+            Expression::Float(bits) => {
+                let (bits_reg, reg) = (
+                    self.allocator.create_temp(),
+                    self.allocator.create_double_temp(),
+                );
+                let lirs = vec![Lir::Const(bits_reg, bits as Int), Lir::Bits(reg, bits_reg)];
+                (lirs, reg)
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                if let (Expression::Identifier(SSAVar { name, .. }), Expression::Arguments(exprs)) =
+                    (*function, *arguments)
+                {
+                    // Unknown functions take and return integers.
+                    let (double, params) = self.signatures.get(&name).cloned().unwrap_or_default();
+                    let variadic = self.variadic.contains(&name);
+                    let mut lirs = Vec::new();
+                    let mut regs = Vec::new();
+                    for (i, expr) in exprs.into_iter().enumerate() {
+                        let (l, r) = self.build_expr(expr);
+                        lirs.extend(l);
+                        // Arguments past the parameters of a variadic
+                        // function keep their own type.
+                        let double = match params.get(i) {
+                            Some(double) => *double,
+                            None => variadic && r.is_double(),
+                        };
+                        regs.push(self.convert(&mut lirs, r, double));
+                    }
+                    let ret_reg = self.allocator.create(double);
+                    lirs.push(Lir::Call(name, regs, ret_reg, variadic));
+                    (lirs, ret_reg)
+                } else {
+                    unreachable!();
+                }
+            }
+            Expression::Index { array, indices } => {
+                let (mut lirs, array, position, offset) = self.build_position(array, indices);
+                let element = match (position, offset) {
+                    (None, offset) if offset >= 0 => Element::Const(offset as usize),
+                    (position, offset) => {
+                        Element::Var(self.fold_position(&mut lirs, position, offset))
+                    }
+                };
+                let reg = self.allocator.create_temp();
+                lirs.push(Lir::LoadElement(reg, array, element));
+                (lirs, reg)
+            }
+            Expression::Arguments(_) => unreachable!(),
+            Expression::Prefix {
+                operator,
+                expression,
+            } => match operator {
+                "+" => self.build_expr(*expression),
+                "-" => {
+                    let (mut lirs, reg) = self.build_expr(*expression);
+                    if reg.is_double() {
+                        let zero = self.float_zero(&mut lirs);
+                        lirs.push(Lir::Binary(zero, zero, "-", reg));
+                        (lirs, zero)
+                    } else {
+                        // The operand may be a variable read again later.
+                        let neg = self.allocator.create_temp();
+                        lirs.push(Lir::Unary(neg, "-", reg));
+                        (lirs, neg)
+                    }
+                }
+                "!" => {
+                    let (mut lirs, reg) = self.build_expr(*expression);
+                    let reg = self.truth(&mut lirs, reg);
+                    let r = self.allocator.create_temp();
+                    let tag = self.new_tag();
+                    lirs.extend(vec![
+                        Lir::Const(r, 1),
+                        Lir::JumpUnless(reg, tag.clone()),
+                        Lir::Const(r, 0),
+                        Lir::Label(tag),
+                    ]);
+                    (lirs, r)
+                }
+                "&" => match *expression {
+                    Expression::Index { array, indices } => {
+                        let (mut lirs, array, index) = self.build_index(array, indices);
+                        let reg = self.allocator.create_temp();
+                        lirs.push(Lir::Address(reg, array, index));
+                        (lirs, reg)
+                    }
+                    Expression::Identifier(var) => {
+                        panic!("The address of `{}` cannot be taken.", var.name)
+                    }
+                    _ => unreachable!(),
+                },
+                "*" => {
+                    let (mut lirs, address) = self.build_expr(*expression);
+                    let reg = self.allocator.create_temp();
+                    lirs.push(Lir::Load(reg, address));
+                    (lirs, reg)
+                }
+                _ => unreachable!(),
+            },
+            Expression::Infix {
+                left,
+                operator: "=",
+                right,
+            } if matches!(*left, Expression::Index { .. }) => {
+                if let Expression::Index { array, indices } = *left {
+                    let (mut lirs, array, position, offset) = self.build_position(array, indices);
+                    let element = match (position, offset) {
+                        (None, offset) if offset >= 0 => Element::Const(offset as usize),
+                        (position, offset) => {
+                            Element::Var(self.fold_position(&mut lirs, position, offset))
+                        }
+                    };
+                    let (right_lirs, reg) = self.build_expr(*right);
+                    lirs.extend(right_lirs);
+                    let reg = self.convert(&mut lirs, reg, false);
+                    lirs.push(Lir::StoreElement(array, element, reg));
+                    (lirs, reg)
+                } else {
+                    unreachable!();
+                }
+            }
+            Expression::Infix {
+                left,
+                operator: "=",
+                right,
+            } if matches!(*left, Expression::Prefix { operator: "*", .. }) => {
+                if let Expression::Prefix { expression, .. } = *left {
+                    let (mut lirs, address) = self.build_expr(*expression);
+                    let (right_lirs, reg) = self.build_expr(*right);
+                    lirs.extend(right_lirs);
+                    let reg = self.convert(&mut lirs, reg, false);
+                    lirs.push(Lir::Store(address, reg));
+                    (lirs, reg)
+                } else {
+                    unreachable!();
+                }
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } => {
+                let (mut lirs, left_reg) = self.build_expr(*left);
+                let (right_lirs, right_reg) = self.build_expr(*right);
+                lirs.extend(right_lirs);
+                let reg = if operator == "=" {
+                    let right_reg = self.convert(&mut lirs, right_reg, left_reg.is_double());
+                    lirs.push(Lir::Copy(left_reg, right_reg));
+                    left_reg
+                } else if matches!(operator, "&&" | "||") {
+                    let left_reg = self.truth(&mut lirs, left_reg);
+                    let right_reg = self.truth(&mut lirs, right_reg);
+                    let reg = self.allocator.create_temp();
+                    let op = match operator {
+                        "&&" => "&",
+                        _ => "|",
+                    };
+                    lirs.push(Lir::Binary(reg, left_reg, op, right_reg));
+                    reg
+                } else if is_float(left_reg, right_reg) {
+                    let left_reg = self.convert(&mut lirs, left_reg, true);
+                    let right_reg = self.convert(&mut lirs, right_reg, true);
+                    self.build_float_infix(&mut lirs, operator, left_reg, right_reg)
+                } else {
+                    let reg = self.allocator.create_temp();
+                    match operator {
+                        "*" | "/" | "%" | "+" | "-" => {
+                            lirs.push(Lir::Binary(reg, left_reg, operator, right_reg))
+                        }
+                        op => self.build_compare(&mut lirs, reg, left_reg, op, right_reg),
+                    }
+                    reg
+                };
+                (lirs, reg)
+            }
+            Expression::Conditional { .. } => unreachable!(),
+        }
+    }
+
+    // Compute the row-major position of an element, returning the number of
+    // the innermost array declared as `array` and the register holding the
+    // position. Strides are known at compile time, and constant indices are
+    // folded into a single offset.
+    fn build_index(&mut self, array: SSAVar, indices: Vec<Expression>) -> (Vec<Lir>, usize, Var) {
+        let (mut lirs, array, position, offset) = self.build_position(array, indices);
+        let index = self.fold_position(&mut lirs, position, offset);
+        (lirs, array, index)
+    }
+
+    // The number of the array, and the position of the element as the sum of
+    // the variable indices scaled by their strides, if any, and a constant.
+    fn build_position(
+        &mut self,
+        array: SSAVar,
+        indices: Vec<Expression>,
+    ) -> (Vec<Lir>, usize, Option<Var>, Int) {
+        let (number, (_, dims)) = self
+            .arrays
+            .iter()
+            .enumerate()
+            .rfind(|(_, (name, _))| *name == array.name)
+            .unwrap_or_else(|| panic!("`{}` is not an array.", array.name));
+        if indices.len() != dims.len() {
+            panic!(
+                "`{}` has {} dimension(s) but is indexed with {}.",
+                array.name,
+                dims.len(),
+                indices.len()
+            );
+        }
+        let strides: Vec<usize> = (0..dims.len())
+            .map(|k| dims[k + 1..].iter().product())
+            .collect();
+        let mut lirs = Vec::new();
+        let mut offset = 0;
+        let mut position = None;
+        for (index, stride) in indices.into_iter().zip(strides) {
+            let (index_lirs, index) = match index {
+                Expression::Number(num) => {
+                    offset += num * stride as Int;
+                    continue;
+                }
+                index => self.build_expr(index),
+            };
+            lirs.extend(index_lirs);
+            let term = match stride {
+                1 => index,
+                stride => {
+                    let (reg, stride_reg) =
+                        (self.allocator.create_temp(), self.allocator.create_temp());
+                    lirs.extend(vec![
+                        Lir::Const(stride_reg, stride as Int),
+                        Lir::Binary(reg, index, "*", stride_reg),
+                    ]);
+                    reg
+                }
+            };
+            position = Some(match position {
+                Some(sum) => {
+                    let reg = self.allocator.create_temp();
+                    lirs.push(Lir::Binary(reg, sum, "+", term));
+                    reg
+                }
+                None => term,
+            });
+        }
+        (lirs, number, position, offset)
+    }
+
+    fn fold_position(&mut self, lirs: &mut Vec<Lir>, position: Option<Var>, offset: Int) -> Var {
+        match (position, offset) {
+            (Some(position), 0) => position,
+            (position, offset) => {
+                let offset_reg = self.allocator.create_temp();
+                lirs.push(Lir::Const(offset_reg, offset));
+                match position {
+                    Some(position) => {
+                        let reg = self.allocator.create_temp();
+                        lirs.push(Lir::Binary(reg, position, "+", offset_reg));
+                        reg
+                    }
+                    None => offset_reg,
+                }
+            }
+        }
+    }
+
+    fn build_float_infix(
+        &mut self,
+        lirs: &mut Vec<Lir>,
+        operator: &'static str,
+        left_reg: Var,
+        right_reg: Var,
+    ) -> Var {
+        match operator {
+            "*" | "/" | "+" | "-" => {
+                let reg = self.allocator.create_double_temp();
+                lirs.push(Lir::Binary(reg, left_reg, operator, right_reg));
+                reg
+            }
+            "%" => panic!("`%` cannot be applied to a `double`."),
+            op => {
+                let reg = self.allocator.create_temp();
+                self.build_compare(lirs, reg, left_reg, op, right_reg);
+                reg
+            }
+        }
+    }
+
+    // Set `reg` to 1 if the comparison holds, and to 0 otherwise.
+    fn build_compare(
+        &mut self,
+        lirs: &mut Vec<Lir>,
+        reg: Var,
+        left_reg: Var,
+        operator: &'static str,
+        right_reg: Var,
+    ) {
+        let tag = self.new_tag();
+        let cond = self.allocator.create_cond();
+        lirs.extend(vec![
+            Lir::Const(reg, 1),
+            Lir::Compare(cond, left_reg, operator, right_reg),
+            Lir::JumpIf(cond, tag.clone()),
+            Lir::Const(reg, 0),
+            Lir::Label(tag),
+        ]);
+    }
+
+    // Convert the value in `reg` to a `double` or an integer, truncating a
+    // `double` to an integer.
+    fn convert(&mut self, lirs: &mut Vec<Lir>, reg: Var, double: bool) -> Var {
+        if reg.is_double() == double {
+            return reg;
+        }
+        let new = self.allocator.create(double);
+        lirs.push(Lir::Convert(new, reg));
+        new
+    }
+
+    // A register that is nonzero exactly when the value in `reg` is, which a
+    // `double` cannot be tested for directly.
+    fn truth(&mut self, lirs: &mut Vec<Lir>, reg: Var) -> Var {
+        if !reg.is_double() {
+            return reg;
+        }
+        let zero = self.float_zero(lirs);
+        let r = self.allocator.create_temp();
+        self.build_compare(lirs, r, reg, "!=", zero);
+        r
+    }
+
+    // A new `double` register holding 0.0, whose bits are all zero.
+    fn float_zero(&mut self, lirs: &mut Vec<Lir>) -> Var {
+        let (bits_reg, reg) = (
+            self.allocator.create_temp(),
+            self.allocator.create_double_temp(),
+        );
+        lirs.extend(vec![Lir::Const(bits_reg, 0), Lir::Bits(reg, bits_reg)]);
+        reg
+    }
+
+    fn is_double(&self, var: &SSAVar) -> bool {
+        declared_double(&self.doubles, var)
+    }
+
+    fn block_tag(&self, index: usize) -> String {
+        format!("{}_Block{}", self.function, index)
+    }
+
+    fn new_tag(&mut self) -> String {
+        self.tag_cnt += 1;
+        format!("{}_L{}", self.function, self.tag_cnt)
+    }
+}
+
+fn is_float(left: Var, right: Var) -> bool {
+    left.is_double() || right.is_double()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, ssa};
+
+    fn lower_source(source: &str) -> Vec<Lir> {
+        let cfg = ssa::destruct(ssa::construct(parser::parse(source)));
+        lower(cfg).remove(0).body
+    }
+
+    #[test]
+    fn condition() {
+        let body = lower_source(
+            "
+            int main(int a, int b) {
+                do {
+                    a = a + 1;
+                } while (a < b);
+                return a;
+            }
+        ",
+        );
+        let v = Var::Int;
+        // The phi copy comes between the comparison and the jump reading it.
+        let expected = vec![
+            Lir::Param(v(0), 0),
+            Lir::Param(v(1), 1),
+            Lir::Copy(v(2), v(0)),
+            Lir::Label(String::from("main_Block1")),
+            Lir::Const(v(4), 1),
+            Lir::Binary(v(5), v(2), "+", v(4)),
+            Lir::Copy(v(3), v(5)),
+            Lir::Compare(Var::Cond(0), v(3), "<", v(1)),
+            Lir::Copy(v(2), v(3)),
+            Lir::JumpUnless(Var::Cond(0), String::from("main_Block3")),
+            Lir::Jump(String::from("main_Block1")),
+            Lir::Label(String::from("main_Block3")),
+            Lir::Return(Some(v(3))),
+        ];
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn doubles() {
+        let body = lower_source(
+            "
+            double half(int a) {
+                return a / 2.0;
+            }
+        ",
+        );
+        let (v, d) = (Var::Int, Var::Double);
+        let expected = vec![
+            Lir::Param(v(0), 0),
+            Lir::Const(v(1), 2.0f64.to_bits() as Int),
+            Lir::Bits(d(2), v(1)),
+            Lir::Convert(d(3), v(0)),
+            Lir::Binary(d(4), d(3), "/", d(2)),
+            Lir::Return(Some(d(4))),
+        ];
+        assert_eq!(body, expected);
+    }
+}
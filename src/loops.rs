@@ -0,0 +1,337 @@
+use crate::dataflow;
+use crate::ir::{Block, Expression, Int, SSAFunction, SSAProgram, SSAVar, Statement};
+use crate::traversal;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+// The natural loops of every function, found once on the SSA form, and the
+// passes that have changed a statement in each since. Passes before `ssa::destruct`
+// keep the blocks where they are, so a loop is told by its blocks throughout.
+pub struct Nests {
+    functions: Vec<FunctionLoops>,
+}
+
+struct FunctionLoops {
+    name: String,
+    loops: Vec<Loop>,
+    // The statements of every block when the last pass was noted.
+    snapshot: Vec<Vec<Statement>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Loop {
+    header: usize,
+    blocks: BTreeSet<usize>,
+    depth: usize, // 1 for an outermost loop.
+    trips: Option<Int>,
+    fired: Vec<&'static str>,
+}
+
+impl Nests {
+    pub fn find(ssa: &SSAProgram) -> Self {
+        let functions = ssa
+            .iter()
+            .map(|func| FunctionLoops {
+                name: func.name.clone(),
+                loops: find_loops(func),
+                snapshot: snapshot(&func.body),
+            })
+            .collect();
+        Nests { functions }
+    }
+
+    // Record `pass` in every loop with a statement it changed.
+    pub fn note(&mut self, pass: &'static str, ssa: &SSAProgram) {
+        for (func, new) in self.functions.iter_mut().zip(ssa) {
+            let snapshot = &func.snapshot;
+            for lp in &mut func.loops {
+                let changed = lp
+                    .blocks
+                    .iter()
+                    .any(|&i| snapshot[i] != new.body[i].statements);
+                if changed && !lp.fired.contains(&pass) {
+                    lp.fired.push(pass);
+                }
+            }
+        }
+        self.sync(ssa);
+    }
+
+    // Take in the changes of a pass without recording it, such as those of
+    // SSA destruction, which optimizes nothing.
+    pub fn sync(&mut self, ssa: &SSAProgram) {
+        for (func, new) in self.functions.iter_mut().zip(ssa) {
+            func.snapshot = snapshot(&new.body);
+        }
+    }
+
+    // Every loop by function, indented by its nesting depth, with its header
+    // block, its size, its trip count when it is constant, and the passes that
+    // changed it.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for func in &self.functions {
+            report += &format!("{}: {} loop(s)\n", func.name, func.loops.len());
+            for lp in &func.loops {
+                let trips = match lp.trips {
+                    Some(trips) => format!("{} iteration(s)", trips),
+                    None => String::from("unknown iterations"),
+                };
+                let fired = match lp.fired.is_empty() {
+                    true => String::from("not optimized"),
+                    false => lp.fired.join(", "),
+                };
+                report += &format!(
+                    "{}loop at block {}, depth {}, {} block(s), {}: {}\n",
+                    "    ".repeat(lp.depth),
+                    lp.header,
+                    lp.depth,
+                    lp.blocks.len(),
+                    trips,
+                    fired
+                );
+            }
+        }
+        report
+    }
+}
+
+fn snapshot(cfg: &[Block]) -> Vec<Vec<Statement>> {
+    cfg.iter().map(|block| block.statements.clone()).collect()
+}
+
+// Every natural loop, with the loops sharing a header merged, in the order of
+// their headers. Blocks are numbered in the order of the source, so loops come
+// right after the loops they are nested in. An edge is a back edge where its target
+// dominates its source, and the loop is the header and every block reaching
+// the source without passing through the header.
+fn find_loops(func: &SSAFunction) -> Vec<Loop> {
+    let cfg = &func.body;
+    let idoms = dataflow::dominators(cfg);
+    let order: Vec<_> = traversal::reverse_postorder(cfg, 0).collect();
+    let mut loops: Vec<Loop> = Vec::new();
+    for header in (0..cfg.len()).filter(|i| order.contains(i)) {
+        let mut blocks = BTreeSet::new();
+        let latches: Vec<_> = cfg[header]
+            .predecessors
+            .iter()
+            .copied()
+            .filter(|&pred| order.contains(&pred) && dominates(&idoms, header, pred))
+            .collect();
+        for &latch in &latches {
+            blocks.insert(header);
+            let mut stack = vec![latch];
+            while let Some(i) = stack.pop() {
+                if blocks.insert(i) {
+                    let preds = cfg[i].predecessors.iter().copied();
+                    stack.extend(preds.filter(|pred| order.contains(pred)));
+                }
+            }
+        }
+        if !blocks.is_empty() {
+            loops.push(Loop {
+                header,
+                trips: trip_count(func, header, &blocks),
+                blocks,
+                depth: 0,
+                fired: Vec::new(),
+            });
+        }
+    }
+    // A loop contains the headers of the loops nested in it.
+    let depths: Vec<_> = loops
+        .iter()
+        .map(|lp| {
+            loops
+                .iter()
+                .filter(|other| other.blocks.contains(&lp.header))
+                .count()
+        })
+        .collect();
+    for (lp, depth) in loops.iter_mut().zip(depths) {
+        lp.depth = depth;
+    }
+    loops
+}
+
+fn dominates(idoms: &[Option<usize>], dom: usize, mut block: usize) -> bool {
+    loop {
+        if block == dom {
+            return true;
+        }
+        match idoms[block] {
+            Some(idom) => block = idom,
+            None => return false,
+        }
+    }
+}
+
+// The number of times the body of a `while` loop runs, when its condition
+// compares a variable with a constant, and the variable starts at a constant
+// and moves by a constant step every iteration. A `break` may end it sooner.
+fn trip_count(func: &SSAFunction, header: usize, blocks: &BTreeSet<usize>) -> Option<Int> {
+    let statements = &func.body[header].statements;
+    let (left, operator, right) = match statements.last() {
+        Some(Statement::While {
+            condition:
+                Expression::Infix {
+                    left,
+                    operator,
+                    right,
+                },
+            ..
+        }) => (&**left, *operator, &**right),
+        _ => return None,
+    };
+    let (var, operator, bound) = match (left, right) {
+        (Expression::Identifier(var), Expression::Number(bound)) => (var, operator, *bound),
+        (Expression::Number(bound), Expression::Identifier(var)) => {
+            let operator = match operator {
+                "<" => ">",
+                "<=" => ">=",
+                ">" => "<",
+                ">=" => "<=",
+                operator => operator,
+            };
+            (var, operator, *bound)
+        }
+        _ => return None,
+    };
+    let values = statements.iter().find_map(|stmt| match stmt {
+        Statement::Phi(phi, values) if phi == var => Some(values),
+        _ => None,
+    })?;
+    let (nexts, starts): (Vec<_>, Vec<_>) =
+        values.iter().partition(|(pred, _)| blocks.contains(pred));
+    let (start, next) = match (&starts[..], &nexts[..]) {
+        ([(_, start)], [(_, next)]) => (start, next),
+        _ => return None,
+    };
+    let start = match assigned(func, start)? {
+        Expression::Number(start) => *start as i128,
+        _ => return None,
+    };
+    let step = match assigned(func, next)? {
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => match (&**left, *operator, &**right) {
+            (Expression::Identifier(left), "+", Expression::Number(step))
+            | (Expression::Number(step), "+", Expression::Identifier(left))
+                if left == var =>
+            {
+                *step as i128
+            }
+            (Expression::Identifier(left), "-", Expression::Number(step)) if left == var => {
+                -(*step as i128)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let bound = bound as i128;
+    let trips = match operator {
+        "<" if start >= bound => 0,
+        "<=" if start > bound => 0,
+        ">" if start <= bound => 0,
+        ">=" if start < bound => 0,
+        "!=" if start == bound => 0,
+        "<" if step > 0 => (bound - start + step - 1) / step,
+        "<=" if step > 0 => (bound - start) / step + 1,
+        ">" if step < 0 => (start - bound - step - 1) / -step,
+        ">=" if step < 0 => (start - bound) / -step + 1,
+        "!=" if step != 0 && (bound - start) % step == 0 && (bound - start) / step > 0 => {
+            (bound - start) / step
+        }
+        _ => return None,
+    };
+    Int::try_from(trips).ok()
+}
+
+// The expression assigned to `var` by its only definition.
+fn assigned<'a>(func: &'a SSAFunction, var: &SSAVar) -> Option<&'a Expression> {
+    func.body
+        .iter()
+        .flat_map(|block| &block.statements)
+        .find_map(|stmt| match stmt {
+            Statement::Expression(Expression::Infix {
+                left,
+                operator: "=",
+                right,
+            }) if **left == Expression::Identifier(var.clone()) => Some(&**right),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{available, parser, ssa};
+
+    fn nests(source: &str) -> (SSAProgram, Nests) {
+        let ssa = ssa::construct(parser::parse(source));
+        let nests = Nests::find(&ssa);
+        (ssa, nests)
+    }
+
+    #[test]
+    fn nesting() {
+        let (_, nests) = nests(
+            "
+            int main() {
+                int s = 0;
+                int i;
+                for (i = 0; i < 10; i = i + 3) {
+                    int j = 5;
+                    while (j >= 0) {
+                        s = s + j;
+                        j = j - 1;
+                    }
+                    do {
+                        s = s - 1;
+                    } while (s > 100);
+                }
+                while (s != 0) {
+                    s = s / 2;
+                }
+                return s;
+            }
+        ",
+        );
+        let loops = &nests.functions[0].loops;
+        let summary: Vec<_> = loops.iter().map(|lp| (lp.depth, lp.trips)).collect();
+        let expected = vec![(1, Some(4)), (2, Some(6)), (2, None), (1, None)];
+        assert_eq!(summary, expected);
+        assert!(loops[1].blocks.is_subset(&loops[0].blocks));
+        assert!(loops[2].blocks.is_subset(&loops[0].blocks));
+    }
+
+    #[test]
+    fn report() {
+        let (mut ssa, mut nests) = nests(
+            "
+            int main(int a, int b) {
+                int s = a + b;
+                int i = 0;
+                while (i < 3) {
+                    s = s + (a + b);
+                    i = i + 1;
+                }
+                while (a > 0) {
+                    a = a - b;
+                }
+                return s;
+            }
+        ",
+        );
+        available::eliminate(&mut ssa);
+        nests.note("available::eliminate", &ssa);
+        let expected = "\
+main: 2 loop(s)
+    loop at block 1, depth 1, 2 block(s), 3 iteration(s): available::eliminate
+    loop at block 3, depth 1, 2 block(s), unknown iterations: not optimized
+";
+        assert_eq!(nests.report(), expected);
+    }
+}
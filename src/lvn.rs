@@ -0,0 +1,256 @@
+use crate::ir::Int;
+use crate::x64::{Register, X64Function, X64Program, X64};
+use std::collections::HashMap;
+
+// Local value numbering over the virtual assembly. Within each run of
+// instructions between tags, a constant or an arithmetic result computed again
+// is not recomputed: the temporary holding the repeat is replaced by the one
+// holding the first computation. Only temporaries defined once (or by a single
+// `mov`-then-operate pair) are involved, so the first one still holds its value
+// wherever the repeat is used. Returns the number of instructions removed.
+pub fn number(asm: &mut X64Program) -> usize {
+    asm.iter_mut().map(number_function).sum()
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Value {
+    Number(Int),
+    Operation(Operator, Register, Register),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Operator {
+    Imul,
+    Idiv,
+    Irem,
+    Add,
+    Sub,
+    And,
+    Or,
+}
+
+fn number_function(X64Function { body, .. }: &mut X64Function) -> usize {
+    let mut defs: HashMap<Register, usize> = HashMap::new();
+    for asm in body.iter() {
+        if let Some(reg) = defined(asm) {
+            *defs.entry(reg).or_default() += 1;
+        }
+    }
+    let mut values: HashMap<Value, Register> = HashMap::new();
+    let mut renames = HashMap::new();
+    let mut removed = vec![false; body.len()];
+    let mut index = 0;
+    while index < body.len() {
+        if let X64::Tag(_) = body[index] {
+            values.clear();
+        }
+        rename(&mut body[index], &renames);
+        let (value, len) = match (&body[index], body.get(index + 1)) {
+            (X64::MovNum(reg, num), _) if defs[reg] == 1 => (Some((*reg, Value::Number(*num))), 1),
+            (X64::MovReg(reg, left), Some(next)) if defs[reg] == 2 => {
+                let mut next = next.clone();
+                rename(&mut next, &renames);
+                match operation(&next) {
+                    Some((op, dest, right)) if dest == *reg && *left != dest && right != dest => {
+                        (Some((*reg, normalize(op, *left, right))), 2)
+                    }
+                    _ => (None, 1),
+                }
+            }
+            _ => (None, 1),
+        };
+        match value.and_then(|(reg, value)| Some((reg, *values.get(&value)?))) {
+            Some((reg, holder)) => {
+                renames.insert(reg, holder);
+                for removed in &mut removed[index..index + len] {
+                    *removed = true;
+                }
+            }
+            None => {
+                for asm in &mut body[index + 1..index + len] {
+                    rename(asm, &renames);
+                }
+                for asm in &body[index..index + len] {
+                    if let Some(reg) = defined(asm) {
+                        invalidate(&mut values, reg);
+                    }
+                }
+                if let Some((reg, value)) = value {
+                    values.insert(value, reg);
+                }
+            }
+        }
+        index += len;
+    }
+    let cnt = removed.iter().filter(|removed| **removed).count();
+    let mut removed = removed.into_iter();
+    body.retain(|_| !removed.next().unwrap());
+    cnt
+}
+
+// Forget every value held in `reg` or computed from it, since it is redefined.
+fn invalidate(values: &mut HashMap<Value, Register>, reg: Register) {
+    values.retain(|value, holder| {
+        *holder != reg
+            && match value {
+                Value::Number(_) => true,
+                Value::Operation(_, left, right) => *left != reg && *right != reg,
+            }
+    });
+}
+
+// Commutative operations are numbered the same regardless of operand order.
+fn normalize(op: Operator, left: Register, right: Register) -> Value {
+    match op {
+        Operator::Imul | Operator::Add | Operator::And | Operator::Or => {
+            Value::Operation(op, left.min(right), left.max(right))
+        }
+        Operator::Idiv | Operator::Irem | Operator::Sub => Value::Operation(op, left, right),
+    }
+}
+
+fn operation(asm: &X64) -> Option<(Operator, Register, Register)> {
+    match *asm {
+        X64::Imul(left, right) => Some((Operator::Imul, left, right)),
+        X64::Idiv(left, right) => Some((Operator::Idiv, left, right)),
+        X64::Irem(left, right) => Some((Operator::Irem, left, right)),
+        X64::Add(left, right) => Some((Operator::Add, left, right)),
+        X64::Sub(left, right) => Some((Operator::Sub, left, right)),
+        X64::And(left, right) => Some((Operator::And, left, right)),
+        X64::Or(left, right) => Some((Operator::Or, left, right)),
+        _ => None,
+    }
+}
+
+// The register the instruction writes, if any.
+pub fn defined(asm: &X64) -> Option<Register> {
+    match *asm {
+        X64::Param(reg, _)
+        | X64::MovNum(reg, _)
+        | X64::MovReg(reg, _)
+        | X64::MovFromStack(reg, _)
+        | X64::LoadIndex(reg, _, _)
+        | X64::LeaIndex(reg, _, _)
+        | X64::Lea(reg, _, _, _)
+        | X64::LoadField(reg, _, _)
+        | X64::Load(reg, _)
+        | X64::Call(_, _, reg, _)
+        | X64::Neg(reg)
+        | X64::Imul(reg, _)
+        | X64::Idiv(reg, _)
+        | X64::Irem(reg, _)
+        | X64::Add(reg, _)
+        | X64::Sub(reg, _)
+        | X64::And(reg, _)
+        | X64::Or(reg, _)
+        | X64::Xor(reg, _)
+        | X64::Cvtsi2sd(reg, _)
+        | X64::Cvttsd2si(reg, _)
+        | X64::Addsd(reg, _)
+        | X64::Subsd(reg, _)
+        | X64::Mulsd(reg, _)
+        | X64::Divsd(reg, _)
+        | X64::Pop(reg) => Some(reg),
+        _ => None,
+    }
+}
+
+fn rename(asm: &mut X64, renames: &HashMap<Register, Register>) {
+    let renamed = |reg: &mut Register| {
+        if let Some(holder) = renames.get(reg) {
+            *reg = *holder;
+        }
+    };
+    match asm {
+        X64::MovNum(reg, _)
+        | X64::MovToStack(_, reg)
+        | X64::MovFromStack(reg, _)
+        | X64::LoadField(reg, _, _)
+        | X64::StoreField(_, _, reg)
+        | X64::Neg(reg)
+        | X64::CmpNum(reg, _)
+        | X64::Push(reg)
+        | X64::Pop(reg)
+        | X64::Ret(Some(reg)) => renamed(reg),
+        X64::MovReg(left, right)
+        | X64::CmpReg(left, right)
+        | X64::Imul(left, right)
+        | X64::Idiv(left, right)
+        | X64::Irem(left, right)
+        | X64::Add(left, right)
+        | X64::Sub(left, right)
+        | X64::And(left, right)
+        | X64::Or(left, right)
+        | X64::Xor(left, right)
+        | X64::Test(left, right)
+        | X64::Cvtsi2sd(left, right)
+        | X64::Cvttsd2si(left, right)
+        | X64::Addsd(left, right)
+        | X64::Subsd(left, right)
+        | X64::Mulsd(left, right)
+        | X64::Divsd(left, right)
+        | X64::Ucomisd(left, right)
+        | X64::LoadIndex(left, _, right)
+        | X64::StoreIndex(_, right, left)
+        | X64::LeaIndex(left, _, right)
+        | X64::Load(left, right)
+        | X64::Store(left, right) => {
+            renamed(left);
+            renamed(right);
+        }
+        X64::Lea(reg, base, index, _) => {
+            renamed(reg);
+            renamed(base);
+            renamed(index);
+        }
+        X64::Call(_, args, ret, _) => {
+            args.iter_mut().for_each(renamed);
+            renamed(ret);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x64::Register::Virtual;
+
+    #[test]
+    fn values() {
+        let mut asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 2,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(Virtual(2), 1),
+                X64::MovNum(Virtual(3), 1),
+                X64::MovReg(Virtual(4), Virtual(0)),
+                X64::Add(Virtual(4), Virtual(3)),
+                X64::MovReg(Virtual(5), Virtual(2)),
+                X64::Add(Virtual(5), Virtual(0)),
+                X64::MovReg(Virtual(1), Virtual(5)),
+                X64::MovReg(Virtual(0), Virtual(4)),
+                X64::MovReg(Virtual(6), Virtual(0)),
+                X64::Add(Virtual(6), Virtual(2)),
+                X64::Tag(String::from("main_Block1")),
+                X64::MovNum(Virtual(7), 1),
+                X64::Ret(Some(Virtual(7))),
+            ],
+        }];
+        assert_eq!(number(&mut asm), 3);
+        let expected = vec![
+            X64::MovNum(Virtual(2), 1),
+            X64::MovReg(Virtual(4), Virtual(0)),
+            X64::Add(Virtual(4), Virtual(2)),
+            X64::MovReg(Virtual(1), Virtual(4)),
+            X64::MovReg(Virtual(0), Virtual(4)),
+            X64::MovReg(Virtual(6), Virtual(0)),
+            X64::Add(Virtual(6), Virtual(2)),
+            X64::Tag(String::from("main_Block1")),
+            X64::MovNum(Virtual(7), 1),
+            X64::Ret(Some(Virtual(7))),
+        ];
+        assert_eq!(asm[0].body, expected);
+    }
+}
@@ -1,27 +1,142 @@
-use eac_compiler::{compile, Opt};
+use eac_compiler::{
+    build, command_line_len, manifest, response_file, Build, DiskFiles, Opt, Quoting, Recorder,
+    Subsystem, Toolchain, COMMAND_LINE_LIMIT,
+};
+use std::env;
 use std::fs;
-use std::process::{Command, Stdio};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
 use structopt::StructOpt;
 
 fn main() {
     let opt = Opt::from_args();
-    let source = fs::read_to_string(&opt.input).expect("Invalid input file path.");
-    if let Some(asm) = compile(&source, opt) {
-        fs::write("main.asm", asm).expect("Fail to write the output assembly file.");
-        Command::new("ml64")
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .args(&[
-                "driver.asm",
-                "main.asm",
-                "/Fe",
-                "main.exe",
-                "/link",
-                "/subsystem:console",
-                "/defaultlib:kernel32.lib",
-                "/entry:drive",
-            ])
-            .output()
-            .expect("Fail to call MASM for x64.");
+    let toolchain = opt.toolchain;
+    let asm_path = opt.asm_path.clone();
+    let link_args = opt.link_args.clone();
+    let (subsystem, entry) = (opt.subsystem, opt.link_entry());
+    let manifest_path = opt.manifest.clone();
+    let mut files = Recorder::new(DiskFiles);
+    let mut artifacts = match build(opt, &mut files) {
+        Build::Assemble { asm, output, libs } => {
+            let mut artifacts = files.written;
+            let link_args: Vec<_> = libs
+                .iter()
+                .map(|lib| toolchain.library_arg(lib))
+                .chain(link_args)
+                .collect();
+            artifacts.extend(link(
+                toolchain,
+                subsystem,
+                entry.as_deref(),
+                asm_path,
+                &link_args,
+                asm,
+                output,
+            ));
+            artifacts
+        }
+        Build::Unsupported | Build::Denied | Build::Failed => process::exit(1),
+        Build::Done => files.written,
     };
+    if let Some(path) = manifest_path {
+        artifacts.dedup();
+        let artifacts: Vec<_> = artifacts
+            .into_iter()
+            .filter_map(|path| fs::read(&path).ok().map(|bytes| (path, bytes)))
+            .collect();
+        let arguments: Vec<_> = env::args().skip(1).collect();
+        fs::write(path, manifest(&arguments, &artifacts)).expect("Fail to write the manifest.");
+    }
+}
+
+// Assemble and link `asm` into `output`, returning the files this may produce.
+// Without an `entry`, the C runtime is linked in and starts the program.
+fn link(
+    toolchain: Toolchain,
+    subsystem: Subsystem,
+    entry: Option<&str>,
+    asm_path: Option<PathBuf>,
+    link_args: &[String],
+    asm: PathBuf,
+    output: String,
+) -> Vec<PathBuf> {
+    let exe = PathBuf::from(&output).with_extension("exe");
+    let tool = |default| asm_path.unwrap_or_else(|| PathBuf::from(default));
+    let path = |path: &PathBuf| path.display().to_string();
+    // Only the arguments from `start` on may move to a response file: `ml64`
+    // itself does not read one, but the linker it calls after `/link` does.
+    let (program, mut args, start, quoting) = match toolchain {
+        Toolchain::Masm => {
+            let mut args = vec![
+                String::from("driver.asm"),
+                path(&asm),
+                String::from("/Fe"),
+                path(&exe),
+                String::from("/link"),
+                format!("/subsystem:{}", subsystem.name()),
+                String::from("/defaultlib:kernel32.lib"),
+            ];
+            args.extend(match entry {
+                Some(entry) => vec![format!("/entry:{}", entry)],
+                // `printf` and its kin are inline functions in the headers of
+                // the Universal CRT, with linkable copies in a separate library.
+                None => vec![
+                    String::from("/defaultlib:libcmt.lib"),
+                    String::from("/defaultlib:legacy_stdio_definitions.lib"),
+                    String::from("/entry:mainCRTStartup"),
+                ],
+            });
+            (tool("ml64"), args, 5, Quoting::Msvc)
+        }
+        Toolchain::Mingw => {
+            let mut args = match entry {
+                Some(entry) => vec![String::from("-nostartfiles"), format!("-Wl,-e,{}", entry)],
+                None => Vec::new(),
+            };
+            args.extend(vec![
+                format!("-Wl,--subsystem,{}", subsystem.name()),
+                String::from("driver.s"),
+                path(&asm),
+                String::from("-o"),
+                path(&exe),
+                String::from("-lkernel32"),
+            ]);
+            (tool("gcc"), args, 0, Quoting::Gnu)
+        }
+        Toolchain::Macos => {
+            let mut args = match entry {
+                Some(_) => vec![String::from("-e"), String::from("_drive")],
+                None => Vec::new(),
+            };
+            args.extend(vec![
+                String::from("driver-macos.s"),
+                path(&asm),
+                String::from("-o"),
+                output.clone(),
+            ]);
+            (tool("clang"), args, 0, Quoting::Gnu)
+        }
+    };
+    // Extra arguments come last, so `ml64` passes them on to the linker.
+    args.extend(link_args.iter().cloned());
+    let mut produced = Vec::new();
+    if command_line_len(&path(&program), &args) > COMMAND_LINE_LIMIT {
+        let rsp = PathBuf::from(&output).with_extension("rsp");
+        let file = response_file(&args.split_off(start), quoting);
+        fs::write(&rsp, file).expect("Fail to write the response file.");
+        args.push(format!("@{}", rsp.display()));
+        produced.push(rsp);
+    }
+    Command::new(program)
+        .args(&args)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .expect("Fail to call the assembler.");
+    produced.extend(match toolchain {
+        Toolchain::Masm => vec![asm.with_extension("obj"), exe],
+        Toolchain::Mingw => vec![exe],
+        Toolchain::Macos => vec![PathBuf::from(output)],
+    });
+    produced
 }
@@ -0,0 +1,85 @@
+use crate::files::FileProvider;
+use crate::serializer::escape_json;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+// A `FileProvider` that remembers every path written through it.
+pub struct Recorder<F> {
+    pub files: F,
+    pub written: Vec<PathBuf>,
+}
+
+impl<F: FileProvider> Recorder<F> {
+    pub fn new(files: F) -> Self {
+        Recorder {
+            files,
+            written: Vec::new(),
+        }
+    }
+}
+
+impl<F: FileProvider> FileProvider for Recorder<F> {
+    fn read(&self, path: &Path) -> Result<String> {
+        self.files.read(path)
+    }
+
+    fn write(&mut self, path: &Path, contents: &str) -> Result<()> {
+        self.written.push(path.to_path_buf());
+        self.files.write(path, contents)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.is_file(path)
+    }
+}
+
+// A JSON manifest of a build: the command line arguments it ran with, and the
+// path, size, and 64-bit FNV-1a hash of every artifact it produced.
+pub fn manifest(arguments: &[String], artifacts: &[(PathBuf, Vec<u8>)]) -> String {
+    let arguments: Vec<_> = arguments
+        .iter()
+        .map(|arg| format!("\"{}\"", escape_json(arg)))
+        .collect();
+    let artifacts: Vec<_> = artifacts
+        .iter()
+        .map(|(path, bytes)| {
+            format!(
+                "{{\"path\": \"{}\", \"size\": {}, \"fnv1a64\": \"{:016x}\"}}",
+                escape_json(&path.display().to_string()),
+                bytes.len(),
+                fnv1a64(bytes)
+            )
+        })
+        .collect();
+    format!(
+        "{{\"arguments\": [{}], \"artifacts\": [{}]}}\n",
+        arguments.join(", "),
+        artifacts.join(", ")
+    )
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let arguments = vec![String::from("fib.c"), String::from("--asm-path=C:\\ml64")];
+        let artifacts = vec![
+            (PathBuf::from("main.asm"), Vec::new()),
+            (PathBuf::from("main.exe"), b"a".to_vec()),
+        ];
+        let expected = concat!(
+            "{\"arguments\": [\"fib.c\", \"--asm-path=C:\\\\ml64\"], \"artifacts\": [",
+            "{\"path\": \"main.asm\", \"size\": 0, \"fnv1a64\": \"cbf29ce484222325\"}, ",
+            "{\"path\": \"main.exe\", \"size\": 1, \"fnv1a64\": \"af63dc4c8601ec8c\"}]}\n"
+        );
+        assert_eq!(manifest(&arguments, &artifacts), expected);
+    }
+}
@@ -1,18 +1,289 @@
-use crate::ir::{Expression, Function, Program, SSAVar, Statement};
+use crate::ir::{
+    Expression, Function, Int, Optimization, Pragmas, Program, Prototype, SSAVar, Statement,
+};
+use crate::layout::{Layouts, MemberType, Struct};
+use crate::lexer::{self, TokenKind};
+use crate::lines::{self, Lines};
+use crate::preprocessor;
 use libc::{c_char, size_t};
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::fmt;
+use std::ops::Range;
+use std::ptr;
 
-static mut EVENTS: Vec<(String, String)> = Vec::new();
+// The tag and the text of every event the C++ parser sent, in order.
+pub type Events = Vec<(String, String)>;
 
+static mut EVENTS: Events = Vec::new();
+
+// An event stream from the C++ parser that cannot be built into an AST, such
+// as an unknown tag, or an expression event without its operands. Mistakes in
+// the source are reported as they are found instead. `Frontend` is a build
+// without the C++ parser, which can only replay recorded events, or a
+// construct the parser accepts but the compiler does not build, such as a
+// declaration in a `for` initializer.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    Parsing(String),
+    Frontend(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parsing(message) => write!(f, "Parsing error: {}", message),
+            Error::Frontend(message) => write!(f, "Frontend error: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
 pub fn parse(source: &str) -> Program {
+    parse_unit(source, "<source>", &mut Vec::new(), &mut Vec::new())
+}
+
+// Parse the translation unit at `path`. Every `assert(expr)` becomes a call to
+// the stub `eac_assert_N`, where `N` indexes the failure message it pushes to
+// `assertions`. Every function prototype is pushed to `prototypes`.
+#[cfg(test)]
+pub fn parse_unit(
+    source: &str,
+    path: &str,
+    assertions: &mut Vec<String>,
+    prototypes: &mut Vec<Prototype>,
+) -> Program {
+    match try_parse_unit(source, path, assertions, prototypes) {
+        Ok(program) => program,
+        Err(error) => panic!("{}", error),
+    }
+}
+
+// `parse_unit`, returning an event stream it cannot build an AST from as an
+// error, for callers such as fuzzers that must not stop there.
+#[cfg(test)]
+pub fn try_parse_unit(
+    source: &str,
+    path: &str,
+    assertions: &mut Vec<String>,
+    prototypes: &mut Vec<Prototype>,
+) -> Result<Program, Error> {
+    let events = try_events(source)?;
+    replay_unit(events, path, assertions, prototypes, &mut Lines::new())
+}
+
+// The events the C++ parser sends for `source`, which `parse_unit` builds the
+// AST from.
+pub fn events(source: &str) -> Events {
+    try_events(source).unwrap_or_else(|error| panic!("{}", error))
+}
+
+// `events`, returning the errors of the C++ parser.
+pub fn try_events(source: &str) -> Result<Events, Error> {
+    run(Entry::Parse, &preprocessor::expand(source))
+}
+
+// `try_parse_unit` on events recorded by `events`, without the C++ parser.
+// The lines of the statements of every function go to `lines`.
+pub fn replay_unit(
+    events: Events,
+    path: &str,
+    assertions: &mut Vec<String>,
+    prototypes: &mut Vec<Prototype>,
+    lines: &mut Lines,
+) -> Result<Program, Error> {
+    unsafe {
+        EVENTS = events;
+    }
+    build_ast(path, assertions, prototypes, lines)
+}
+
+// The pragmas of the functions `parse_unit` found in `source`. A line
+// `// eac: no-pre, no-lvn` applies to the function defined right after it and
+// any comments below it. The grammar skips comments, so they are found with
+// the lexer, which tells them apart from code and from each other: an
+// `// eac:` inside a block comment is no pragma.
+pub fn find_pragmas(source: &str, program: &[Function]) -> Result<Pragmas, Error> {
+    let starts: HashMap<_, _> = program.iter().map(|func| (func.line, &func.name)).collect();
+    let line = |offset: usize| source[..offset].matches('\n').count() + 1;
+    let tokens = lexer::lex(source);
+    let mut pragmas = Pragmas::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let text = &source[token.span.clone()];
+        let list = match text.strip_prefix("// eac:") {
+            Some(list) if token.kind == TokenKind::Comment => list,
+            _ => continue,
+        };
+        // Only a comment on a line of its own is a pragma.
+        let start = token.span.start;
+        if !source[..start]
+            .rsplit('\n')
+            .next()
+            .unwrap()
+            .trim()
+            .is_empty()
+        {
+            continue;
+        }
+        // Comments may come in between, but no blank line.
+        let below = tokens[i + 1..].iter().find(|token| {
+            let text = &source[token.span.clone()];
+            token.kind != TokenKind::Comment
+                && !(token.kind == TokenKind::Whitespace && text.matches('\n').count() < 2)
+        });
+        let name = match below.and_then(|token| starts.get(&line(token.span.start))) {
+            Some(name) => name,
+            None => {
+                let message = "`// eac:` is not right above a function definition.";
+                return Err(unsupported(Some(line(start)), message.to_string()));
+            }
+        };
+        for pragma in list.split(',').map(str::trim) {
+            let pragma = match pragma {
+                "no-fold" => Optimization::Fold,
+                "no-available" => Optimization::Available,
+                "no-pre" => Optimization::Pre,
+                "no-lvn" => Optimization::Lvn,
+                "no-schedule" => Optimization::Schedule,
+                _ => {
+                    let message = format!("unknown pragma `{}`.", pragma);
+                    return Err(unsupported(Some(line(start)), message));
+                }
+            };
+            pragmas.entry(name.to_string()).or_default().insert(pragma);
+        }
+    }
+    Ok(pragmas)
+}
+
+// Every construct in `source` that `parse` does not understand, as
+// "line:column: construct" in source order.
+pub fn check_subset(source: &str) -> Vec<String> {
+    let events = run(Entry::CheckSubset, &preprocessor::expand(source))
+        .unwrap_or_else(|error| panic!("{}", error));
+    let mut constructs: Vec<(usize, usize, String)> = events
+        .into_iter()
+        .map(|(_, text)| {
+            let mut location = text.splitn(3, ' ');
+            let line = location.next().unwrap().parse().unwrap();
+            let column = location.next().unwrap().parse().unwrap();
+            (line, column, location.next().unwrap().to_string())
+        })
+        .collect();
+    // Rules are reported on entry, before the tokens inside them.
+    constructs.sort_by_key(|(line, column, _)| (*line, *column));
+    constructs
+        .into_iter()
+        .map(|(line, column, construct)| format!("{}:{}: {}", line, column, construct))
+        .collect()
+}
+
+// A use of a name and the declaration it refers to, both as (line, column).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub location: (usize, usize),
+    pub declaration: (usize, usize),
+}
+
+// Resolve every use of a variable, a parameter, or a function in `source` to
+// its declaration, in source order. Names declared nowhere are left out.
+pub fn resolve_names(source: &str) -> Vec<Reference> {
+    let events = run(Entry::ResolveNames, source).unwrap_or_else(|error| panic!("{}", error));
+    let site = |text: &str| {
+        let mut site = text.splitn(3, ' ');
+        let line = site.next().unwrap().parse().unwrap();
+        let column = site.next().unwrap().parse().unwrap();
+        (site.next().unwrap().to_string(), (line, column))
+    };
+    // Functions may be called before they are defined.
+    let functions: HashMap<_, _> = events
+        .iter()
+        .filter(|(tag, _)| tag == "Function")
+        .map(|(_, text)| site(text))
+        .collect();
+    let mut scopes = vec![HashMap::new()];
+    let mut references = Vec::new();
+    for (tag, text) in &events {
+        match tag.as_str() {
+            "EnterScope" => scopes.push(HashMap::new()),
+            "ExitScope" => {
+                scopes.pop();
+            }
+            "Declare" => {
+                let (name, declaration) = site(text);
+                scopes.last_mut().unwrap().insert(name, declaration);
+            }
+            "Use" => {
+                let (name, location) = site(text);
+                let declaration = scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(&name))
+                    .or_else(|| functions.get(&name));
+                if let Some(declaration) = declaration {
+                    references.push(Reference {
+                        name,
+                        location,
+                        declaration: *declaration,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    references
+}
+
+// The character range and the symbolic name of every token in `source`, in order.
+pub fn tokens(source: &str) -> Vec<(Range<usize>, String)> {
+    run(Entry::Lex, source)
+        .unwrap_or_else(|error| panic!("{}", error))
+        .into_iter()
+        .map(|(_, text)| {
+            let mut fields = text.splitn(3, ' ');
+            let start: usize = fields.next().unwrap().parse().unwrap();
+            let stop: usize = fields.next().unwrap().parse().unwrap();
+            (start..stop + 1, fields.next().unwrap_or("").to_string())
+        })
+        .collect()
+}
+
+// The entry points of the C++ parser, which all send their results as events.
+#[cfg_attr(not(native_parser), allow(dead_code))]
+enum Entry {
+    Parse,
+    CheckSubset,
+    ResolveNames,
+    Lex,
+}
+
+// The events `entry` sends for `source`. The C++ parser is only linked when
+// the `native-parser` feature is on and the build script found CMake to build
+// it, so `build.rs` sets `native_parser`. Otherwise every entry point fails
+// here instead of the build failing to link.
+#[cfg(native_parser)]
+fn run(entry: Entry, source: &str) -> Result<Events, Error> {
     let source = CString::new(source).unwrap().into_raw();
     unsafe {
-        EVENTS.clear();
-        CString::from_raw(_parse(source, rs_get_str, rs_emit_event));
+        (*ptr::addr_of_mut!(EVENTS)).clear();
+        let source = match entry {
+            Entry::Parse => _parse(source, rs_get_str, rs_emit_event),
+            Entry::CheckSubset => _check_subset(source, rs_get_str, rs_emit_event),
+            Entry::ResolveNames => _resolve_names(source, rs_get_str, rs_emit_event),
+            Entry::Lex => _lex(source, rs_get_str, rs_emit_event),
+        };
+        drop(CString::from_raw(source));
+        Ok(std::mem::take(&mut *ptr::addr_of_mut!(EVENTS)))
     }
-    build_ast()
 }
 
+#[cfg(not(native_parser))]
+fn run(_: Entry, _: &str) -> Result<Events, Error> {
+    Err(Error::Frontend(String::from("native parser unavailable")))
+}
+
+#[cfg(native_parser)]
 #[link(name = "parser")]
 extern "C" {
     fn _parse(
@@ -20,62 +291,222 @@ extern "C" {
         rs_get_str: extern "C" fn(size_t) -> *mut c_char,
         rs_emit_event: extern "C" fn(*mut c_char, *mut c_char),
     ) -> *mut c_char;
+    fn _check_subset(
+        path: *const c_char,
+        rs_get_str: extern "C" fn(size_t) -> *mut c_char,
+        rs_emit_event: extern "C" fn(*mut c_char, *mut c_char),
+    ) -> *mut c_char;
+    fn _resolve_names(
+        path: *const c_char,
+        rs_get_str: extern "C" fn(size_t) -> *mut c_char,
+        rs_emit_event: extern "C" fn(*mut c_char, *mut c_char),
+    ) -> *mut c_char;
+    fn _lex(
+        path: *const c_char,
+        rs_get_str: extern "C" fn(size_t) -> *mut c_char,
+        rs_emit_event: extern "C" fn(*mut c_char, *mut c_char),
+    ) -> *mut c_char;
 }
 
+#[cfg_attr(not(native_parser), allow(dead_code))]
 extern "C" fn rs_get_str(len: size_t) -> *mut c_char {
     CString::new(vec![1; len]).unwrap().into_raw()
 }
 
+#[cfg_attr(not(native_parser), allow(dead_code))]
 extern "C" fn rs_emit_event(tag: *mut c_char, text: *mut c_char) {
     unsafe {
-        let tag = CString::from_raw(tag).into_string().unwrap();
-        let text = CString::from_raw(text).into_string().unwrap();
-        EVENTS.push((tag, text));
+        // The text of a token is not necessarily valid UTF-8.
+        let tag = CString::from_raw(tag).to_string_lossy().into_owned();
+        let text = CString::from_raw(text).to_string_lossy().into_owned();
+        (*ptr::addr_of_mut!(EVENTS)).push((tag, text));
     }
 }
 
-fn build_ast() -> Program {
+fn build_ast(
+    path: &str,
+    assertions: &mut Vec<String>,
+    prototypes: &mut Vec<Prototype>,
+    lines: &mut Lines,
+) -> Result<Program, Error> {
     let mut program = Program::new();
     let mut expr_stack = Vec::new();
-    let mut stmt_stack = Vec::new();
+    // Every statement comes with the lines of its statements in pre-order,
+    // itself first, from the innermost statement being built when it was.
+    // Events recorded without them leave the line of the function instead.
+    let mut stmt_stack: Vec<(Statement, Vec<Option<usize>>)> = Vec::new();
+    let mut line_stack = Vec::new();
     let mut compound_stmt_ptr_stack = Vec::new();
+    // A struct or union variable is an array of words, and each of its
+    // members is an element at a constant index. An enumerator is replaced
+    // by its value. The innermost scope comes last.
+    let mut layouts = Layouts::new();
+    let mut scopes: Vec<HashMap<String, Binding>> = vec![HashMap::new()];
+    let mut struct_type: Option<String> = None;
+    // The literal `9223372036854775808`, which only fits in an `Int` when it
+    // is negated right away, as in `-9223372036854775808`.
+    let mut minimum: Option<&str> = None;
+    let mut next_enumerator = 0;
+    // Every variable declared so far in the current function, and whether it
+    // is a `double`.
+    let mut types = HashMap::new();
     unsafe {
-        for (tag, text) in &EVENTS {
+        for (tag, text) in &*ptr::addr_of!(EVENTS) {
+            // The struct of the expression just built, which only member
+            // access and `&` may use.
+            let operand_type = struct_type.take();
+            let line = line_stack.last().copied();
+            let negated = tag == "ExitUnaryExpression" && text == "-";
+            let literal = minimum.take();
+            if let (Some(literal), false) = (literal, negated) {
+                let message = format!("Integer constant `{}` is too large.", literal);
+                return Err(unsupported(line, message));
+            }
+            let member = tag == "ExitPostfixExpression" && text.starts_with('.');
+            let address = tag == "ExitUnaryExpression" && text == "&";
+            if let (Some(name), false, false) = (&operand_type, member, address) {
+                let message = format!("A `{}` can only be used through its members.", name);
+                return Err(unsupported(line, message));
+            }
             match tag.as_str() {
                 "ExitPrimaryExpression" => {
-                    let expr = match text.parse::<i32>() {
+                    let expr = match text.parse::<Int>() {
                         Ok(num) => Expression::Number(num),
-                        Err(_) => Expression::Identifier(SSAVar::new(text)),
+                        Err(_) if text.contains(['.', 'e', 'E']) && !is_name(text) => {
+                            Expression::Float(
+                                text.parse::<f64>()
+                                    .map_err(|_| malformed(tag, text))?
+                                    .to_bits(),
+                            )
+                        }
+                        Err(_) if text.bytes().all(|c| c.is_ascii_digit()) => {
+                            if text.parse::<u64>().ok() != Some(Int::MIN.unsigned_abs()) {
+                                let message = format!("Integer constant `{}` is too large.", text);
+                                return Err(unsupported(line, message));
+                            }
+                            minimum = Some(text);
+                            Expression::Number(Int::MIN)
+                        }
+                        Err(_) if text.starts_with(|c: char| c.is_ascii_digit()) => {
+                            let message = format!("Integer constant `{}` is not supported.", text);
+                            return Err(unsupported(line, message));
+                        }
+                        Err(_) => match scopes.iter().rev().find_map(|scope| scope.get(text)) {
+                            Some(Binding::Constant(value)) => Expression::Number(*value),
+                            Some(Binding::Struct(name)) => {
+                                struct_type = Some(name.clone());
+                                Expression::Identifier(SSAVar::new(text))
+                            }
+                            _ => Expression::Identifier(SSAVar::new(text)),
+                        },
+                    };
+                    expr_stack.push(expr);
+                }
+                "ExitPostfixExpression" if member => {
+                    let name = match operand_type {
+                        Some(name) => name,
+                        None => panic!("Only structs and unions have members like `{}`.", text),
+                    };
+                    let (offset, ty) = layouts.member(&name, &text[1..]);
+                    let offset = (offset / Layouts::WORD_SIZE) as Int;
+                    // `a.b.c` moves further into the words of `a`.
+                    let expr = match pop(&mut expr_stack, tag)? {
+                        Expression::Identifier(array) => Expression::Index {
+                            array,
+                            indices: vec![Expression::Number(offset)],
+                        },
+                        Expression::Index { array, indices } => match indices[..] {
+                            [Expression::Number(start)] => Expression::Index {
+                                array,
+                                indices: vec![Expression::Number(start + offset)],
+                            },
+                            _ => unreachable!(),
+                        },
+                        _ => unreachable!(),
+                    };
+                    if let MemberType::Struct(inner) = ty {
+                        struct_type = Some(inner.clone());
+                    }
+                    expr_stack.push(expr);
+                }
+                "ExitPostfixExpression" if text == "[]" => {
+                    // `m[i][j]` indexes the result of `m[i]`.
+                    let index = pop(&mut expr_stack, tag)?;
+                    let expr = match pop(&mut expr_stack, tag)? {
+                        Expression::Identifier(array) => Expression::Index {
+                            array,
+                            indices: vec![index],
+                        },
+                        Expression::Index { array, mut indices } => {
+                            indices.push(index);
+                            Expression::Index { array, indices }
+                        }
+                        _ => panic!("Only arrays may be indexed."),
                     };
                     expr_stack.push(expr);
                 }
                 "ExitPostfixExpression" => {
                     let args = match expr_stack.last() {
-                        Some(Expression::Arguments(_)) => expr_stack.pop().unwrap(),
+                        Some(Expression::Arguments(_)) => pop(&mut expr_stack, tag)?,
                         _ => Expression::Arguments(Vec::new()),
                     };
-                    let func = expr_stack.pop().unwrap();
+                    let func = pop(&mut expr_stack, tag)?;
                     let call = Expression::Call {
                         function: Box::new(func),
                         arguments: Box::new(args),
                     };
                     expr_stack.push(call);
                 }
+                "ExitAssertion" => {
+                    let condition = match pop(&mut expr_stack, tag)? {
+                        Expression::Arguments(args) if args.len() == 1 => args,
+                        _ => panic!("`assert` takes exactly one argument."),
+                    };
+                    pop(&mut expr_stack, tag)?;
+                    let (line, text) = text.split_once(' ').ok_or_else(|| malformed(tag, text))?;
+                    let text: Vec<_> = text.split_whitespace().collect();
+                    let name = format!("eac_assert_{}", assertions.len());
+                    assertions.push(format!(
+                        "{}:{}: assertion `{}` failed",
+                        path,
+                        line,
+                        text.join(" ")
+                    ));
+                    let call = Expression::Call {
+                        function: Box::new(Expression::Identifier(SSAVar::new(&name))),
+                        arguments: Box::new(Expression::Arguments(condition)),
+                    };
+                    expr_stack.push(call);
+                }
                 "ExitArgumentExpressionList" => {
-                    let arg = expr_stack.pop().unwrap();
+                    let arg = pop(&mut expr_stack, tag)?;
                     let args = match expr_stack.last_mut() {
                         Some(Expression::Arguments(args)) => {
                             args.push(arg);
-                            expr_stack.pop().unwrap()
+                            pop(&mut expr_stack, tag)?
                         }
                         _ => Expression::Arguments(vec![arg]),
                     };
                     expr_stack.push(args);
                 }
+                // The literal is already negated.
+                "ExitUnaryExpression" if literal.is_some() => {}
                 "ExitUnaryExpression" => {
-                    let expr = expr_stack.pop().unwrap();
+                    let mut expr = pop(&mut expr_stack, tag)?;
+                    // A struct starts at its first word.
+                    if let (Some(_), Expression::Identifier(array)) = (&operand_type, &expr) {
+                        expr = Expression::Index {
+                            array: array.clone(),
+                            indices: vec![Expression::Number(0)],
+                        };
+                    }
+                    if text == "&"
+                        && !matches!(expr, Expression::Identifier(_) | Expression::Index { .. })
+                    {
+                        panic!("Only variables and array elements have an address.");
+                    }
                     let expr = Expression::Prefix {
-                        operator: text,
+                        operator: operator(text),
                         expression: Box::new(expr),
                     };
                     expr_stack.push(expr);
@@ -87,44 +518,241 @@ fn build_ast() -> Program {
                 | "ExitLogicalAndExpression"
                 | "ExitLogicalOrExpression"
                 | "ExitAssignmentExpression" => {
-                    let right = expr_stack.pop().unwrap();
-                    let left = expr_stack.pop().unwrap();
+                    let right = pop(&mut expr_stack, tag)?;
+                    let left = pop(&mut expr_stack, tag)?;
+                    // Enumerators are constants by now.
+                    let assignable = matches!(
+                        left,
+                        Expression::Identifier(_)
+                            | Expression::Index { .. }
+                            | Expression::Prefix { operator: "*", .. }
+                    );
+                    if text == "=" && !assignable {
+                        panic!(
+                            "Only variables, array elements, and dereferences can be assigned to."
+                        );
+                    }
                     let expr = Expression::Infix {
                         left: Box::new(left),
-                        operator: text,
+                        operator: operator(text),
                         right: Box::new(right),
                     };
                     expr_stack.push(expr);
                 }
+                "ExitConditionalExpression" => {
+                    let alternative = pop(&mut expr_stack, tag)?;
+                    let consequent = pop(&mut expr_stack, tag)?;
+                    let condition = pop(&mut expr_stack, tag)?;
+                    let expr = Expression::Conditional {
+                        condition: Box::new(condition),
+                        consequent: Box::new(consequent),
+                        alternative: Box::new(alternative),
+                    };
+                    expr_stack.push(expr);
+                }
+                "StructDefinition" => {
+                    let mut words = text.split(' ');
+                    let union = words.next() == Some("union");
+                    let name = match words.next() {
+                        Some(name) if !name.is_empty() => name.to_string(),
+                        _ => panic!("Anonymous structs and unions are not supported."),
+                    };
+                    let members = words
+                        .map(|member| {
+                            if let Some(member) = member.strip_prefix('!') {
+                                panic!(
+                                    "Member `{}` of struct `{}` is not supported; members are `int`s, pointers, structs, and unions.",
+                                    member, name
+                                );
+                            }
+                            match member.split_once(':') {
+                                Some((member, ty)) => {
+                                    (member.to_string(), MemberType::Struct(ty.to_string()))
+                                }
+                                None => (member.to_string(), MemberType::Scalar),
+                            }
+                        })
+                        .collect();
+                    layouts.define(Struct {
+                        name,
+                        union,
+                        members,
+                    });
+                }
+                "EnterEnumSpecifier" => next_enumerator = 0,
+                "ExitEnumerator" => {
+                    // An enumerator without a value follows the previous one.
+                    let value = match text.strip_suffix(" =") {
+                        Some(name) => match constant(&pop(&mut expr_stack, tag)?) {
+                            Some(value) => value,
+                            None => panic!(
+                                "The value of enumerator `{}` must be an integer constant.",
+                                name
+                            ),
+                        },
+                        None => next_enumerator,
+                    };
+                    let name = text.trim_end_matches(" =").to_string();
+                    scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert(name, Binding::Constant(value));
+                    next_enumerator = value + 1;
+                }
                 "ExitDeclaration" => {
-                    let stmt = Statement::Declaration(SSAVar::new(text));
-                    stmt_stack.push(stmt);
+                    // `int a = expr, b;` is `int a; a = expr; int b;`. The sizes
+                    // of arrays and the initializers are on the stack in order.
+                    // A pointer is declared like an `int`.
+                    let (ty, list) = text.split_once(':').ok_or_else(|| malformed(tag, text))?;
+                    let declarators: Vec<_> = list
+                        .split(',')
+                        .filter(|declarator| !declarator.is_empty())
+                        .map(|declarator| match declarator.strip_prefix('*') {
+                            Some(declarator) => (false, declarator),
+                            None => (!matches!(ty, "int" | "double"), declarator),
+                        })
+                        .collect();
+                    if ty == "double" {
+                        for declarator in list.split(',') {
+                            let name = declarator.trim_end_matches(" =");
+                            if let Some(name) = name.strip_prefix('*') {
+                                panic!("Pointer `{}` to `double` is not supported.", name);
+                            }
+                            if name.ends_with("[]") {
+                                panic!(
+                                    "Array `{}` of `double` is not supported.",
+                                    name.trim_end_matches("[]")
+                                );
+                            }
+                        }
+                    }
+                    if let Some(name) = ty.strip_prefix("struct ") {
+                        if name == "!" {
+                            panic!("Anonymous structs and unions are not supported.");
+                        }
+                        for (is_struct, declarator) in &declarators {
+                            if !is_struct {
+                                continue;
+                            }
+                            if declarator.ends_with(" =") {
+                                panic!(
+                                    "Struct or union `{}` cannot have an initializer.",
+                                    declarator.trim_end_matches(" =").trim_end_matches("[]")
+                                );
+                            }
+                            if declarator.ends_with("[]") {
+                                panic!("Arrays of structs and unions are not supported.");
+                            }
+                        }
+                    }
+                    for (_, declarator) in &declarators {
+                        if let Some(declarator) = declarator.strip_prefix('!') {
+                            panic!(
+                                "Unsupported declarator `{}`; every dimension of an array needs a size.",
+                                declarator
+                            );
+                        }
+                        if let Some(name) = declarator.strip_suffix("[] =") {
+                            panic!("Array `{}` cannot have an initializer.", name);
+                        }
+                    }
+                    let operands: usize = declarators
+                        .iter()
+                        .map(|(_, d)| d.matches("[]").count() + d.ends_with(" =") as usize)
+                        .sum();
+                    if operands > expr_stack.len() {
+                        return Err(underflow(tag));
+                    }
+                    let mut operands = expr_stack
+                        .split_off(expr_stack.len() - operands)
+                        .into_iter();
+                    for (is_struct, declarator) in declarators {
+                        let name = declarator.trim_end_matches(" =").trim_end_matches("[]");
+                        let scope = scopes.last_mut().unwrap();
+                        if is_struct {
+                            let ty = ty.trim_start_matches("struct ");
+                            let words = layouts.size(ty) / Layouts::WORD_SIZE;
+                            scope.insert(name.to_string(), Binding::Struct(ty.to_string()));
+                            let stmt = Statement::ArrayDeclaration(SSAVar::new(name), vec![words]);
+                            stmt_stack.push((stmt, vec![line]));
+                            continue;
+                        }
+                        scope.insert(name.to_string(), Binding::Variable);
+                        declare(&mut types, name, ty == "double");
+                        if declarator.ends_with("[]") {
+                            let name = declarator.trim_end_matches("[]");
+                            let dims = declarator
+                                .matches("[]")
+                                .map(|_| match operands.next().unwrap() {
+                                    Expression::Number(len) if len > 0 => len as usize,
+                                    _ => panic!(
+                                        "The size of array `{}` must be a positive integer constant.",
+                                        name
+                                    ),
+                                })
+                                .collect();
+                            let stmt = Statement::ArrayDeclaration(SSAVar::new(name), dims);
+                            stmt_stack.push((stmt, vec![line]));
+                            continue;
+                        }
+                        match declarator.strip_suffix(" =") {
+                            Some(name) => {
+                                let var = SSAVar::new(name);
+                                stmt_stack.push((Statement::Declaration(var.clone()), vec![line]));
+                                let expr = Expression::Infix {
+                                    left: Box::new(Expression::Identifier(var)),
+                                    operator: "=",
+                                    right: Box::new(operands.next().unwrap()),
+                                };
+                                stmt_stack.push((Statement::Expression(expr), vec![line]));
+                            }
+                            None => {
+                                let stmt = Statement::Declaration(SSAVar::new(declarator));
+                                stmt_stack.push((stmt, vec![line]));
+                            }
+                        }
+                    }
+                }
+                "EnterStatement" => {
+                    line_stack.push(text.parse().map_err(|_| malformed(tag, text))?);
+                }
+                "ExitStatement" => {
+                    pop(&mut line_stack, tag)?;
                 }
                 "EnterCompoundStatement" => {
                     compound_stmt_ptr_stack.push(stmt_stack.len());
+                    scopes.push(HashMap::new());
                 }
                 "ExitCompoundStatement" => {
-                    let compound_stmt_ptr = compound_stmt_ptr_stack.pop().unwrap();
+                    let compound_stmt_ptr = pop(&mut compound_stmt_ptr_stack, tag)?;
+                    scopes.pop();
+                    if compound_stmt_ptr > stmt_stack.len() {
+                        return Err(underflow(tag));
+                    }
+                    let mut lines = vec![line];
                     let mut stmts = Vec::new();
-                    while stmt_stack.len() != compound_stmt_ptr {
-                        stmts.push(stmt_stack.pop().unwrap());
+                    for (stmt, stmt_lines) in stmt_stack.split_off(compound_stmt_ptr) {
+                        stmts.push(stmt);
+                        lines.extend(stmt_lines);
                     }
-                    stmts.reverse();
-                    let stmt = Statement::Compound(stmts);
-                    stmt_stack.push(stmt);
+                    stmt_stack.push((Statement::Compound(stmts), lines));
                 }
                 "ExitExpressionStatement" => {
-                    let expr = expr_stack.pop().unwrap();
-                    let stmt = Statement::Expression(expr);
-                    stmt_stack.push(stmt);
+                    let expr = pop(&mut expr_stack, tag)?;
+                    stmt_stack.push((Statement::Expression(expr), vec![line]));
                 }
                 "ExitSelectionStatement" => {
-                    let condition = expr_stack.pop().unwrap();
+                    let condition = pop(&mut expr_stack, tag)?;
+                    let mut lines = vec![line];
                     let (body, alternative) = if text.is_empty() {
-                        (stmt_stack.pop().unwrap(), None)
+                        let (body, body_lines) = pop(&mut stmt_stack, tag)?;
+                        lines.extend(body_lines);
+                        (body, None)
                     } else {
-                        let alternative = stmt_stack.pop().unwrap();
-                        let body = stmt_stack.pop().unwrap();
+                        let (alternative, alternative_lines) = pop(&mut stmt_stack, tag)?;
+                        let (body, body_lines) = pop(&mut stmt_stack, tag)?;
+                        lines.extend(body_lines);
+                        lines.extend(alternative_lines);
                         (body, Some(Box::new(alternative)))
                     };
                     let stmt = Statement::If {
@@ -132,47 +760,324 @@ fn build_ast() -> Program {
                         body: Box::new(body),
                         alternative,
                     };
-                    stmt_stack.push(stmt);
+                    stmt_stack.push((stmt, lines));
                 }
                 "ExitIterationStatement" => {
+                    let (body, body_lines) = pop(&mut stmt_stack, tag)?;
                     let stmt = Statement::While {
-                        condition: expr_stack.pop().unwrap(),
-                        body: Box::new(stmt_stack.pop().unwrap()),
+                        condition: pop(&mut expr_stack, tag)?,
+                        body: Box::new(body),
                     };
-                    stmt_stack.push(stmt);
+                    stmt_stack.push((stmt, [vec![line], body_lines].concat()));
+                }
+                "ExitDoStatement" => {
+                    let (body, body_lines) = pop(&mut stmt_stack, tag)?;
+                    let stmt = Statement::DoWhile {
+                        body: Box::new(body),
+                        condition: pop(&mut expr_stack, tag)?,
+                    };
+                    stmt_stack.push((stmt, [vec![line], body_lines].concat()));
+                }
+                "ExitForStatement" => {
+                    // `for (init; cond; step) body` runs as
+                    // `init; while (cond) { body step; }`.
+                    let clauses: Vec<_> = text.split(' ').collect();
+                    if clauses.contains(&"decl") {
+                        return Err(Error::Frontend(String::from(
+                            "Declarations in `for` are not supported.",
+                        )));
+                    }
+                    let mut pop_clause = |clause| match clauses.contains(&clause) {
+                        true => pop(&mut expr_stack, tag).map(Some),
+                        false => Ok(None),
+                    };
+                    let step = pop_clause("step")?;
+                    let condition = pop_clause("cond")?.unwrap_or(Expression::Number(1));
+                    let init = pop_clause("init")?;
+                    let (mut body, mut body_lines) = pop(&mut stmt_stack, tag)?;
+                    if let Some(step) = &step {
+                        step_continues(&mut body, step, &mut body_lines, &mut 0, line);
+                    }
+                    // Everything the `for` adds is at its own line.
+                    let mut lines = vec![line; 1 + init.is_some() as usize + 2];
+                    lines.extend(body_lines);
+                    lines.extend(step.as_ref().map(|_| line));
+                    let mut body = vec![body];
+                    body.extend(step.map(Statement::Expression));
+                    let mut stmts: Vec<_> = init.map(Statement::Expression).into_iter().collect();
+                    stmts.push(Statement::While {
+                        condition,
+                        body: Box::new(Statement::Compound(body)),
+                    });
+                    stmt_stack.push((Statement::Compound(stmts), lines));
                 }
                 "ExitJumpStatement" => {
-                    let expr = match text.is_empty() {
-                        true => None,
-                        false => Some(expr_stack.pop().unwrap()),
+                    let stmt = match text.as_str() {
+                        "break" => Statement::Break,
+                        "continue" => Statement::Continue,
+                        "" => Statement::Return(None),
+                        _ => Statement::Return(Some(pop(&mut expr_stack, tag)?)),
                     };
-                    let stmt = Statement::Return(expr);
-                    stmt_stack.push(stmt);
+                    stmt_stack.push((stmt, vec![line]));
                 }
                 "ExitFunctionDefinition" => {
                     let mut sig = text.split(' ');
-                    let void = matches!(sig.next().unwrap(), "void");
-                    let name = sig.next().unwrap().to_string();
-                    let parameters = sig.map(SSAVar::new).rev().collect();
-                    let body = stmt_stack.pop().unwrap();
+                    let line = sig.next().and_then(|line| line.parse().ok());
+                    let (line, ret, name) = match (line, sig.next(), sig.next()) {
+                        (Some(line), Some(ret), Some(name)) => (line, ret, name.to_string()),
+                        _ => return Err(malformed(tag, text)),
+                    };
+                    let void = matches!(ret, "void");
+                    let double = matches!(ret, "double");
+                    if ret == "struct" {
+                        panic!(
+                            "Function `{}` cannot return a struct or union; return a pointer instead.",
+                            name
+                        );
+                    }
+                    let sig: Vec<_> = sig.collect();
+                    if sig.contains(&"...") {
+                        panic!(
+                            "Function `{}` cannot be defined with `...`; only a prototype may declare it.",
+                            name
+                        );
+                    }
+                    let parameters = sig
+                        .into_iter()
+                        .map(|param| match param.find('[') {
+                            Some(_) => panic!("Array parameter `{}` is not supported.", param),
+                            None => match param.strip_prefix('!') {
+                                Some(param) => panic!(
+                                    "Struct or union parameter `{}` is not supported; pass a pointer instead.",
+                                    param
+                                ),
+                                None => match param.strip_prefix("double:") {
+                                    Some(param) if param.starts_with('*') => panic!(
+                                        "Pointer `{}` to `double` is not supported.",
+                                        &param[1..]
+                                    ),
+                                    Some(param) => {
+                                        declare(&mut types, param, true);
+                                        SSAVar::new(param)
+                                    }
+                                    None => {
+                                        declare(&mut types, param, false);
+                                        SSAVar::new(param)
+                                    }
+                                },
+                            },
+                        })
+                        .rev()
+                        .collect();
+                    let doubles = types
+                        .drain()
+                        .filter(|(_, double)| *double)
+                        .map(|(name, _)| name)
+                        .collect();
+                    let (body, body_lines) = pop(&mut stmt_stack, tag)?;
+                    let body_lines = body_lines.into_iter().map(|l| l.unwrap_or(line));
+                    lines.insert(name.clone(), body_lines.collect());
                     let func = Function {
+                        line,
                         void,
+                        double,
                         name,
                         parameters,
+                        doubles,
                         body,
                     };
                     program.push(func);
                 }
-                s => panic!("Invalid event: {}", s),
+                "ExitPrototype" => {
+                    let mut sig = text.split(' ');
+                    let line = sig.next().and_then(|line| line.parse().ok());
+                    let (line, ret, name) = match (line, sig.next(), sig.next()) {
+                        (Some(line), Some(ret), Some(name)) => (line, ret, name.to_string()),
+                        _ => return Err(malformed(tag, text)),
+                    };
+                    if ret == "struct" {
+                        panic!(
+                            "Function `{}` cannot return a struct or union; return a pointer instead.",
+                            name
+                        );
+                    }
+                    let mut types: Vec<_> = sig.rev().collect();
+                    let variadic = types.last() == Some(&"...");
+                    if variadic {
+                        types.pop();
+                    }
+                    // `(void)` declares no parameters.
+                    if types == ["void"] {
+                        types.clear();
+                    }
+                    let parameters = types
+                        .into_iter()
+                        .map(|ty| match ty {
+                            "int" => false,
+                            "double" => true,
+                            "double*" => panic!(
+                                "Pointer parameter of `{}` to `double` is not supported.",
+                                name
+                            ),
+                            "struct" => panic!(
+                                "Struct or union parameter of `{}` is not supported; pass a pointer instead.",
+                                name
+                            ),
+                            "[" => panic!("Array parameter of `{}` is not supported.", name),
+                            "..." => panic!("`...` must be the last parameter of `{}`.", name),
+                            _ => panic!("`void` must be the only parameter of `{}`.", name),
+                        })
+                        .collect();
+                    prototypes.push(Prototype {
+                        line,
+                        void: ret == "void",
+                        double: ret == "double",
+                        name,
+                        parameters,
+                        variadic,
+                    });
+                }
+                // Sent alone, as the listener does not walk a tree with syntax
+                // errors, which the C++ parser has already printed.
+                "SyntaxError" => panic!("`{}` has {} syntax error(s).", path, text),
+                _ => return Err(Error::Parsing(format!("Unknown event `{}`.", tag))),
             }
         }
     }
-    program
+    if !compound_stmt_ptr_stack.is_empty() {
+        return Err(Error::Parsing(String::from(
+            "The events end inside a compound statement.",
+        )));
+    }
+    Ok(program)
+}
+
+// Pop the operand of the event `tag`, which a malformed event stream may not
+// have pushed.
+fn pop<T>(stack: &mut Vec<T>, tag: &str) -> Result<T, Error> {
+    stack.pop().ok_or_else(|| underflow(tag))
+}
+
+fn underflow(tag: &str) -> Error {
+    Error::Parsing(format!("Event `{}` is missing its operands.", tag))
+}
+
+fn malformed(tag: &str, text: &str) -> Error {
+    Error::Parsing(format!("Event `{}` has malformed text `{}`.", tag, text))
+}
+
+// A construct the front end does not support, on `line` if the events tell it.
+fn unsupported(line: Option<usize>, message: String) -> Error {
+    match line {
+        Some(line) => Error::Frontend(format!("line {}: {}", line, message)),
+        None => Error::Frontend(message),
+    }
+}
+
+// Record that `name` is declared in the current function, as a `double` or
+// not. Variables that shadow each other share their type.
+fn declare(types: &mut HashMap<String, bool>, name: &str, double: bool) {
+    if types.insert(name.to_string(), double) == Some(!double) {
+        panic!(
+            "`{}` is declared both as a `double` and as another type; rename one of them.",
+            name
+        );
+    }
+}
+
+// The operator spelled `text`, which outlives the events it came from, as
+// the next call into the parser or the lexer clears them.
+fn operator(text: &str) -> &'static str {
+    const OPERATORS: [&str; 16] = [
+        "=", "||", "&&", "==", "!=", "<", ">", "<=", ">=", "+", "-", "*", "/", "%", "!", "&",
+    ];
+    match OPERATORS.iter().find(|operator| **operator == text) {
+        Some(operator) => operator,
+        None => panic!("Unsupported operator `{}`.", text),
+    }
+}
+
+// Whether `text` is an identifier rather than a constant.
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+// What a name in scope stands for.
+enum Binding {
+    Variable,       // An `int`, a `double`, a pointer, or an array.
+    Struct(String), // A struct or union, with the name of its type.
+    Constant(Int),  // An enumerator.
+}
+
+// The value of an integer constant expression, if `expr` is one.
+fn constant(expr: &Expression) -> Option<Int> {
+    match expr {
+        Expression::Number(num) => Some(*num),
+        Expression::Prefix {
+            operator: "-",
+            expression,
+        } => constant(expression)?.checked_neg(),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            match *operator {
+                "+" => left.checked_add(right),
+                "-" => left.checked_sub(right),
+                "*" => left.checked_mul(right),
+                "/" => left.checked_div(right),
+                "%" => left.checked_rem(right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// A `continue` in a `for` body must still run the step, so it runs it right
+// before jumping. Loops nested in the body have their own `continue`. The
+// lines of the body follow along from `index`, where `stmt` is, and the step
+// is at `line`, that of the `for`.
+fn step_continues(
+    stmt: &mut Statement,
+    step: &Expression,
+    lines: &mut Vec<Option<usize>>,
+    index: &mut usize,
+    line: Option<usize>,
+) {
+    match stmt {
+        Statement::Continue => {
+            let stmts = vec![Statement::Expression(step.clone()), Statement::Continue];
+            *stmt = Statement::Compound(stmts);
+            let continue_line = lines[*index];
+            lines.splice(*index + 1..*index + 1, [line, continue_line]);
+            *index += 3;
+        }
+        Statement::Compound(stmts) => {
+            *index += 1;
+            for stmt in stmts {
+                step_continues(stmt, step, lines, index, line);
+            }
+        }
+        Statement::If {
+            body, alternative, ..
+        } => {
+            *index += 1;
+            step_continues(body, step, lines, index, line);
+            if let Some(alt) = alternative {
+                step_continues(alt, step, lines, index, line);
+            }
+        }
+        stmt => *index += lines::span(stmt),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     #[test]
     fn expression_identifier() {
@@ -184,9 +1089,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Identifier(
                 SSAVar::new("a"),
             ))]),
@@ -200,18 +1108,74 @@ mod tests {
             "
             int main() {
                 1;
+                4294967296;
+                -2147483648;
+                -9223372036854775808;
             }
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
-            body: Statement::Compound(vec![Statement::Expression(Expression::Number(1))]),
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::Expression(Expression::Number(1)),
+                Statement::Expression(Expression::Number(4294967296)),
+                Statement::Expression(Expression::Prefix {
+                    operator: "-",
+                    expression: Box::new(Expression::Number(2147483648)),
+                }),
+                Statement::Expression(Expression::Number(Int::MIN)),
+            ]),
         }];
         assert_eq!(ast, expected);
     }
 
+    #[test]
+    fn expression_number_overflow() {
+        let source = "
+            int main() {
+                9223372036854775808;
+            }
+        ";
+        assert_eq!(
+            try_parse_unit(source, "main.c", &mut Vec::new(), &mut Vec::new()),
+            Err(Error::Frontend(String::from(
+                "line 3: Integer constant `9223372036854775808` is too large."
+            )))
+        );
+        // Only a negation, not a subtraction, brings it in range.
+        let source = "
+            int main() {
+                1 - 9223372036854775808;
+            }
+        ";
+        assert_eq!(
+            try_parse_unit(source, "main.c", &mut Vec::new(), &mut Vec::new()),
+            Err(Error::Frontend(String::from(
+                "line 3: Integer constant `9223372036854775808` is too large."
+            )))
+        );
+    }
+
+    #[test]
+    fn expression_number_unsupported() {
+        let source = "
+            int main() {
+                0x10;
+            }
+        ";
+        assert_eq!(
+            try_parse_unit(source, "main.c", &mut Vec::new(), &mut Vec::new()),
+            Err(Error::Frontend(String::from(
+                "line 3: Integer constant `0x10` is not supported."
+            )))
+        );
+    }
+
     #[test]
     fn expression_call() {
         let ast = parse(
@@ -224,9 +1188,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![
                 Statement::Expression(Expression::Call {
                     function: Box::new(Expression::Identifier(SSAVar::new("f_1"))),
@@ -248,26 +1215,73 @@ mod tests {
         assert_eq!(ast, expected);
     }
 
+    #[test]
+    fn expression_assertion() {
+        let mut assertions = vec![String::from("main.c:1: assertion `1` failed")];
+        let ast = parse_unit(
+            "
+            int main() {
+                assert(a <
+                    1);
+            }
+        ",
+            "fib.c",
+            &mut assertions,
+            &mut Vec::new(),
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![Statement::Expression(Expression::Call {
+                function: Box::new(Expression::Identifier(SSAVar::new("eac_assert_1"))),
+                arguments: Box::new(Expression::Arguments(vec![Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("a"))),
+                    operator: "<",
+                    right: Box::new(Expression::Number(1)),
+                }])),
+            })]),
+        }];
+        assert_eq!(ast, expected);
+        assert_eq!(assertions[1], "fib.c:3: assertion `a < 1` failed");
+    }
+
     #[test]
     fn expression_prefix() {
         let ast = parse(
             "
             int main() {
                 !-1;
+                *&a;
             }
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
-            body: Statement::Compound(vec![Statement::Expression(Expression::Prefix {
-                operator: "!",
-                expression: Box::new(Expression::Prefix {
-                    operator: "-",
-                    expression: Box::new(Expression::Number(1)),
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::Expression(Expression::Prefix {
+                    operator: "!",
+                    expression: Box::new(Expression::Prefix {
+                        operator: "-",
+                        expression: Box::new(Expression::Number(1)),
+                    }),
                 }),
-            })]),
+                Statement::Expression(Expression::Prefix {
+                    operator: "*",
+                    expression: Box::new(Expression::Prefix {
+                        operator: "&",
+                        expression: Box::new(Expression::Identifier(SSAVar::new("a"))),
+                    }),
+                }),
+            ]),
         }];
         assert_eq!(ast, expected);
     }
@@ -277,22 +1291,29 @@ mod tests {
         let ast = parse(
             "
             int main() {
-                1 * 2 / 3;
+                1 * 2 / 3 % 4;
             }
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Infix {
-                    left: Box::new(Expression::Number(1)),
-                    operator: "*",
-                    right: Box::new(Expression::Number(2)),
+                    left: Box::new(Expression::Infix {
+                        left: Box::new(Expression::Number(1)),
+                        operator: "*",
+                        right: Box::new(Expression::Number(2)),
+                    }),
+                    operator: "/",
+                    right: Box::new(Expression::Number(3)),
                 }),
-                operator: "/",
-                right: Box::new(Expression::Number(3)),
+                operator: "%",
+                right: Box::new(Expression::Number(4)),
             })]),
         }];
         assert_eq!(ast, expected);
@@ -308,9 +1329,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Infix {
                     left: Box::new(Expression::Number(1)),
@@ -334,9 +1358,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Infix {
                     left: Box::new(Expression::Infix {
@@ -368,9 +1395,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Infix {
                     left: Box::new(Expression::Number(1)),
@@ -394,9 +1424,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Number(1)),
                 operator: "&&",
@@ -416,9 +1449,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Number(1)),
                 operator: "||",
@@ -438,9 +1474,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Identifier(SSAVar::new("a"))),
                 operator: "=",
@@ -450,6 +1489,247 @@ mod tests {
         assert_eq!(ast, expected);
     }
 
+    #[test]
+    fn expression_assign_chain() {
+        let ast = parse(
+            "
+            int main() {
+                a = b = c = 0;
+            }
+        ",
+        );
+        let var = |name| Box::new(Expression::Identifier(SSAVar::new(name)));
+        let assign = |left, right| Expression::Infix {
+            left,
+            operator: "=",
+            right: Box::new(right),
+        };
+        let chain = assign(
+            var("a"),
+            assign(var("b"), assign(var("c"), Expression::Number(0))),
+        );
+        assert_eq!(
+            ast[0].body,
+            Statement::Compound(vec![Statement::Expression(chain)])
+        );
+    }
+
+    #[test]
+    fn expression_index() {
+        let ast = parse(
+            "
+            int main() {
+                int a[2], b = 1, m[3][4];
+                a[b] = m[0][b];
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::ArrayDeclaration(SSAVar::new("a"), vec![2]),
+                Statement::Declaration(SSAVar::new("b")),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("b"))),
+                    operator: "=",
+                    right: Box::new(Expression::Number(1)),
+                }),
+                Statement::ArrayDeclaration(SSAVar::new("m"), vec![3, 4]),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Index {
+                        array: SSAVar::new("a"),
+                        indices: vec![Expression::Identifier(SSAVar::new("b"))],
+                    }),
+                    operator: "=",
+                    right: Box::new(Expression::Index {
+                        array: SSAVar::new("m"),
+                        indices: vec![
+                            Expression::Number(0),
+                            Expression::Identifier(SSAVar::new("b")),
+                        ],
+                    }),
+                }),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn expression_member() {
+        let ast = parse(
+            "
+            struct point { int x, y; };
+            struct line { struct point from; struct point to; int *tag; };
+            int main() {
+                struct line l;
+                struct point *p = &l.to;
+                l.to.y = l.tag;
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 4,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::ArrayDeclaration(SSAVar::new("l"), vec![5]),
+                Statement::Declaration(SSAVar::new("p")),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("p"))),
+                    operator: "=",
+                    right: Box::new(Expression::Prefix {
+                        operator: "&",
+                        expression: Box::new(Expression::Index {
+                            array: SSAVar::new("l"),
+                            indices: vec![Expression::Number(2)],
+                        }),
+                    }),
+                }),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Index {
+                        array: SSAVar::new("l"),
+                        indices: vec![Expression::Number(3)],
+                    }),
+                    operator: "=",
+                    right: Box::new(Expression::Index {
+                        array: SSAVar::new("l"),
+                        indices: vec![Expression::Number(4)],
+                    }),
+                }),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn expression_union() {
+        let ast = parse(
+            "
+            struct pair { int a, b; };
+            union value { int n; struct pair p; };
+            int main() {
+                union value v;
+                return v.p.b + v.n;
+            }
+        ",
+        );
+        let member = |index| {
+            Box::new(Expression::Index {
+                array: SSAVar::new("v"),
+                indices: vec![Expression::Number(index)],
+            })
+        };
+        let expected = vec![Function {
+            line: 4,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::ArrayDeclaration(SSAVar::new("v"), vec![2]),
+                Statement::Return(Some(Expression::Infix {
+                    left: member(1),
+                    operator: "+",
+                    right: member(0),
+                })),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+        let source = "
+            union value { int n; int m; };
+            int main() {
+                union value v;
+                return v;
+            }
+        ";
+        assert_eq!(
+            try_parse_unit(source, "main.c", &mut Vec::new(), &mut Vec::new()),
+            Err(Error::Frontend(String::from(
+                "line 5: A `value` can only be used through its members."
+            )))
+        );
+    }
+
+    #[test]
+    fn expression_enum() {
+        let ast = parse(
+            "
+            enum color { RED, GREEN = 5, BLUE, LAST = BLUE * 2 };
+            int main() {
+                int a[LAST];
+                {
+                    int BLUE;
+                    BLUE = GREEN;
+                }
+                return BLUE;
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 3,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::ArrayDeclaration(SSAVar::new("a"), vec![12]),
+                Statement::Compound(vec![
+                    Statement::Declaration(SSAVar::new("BLUE")),
+                    Statement::Expression(Expression::Infix {
+                        left: Box::new(Expression::Identifier(SSAVar::new("BLUE"))),
+                        operator: "=",
+                        right: Box::new(Expression::Number(5)),
+                    }),
+                ]),
+                Statement::Return(Some(Expression::Number(6))),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn expression_conditional() {
+        let ast = parse(
+            "
+            int main() {
+                a = 1 ? 2 : 3 ? 4 : 5;
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Identifier(SSAVar::new("a"))),
+                operator: "=",
+                right: Box::new(Expression::Conditional {
+                    condition: Box::new(Expression::Number(1)),
+                    consequent: Box::new(Expression::Number(2)),
+                    alternative: Box::new(Expression::Conditional {
+                        condition: Box::new(Expression::Number(3)),
+                        consequent: Box::new(Expression::Number(4)),
+                        alternative: Box::new(Expression::Number(5)),
+                    }),
+                }),
+            })]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
     #[test]
     fn expression_precedence() {
         let ast = parse(
@@ -460,9 +1740,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Identifier(SSAVar::new("a"))),
                 operator: "=",
@@ -514,9 +1797,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::Expression(Expression::Infix {
                 left: Box::new(Expression::Infix {
                     left: Box::new(Expression::Number(1)),
@@ -536,14 +1822,37 @@ mod tests {
             "
             int main() {
                 int a;
+                int b = a + 1, c, d = 2;
             }
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
-            body: Statement::Compound(vec![Statement::Declaration(SSAVar::new("a"))]),
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::Declaration(SSAVar::new("a")),
+                Statement::Declaration(SSAVar::new("b")),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("b"))),
+                    operator: "=",
+                    right: Box::new(Expression::Infix {
+                        left: Box::new(Expression::Identifier(SSAVar::new("a"))),
+                        operator: "+",
+                        right: Box::new(Expression::Number(1)),
+                    }),
+                }),
+                Statement::Declaration(SSAVar::new("c")),
+                Statement::Declaration(SSAVar::new("d")),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("d"))),
+                    operator: "=",
+                    right: Box::new(Expression::Number(2)),
+                }),
+            ]),
         }];
         assert_eq!(ast, expected);
     }
@@ -565,9 +1874,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![
                 Statement::If {
                     condition: Expression::Number(1),
@@ -602,9 +1914,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![Statement::While {
                 condition: Expression::Number(1),
                 body: Box::new(Statement::Compound(vec![Statement::Expression(
@@ -615,6 +1930,142 @@ mod tests {
         assert_eq!(ast, expected);
     }
 
+    #[test]
+    fn statement_do_while() {
+        let ast = parse(
+            "
+            int main() {
+                do {
+                    1;
+                } while (2);
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![Statement::DoWhile {
+                body: Box::new(Statement::Compound(vec![Statement::Expression(
+                    Expression::Number(1),
+                )])),
+                condition: Expression::Number(2),
+            }]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    fn statement_for() {
+        let ast = parse(
+            "
+            int main() {
+                for (i = 0; i < 2; i = i + 1) {
+                    3;
+                }
+                for (;;) {}
+            }
+        ",
+        );
+        let i = || Box::new(Expression::Identifier(SSAVar::new("i")));
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![
+                Statement::Compound(vec![
+                    Statement::Expression(Expression::Infix {
+                        left: i(),
+                        operator: "=",
+                        right: Box::new(Expression::Number(0)),
+                    }),
+                    Statement::While {
+                        condition: Expression::Infix {
+                            left: i(),
+                            operator: "<",
+                            right: Box::new(Expression::Number(2)),
+                        },
+                        body: Box::new(Statement::Compound(vec![
+                            Statement::Compound(vec![Statement::Expression(Expression::Number(3))]),
+                            Statement::Expression(Expression::Infix {
+                                left: i(),
+                                operator: "=",
+                                right: Box::new(Expression::Infix {
+                                    left: i(),
+                                    operator: "+",
+                                    right: Box::new(Expression::Number(1)),
+                                }),
+                            }),
+                        ])),
+                    },
+                ]),
+                Statement::Compound(vec![Statement::While {
+                    condition: Expression::Number(1),
+                    body: Box::new(Statement::Compound(vec![Statement::Compound(vec![])])),
+                }]),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+        let source = "int main() { for (int i = 0; i < 2; i = i + 1) {} }";
+        assert_eq!(
+            try_parse_unit(source, "main.c", &mut Vec::new(), &mut Vec::new()),
+            Err(Error::Frontend(String::from(
+                "Declarations in `for` are not supported."
+            )))
+        );
+    }
+
+    #[test]
+    fn statement_break_continue() {
+        let ast = parse(
+            "
+            int main() {
+                for (;; 1) {
+                    if (2) continue;
+                    while (3) continue;
+                    break;
+                }
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: Statement::Compound(vec![Statement::Compound(vec![Statement::While {
+                condition: Expression::Number(1),
+                body: Box::new(Statement::Compound(vec![
+                    Statement::Compound(vec![
+                        Statement::If {
+                            condition: Expression::Number(2),
+                            body: Box::new(Statement::Compound(vec![
+                                Statement::Expression(Expression::Number(1)),
+                                Statement::Continue,
+                            ])),
+                            alternative: None,
+                        },
+                        Statement::While {
+                            condition: Expression::Number(3),
+                            body: Box::new(Statement::Continue),
+                        },
+                        Statement::Break,
+                    ]),
+                    Statement::Expression(Expression::Number(1)),
+                ])),
+            }])]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
     #[test]
     fn statement_return() {
         let ast = parse(
@@ -626,9 +2077,12 @@ mod tests {
         ",
         );
         let expected = vec![Function {
+            line: 2,
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: Statement::Compound(vec![
                 Statement::Return(None),
                 Statement::Return(Some(Expression::Number(1))),
@@ -648,24 +2102,282 @@ mod tests {
         );
         let expected = vec![
             Function {
+                line: 2,
                 void: false,
+                double: false,
                 name: String::from("f_1"),
                 parameters: vec![],
+                doubles: HashSet::new(),
                 body: Statement::Compound(vec![]),
             },
             Function {
+                line: 3,
                 void: true,
+                double: false,
                 name: String::from("f_2"),
                 parameters: vec![SSAVar::new("a")],
+                doubles: HashSet::new(),
                 body: Statement::Compound(vec![]),
             },
             Function {
+                line: 4,
                 void: true,
+                double: false,
                 name: String::from("f_3"),
                 parameters: vec![SSAVar::new("a"), SSAVar::new("b")],
+                doubles: HashSet::new(),
                 body: Statement::Compound(vec![]),
             },
         ];
         assert_eq!(ast, expected);
     }
+
+    #[test]
+    fn function_double() {
+        let ast = parse(
+            "
+            double f(double a, int b) {
+                double c = 1.5;
+                return a + 2e1;
+            }
+        ",
+        );
+        let expected = vec![Function {
+            line: 2,
+            void: false,
+            double: true,
+            name: String::from("f"),
+            parameters: vec![SSAVar::new("a"), SSAVar::new("b")],
+            doubles: HashSet::from([String::from("a"), String::from("c")]),
+            body: Statement::Compound(vec![
+                Statement::Declaration(SSAVar::new("c")),
+                Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("c"))),
+                    operator: "=",
+                    right: Box::new(Expression::Float(1.5_f64.to_bits())),
+                }),
+                Statement::Return(Some(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar::new("a"))),
+                    operator: "+",
+                    right: Box::new(Expression::Float(20.0_f64.to_bits())),
+                })),
+            ]),
+        }];
+        assert_eq!(ast, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Array `a` of `double` is not supported.")]
+    fn function_double_array() {
+        parse(
+            "
+            int main() {
+                double a[2];
+            }
+        ",
+        );
+    }
+
+    #[test]
+    fn subset() {
+        let constructs = check_subset(
+            "
+            int g;
+            int main(void) {
+                int a = {1}, b;
+                for (int i;;) a += 0x10;
+                return (int) *b & a, 'c';
+                l: a ? b : 1;
+                int c[2][], d[];
+            }
+            struct s { int a : 1; int b[2]; struct { int c; } d; };
+            int f(struct s e) { return e.a; }
+        ",
+        );
+        let expected = vec![
+            "2:13: global declaration",
+            "3:22: unnamed parameter",
+            "4:25: initializer list",
+            "5:22: declaration in `for`",
+            "5:33: `+=`",
+            "5:36: `0x10`",
+            "6:24: cast",
+            "6:33: `&`",
+            "6:36: comma operator",
+            "6:38: `'c'`",
+            "7:17: label",
+            "8:25: array without a size",
+            "8:30: array without a size",
+            "10:30: bit-field",
+            "10:40: array member",
+            "10:45: anonymous struct",
+            "11:19: struct parameter",
+        ];
+        assert_eq!(constructs, expected);
+    }
+
+    #[test]
+    fn names() {
+        let references = resolve_names(
+            "
+            int main(int a) {
+                int b = a;
+                {
+                    int a;
+                    a = f(b);
+                }
+                return a;
+            }
+            int f(int n) { return n + g; }
+            int h(int *p) { return h(p) + *p; }
+        ",
+        );
+        let reference = |name: &str, location, declaration| Reference {
+            name: name.to_string(),
+            location,
+            declaration,
+        };
+        let expected = vec![
+            reference("a", (3, 25), (2, 26)),
+            reference("a", (6, 21), (5, 25)),
+            reference("f", (6, 25), (10, 17)),
+            reference("b", (6, 27), (3, 21)),
+            reference("a", (8, 24), (2, 26)),
+            reference("n", (10, 35), (10, 23)),
+            reference("h", (11, 36), (11, 17)),
+            reference("p", (11, 38), (11, 24)),
+            reference("p", (11, 44), (11, 24)),
+        ];
+        assert_eq!(references, expected);
+    }
+
+    #[test]
+    fn pragmas() {
+        let source = "
+            int f() { return 1; }
+            // eac: no-pre, no-lvn
+            // Counts down.
+            int main() {
+                return f();
+            }
+        ";
+        let pragmas = find_pragmas(source, &parse(source)).unwrap();
+        let expected = vec![(
+            String::from("main"),
+            vec![Optimization::Pre, Optimization::Lvn],
+        )]
+        .into_iter()
+        .map(|(name, pragmas)| (name, pragmas.into_iter().collect()))
+        .collect();
+        assert_eq!(pragmas, expected);
+    }
+
+    #[test]
+    fn comments() {
+        let mut assertions = Vec::new();
+        let ast = parse_unit(
+            "
+            /* A block comment
+               over two lines. */
+            int main() { // A line comment.
+                /* // eac: no-pre */ assert(/* inside */ 1);
+            }
+        ",
+            "main.c",
+            &mut assertions,
+            &mut Vec::new(),
+        );
+        assert_eq!(ast[0].line, 4);
+        assert_eq!(assertions, vec!["main.c:5: assertion `1` failed"]);
+        let source = "
+            // eac: no-lvn
+            /* Returns one,
+               always. */
+            // eac: no-fold
+            int f() { return 1; } // eac: no-pre
+        ";
+        let pragmas = find_pragmas(source, &parse(source)).unwrap();
+        let expected: HashSet<_> = vec![Optimization::Lvn, Optimization::Fold]
+            .into_iter()
+            .collect();
+        assert_eq!(pragmas["f"], expected);
+    }
+
+    #[test]
+    fn unknown_pragma() {
+        let source = "
+            // eac: unroll(4)
+            int main() {}
+        ";
+        assert_eq!(
+            find_pragmas(source, &parse(source)),
+            Err(Error::Frontend(String::from(
+                "line 2: unknown pragma `unroll(4)`."
+            )))
+        );
+    }
+
+    #[test]
+    fn statement_lines() {
+        let source = "
+            int main() {
+                int a = 1;
+                for (a = 0; a < 3; a = a + 1)
+                    if (a)
+                        continue;
+            }
+        ";
+        let mut lines = Lines::new();
+        replay_unit(
+            events(source),
+            "main.c",
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut lines,
+        )
+        .unwrap();
+        // The body, the declaration and its assignment, then the `for` as
+        // `{ init; while (cond) { if (a) { step; continue; } step; } }`.
+        let expected = [2, 3, 3, 4, 4, 4, 4, 5, 6, 4, 6, 4];
+        assert_eq!(lines["main"], expected);
+    }
+
+    #[test]
+    fn malformed_events() {
+        let build = |events: &[(&str, &str)]| unsafe {
+            EVENTS = events
+                .iter()
+                .map(|(tag, text)| (tag.to_string(), text.to_string()))
+                .collect();
+            build_ast(
+                "main.c",
+                &mut Vec::new(),
+                &mut Vec::new(),
+                &mut Lines::new(),
+            )
+        };
+        let parsing = |message: &str| Err(Error::Parsing(String::from(message)));
+        assert_eq!(
+            build(&[("ExitAdditiveExpression", "+")]),
+            parsing("Event `ExitAdditiveExpression` is missing its operands.")
+        );
+        assert_eq!(
+            build(&[("ExitFortran", "")]),
+            parsing("Unknown event `ExitFortran`.")
+        );
+        assert_eq!(
+            build(&[("ExitPrototype", "one int f")]),
+            parsing("Event `ExitPrototype` has malformed text `one int f`.")
+        );
+        assert_eq!(
+            build(&[("EnterCompoundStatement", "")]),
+            parsing("The events end inside a compound statement.")
+        );
+        // Tokens are kept even when they are not UTF-8.
+        let tag = CString::new("ExitPrimaryExpression").unwrap().into_raw();
+        let text = CString::new(vec![b'a', 0xff]).unwrap().into_raw();
+        rs_emit_event(tag, text);
+        let text = unsafe { (*ptr::addr_of_mut!(EVENTS)).pop().unwrap().1 };
+        assert_eq!(text, "a\u{fffd}");
+    }
 }
@@ -0,0 +1,328 @@
+use crate::x64::{X64Function, X64Program, X64};
+use std::collections::{HashMap, HashSet};
+
+// Counts of the instructions removed by `cleanup`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub self_moves: usize,
+    pub stack_moves: usize, // Reloads of a value just stored, and stores of a value just reloaded.
+    pub jumps: usize,
+    pub tags: usize,
+    pub idioms: usize, // Instructions rewritten, not removed.
+}
+
+// Final cleanup of the allocated assembly: moves that change nothing are
+// dropped, jumps to unconditional jumps are redirected to the final target,
+// jumps to the next instruction are dropped, and so are tags that no jump
+// refers to. Finally, zero constants and comparisons with zero are rewritten
+// to their shorter idioms.
+pub fn cleanup(asm: X64Program) -> (X64Program, Stats) {
+    let mut stats = Stats::default();
+    let asm = asm
+        .into_iter()
+        .map(
+            |X64Function {
+                 name,
+                 param_cnt,
+                 arrays,
+                 body,
+             }| X64Function {
+                name,
+                param_cnt,
+                arrays,
+                body: cleanup_body(body, &mut stats),
+            },
+        )
+        .collect();
+    (asm, stats)
+}
+
+fn cleanup_body(body: Vec<X64>, stats: &mut Stats) -> Vec<X64> {
+    let mut body = remove_noops(body, stats);
+    loop {
+        let len = body.len();
+        thread_jumps(&mut body);
+        body = remove_next_jumps(body, stats);
+        body = remove_dead_tags(body, stats);
+        if body.len() == len {
+            return use_idioms(body, stats);
+        }
+    }
+}
+
+fn remove_noops(body: Vec<X64>, stats: &mut Stats) -> Vec<X64> {
+    let mut asms: Vec<X64> = Vec::new();
+    for asm in body {
+        match (&asm, asms.last()) {
+            (X64::MovReg(left, right), _) if left == right => stats.self_moves += 1,
+            (X64::MovFromStack(reg, offset), Some(X64::MovToStack(last_offset, last_reg)))
+            | (X64::MovToStack(offset, reg), Some(X64::MovFromStack(last_reg, last_offset)))
+                if reg == last_reg && offset == last_offset =>
+            {
+                stats.stack_moves += 1
+            }
+            _ => asms.push(asm),
+        }
+    }
+    asms
+}
+
+fn use_idioms(mut body: Vec<X64>, stats: &mut Stats) -> Vec<X64> {
+    for index in 0..body.len() {
+        let idiom = match body[index] {
+            // `xor` clobbers the flags, unlike `mov`.
+            X64::MovNum(reg, 0) if !flags_live(&body[index + 1..]) => X64::Xor(reg, reg),
+            X64::CmpNum(reg, 0) => X64::Test(reg, reg),
+            _ => continue,
+        };
+        body[index] = idiom;
+        stats.idioms += 1;
+    }
+    body
+}
+
+// Whether a conditional jump reads the flags before they are set again. The
+// code generator never keeps flags alive across a call or an unconditional
+// jump.
+fn flags_live(asms: &[X64]) -> bool {
+    for asm in asms {
+        match asm {
+            X64::Jl(_)
+            | X64::Jg(_)
+            | X64::Jle(_)
+            | X64::Jge(_)
+            | X64::Je(_)
+            | X64::Jne(_)
+            | X64::Jb(_)
+            | X64::Ja(_)
+            | X64::Jbe(_)
+            | X64::Jae(_) => return true,
+            X64::MovNum(..)
+            | X64::MovReg(..)
+            | X64::MovToStack(..)
+            | X64::MovFromStack(..)
+            | X64::LoadIndex(..)
+            | X64::StoreIndex(..)
+            | X64::LeaIndex(..)
+            | X64::Lea(..)
+            | X64::LoadField(..)
+            | X64::StoreField(..)
+            | X64::Load(..)
+            | X64::Store(..)
+            | X64::Cvtsi2sd(..)
+            | X64::Cvttsd2si(..)
+            | X64::Addsd(..)
+            | X64::Subsd(..)
+            | X64::Mulsd(..)
+            | X64::Divsd(..)
+            | X64::Tag(_)
+            | X64::Push(_)
+            | X64::Pop(_) => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+fn thread_jumps(body: &mut [X64]) {
+    let positions: HashMap<_, _> = body
+        .iter()
+        .enumerate()
+        .filter_map(|(index, asm)| match asm {
+            X64::Tag(tag) => Some((tag.clone(), index)),
+            _ => None,
+        })
+        .collect();
+    // The `jmp` a tag leads to, if that is the first instruction after it.
+    let forward = |tag: &String| {
+        let index = positions.get(tag)?;
+        match body[*index..]
+            .iter()
+            .find(|asm| !matches!(asm, X64::Tag(_)))
+        {
+            Some(X64::Jmp(next)) => Some(next.clone()),
+            _ => None,
+        }
+    };
+    let mut finals = HashMap::new();
+    for tag in positions.keys() {
+        // Stop at the first repeated tag, so an endless loop of jumps is left alone.
+        let mut visited = HashSet::new();
+        let mut target = tag.clone();
+        while visited.insert(target.clone()) {
+            match forward(&target) {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+        finals.insert(tag.clone(), target);
+    }
+    for asm in body {
+        if let Some(tag) = asm.jump_target_mut() {
+            if let Some(target) = finals.get(tag) {
+                *tag = target.clone();
+            }
+        }
+    }
+}
+
+fn remove_next_jumps(body: Vec<X64>, stats: &mut Stats) -> Vec<X64> {
+    let keep: Vec<_> = (0..body.len())
+        .map(|index| !jumps_to_next(&body, index))
+        .collect();
+    stats.jumps += keep.iter().filter(|keep| !**keep).count();
+    body.into_iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(asm, _)| asm)
+        .collect()
+}
+
+fn jumps_to_next(body: &[X64], index: usize) -> bool {
+    match body[index].jump_target() {
+        Some(target) => body[index + 1..]
+            .iter()
+            .take_while(|asm| matches!(asm, X64::Tag(_)))
+            .any(|asm| matches!(asm, X64::Tag(tag) if tag == target)),
+        None => false,
+    }
+}
+
+fn remove_dead_tags(body: Vec<X64>, stats: &mut Stats) -> Vec<X64> {
+    let targets: HashSet<_> = body
+        .iter()
+        .filter_map(|asm| asm.jump_target().cloned())
+        .collect();
+    let len = body.len();
+    let body: Vec<_> = body
+        .into_iter()
+        .filter(|asm| match asm {
+            X64::Tag(tag) => targets.contains(tag),
+            _ => true,
+        })
+        .collect();
+    stats.tags += len - body.len();
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x64::Register;
+
+    #[test]
+    fn jumps_and_tags() {
+        let reg = Register::Virtual(0);
+        let asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::CmpNum(reg, 1),
+                X64::Je(String::from("A")),
+                X64::Jmp(String::from("B")),
+                X64::Tag(String::from("Unused")),
+                X64::Tag(String::from("B")),
+                X64::Neg(reg),
+                X64::Tag(String::from("A")),
+                X64::Jmp(String::from("C")),
+                X64::Neg(reg),
+                X64::Tag(String::from("C")),
+                X64::Tag(String::from("Loop")),
+                X64::Jmp(String::from("Loop")),
+            ],
+        }];
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::CmpNum(reg, 1),
+                X64::Je(String::from("Loop")),
+                X64::Neg(reg),
+                X64::Jmp(String::from("Loop")),
+                X64::Neg(reg),
+                X64::Tag(String::from("Loop")),
+                X64::Jmp(String::from("Loop")),
+            ],
+        }];
+        let (asm, stats) = cleanup(asm);
+        assert_eq!(asm, expected);
+        assert_eq!(stats.jumps, 1);
+        assert_eq!(stats.tags, 4);
+    }
+
+    #[test]
+    fn idioms() {
+        let (a, b) = (Register::Virtual(0), Register::Virtual(1));
+        let asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(a, 1),
+                X64::CmpNum(b, 0),
+                X64::MovNum(a, 0),
+                X64::Je(String::from("End")),
+                X64::MovNum(a, 0),
+                X64::Tag(String::from("End")),
+            ],
+        }];
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(a, 1),
+                X64::Test(b, b),
+                X64::MovNum(a, 0),
+                X64::Je(String::from("End")),
+                X64::Xor(a, a),
+                X64::Tag(String::from("End")),
+            ],
+        }];
+        let (asm, stats) = cleanup(asm);
+        assert_eq!(asm, expected);
+        assert_eq!(stats.idioms, 2);
+    }
+
+    #[test]
+    fn noops() {
+        let (a, b) = (Register::Virtual(0), Register::Virtual(1));
+        let asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovReg(a, a),
+                X64::MovToStack(0, a),
+                X64::MovFromStack(a, 0),
+                X64::MovFromStack(b, 8),
+                X64::MovToStack(8, b),
+                X64::MovToStack(0, b),
+            ],
+        }];
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovToStack(0, a),
+                X64::MovFromStack(b, 8),
+                X64::MovToStack(0, b),
+            ],
+        }];
+        let (asm, stats) = cleanup(asm);
+        assert_eq!(asm, expected);
+        assert_eq!(
+            stats,
+            Stats {
+                self_moves: 1,
+                stack_moves: 2,
+                jumps: 0,
+                tags: 0,
+                idioms: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,604 @@
+use crate::available::{find_assigned, find_stmt_defs, mentions, mentions_double};
+use crate::dataflow::{self, DataflowAnalysis, Direction};
+use crate::ir::{Block, Expression, SSAProgram, SSAVar, Statement, CFG};
+use crate::traversal;
+use std::collections::{HashMap, HashSet};
+
+// Partial redundancy elimination by lazy code motion (Chapter 10.3.1 of the
+// book), on the CFG after SSA destruction, where a temporary may be assigned
+// in several blocks. A computation is `-` or `!` of a variable or a constant,
+// or a binary operator other than `/` and `%` over two of them, so moving it
+// earlier never moves a division by zero before a call. Computations on the
+// right of `&&` and `||` may not run, so they are left alone, and so are
+// statements that assign a variable other than at the top.
+// Expressions that would need a new block on a critical edge stay where they
+// are, and so do computations on `double` values, as temporaries are integers.
+// Returns the number of computations removed.
+pub fn eliminate(cfg: &mut SSAProgram) -> usize {
+    let mut cnt = 0;
+    let mut temps = 0;
+    for func in cfg {
+        cnt += eliminate_function(&mut func.body, &func.doubles, &mut temps);
+    }
+    cnt
+}
+
+// A set of computations, as bits indexed by the order they first appear in.
+#[derive(Debug, PartialEq, Clone)]
+struct Exprs(Vec<u64>);
+
+impl Exprs {
+    fn new(len: usize) -> Self {
+        Exprs(vec![0; len.div_ceil(64)])
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.0[index / 64] & 1 << (index % 64) != 0
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    fn and(&self, other: &Exprs) -> Exprs {
+        Exprs(self.0.iter().zip(&other.0).map(|(l, r)| l & r).collect())
+    }
+
+    fn or(&self, other: &Exprs) -> Exprs {
+        Exprs(self.0.iter().zip(&other.0).map(|(l, r)| l | r).collect())
+    }
+
+    fn minus(&self, other: &Exprs) -> Exprs {
+        Exprs(self.0.iter().zip(&other.0).map(|(l, r)| l & !r).collect())
+    }
+}
+
+// Where an edge gets the computations inserted on it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Place {
+    Start(usize),
+    End(usize),
+}
+
+// The computations of a block that are upward exposed (their operands are not
+// assigned before them in the block), downward exposed (nor after them), and
+// killed (an operand is assigned in the block).
+struct Local {
+    upward: Exprs,
+    downward: Exprs,
+    killed: Exprs,
+}
+
+struct Availability<'a> {
+    universe: &'a Exprs,
+    locals: &'a [Local],
+}
+
+impl DataflowAnalysis for Availability<'_> {
+    type Fact = Exprs;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Exprs {
+        Exprs(vec![0; self.universe.0.len()])
+    }
+
+    fn top(&self) -> Exprs {
+        self.universe.clone()
+    }
+
+    fn meet(&self, left: &Exprs, right: &Exprs) -> Exprs {
+        left.and(right)
+    }
+
+    fn transfer(&self, index: usize, _: &Block, avail: &Exprs) -> Exprs {
+        let local = &self.locals[index];
+        avail.minus(&local.killed).or(&local.downward)
+    }
+}
+
+struct Anticipability<'a> {
+    universe: &'a Exprs,
+    locals: &'a [Local],
+}
+
+impl DataflowAnalysis for Anticipability<'_> {
+    type Fact = Exprs;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self) -> Exprs {
+        Exprs(vec![0; self.universe.0.len()])
+    }
+
+    fn top(&self) -> Exprs {
+        self.universe.clone()
+    }
+
+    fn meet(&self, left: &Exprs, right: &Exprs) -> Exprs {
+        left.and(right)
+    }
+
+    fn transfer(&self, index: usize, _: &Block, ant: &Exprs) -> Exprs {
+        let local = &self.locals[index];
+        ant.minus(&local.killed).or(&local.upward)
+    }
+}
+
+// How far the insertions can be delayed. `Later(i, j)` is the part of
+// `AntIn(j)` that is earliest on `(i, j)` or later at the start of `i` and not
+// computed there, and `LaterIn(j)`, always within `AntIn(j)`, is the
+// intersection over the predecessors. So the fact leaving `i` is the part that
+// does not depend on `j`, and `LaterIn(j)` is its meet restricted to `AntIn(j)`.
+struct Lateness<'a> {
+    universe: &'a Exprs,
+    locals: &'a [Local],
+    ant_ins: &'a [Exprs],
+    earliest: &'a [Exprs], // Earliest on every edge leaving a block, before `AntIn`.
+}
+
+impl DataflowAnalysis for Lateness<'_> {
+    type Fact = Exprs;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Exprs {
+        Exprs(vec![0; self.universe.0.len()])
+    }
+
+    fn top(&self) -> Exprs {
+        self.universe.clone()
+    }
+
+    fn meet(&self, left: &Exprs, right: &Exprs) -> Exprs {
+        left.and(right)
+    }
+
+    fn transfer(&self, index: usize, _: &Block, later: &Exprs) -> Exprs {
+        let later_in = later.and(&self.ant_ins[index]);
+        later_in
+            .minus(&self.locals[index].upward)
+            .or(&self.earliest[index])
+    }
+}
+
+fn eliminate_function(body: &mut CFG, doubles: &HashSet<String>, temps: &mut usize) -> usize {
+    let mut reachable: Vec<_> = traversal::Dfs::new(body, vec![0], Direction::Forward).collect();
+    reachable.sort_unstable();
+    // Every computation in the order it first appears, which indexes the sets,
+    // orders the insertions, and numbers the temporaries.
+    let mut order = Vec::new();
+    let mut indices = HashMap::new();
+    for stmt in body.iter().flat_map(|block| &block.statements) {
+        for expr in computations(stmt).into_iter().flatten() {
+            indices.entry(expr.clone()).or_insert_with(|| {
+                order.push(expr);
+                order.len() - 1
+            });
+        }
+    }
+    let len = order.len();
+    // Expressions left in place: those on `double` values, and those whose
+    // insertions cannot be placed.
+    let mut pinned = Exprs::new(len);
+    for (index, expr) in order.iter().enumerate() {
+        if mentions_double(expr, doubles) {
+            pinned.insert(index);
+        }
+    }
+    let (inserts, deletes) = loop {
+        let mut universe = Exprs::new(len);
+        for index in (0..len).filter(|&index| !pinned.contains(index)) {
+            universe.insert(index);
+        }
+        let locals: Vec<_> = body
+            .iter()
+            .map(|block| local(block, &order, &indices, &universe))
+            .collect();
+        let avail_outs = dataflow::solve(
+            &Availability {
+                universe: &universe,
+                locals: &locals,
+            },
+            body,
+        )
+        .outs;
+        let ant = dataflow::solve(
+            &Anticipability {
+                universe: &universe,
+                locals: &locals,
+            },
+            body,
+        );
+        let earliest: Vec<Exprs> = (0..body.len())
+            .map(|i| {
+                let unavail = universe.minus(&avail_outs[i]);
+                match i {
+                    0 => unavail,
+                    i => unavail.and(&locals[i].killed.or(&universe.minus(&ant.outs[i]))),
+                }
+            })
+            .collect();
+        let later = dataflow::solve(
+            &Lateness {
+                universe: &universe,
+                locals: &locals,
+                ant_ins: &ant.ins,
+                earliest: &earliest,
+            },
+            body,
+        );
+        let later_ins: Vec<Exprs> = (0..body.len())
+            .map(|j| match j {
+                0 => Exprs::new(len),
+                j => later.ins[j].and(&ant.ins[j]),
+            })
+            .collect();
+        let mut inserts: Vec<(Place, usize)> = Vec::new();
+        let mut stuck = Exprs::new(len);
+        for &i in &reachable {
+            let mut succs: Vec<_> = body[i].successors.iter().copied().collect();
+            succs.sort_unstable();
+            for j in succs {
+                let exprs = later.outs[i].and(&ant.ins[j]).minus(&later_ins[j]);
+                let place = place(body, i, j);
+                for index in (0..len).filter(|&index| exprs.contains(index)) {
+                    match place {
+                        Some(place) => inserts.push((place, index)),
+                        None => stuck.insert(index),
+                    }
+                }
+            }
+        }
+        if stuck == Exprs::new(len) {
+            let deletes: Vec<Exprs> = (0..body.len())
+                .map(|k| match k {
+                    0 => Exprs::new(len),
+                    k => locals[k].upward.minus(&later_ins[k]),
+                })
+                .collect();
+            break (inserts, deletes);
+        }
+        pinned = pinned.or(&stuck);
+    };
+    // Every expression moved gets a temporary, which every computation left in
+    // place assigns for the deleted ones to read.
+    let mut holders: HashMap<usize, SSAVar> = HashMap::new();
+    for index in 0..len {
+        let inserted = inserts.iter().any(|&(_, inserted)| inserted == index);
+        if inserted || deletes.iter().any(|deleted| deleted.contains(index)) {
+            let holder = SSAVar {
+                name: String::from("%pre"),
+                subscript: Some(*temps),
+            };
+            *temps += 1;
+            holders.insert(index, holder);
+        }
+    }
+    let mut cnt = 0;
+    for &k in &reachable {
+        let mut statements = Vec::new();
+        let mut defs = HashSet::new();
+        for mut stmt in body[k].statements.drain(..) {
+            let mut exprs = Vec::new();
+            for expr in computations(&stmt).into_iter().flatten() {
+                if !exprs.contains(&expr) {
+                    exprs.push(expr);
+                }
+            }
+            for expr in exprs {
+                let index = indices[&expr];
+                let holder = match holders.get(&index) {
+                    Some(holder) => holder,
+                    None => continue,
+                };
+                if !reads(&expr, &defs) && deletes[k].contains(index) {
+                    cnt += 1;
+                } else {
+                    statements.push(assign(holder, &expr));
+                }
+                replace_stmt(&mut stmt, &expr, holder);
+            }
+            let mut stmt_defs = Vec::new();
+            find_stmt_defs(&stmt, &mut stmt_defs);
+            defs.extend(stmt_defs);
+            statements.push(stmt);
+        }
+        body[k].statements = statements;
+    }
+    for (place, index) in inserts {
+        let stmt = assign(&holders[&index], &order[index]);
+        match place {
+            Place::Start(j) => body[j].statements.insert(0, stmt),
+            Place::End(i) => body[i].statements.push(stmt),
+        }
+    }
+    cnt
+}
+
+fn local(
+    block: &Block,
+    order: &[Expression],
+    indices: &HashMap<Expression, usize>,
+    universe: &Exprs,
+) -> Local {
+    let len = order.len();
+    let mut upward = Exprs::new(len);
+    let mut downward = Exprs::new(len);
+    let mut defs = HashSet::new();
+    for stmt in &block.statements {
+        for expr in computations(stmt).into_iter().flatten() {
+            let index = indices[&expr];
+            if !universe.contains(index) {
+                continue;
+            }
+            if !reads(&expr, &defs) {
+                upward.insert(index);
+            }
+            downward.insert(index);
+        }
+        let mut stmt_defs = Vec::new();
+        find_stmt_defs(stmt, &mut stmt_defs);
+        if !stmt_defs.is_empty() {
+            let mut killed = Exprs::new(len);
+            for index in (0..len).filter(|&index| downward.contains(index)) {
+                if stmt_defs.iter().any(|def| mentions(&order[index], def)) {
+                    killed.insert(index);
+                }
+            }
+            downward = downward.minus(&killed);
+        }
+        defs.extend(stmt_defs);
+    }
+    let mut killed = Exprs::new(len);
+    for (index, expr) in order.iter().enumerate() {
+        if universe.contains(index) && reads(expr, &defs) {
+            killed.insert(index);
+        }
+    }
+    Local {
+        upward,
+        downward,
+        killed,
+    }
+}
+
+// A computation inserted on the edge from `i` to `j` goes at the start of `j`
+// if `i` is its only predecessor, or at the end of `i` if `j` is its only
+// successor and nothing ends `i` before. Otherwise the edge is critical.
+fn place(body: &CFG, i: usize, j: usize) -> Option<Place> {
+    if body[j].predecessors.len() == 1 {
+        Some(Place::Start(j))
+    } else if body[i].successors.len() == 1
+        && !matches!(body[i].statements.last(), Some(Statement::Return(_)))
+    {
+        Some(Place::End(i))
+    } else {
+        None
+    }
+}
+
+// The computations a statement surely evaluates, in order, or `None` if it
+// assigns a variable anywhere but at the top.
+fn computations(stmt: &Statement) -> Option<Vec<Expression>> {
+    let mut exprs = Vec::new();
+    let mut defs = Vec::new();
+    match stmt {
+        Statement::Expression(Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        }) if matches!(**left, Expression::Identifier(_)) => {
+            find_assigned(right, &mut defs);
+            find_computations(right, &mut exprs);
+        }
+        Statement::Compound(_) => return None,
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => {
+            find_assigned(expr, &mut defs);
+            find_computations(expr, &mut exprs);
+        }
+        _ => {}
+    }
+    match defs.is_empty() {
+        true => Some(exprs),
+        false => None,
+    }
+}
+
+fn find_computations(expr: &Expression, exprs: &mut Vec<Expression>) {
+    if is_computation(expr) {
+        exprs.push(expr.clone());
+        return;
+    }
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_computations(index, exprs);
+            }
+        }
+        Expression::Call { arguments, .. } => find_computations(arguments, exprs),
+        Expression::Arguments(args) => {
+            for arg in args {
+                find_computations(arg, exprs);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_computations(expression, exprs),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            find_computations(left, exprs);
+            if *operator != "&&" && *operator != "||" {
+                find_computations(right, exprs);
+            }
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn is_computation(expr: &Expression) -> bool {
+    let is_leaf =
+        |expr: &Expression| matches!(expr, Expression::Identifier(_) | Expression::Number(_));
+    match expr {
+        Expression::Prefix {
+            operator: "-" | "!",
+            expression,
+        } => is_leaf(expression),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => !matches!(*operator, "=" | "/" | "%") && is_leaf(left) && is_leaf(right),
+        _ => false,
+    }
+}
+
+// Whether a computation reads one of `vars`.
+fn reads(expr: &Expression, vars: &HashSet<SSAVar>) -> bool {
+    match expr {
+        Expression::Identifier(var) => vars.contains(var),
+        Expression::Prefix { expression, .. } => reads(expression, vars),
+        Expression::Infix { left, right, .. } => reads(left, vars) || reads(right, vars),
+        _ => false,
+    }
+}
+
+// Replace the occurrences of `expr` that `computations` finds by `holder`.
+fn replace_stmt(stmt: &mut Statement, expr: &Expression, holder: &SSAVar) {
+    match stmt {
+        Statement::Expression(Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        }) if matches!(**left, Expression::Identifier(_)) => replace_expr(right, expr, holder),
+        Statement::Expression(cond)
+        | Statement::If {
+            condition: cond, ..
+        }
+        | Statement::While {
+            condition: cond, ..
+        }
+        | Statement::DoWhile {
+            condition: cond, ..
+        }
+        | Statement::Return(Some(cond)) => replace_expr(cond, expr, holder),
+        _ => {}
+    }
+}
+
+fn replace_expr(current: &mut Expression, expr: &Expression, holder: &SSAVar) {
+    if current == expr {
+        *current = Expression::Identifier(holder.clone());
+        return;
+    }
+    match current {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                replace_expr(index, expr, holder);
+            }
+        }
+        Expression::Call { arguments, .. } => replace_expr(arguments, expr, holder),
+        Expression::Arguments(args) => {
+            for arg in args {
+                replace_expr(arg, expr, holder);
+            }
+        }
+        Expression::Prefix { expression, .. } => replace_expr(expression, expr, holder),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            replace_expr(left, expr, holder);
+            if *operator != "&&" && *operator != "||" {
+                replace_expr(right, expr, holder);
+            }
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn assign(holder: &SSAVar, expr: &Expression) -> Statement {
+    Statement::Expression(Expression::Infix {
+        left: Box::new(Expression::Identifier(holder.clone())),
+        operator: "=",
+        right: Box::new(expr.clone()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::X64Builder;
+    use crate::lir;
+    use crate::x64::Spill;
+    use crate::{emulator, parser, peephole, reg_allocator, ssa};
+
+    #[test]
+    fn lazy_code_motion() {
+        let ast = parser::parse(
+            "
+            int main(int a, int b, int c) {
+                int x;
+                int s = 0;
+                int i = 0;
+                if (c) {
+                    x = a + b;
+                } else {
+                    x = 1;
+                }
+                do {
+                    s = s + a * b;
+                    i = i + 1;
+                } while (i < 3);
+                return x * (a + b) + s;
+            }
+        ",
+        );
+        let mut cfg = ssa::destruct(ssa::construct(ast));
+        // `a + b` is computed on the `else` path too instead of after the
+        // join, and `a * b` before the loop instead of in it.
+        assert_eq!(eliminate(&mut cfg), 2);
+        let a_times_b = Statement::Expression(Expression::Infix {
+            left: Box::new(Expression::Identifier(SSAVar {
+                name: String::from("%pre"),
+                subscript: Some(1),
+            })),
+            operator: "=",
+            right: Box::new(Expression::Infix {
+                left: Box::new(Expression::Identifier(SSAVar {
+                    name: String::from("a"),
+                    subscript: Some(0),
+                })),
+                operator: "*",
+                right: Box::new(Expression::Identifier(SSAVar {
+                    name: String::from("b"),
+                    subscript: Some(0),
+                })),
+            }),
+        });
+        let hoisted: Vec<_> = cfg[0]
+            .body
+            .iter()
+            .map(|block| block.statements.contains(&a_times_b))
+            .collect();
+        assert_eq!(hoisted, [false, false, true, true, false, false, false]);
+        let vasm = X64Builder::new().build(lir::lower(cfg));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(emulator::run(&asm, &[3, 4, 1]), Ok(85));
+        assert_eq!(emulator::run(&asm, &[3, 4, 0]), Ok(43));
+    }
+}
@@ -0,0 +1,297 @@
+// Expand `#define` macros before the source reaches the C++ parser, whose
+// grammar skips every other directive. Object-like macros (`#define N 10`)
+// and function-like ones (`#define MAX(a, b) ((a) > (b) ? (a) : (b))`) are
+// replaced wherever they are used after their definition, until an `#undef`.
+// Arguments are expanded before they are substituted, and the result is
+// scanned again for more macros, except the ones being expanded, so a macro
+// naming itself stops there as in C. `#` and `##` are not supported.
+// Directive lines are left blank, and a call spanning several lines keeps its
+// line breaks after the expansion, so every line keeps its number.
+use std::collections::HashMap;
+
+struct Macro {
+    parameters: Option<Vec<String>>, // `None` for an object-like macro.
+    body: String,
+}
+
+type Macros = HashMap<String, Macro>;
+
+pub fn expand(source: &str) -> String {
+    if !source.contains('#') {
+        return source.to_string();
+    }
+    let mut macros = Macros::new();
+    let mut expanded = String::new();
+    // The lines since the last directive, which start at line `start`.
+    let mut chunk = String::new();
+    let mut start = 1;
+    let lines: Vec<_> = source.split_inclusive('\n').collect();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = i + 1;
+        let directive = lines[i].trim_start().strip_prefix('#').map(str::trim_start);
+        let name = directive.map(|directive| identifier(directive).0);
+        if !matches!(name, Some("define") | Some("undef")) {
+            chunk += lines[i];
+            i += 1;
+            continue;
+        }
+        expanded += &expand_text(&chunk, &macros, &mut Vec::new(), start);
+        chunk.clear();
+        // A directive goes on over the lines ending with `\`.
+        let mut text = String::new();
+        loop {
+            let physical = lines[i].trim_end_matches(['\r', '\n']);
+            expanded += &lines[i][physical.len()..];
+            i += 1;
+            match physical.strip_suffix('\\') {
+                Some(physical) if i < lines.len() => text += physical,
+                _ => {
+                    text += physical;
+                    break;
+                }
+            }
+            text.push(' ');
+        }
+        start = i + 1;
+        let text = text.trim_start().trim_start_matches('#').trim_start();
+        let (directive, rest) = identifier(text);
+        let (name, rest) = identifier(rest.trim_start());
+        if name.is_empty() {
+            panic!("line {}: `#{}` needs the name of a macro.", line, directive);
+        }
+        if directive == "undef" {
+            macros.remove(name);
+            continue;
+        }
+        let (parameters, body) = match rest.strip_prefix('(') {
+            Some(rest) => {
+                let (list, body) = match rest.split_once(')') {
+                    Some(split) => split,
+                    None => panic!(
+                        "line {}: the parameters of `{}` are not closed.",
+                        line, name
+                    ),
+                };
+                let parameters: Vec<_> = match list.trim() {
+                    "" => Vec::new(),
+                    list => list.split(',').map(|p| p.trim().to_string()).collect(),
+                };
+                if let Some(p) = parameters.iter().find(|p| identifier(p).0 != p.as_str()) {
+                    panic!(
+                        "line {}: `{}` is not a parameter name of `{}`.",
+                        line, p, name
+                    );
+                }
+                (Some(parameters), body)
+            }
+            None => (None, rest),
+        };
+        // Comments in the body would swallow what follows a use.
+        let body: String = split(body)
+            .into_iter()
+            .map(|token| if is_comment(token) { " " } else { token })
+            .collect();
+        if body.contains('#') {
+            panic!(
+                "line {}: `#` and `##` in `{}` are not supported.",
+                line, name
+            );
+        }
+        let body = body.trim().to_string();
+        macros.insert(name.to_string(), Macro { parameters, body });
+    }
+    expanded + &expand_text(&chunk, &macros, &mut Vec::new(), start)
+}
+
+// Expand the macros in `text`, which starts at line `line`, except the ones
+// in `hidden`, which are being expanded already.
+fn expand_text(text: &str, macros: &Macros, hidden: &mut Vec<String>, line: usize) -> String {
+    let tokens = split(text);
+    let mut expanded = String::new();
+    let mut line = line;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        i += 1;
+        let found = macros
+            .get(token)
+            .filter(|_| !hidden.iter().any(|h| h == token));
+        let mac = match found {
+            Some(mac) => mac,
+            None => {
+                line += token.matches('\n').count();
+                expanded += token;
+                continue;
+            }
+        };
+        let body = match &mac.parameters {
+            None => mac.body.clone(),
+            Some(parameters) => {
+                // A function-like macro without arguments is just a name.
+                let open = tokens[i..]
+                    .iter()
+                    .position(|token| !token.trim().is_empty() && !is_comment(token));
+                let open = match open {
+                    Some(open) if tokens[i + open] == "(" => i + open,
+                    _ => {
+                        expanded += token;
+                        continue;
+                    }
+                };
+                let (arguments, end) = arguments(&tokens, open + 1, token, line);
+                let breaks: usize = tokens[i..end].iter().map(|t| t.matches('\n').count()).sum();
+                i = end;
+                let arguments = match (parameters.len(), &arguments[..]) {
+                    (0, [argument]) if argument.trim().is_empty() => Vec::new(),
+                    _ => arguments,
+                };
+                if arguments.len() != parameters.len() {
+                    panic!(
+                        "line {}: macro `{}` takes {} argument(s) but {} were given.",
+                        line,
+                        token,
+                        parameters.len(),
+                        arguments.len()
+                    );
+                }
+                let arguments: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| expand_text(argument.trim(), macros, hidden, line))
+                    .collect();
+                let body: String = split(&mac.body)
+                    .into_iter()
+                    .map(|token| match parameters.iter().position(|p| p == token) {
+                        Some(index) => arguments[index].as_str(),
+                        None => token,
+                    })
+                    .collect();
+                line += breaks;
+                body + &"\n".repeat(breaks)
+            }
+        };
+        hidden.push(token.to_string());
+        expanded += &expand_text(&body, macros, hidden, line);
+        hidden.pop();
+    }
+    expanded
+}
+
+// The arguments of a call whose `(` is right before `tokens[start]`, split at
+// the commas outside of parentheses, and the index past its `)`.
+fn arguments(tokens: &[&str], start: usize, name: &str, line: usize) -> (Vec<String>, usize) {
+    let mut arguments = vec![String::new()];
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match *token {
+            ")" if depth == 0 => return (arguments, i + 1),
+            "," if depth == 0 => {
+                arguments.push(String::new());
+                continue;
+            }
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {}
+        }
+        arguments.last_mut().unwrap().push_str(token);
+    }
+    panic!("line {}: the call to macro `{}` is not closed.", line, name);
+}
+
+// Split `text` into identifiers, numbers, string and character literals,
+// comments, runs of whitespace, and single other characters, which put
+// together give back `text`.
+fn split(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            // A number such as `1e5` or `0x1F` is one token, like a name.
+            rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .filter(|_| c.is_ascii_digit())
+                .or_else(|| rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')))
+                .unwrap_or(rest.len())
+        } else if c.is_whitespace() {
+            rest.find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len())
+        } else if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            comment.find("*/").map_or(rest.len(), |len| len + 4)
+        } else if c == '"' || c == '\'' {
+            let mut escaped = false;
+            let end = rest[1..].find(|d: char| {
+                let end = d == c && !escaped;
+                escaped = d == '\\' && !escaped;
+                end
+            });
+            end.map_or(rest.len(), |end| end + 2)
+        } else {
+            c.len_utf8()
+        };
+        tokens.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+fn is_comment(token: &str) -> bool {
+    token.starts_with("//") || token.starts_with("/*")
+}
+
+// The identifier `text` starts with, if any, and the text after it.
+fn identifier(text: &str) -> (&str, &str) {
+    let len = match text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        true => text
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(text.len()),
+        false => 0,
+    };
+    text.split_at(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_and_function_like() {
+        let source = "\
+#define N 10 // The size.
+#define MAX(a, b) ((a) > (b) ? (a) : (b))
+#define SQUARE(x) \\
+    ((x) * (x))
+int main() {
+    int n = MAX(N, SQUARE(3));
+    int MAX = 1; /* N is not expanded here */
+    return MAX(n,
+               MAX) + \"N\";
+}
+#undef N
+int N;
+";
+        let expected = "\n\n\n\nint main() {
+    int n = ((10) > (((3) * (3))) ? (10) : (((3) * (3))));
+    int MAX = 1; /* N is not expanded here */
+    return ((n) > (MAX) ? (n) : (MAX))\n + \"N\";
+}
+
+int N;
+";
+        assert_eq!(expand(source), expected);
+    }
+
+    #[test]
+    fn recursion() {
+        let source =
+            "#define a a + b\n#define b a\n#define f(x) f(x + 1)\nint g() { return a + f(f(0)); }";
+        let expected = "\n\n\nint g() { return a + a + f(f(0 + 1) + 1); }";
+        assert_eq!(expand(source), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2: macro `MAX` takes 2 argument(s) but 1 were given.")]
+    fn argument_count() {
+        expand("#define MAX(a, b) a\nint main() { return MAX(1); }");
+    }
+}
@@ -0,0 +1,341 @@
+use crate::lvn;
+use crate::x64::{Register, RegisterClass, X64Function, X64Program, X64RegisterAllocator, X64};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+// Register pressure over the pseudo-x64 assembly: how many virtual registers
+// of each class are live at once, per block, from a liveness analysis over
+// the blocks between tags and jumps. Where a loop needs more registers than
+// there are, the values live across it but unused in it are the ones to keep
+// on the stack while it runs. `splits` names them so the allocator can spill
+// them before the loop, once, instead of wherever the loop runs out of
+// registers, which would store and reload them on every iteration.
+const CLASSES: [RegisterClass; 2] = [RegisterClass::General, RegisterClass::Xmm];
+
+// The values to spill before the loop headed by a tag, of one class.
+#[derive(Debug, PartialEq)]
+pub struct Split {
+    // How many more values are live in the loop than there are registers.
+    pub excess: usize,
+    // The values live across the loop and unused in it, the furthest used
+    // after it first.
+    pub candidates: Vec<Register>,
+}
+
+struct Liveness {
+    blocks: Vec<Range<usize>>,
+    live_in: Vec<HashSet<Register>>,
+    // The values live after every instruction.
+    live_after: Vec<HashSet<Register>>,
+    // The number of values of each class live at every instruction.
+    pressure: Vec<[usize; 2]>,
+}
+
+// For every function, the most values of each class live at once, how many
+// blocks exceed the registers, and the most values live in each block.
+pub fn report(asm: &X64Program) -> String {
+    let limits = CLASSES.map(limit);
+    let mut report = String::new();
+    for X64Function { name, body, .. } in asm {
+        let liveness = analyze(body);
+        let blocks: Vec<_> = liveness
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut max = [0, 0];
+                for pressure in &liveness.pressure[block.clone()] {
+                    max = [max[0].max(pressure[0]), max[1].max(pressure[1])];
+                }
+                max
+            })
+            .collect();
+        let max = blocks.iter().fold([0, 0], |max, block| {
+            [max[0].max(block[0]), max[1].max(block[1])]
+        });
+        let over = blocks
+            .iter()
+            .filter(|block| block[0] > limits[0] || block[1] > limits[1])
+            .count();
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|block| format!("{}/{}", block[0], block[1]))
+            .collect();
+        report += &format!(
+            "{}: at most {} general and {} xmm values live, {} of {} blocks over {} and {} registers\n    {}\n",
+            name,
+            max[0],
+            max[1],
+            over,
+            blocks.len(),
+            limits[0],
+            limits[1],
+            blocks.join(" ")
+        );
+    }
+    report
+}
+
+// The values to spill before each loop, by the position of its header tag,
+// where a loop spans from a tag to the last jump back to it.
+pub fn splits(body: &[X64]) -> HashMap<usize, Vec<Split>> {
+    let liveness = analyze(body);
+    let mut tags = HashMap::new();
+    let mut ends = HashMap::new();
+    for (index, asm) in body.iter().enumerate() {
+        if let X64::Tag(tag) = asm {
+            tags.insert(tag, index);
+        }
+        if let Some(&start) = asm.jump_target().and_then(|tag| tags.get(tag)) {
+            ends.insert(start, index);
+        }
+    }
+    let mut splits = HashMap::new();
+    for (start, end) in ends {
+        let used: HashSet<_> = body[start..=end]
+            .iter()
+            .flat_map(|asm| asm.registers())
+            .collect();
+        let header = liveness
+            .blocks
+            .iter()
+            .position(|block| block.start == start)
+            .unwrap();
+        // The first use after the loop, if any, of every value.
+        let next = |vreg: &Register| {
+            body[end + 1..]
+                .iter()
+                .position(|asm| asm.registers().contains(vreg))
+        };
+        let mut loop_splits = Vec::new();
+        for class in CLASSES {
+            let pressure = liveness.pressure[start..=end]
+                .iter()
+                .map(|pressure| pressure[index(class)])
+                .max()
+                .unwrap();
+            if pressure <= limit(class) {
+                continue;
+            }
+            let mut candidates: Vec<_> = liveness.live_in[header]
+                .iter()
+                .filter(|vreg| vreg.class() == class && !used.contains(vreg))
+                .copied()
+                .collect();
+            candidates.sort_by_key(|vreg| (Reverse(next(vreg).unwrap_or(usize::MAX)), *vreg));
+            if !candidates.is_empty() {
+                loop_splits.push(Split {
+                    excess: pressure - limit(class),
+                    candidates,
+                });
+            }
+        }
+        if !loop_splits.is_empty() {
+            splits.insert(start, loop_splits);
+        }
+    }
+    splits
+}
+
+// The values live after every instruction, which the allocator may drop
+// from their registers without storing them.
+pub fn live_after(body: &[X64]) -> Vec<HashSet<Register>> {
+    analyze(body).live_after
+}
+
+fn analyze(body: &[X64]) -> Liveness {
+    // A block starts at a tag and after a jump or a return.
+    let mut leaders = vec![0];
+    for (index, asm) in body.iter().enumerate() {
+        match asm {
+            X64::Tag(_) => leaders.push(index),
+            X64::Ret(_) => leaders.push(index + 1),
+            asm if asm.jump_target().is_some() => leaders.push(index + 1),
+            _ => {}
+        }
+    }
+    leaders.push(body.len());
+    leaders.sort_unstable();
+    leaders.dedup();
+    let blocks: Vec<_> = leaders.windows(2).map(|w| w[0]..w[1]).collect();
+    let starts: HashMap<_, _> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, block)| match &body[block.start] {
+            X64::Tag(tag) => Some((tag, i)),
+            _ => None,
+        })
+        .collect();
+    let successors: Vec<Vec<usize>> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let last = &body[block.end - 1];
+            let mut successors: Vec<_> = last
+                .jump_target()
+                .and_then(|tag| starts.get(tag))
+                .copied()
+                .into_iter()
+                .collect();
+            if !matches!(last, X64::Jmp(_) | X64::Ret(_)) && i + 1 < blocks.len() {
+                successors.push(i + 1);
+            }
+            successors
+        })
+        .collect();
+    // The values read before being written, and the values written, in
+    // every block.
+    let (gens, kills): (Vec<_>, Vec<_>) = blocks
+        .iter()
+        .map(|block| {
+            let mut gen = HashSet::new();
+            let mut kill = HashSet::new();
+            for asm in &body[block.clone()] {
+                let (uses, def) = operands(asm);
+                gen.extend(uses.into_iter().filter(|vreg| !kill.contains(vreg)));
+                kill.extend(def);
+            }
+            (gen, kill)
+        })
+        .unzip();
+    let mut live_in = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..blocks.len()).rev() {
+            let mut live: HashSet<_> = successors[i]
+                .iter()
+                .flat_map(|s| live_in[*s].iter().copied())
+                .collect();
+            live.retain(|vreg| !kills[i].contains(vreg));
+            live.extend(gens[i].iter().copied());
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    let mut pressure = vec![[0, 0]; body.len()];
+    let mut live_after = vec![HashSet::new(); body.len()];
+    for (i, block) in blocks.iter().enumerate() {
+        let mut live: HashSet<_> = successors[i]
+            .iter()
+            .flat_map(|s| live_in[*s].iter().copied())
+            .collect();
+        for position in block.clone().rev() {
+            live_after[position] = live.clone();
+            let (uses, def) = operands(&body[position]);
+            // A value written takes a register even if it is never read.
+            let after = count(live.iter().chain(def.iter().filter(|d| !live.contains(d))));
+            if let Some(def) = def {
+                live.remove(&def);
+            }
+            live.extend(uses);
+            let before = count(live.iter());
+            pressure[position] = [after[0].max(before[0]), after[1].max(before[1])];
+        }
+    }
+    Liveness {
+        blocks,
+        live_in,
+        live_after,
+        pressure,
+    }
+}
+
+// The virtual registers the instruction reads, and the one it writes.
+fn operands(asm: &X64) -> (Vec<Register>, Option<Register>) {
+    let mut uses = asm.registers();
+    let def = lvn::defined(asm);
+    let updates = matches!(
+        asm,
+        X64::Neg(_)
+            | X64::Imul(..)
+            | X64::Idiv(..)
+            | X64::Irem(..)
+            | X64::Add(..)
+            | X64::Sub(..)
+            | X64::And(..)
+            | X64::Or(..)
+            | X64::Xor(..)
+            | X64::Addsd(..)
+            | X64::Subsd(..)
+            | X64::Mulsd(..)
+            | X64::Divsd(..)
+    );
+    if let (Some(def), false) = (def, updates) {
+        if let Some(index) = uses.iter().position(|reg| *reg == def) {
+            uses.remove(index);
+        }
+    }
+    uses.retain(is_virtual);
+    (uses, def.filter(is_virtual))
+}
+
+fn is_virtual(reg: &Register) -> bool {
+    matches!(reg, Register::Virtual(_) | Register::VirtualXmm(_))
+}
+
+fn count<'a>(vregs: impl Iterator<Item = &'a Register>) -> [usize; 2] {
+    let mut count = [0, 0];
+    for vreg in vregs {
+        count[index(vreg.class())] += 1;
+    }
+    count
+}
+
+fn index(class: RegisterClass) -> usize {
+    match class {
+        RegisterClass::General => 0,
+        RegisterClass::Xmm => 1,
+    }
+}
+
+fn limit(class: RegisterClass) -> usize {
+    X64RegisterAllocator::ALLOCATABLE
+        .iter()
+        .filter(|reg| reg.class() == class)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Int;
+    use Register::Virtual;
+
+    #[test]
+    fn loop_pressure() {
+        // Values 1 to 13 are live across the loop, which uses only value 0.
+        let mut body: Vec<_> = (0..14).map(|i| X64::MovNum(Virtual(i), i as Int)).collect();
+        body.extend(vec![
+            X64::Tag(String::from("Loop")),
+            X64::Neg(Virtual(0)),
+            X64::CmpNum(Virtual(0), 0),
+            X64::Jne(String::from("Loop")),
+        ]);
+        for i in 1..14 {
+            body.push(X64::Neg(Virtual(i)));
+        }
+        body.push(X64::Ret(Some(Virtual(0))));
+        let liveness = analyze(&body);
+        assert_eq!(liveness.blocks, vec![0..14, 14..18, 18..32]);
+        assert_eq!(liveness.pressure[15], [14, 0]);
+        assert_eq!(liveness.pressure[31], [1, 0]);
+        let splits = splits(&body);
+        let expected = Split {
+            excess: 1,
+            candidates: (1..14).rev().map(Virtual).collect(),
+        };
+        assert_eq!(splits[&14], vec![expected]);
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let report = report(&vec![func]);
+        let expected = "main: at most 14 general and 0 xmm values live, 3 of 3 blocks over 13 and 5 registers\n    14/0 14/0 14/0\n";
+        assert_eq!(report, expected);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::files::FileProvider;
+use std::path::{Path, PathBuf};
+
+// A project file lists the translation units of one program in a small
+// subset of TOML, with one `key = value` pair per line:
+//
+//     sources = ["main.c", "fib.c"]
+//     include = ["lib"]
+//     output = "fib"
+//     target = "x64"
+//     libs = ["user32"]
+//
+// Sources are looked up relative to the project file first, then in each
+// include directory in order. The libraries in `libs` are linked in as if
+// passed with `--link-lib`. Only `sources` is required.
+#[derive(Debug, PartialEq)]
+pub struct Project {
+    pub sources: Vec<PathBuf>,
+    pub include: Vec<PathBuf>,
+    pub output: String,
+    pub libs: Vec<String>,
+}
+
+pub fn load(path: &Path, files: &dyn FileProvider) -> Project {
+    let text = files.read(path).expect("Invalid project file path.");
+    let root = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut project = parse(&text);
+    project.include = project.include.iter().map(|dir| root.join(dir)).collect();
+    project.sources = project
+        .sources
+        .iter()
+        .map(|source| {
+            std::iter::once(root.to_path_buf())
+                .chain(project.include.iter().cloned())
+                .map(|dir| dir.join(source))
+                .find(|path| files.is_file(path))
+                .unwrap_or_else(|| panic!("Source `{}` not found.", source.display()))
+        })
+        .collect();
+    project
+}
+
+fn parse(text: &str) -> Project {
+    let mut sources = None;
+    let mut include = Vec::new();
+    let mut output = String::from("main");
+    let mut libs = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => panic!("Invalid project file line {}: {}", number + 1, line),
+        };
+        match key {
+            "sources" => sources = Some(parse_paths(value, number)),
+            "include" => include = parse_paths(value, number),
+            "output" => output = parse_string(value, number),
+            "libs" => libs = parse_strings(value, number),
+            "target" => match parse_string(value, number).as_str() {
+                "x64" => {}
+                target => panic!("Unsupported target: {}", target),
+            },
+            key => panic!("Unknown project file key: {}", key),
+        }
+    }
+    Project {
+        sources: sources.expect("The project file lists no sources."),
+        include,
+        output,
+        libs,
+    }
+}
+
+fn parse_paths(value: &str, number: usize) -> Vec<PathBuf> {
+    parse_strings(value, number)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn parse_strings(value: &str, number: usize) -> Vec<String> {
+    match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        Some(items) => items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| parse_string(item, number))
+            .collect(),
+        None => panic!("Invalid project file array at line {}.", number + 1),
+    }
+}
+
+fn parse_string(value: &str, number: usize) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(string) => string.to_string(),
+        None => panic!("Invalid project file string at line {}.", number + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest() {
+        let project = parse(
+            r#"
+            # Fibonacci, split in two.
+            sources = ["main.c", "fib.c",]
+            include = ["lib"]
+            output = "fib"  # Builds fib.exe.
+            target = "x64"
+            libs = ["user32", "gdi32"]
+        "#,
+        );
+        let expected = Project {
+            sources: vec![PathBuf::from("main.c"), PathBuf::from("fib.c")],
+            include: vec![PathBuf::from("lib")],
+            output: String::from("fib"),
+            libs: vec![String::from("user32"), String::from("gdi32")],
+        };
+        assert_eq!(project, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported target: arm64")]
+    fn target() {
+        parse("sources = [\"main.c\"]\ntarget = \"arm64\"");
+    }
+}
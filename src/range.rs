@@ -0,0 +1,423 @@
+use crate::ir::{Expression, Int, SSAFunction, SSAProgram, SSAVar, Statement};
+use crate::traversal;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Value-range analysis over SSA. The interval of a variable covers every value
+// it may hold anywhere in its function: the union of its phi operands, or the
+// value assigned to it.
+// Reading a variable with no assignment yet gives `TOP`, so the intervals
+// only grow and blocks are visited in reverse postorder to keep them tight.
+// Bounds are wider than `Int`, so no arithmetic on them overflows.
+pub type Interval = (i128, i128);
+
+const TOP: Interval = (Int::MIN as i128, Int::MAX as i128);
+
+// Rounds after which an interval that still grows is widened to `TOP`, so
+// loops counting up or down reach a fixpoint.
+const WIDEN_AFTER: usize = 3;
+
+type Ranges = HashMap<SSAVar, Interval>;
+
+// What the ranges assume of signed arithmetic that leaves `Int`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Overflow {
+    #[default]
+    Wrap, // It wraps around in two's complement, as the generated code does.
+    Undefined, // It never happens, so every result stays within `Int`.
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Overflow::Wrap),
+            "undefined" => Ok(Overflow::Undefined),
+            s => Err(format!("Unknown overflow semantics `{}`.", s)),
+        }
+    }
+}
+
+// Replace every comparison whose result the ranges of its operands decide by
+// the constant result. Returns the number of comparisons removed.
+pub fn fold_comparisons(ssa: &mut SSAProgram, overflow: Overflow) -> usize {
+    let mut cnt = 0;
+    for func in ssa {
+        let ranges = analyze(func, overflow);
+        for block in &mut func.body {
+            for stmt in &mut block.statements {
+                cnt += fold_stmt(stmt, &ranges, overflow);
+            }
+        }
+    }
+    cnt
+}
+
+pub fn analyze(func: &SSAFunction, overflow: Overflow) -> Ranges {
+    let mut ranges = Ranges::new();
+    for param in &func.parameters {
+        ranges.insert(param.clone(), TOP);
+    }
+    let mut order: Vec<_> = traversal::reverse_postorder(&func.body, 0).collect();
+    let unreachable: Vec<_> = (0..func.body.len())
+        .filter(|i| !order.contains(i))
+        .collect();
+    order.extend(unreachable);
+    let mut round = 0;
+    loop {
+        let mut changed = false;
+        for &i in &order {
+            for stmt in &func.body[i].statements {
+                let mut defs = Vec::new();
+                find_stmt_defs(stmt, &ranges, overflow, &mut defs);
+                for (var, mut interval) in defs {
+                    if func.is_double(&var) {
+                        interval = TOP;
+                    }
+                    let old = ranges.get(&var).copied();
+                    let mut new = match old {
+                        Some(old) => union(old, interval),
+                        None => interval,
+                    };
+                    if Some(new) != old {
+                        if round >= WIDEN_AFTER {
+                            new = TOP;
+                        }
+                        ranges.insert(var, new);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            return ranges;
+        }
+        round += 1;
+    }
+}
+
+// Every definition in `stmt`, with the interval of the value it defines.
+fn find_stmt_defs(
+    stmt: &Statement,
+    ranges: &Ranges,
+    overflow: Overflow,
+    defs: &mut Vec<(SSAVar, Interval)>,
+) {
+    match stmt {
+        Statement::Phi(var, values) => {
+            let interval = values
+                .iter()
+                .map(|(_, value)| read(value, ranges))
+                .reduce(union);
+            if let Some(interval) = interval {
+                defs.push((var.clone(), interval));
+            }
+        }
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                find_stmt_defs(stmt, ranges, overflow, defs);
+            }
+        }
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => find_expr_defs(expr, ranges, overflow, defs),
+        Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Declaration(_)
+        | Statement::ArrayDeclaration(..)
+        | Statement::Return(None) => {}
+    }
+}
+
+fn find_expr_defs(
+    expr: &Expression,
+    ranges: &Ranges,
+    overflow: Overflow,
+    defs: &mut Vec<(SSAVar, Interval)>,
+) {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_expr_defs(index, ranges, overflow, defs);
+            }
+        }
+        Expression::Call { arguments, .. } => find_expr_defs(arguments, ranges, overflow, defs),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                find_expr_defs(expr, ranges, overflow, defs);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_expr_defs(expression, ranges, overflow, defs),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            if let (Expression::Identifier(var), "=") = (&**left, *operator) {
+                defs.push((var.clone(), eval(right, ranges, overflow)));
+            }
+            find_expr_defs(left, ranges, overflow, defs);
+            find_expr_defs(right, ranges, overflow, defs);
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn read(var: &SSAVar, ranges: &Ranges) -> Interval {
+    ranges.get(var).copied().unwrap_or(TOP)
+}
+
+fn eval(expr: &Expression, ranges: &Ranges, overflow: Overflow) -> Interval {
+    let interval = match expr {
+        Expression::Identifier(var) => read(var, ranges),
+        Expression::Number(num) => (*num as i128, *num as i128),
+        // Fractions fall between the bounds, so `double` values are unknown.
+        Expression::Float(_) => TOP,
+        Expression::Index { .. } | Expression::Call { .. } | Expression::Arguments(_) => TOP,
+        Expression::Prefix {
+            operator,
+            expression,
+        } => {
+            let (lo, hi) = eval(expression, ranges, overflow);
+            match *operator {
+                "-" => (-hi, -lo),
+                "!" if lo == 0 && hi == 0 => (1, 1),
+                "!" if lo > 0 || hi < 0 => (0, 0),
+                "!" => (0, 1),
+                // Addresses and values in memory are unknown.
+                "&" | "*" => TOP,
+                _ => (lo, hi),
+            }
+        }
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let right = eval(right, ranges, overflow);
+            if *operator == "=" {
+                return right;
+            }
+            let left = eval(left, ranges, overflow);
+            match *operator {
+                "+" => (left.0 + right.0, left.1 + right.1),
+                "-" => (left.0 - right.1, left.1 - right.0),
+                "*" => corners(left, right, |a, b| a * b),
+                "/" if right.0 > 0 || right.1 < 0 => corners(left, right, |a, b| a / b),
+                // A remainder is smaller than the divisor and takes the sign of the dividend.
+                "%" if right.0 > 0 || right.1 < 0 => {
+                    let max = right.0.abs().max(right.1.abs()) - 1;
+                    (left.0.max(-max).min(0), left.1.min(max).max(0))
+                }
+                // `&&` and `||` are bitwise on their operands' values.
+                "&&" if is_const(left) && is_const(right) => (left.0 & right.0, left.0 & right.0),
+                "||" if is_const(left) && is_const(right) => (left.0 | right.0, left.0 | right.0),
+                op => match compare(op, left, right) {
+                    Some(true) => (1, 1),
+                    Some(false) => (0, 0),
+                    None if is_comparison(op) => (0, 1),
+                    None => TOP,
+                },
+            }
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    };
+    // Values outside `Int` wrap around at run time, unless overflow is
+    // undefined, when only the values within `Int` are left. A result that
+    // always overflows is still unknown.
+    let (lo, hi) = (interval.0.max(TOP.0), interval.1.min(TOP.1));
+    match overflow {
+        Overflow::Undefined if lo <= hi => (lo, hi),
+        _ if interval == (lo, hi) => interval,
+        _ => TOP,
+    }
+}
+
+// The result of the comparison `op` if it is the same for all operand values.
+fn compare(op: &str, (l_lo, l_hi): Interval, (r_lo, r_hi): Interval) -> Option<bool> {
+    let (always, never) = match op {
+        "<" => (l_hi < r_lo, l_lo >= r_hi),
+        ">" => (l_lo > r_hi, l_hi <= r_lo),
+        "<=" => (l_hi <= r_lo, l_lo > r_hi),
+        ">=" => (l_lo >= r_hi, l_hi < r_lo),
+        "==" => (
+            l_lo == l_hi && r_lo == r_hi && l_lo == r_lo,
+            l_hi < r_lo || r_hi < l_lo,
+        ),
+        "!=" => (
+            l_hi < r_lo || r_hi < l_lo,
+            l_lo == l_hi && r_lo == r_hi && l_lo == r_lo,
+        ),
+        _ => return None,
+    };
+    match (always, never) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+fn is_comparison(op: &str) -> bool {
+    matches!(op, "<" | ">" | "<=" | ">=" | "==" | "!=")
+}
+
+fn is_const((lo, hi): Interval) -> bool {
+    lo == hi
+}
+
+fn corners(left: Interval, right: Interval, op: impl Fn(i128, i128) -> i128) -> Interval {
+    let values = [
+        op(left.0, right.0),
+        op(left.0, right.1),
+        op(left.1, right.0),
+        op(left.1, right.1),
+    ];
+    (*values.iter().min().unwrap(), *values.iter().max().unwrap())
+}
+
+fn union(a: Interval, b: Interval) -> Interval {
+    (a.0.min(b.0), a.1.max(b.1))
+}
+
+fn fold_stmt(stmt: &mut Statement, ranges: &Ranges, overflow: Overflow) -> usize {
+    match stmt {
+        Statement::Compound(stmts) => stmts
+            .iter_mut()
+            .map(|s| fold_stmt(s, ranges, overflow))
+            .sum(),
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => fold_expr(expr, ranges, overflow),
+        Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Phi(..)
+        | Statement::Declaration(_)
+        | Statement::ArrayDeclaration(..)
+        | Statement::Return(None) => 0,
+    }
+}
+
+fn fold_expr(expr: &mut Expression, ranges: &Ranges, overflow: Overflow) -> usize {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => 0,
+        Expression::Index { indices, .. } => indices
+            .iter_mut()
+            .map(|e| fold_expr(e, ranges, overflow))
+            .sum(),
+        Expression::Call { arguments, .. } => fold_expr(arguments, ranges, overflow),
+        Expression::Arguments(exprs) => exprs
+            .iter_mut()
+            .map(|e| fold_expr(e, ranges, overflow))
+            .sum(),
+        Expression::Prefix { expression, .. } => fold_expr(expression, ranges, overflow),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            // Folding must not drop a call or an assignment.
+            if is_comparison(operator) && is_pure(left) && is_pure(right) {
+                if let Some(result) = compare(
+                    operator,
+                    eval(left, ranges, overflow),
+                    eval(right, ranges, overflow),
+                ) {
+                    *expr = Expression::Number(result as Int);
+                    return 1;
+                }
+            }
+            fold_expr(left, ranges, overflow) + fold_expr(right, ranges, overflow)
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+fn is_pure(expr: &Expression) -> bool {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => true,
+        Expression::Index { indices, .. } => indices.iter().all(is_pure),
+        Expression::Call { .. } | Expression::Arguments(_) => false,
+        Expression::Prefix { expression, .. } => is_pure(expression),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => *operator != "=" && is_pure(left) && is_pure(right),
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, ssa};
+
+    #[test]
+    fn fold() {
+        let ast = parser::parse(
+            "
+            int main(int n) {
+                int a;
+                int b;
+                a = 1;
+                if (n) {
+                    a = 5;
+                }
+                b = 0;
+                while (b < 10) {
+                    b = b + 1;
+                }
+                if (a < 6) {
+                    return a > 0;
+                }
+                return b < n;
+            }
+        ",
+        );
+        let mut ssa = ssa::construct(ast);
+        let ranges = analyze(&ssa[0], Overflow::Wrap);
+        // The phi of `a` after the first `if`.
+        let a = SSAVar {
+            name: String::from("a"),
+            subscript: Some(3),
+        };
+        assert_eq!(ranges[&a], (1, 5));
+        // `a < 6` and `a > 0` are decided; `b < 10` and `b < n` are not.
+        assert_eq!(fold_comparisons(&mut ssa, Overflow::Wrap), 2);
+    }
+
+    #[test]
+    fn overflow() {
+        let source = "
+            int main(int n) {
+                int a = n % 10 + 9223372036854775800;
+                return a > 0;
+            }
+        ";
+        // `a` may wrap around to a negative value, unless overflow is undefined.
+        let mut ssa = ssa::construct(parser::parse(source));
+        assert_eq!(fold_comparisons(&mut ssa, Overflow::Wrap), 0);
+        let mut ssa = ssa::construct(parser::parse(source));
+        assert_eq!(fold_comparisons(&mut ssa, Overflow::Undefined), 1);
+    }
+}
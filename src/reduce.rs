@@ -0,0 +1,232 @@
+// Shrink a program that shows a bug to a small reproducer by delta debugging:
+// remove ever smaller chunks of it as long as the bug still shows. Whether it
+// does is up to `interesting`, such as "the emulated result differs from the
+// expected one". Most candidates are not valid programs, so a candidate that
+// makes `interesting` panic is not interesting, and its panic is not printed.
+// A predicate looking for a crash has to catch the panic itself.
+use crate::formatter;
+use crate::ir::{Program, Statement};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+
+// The smallest source found by removing lines from `source`, after putting
+// every statement on a line of its own. Removing a single line of a block
+// rarely leaves valid C, so whole blocks, from the line opening one to the
+// line closing it, are removed as well.
+pub fn reduce_source(source: &str, mut interesting: impl FnMut(&str) -> bool) -> String {
+    quietly(|| {
+        let mut test = |source: &str| try_interesting(&mut interesting, source);
+        if !test(source) {
+            return Err(());
+        }
+        let formatted = formatter::format(source);
+        let source = if test(&formatted) { &formatted } else { source };
+        let mut lines: Vec<_> = source.split_inclusive('\n').collect();
+        loop {
+            lines = ddmin(lines, |lines| test(&lines.concat()));
+            let block = blocks(&lines).into_iter().find(|block| {
+                let mut candidate = lines[..block.start].to_vec();
+                candidate.extend_from_slice(&lines[block.end..]);
+                test(&candidate.concat())
+            });
+            match block {
+                Some(block) => drop(lines.drain(block)),
+                None => return Ok(lines.concat()),
+            }
+        }
+    })
+}
+
+// The smallest program found by removing functions, and then statements, from
+// `program`.
+pub fn reduce_program(program: Program, mut interesting: impl FnMut(&Program) -> bool) -> Program {
+    quietly(|| {
+        let mut test = |program: &Program| try_interesting(&mut interesting, program);
+        if !test(&program) {
+            return Err(());
+        }
+        let mut program = ddmin(program, |funcs| test(&funcs.to_vec()));
+        for i in 0..program.len() {
+            let body = program[i].body.clone();
+            let mut count = 0;
+            filter(&body, &mut count, &HashSet::new());
+            let kept = ddmin((0..count).collect(), |kept| {
+                let kept = kept.iter().copied().collect();
+                let mut candidate = program.clone();
+                candidate[i].body = filter(&body, &mut 0, &kept);
+                test(&candidate)
+            });
+            program[i].body = filter(&body, &mut 0, &kept.into_iter().collect());
+        }
+        Ok(program)
+    })
+}
+
+// Run `reduce` with the panics of the candidates hidden, and panic if the
+// input itself is not interesting.
+fn quietly<T>(reduce: impl FnOnce() -> Result<T, ()>) -> T {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let reduced = panic::catch_unwind(AssertUnwindSafe(reduce));
+    panic::set_hook(hook);
+    match reduced {
+        Ok(Ok(reduced)) => reduced,
+        Ok(Err(())) => panic!("The input to reduce is not interesting to begin with."),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+fn try_interesting<T: ?Sized>(interesting: &mut impl FnMut(&T) -> bool, candidate: &T) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(|| interesting(candidate))).unwrap_or(false)
+}
+
+// Zeller's ddmin, removing one of `n` chunks at a time and splitting the
+// chunks further whenever none can be removed. Returns a subsequence of
+// `items` that passes `test` and from which no single item can be removed.
+fn ddmin<T: Clone>(items: Vec<T>, mut test: impl FnMut(&[T]) -> bool) -> Vec<T> {
+    let mut items = items;
+    let mut n = 2;
+    while !items.is_empty() {
+        let chunk = items.len().div_ceil(n);
+        let reduced = (0..items.len()).step_by(chunk).find_map(|start| {
+            let mut candidate = items[..start].to_vec();
+            candidate.extend_from_slice(&items[(start + chunk).min(items.len())..]);
+            Some(candidate).filter(|candidate| test(candidate))
+        });
+        match reduced {
+            Some(reduced) => {
+                items = reduced;
+                n = (n - 1).max(2);
+            }
+            None if chunk == 1 => break,
+            None => n = (n * 2).min(items.len()),
+        }
+    }
+    items
+}
+
+// The lines of every block, from the line with its `{` to the line with its
+// `}`, outermost first.
+fn blocks(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut open = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        for c in line.chars() {
+            match c {
+                '{' => open.push(i),
+                '}' => {
+                    if let Some(start) = open.pop() {
+                        blocks.push(start..i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    blocks.sort_by_key(|block| (block.start, usize::MAX - block.end));
+    blocks
+}
+
+// `stmt` with only the statements of its compound statements whose index, in
+// preorder from `next`, is in `kept`. Returns the statement and advances
+// `next` past every statement inside `stmt`, removed or not.
+fn filter(stmt: &Statement, next: &mut usize, kept: &HashSet<usize>) -> Statement {
+    match stmt {
+        Statement::Compound(stmts) => Statement::Compound(
+            stmts
+                .iter()
+                .filter_map(|stmt| {
+                    let index = *next;
+                    *next += 1;
+                    let stmt = filter(stmt, next, kept);
+                    Some(stmt).filter(|_| kept.contains(&index))
+                })
+                .collect(),
+        ),
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => Statement::If {
+            condition: condition.clone(),
+            body: Box::new(filter(body, next, kept)),
+            alternative: alternative
+                .as_ref()
+                .map(|alternative| Box::new(filter(alternative, next, kept))),
+        },
+        Statement::While { condition, body } => Statement::While {
+            condition: condition.clone(),
+            body: Box::new(filter(body, next, kept)),
+        },
+        Statement::DoWhile { body, condition } => Statement::DoWhile {
+            body: Box::new(filter(body, next, kept)),
+            condition: condition.clone(),
+        },
+        stmt => stmt.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, parser, Opt};
+
+    #[test]
+    fn ddmin_items() {
+        let items: Vec<_> = (0..20).collect();
+        let reduced = ddmin(items, |items| items.contains(&3) && items.contains(&17));
+        assert_eq!(reduced, vec![3, 17]);
+    }
+
+    #[test]
+    fn source() {
+        let source = "
+            int f(int a) { return a * 2; }
+            int main(int a, int b) {
+                int c = f(a);
+                while (c < 100) { c = c + b; }
+                if (c > 5) { c = c - 1; } else { c = b / a; }
+                return c;
+            }
+        ";
+        // Whatever still divides.
+        let divides = |source: &str| compile(source, Opt::default()).unwrap().contains("idiv");
+        let reduced = reduce_source(source, divides);
+        // The loop is kept, as it is what initializes `c`.
+        let expected = "\
+int main(int a, int b) {
+    while (c < 100) {
+        c = c + b;
+    }
+    if (c > 5) {
+        c = b / a;
+    }
+}
+";
+        assert_eq!(reduced, expected);
+    }
+
+    #[test]
+    fn program() {
+        let source = "
+            int f(int a) { return a * 2; }
+            int main(int a, int b) {
+                int c = f(a);
+                while (c < 100) { c = c + b; c = c % 7; }
+                return c;
+            }
+        ";
+        let remainder = |program: &Program| format!("{:?}", program).contains("\"%\"");
+        let reduced = reduce_program(parser::parse(source), remainder);
+        let expected = parser::parse("int main(int a, int b) { while (c < 100) { c = c % 7; } }");
+        assert_eq!(reduced.len(), 1);
+        assert_eq!(reduced[0].body, expected[0].body);
+    }
+
+    #[test]
+    #[should_panic(expected = "The input to reduce is not interesting to begin with.")]
+    fn uninteresting() {
+        reduce_source("int main() { return 0; }", |_| false);
+    }
+}
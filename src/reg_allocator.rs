@@ -1,26 +1,99 @@
-use crate::x64::{Register, X64Function, X64Program, X64RegisterAllocator, X64};
+use crate::pressure;
+use crate::x64::{
+    Register, RegisterClass, Spill, X64Function, X64Program, X64RegisterAllocator, X64,
+};
+use std::collections::{HashMap, HashSet};
 
-pub fn alloc(asm: X64Program) -> X64Program {
+pub fn alloc(asm: X64Program, spill: Spill) -> X64Program {
     asm.into_iter()
         .map(
             |X64Function {
                  name,
                  param_cnt,
+                 arrays,
                  body,
              }| X64Function {
                 name,
                 param_cnt,
-                body: alloc_body(param_cnt, body),
+                body: alloc_body(param_cnt, &arrays, body, spill),
+                arrays,
             },
         )
         .collect()
 }
 
-fn alloc_body(param_cnt: usize, body: Vec<X64>) -> Vec<X64> {
-    let mut allocator = X64RegisterAllocator::new(param_cnt);
+fn alloc_body(param_cnt: usize, arrays: &[usize], body: Vec<X64>, spill: Spill) -> Vec<X64> {
+    let mut uses: HashMap<_, Vec<_>> = HashMap::new();
+    for (position, (asm, weight)) in body.iter().zip(loop_weights(&body)).enumerate() {
+        for vreg in asm.registers() {
+            uses.entry(vreg).or_default().push((position, weight));
+        }
+    }
+    let splits = pressure::splits(&body);
+    let live = pressure::live_after(&body);
+    let calls = body.iter().any(|asm| matches!(asm, X64::Call(..)));
+    let mut params = vec![None; param_cnt];
+    for asm in &body {
+        if let X64::Param(vreg, index) = asm {
+            params[*index] = Some(*vreg);
+        }
+    }
+    let mut allocator =
+        X64RegisterAllocator::new(param_cnt, &params, arrays, uses, live, spill, calls);
     let mut assemblies = allocator.prolog();
-    for asm in body {
+    // The allocation state at each tag is set by the first path that reaches
+    // it, and every other path is reconciled to that state. A conditional
+    // jump reconciles on its edge alone: the path falling through branches
+    // around the moves on the inverted condition and keeps its own state.
+    let mut states = HashMap::new();
+    let mut reachable = true;
+    for (position, asm) in body.into_iter().enumerate() {
+        allocator.begin(position, asm.registers());
+        if let X64::Tag(tag) = &asm {
+            match (states.get(tag), reachable) {
+                (Some(state), true) => assemblies.extend(allocator.reconcile(state)),
+                (Some(state), false) => allocator.restore(state),
+                (None, reachable) => {
+                    // Entering a loop that needs more registers than there
+                    // are, which is where its splits go.
+                    if reachable {
+                        for split in splits.get(&position).into_iter().flatten() {
+                            assemblies.extend(allocator.split(&split.candidates, split.excess));
+                        }
+                    }
+                    states.insert(tag.clone(), allocator.snapshot());
+                }
+            }
+            reachable = true;
+        } else if let Some(tag) = asm.jump_target() {
+            match states.get(tag) {
+                Some(state) if matches!(asm, X64::Jmp(_)) => {
+                    assemblies.extend(allocator.reconcile(state))
+                }
+                Some(state) => {
+                    let fall_through = allocator.snapshot();
+                    let moves = allocator.reconcile(state);
+                    allocator.restore(&fall_through);
+                    if !moves.is_empty() {
+                        let skip = format!("{}_Skip{}", tag, position);
+                        assemblies.push(inverse(&asm, skip.clone()));
+                        assemblies.extend(moves);
+                        assemblies.push(X64::Jmp(tag.clone()));
+                        assemblies.push(X64::Tag(skip));
+                        continue;
+                    }
+                }
+                None => {
+                    states.insert(tag.clone(), allocator.snapshot());
+                }
+            }
+        }
+        if let X64::Jmp(_) | X64::Ret(_) = asm {
+            reachable = false;
+        }
         let asms = match asm {
+            // The parameter is already where the calling convention put it.
+            X64::Param(..) => Vec::new(),
             X64::MovNum(vreg, num) => {
                 let (mut asms, reg) = allocator.alloc(vreg);
                 asms.push(X64::MovNum(reg, num));
@@ -33,13 +106,73 @@ fn alloc_body(param_cnt: usize, body: Vec<X64>) -> Vec<X64> {
                 left_asms.push(X64::MovReg(left, right));
                 left_asms
             }
-            X64::Call(func, args, ret) => {
-                let mut asms = allocator.call_prolog(args);
-                asms.push(X64::Call(func, Vec::new(), Register::Virtual(0)));
+            X64::Call(func, args, ret, variadic) => {
+                let mut asms = allocator.call_prolog(args, variadic);
+                // The callee defines RAX, or XMM0 for a `double`, which is then
+                // copied into the result.
+                let result = match ret.class() {
+                    RegisterClass::General => X64RegisterAllocator::RAX,
+                    RegisterClass::Xmm => X64RegisterAllocator::XMM0,
+                };
+                asms.push(X64::Call(func, Vec::new(), result, variadic));
                 asms.extend(allocator.call_epilog());
                 let (a_s, ret) = allocator.alloc(ret);
                 asms.extend(a_s);
-                asms.push(X64::MovReg(ret, X64RegisterAllocator::RAX));
+                asms.push(X64::MovReg(ret, result));
+                asms
+            }
+            X64::LoadIndex(vreg, array, index) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                let (index_asms, index) = allocator.alloc(index);
+                asms.extend(index_asms);
+                asms.push(X64::LoadIndex(reg, allocator.array_offset(array), index));
+                asms
+            }
+            X64::StoreIndex(array, index, vreg) => {
+                let (mut asms, index) = allocator.alloc(index);
+                let (reg_asms, reg) = allocator.alloc(vreg);
+                asms.extend(reg_asms);
+                asms.push(X64::StoreIndex(allocator.array_offset(array), index, reg));
+                asms
+            }
+            X64::LeaIndex(vreg, array, index) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                let (index_asms, index) = allocator.alloc(index);
+                asms.extend(index_asms);
+                asms.push(X64::LeaIndex(reg, allocator.array_offset(array), index));
+                asms
+            }
+            X64::Lea(vreg, base, index, scale) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                let (base_asms, base) = allocator.alloc(base);
+                let (index_asms, index) = allocator.alloc(index);
+                asms.extend(base_asms);
+                asms.extend(index_asms);
+                asms.push(X64::Lea(reg, base, index, scale));
+                asms
+            }
+            X64::LoadField(vreg, array, offset) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                asms.push(X64::LoadField(reg, allocator.array_offset(array), offset));
+                asms
+            }
+            X64::StoreField(array, offset, vreg) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                asms.push(X64::StoreField(allocator.array_offset(array), offset, reg));
+                asms
+            }
+            X64::Load(vreg, address) => {
+                let (mut asms, reg) = allocator.alloc(vreg);
+                let (address_asms, address) = allocator.alloc(address);
+                asms.extend(address_asms);
+                asms.push(X64::Load(reg, address));
+                asms
+            }
+            X64::Store(address, vreg) => {
+                let (mut asms, address) = allocator.alloc(address);
+                let (reg_asms, reg) = allocator.alloc(vreg);
+                asms.extend(reg_asms);
+                asms.push(X64::Store(address, reg));
                 asms
             }
             X64::Neg(vreg) => {
@@ -66,13 +199,8 @@ fn alloc_body(param_cnt: usize, body: Vec<X64>) -> Vec<X64> {
                 left_asms.push(X64::Imul(left, right));
                 left_asms
             }
-            X64::Idiv(left, right) => {
-                let (mut left_asms, left) = allocator.alloc(left);
-                let (right_asms, right) = allocator.alloc(right);
-                left_asms.extend(right_asms);
-                left_asms.push(X64::Idiv(left, right));
-                left_asms
-            }
+            X64::Idiv(left, right) => allocator.divide(left, right, X64RegisterAllocator::RAX),
+            X64::Irem(left, right) => allocator.divide(left, right, X64RegisterAllocator::RDX),
             X64::Add(left, right) => {
                 let (mut left_asms, left) = allocator.alloc(left);
                 let (right_asms, right) = allocator.alloc(right);
@@ -101,8 +229,18 @@ fn alloc_body(param_cnt: usize, body: Vec<X64>) -> Vec<X64> {
                 left_asms.push(X64::Or(left, right));
                 left_asms
             }
-            X64::Ret(Some(vreg)) => {
-                let mut asms = allocator.ret(vreg);
+            X64::Cvtsi2sd(left, right) => binary(&mut allocator, left, right, X64::Cvtsi2sd),
+            X64::Cvttsd2si(left, right) => binary(&mut allocator, left, right, X64::Cvttsd2si),
+            X64::Addsd(left, right) => binary(&mut allocator, left, right, X64::Addsd),
+            X64::Subsd(left, right) => binary(&mut allocator, left, right, X64::Subsd),
+            X64::Mulsd(left, right) => binary(&mut allocator, left, right, X64::Mulsd),
+            X64::Divsd(left, right) => binary(&mut allocator, left, right, X64::Divsd),
+            X64::Ucomisd(left, right) => binary(&mut allocator, left, right, X64::Ucomisd),
+            X64::Ret(vreg) => {
+                let mut asms = match vreg {
+                    Some(vreg) => allocator.ret(vreg),
+                    None => Vec::new(),
+                };
                 asms.extend(allocator.epilog());
                 asms
             }
@@ -110,16 +248,97 @@ fn alloc_body(param_cnt: usize, body: Vec<X64>) -> Vec<X64> {
         };
         assemblies.extend(asms);
     }
-    assemblies.extend(allocator.epilog());
+    // Only a body that can run off its end needs a trailing epilog.
+    if reachable {
+        assemblies.extend(allocator.epilog());
+    }
+    if !calls {
+        drop_unused_saves(&mut assemblies);
+    }
     assemblies
 }
 
+// The conditional jump to `tag` taken exactly when `jump` is not.
+fn inverse(jump: &X64, tag: String) -> X64 {
+    match jump {
+        X64::Jl(_) => X64::Jge(tag),
+        X64::Jg(_) => X64::Jle(tag),
+        X64::Jle(_) => X64::Jg(tag),
+        X64::Jge(_) => X64::Jl(tag),
+        X64::Je(_) => X64::Jne(tag),
+        X64::Jne(_) => X64::Je(tag),
+        X64::Jb(_) => X64::Jae(tag),
+        X64::Ja(_) => X64::Jbe(tag),
+        X64::Jbe(_) => X64::Ja(tag),
+        X64::Jae(_) => X64::Jb(tag),
+        _ => unreachable!(),
+    }
+}
+
+// Allocate both operands of an instruction built by `op`.
+fn binary(
+    allocator: &mut X64RegisterAllocator,
+    left: Register,
+    right: Register,
+    op: fn(Register, Register) -> X64,
+) -> Vec<X64> {
+    let (mut asms, left) = allocator.alloc(left);
+    let (right_asms, right) = allocator.alloc(right);
+    asms.extend(right_asms);
+    asms.push(op(left, right));
+    asms
+}
+
+// A leaf function never moves RBP and allocates no frame for callees, so it
+// only needs to save the callee-saved registers it actually uses.
+fn drop_unused_saves(body: &mut Vec<X64>) {
+    let used: HashSet<_> = body
+        .iter()
+        .filter(|asm| !matches!(asm, X64::Push(_) | X64::Pop(_)))
+        .flat_map(|asm| asm.registers())
+        .collect();
+    body.retain(|asm| match asm {
+        X64::Push(reg) | X64::Pop(reg) => used.contains(reg),
+        _ => true,
+    });
+}
+
+// Uses inside a loop are weighted by 10 for each level of nesting, where a
+// loop spans from a tag to the last jump back to it.
+fn loop_weights(body: &[X64]) -> Vec<usize> {
+    let mut depths = vec![0; body.len()];
+    let mut tags = HashMap::new();
+    for (index, asm) in body.iter().enumerate() {
+        if let X64::Tag(tag) = asm {
+            tags.insert(tag, index);
+        }
+    }
+    for (index, asm) in body.iter().enumerate() {
+        if let Some(&start) = asm.jump_target().and_then(|tag| tags.get(tag)) {
+            if start < index {
+                for depth in &mut depths[start..=index] {
+                    *depth += 1;
+                }
+            }
+        }
+    }
+    depths
+        .into_iter()
+        .map(|depth| 10_usize.saturating_pow(depth))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::asm::X64Builder;
+    use crate::emulator;
+    use crate::ir::Int;
+    use crate::lir;
     use crate::parser;
+    use crate::peephole;
     use crate::ssa;
+    use crate::x64::Register;
     use crate::x64::X64RegisterAllocator as X64R;
 
     #[test]
@@ -140,44 +359,21 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
-        let asm = alloc(asm);
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let asm = alloc(asm, Spill::Cost);
         let expected = vec![
             X64Function {
                 name: String::from("f"),
                 param_cnt: 5,
-                body: vec![
-                    X64::Push(X64R::RBX),
-                    X64::Push(X64R::RSI),
-                    X64::Push(X64R::RDI),
-                    X64::Push(X64R::R12),
-                    X64::Push(X64R::R13),
-                    X64::Push(X64R::R14),
-                    X64::Push(X64R::R15),
-                    X64::MovReg(X64R::RAX, X64R::RCX),
-                    X64::Pop(X64R::R15),
-                    X64::Pop(X64R::R14),
-                    X64::Pop(X64R::R13),
-                    X64::Pop(X64R::R12),
-                    X64::Pop(X64R::RDI),
-                    X64::Pop(X64R::RSI),
-                    X64::Pop(X64R::RBX),
-                    X64::Ret(None),
-                    X64::Pop(X64R::R15),
-                    X64::Pop(X64R::R14),
-                    X64::Pop(X64R::R13),
-                    X64::Pop(X64R::R12),
-                    X64::Pop(X64R::RDI),
-                    X64::Pop(X64R::RSI),
-                    X64::Pop(X64R::RBX),
-                    X64::Ret(None),
-                ],
+                arrays: Vec::new(),
+                body: vec![X64::MovReg(X64R::RAX, X64R::RCX), X64::Ret(None)],
             },
             X64Function {
                 name: String::from("main"),
                 param_cnt: 0,
+                arrays: Vec::new(),
                 body: vec![
                     X64::Push(X64R::RBX),
                     X64::Push(X64R::RSI),
@@ -186,44 +382,27 @@ mod tests {
                     X64::Push(X64R::R13),
                     X64::Push(X64R::R14),
                     X64::Push(X64R::R15),
-                    X64::Push(X64R::RCX),
-                    X64::Push(X64R::RDX),
-                    X64::Push(X64R::R8),
-                    X64::Push(X64R::R9),
-                    X64::Push(X64R::R10),
-                    X64::Push(X64R::R11),
-                    X64::SubNum(X64R::RSP, X64R::FRAME_SIZE),
+                    X64::Push(X64R::RBP),
+                    X64::SubNum(X64R::RSP, X64R::CALL_AREA_SIZE),
+                    X64::MovReg(X64R::RAX, X64R::RBP),
                     X64::MovReg(X64R::RBP, X64R::RSP),
-                    X64::MovToStack(0 * X64R::INT_SIZE, X64R::R15),
+                    X64::MovToStack(X64R::FRAME_SIZE + 8, X64R::RAX),
+                    X64::MovToStack(X64R::FRAME_SIZE + 16, X64R::R11),
+                    X64::MovToStack(4 * X64R::WORD_SIZE, X64R::R11),
                     X64::MovReg(X64R::RCX, X64R::R15),
-                    X64::MovToStack(1 * X64R::INT_SIZE, X64R::R14),
                     X64::MovReg(X64R::RDX, X64R::R14),
-                    X64::MovToStack(2 * X64R::INT_SIZE, X64R::R13),
                     X64::MovReg(X64R::R8, X64R::R13),
-                    X64::MovToStack(3 * X64R::INT_SIZE, X64R::R12),
                     X64::MovReg(X64R::R9, X64R::R12),
-                    X64::MovToStack(4 * X64R::INT_SIZE, X64R::R11),
-                    X64::Call(String::from("f"), Vec::new(), Register::Virtual(0)),
-                    X64::AddNum(X64R::RSP, X64R::FRAME_SIZE),
-                    X64::Pop(X64R::R11),
-                    X64::Pop(X64R::R10),
-                    X64::Pop(X64R::R9),
-                    X64::Pop(X64R::R8),
-                    X64::Pop(X64R::RDX),
-                    X64::Pop(X64R::RCX),
+                    X64::Call(String::from("f"), Vec::new(), X64R::RAX, false),
+                    X64::MovFromStack(X64R::R11, X64R::FRAME_SIZE + 16),
+                    X64::MovFromStack(X64R::RBP, X64R::FRAME_SIZE + 8),
                     X64::MovReg(X64R::R10, X64R::RAX),
                     X64::MovNum(X64R::R9, 1),
                     X64::MovReg(X64R::R8, X64R::R10),
                     X64::Add(X64R::R8, X64R::R9),
                     X64::MovReg(X64R::RAX, X64R::R8),
-                    X64::Pop(X64R::R15),
-                    X64::Pop(X64R::R14),
-                    X64::Pop(X64R::R13),
-                    X64::Pop(X64R::R12),
-                    X64::Pop(X64R::RDI),
-                    X64::Pop(X64R::RSI),
-                    X64::Pop(X64R::RBX),
-                    X64::Ret(None),
+                    X64::AddNum(X64R::RSP, X64R::CALL_AREA_SIZE),
+                    X64::Pop(X64R::RBP),
                     X64::Pop(X64R::R15),
                     X64::Pop(X64R::R14),
                     X64::Pop(X64R::R13),
@@ -238,6 +417,410 @@ mod tests {
         assert_eq!(asm, expected);
     }
 
+    #[test]
+    fn nested_call_arguments() {
+        // Calls among the arguments run first, and only then are the
+        // arguments moved into the registers of the calling convention, so
+        // no call clobbers an argument already in place. `swap` passes its
+        // parameters to `f` in each other's registers.
+        let ast = parser::parse(
+            "
+            int g(int x) { return x + 10; }
+            int h(int x) { return x * 3; }
+            int f(int a, int b) { return a * 100 + b; }
+            int swap(int a, int b) { return f(b, a); }
+            int k(int a, int b, int c, int d, int e, int f) {
+                return a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f;
+            }
+            double m(double x, int n) { return x * n; }
+            int main(int n) {
+                int r = f(g(1), h(2));
+                int s = k(g(n), h(g(2)), 3, f(1, 2), g(h(1)), swap(n, 6));
+                int t = m(m(1.5, g(0)), h(1));
+                return r * 10000 + s * 10 + t % 10;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        for spill in [Spill::Cost, Spill::NextUse] {
+            let (asm, _) = peephole::cleanup(alloc(vasm.clone(), spill));
+            assert_eq!(emulator::run(&asm, &[1]), Ok(1106 * 10000 + 4171 * 10 + 5));
+        }
+    }
+
+    #[test]
+    fn void_return() {
+        let ast = parser::parse(
+            "
+            void main() {
+                return;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let asm = alloc(asm, Spill::Cost);
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![X64::Ret(None)],
+        }];
+        assert_eq!(asm, expected);
+    }
+
+    #[test]
+    fn division() {
+        let vreg = Register::Virtual;
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 2,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Param(vreg(0), 0),
+                X64::Param(vreg(1), 1),
+                X64::Irem(vreg(0), vreg(1)),
+                X64::Ret(Some(vreg(0))),
+            ],
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        // The second argument moves out of RDX, which `idiv` overwrites.
+        assert_eq!(
+            asm[0].body[1..7],
+            [
+                X64::MovReg(X64R::R15, X64R::RDX),
+                X64::MovReg(X64R::RAX, X64R::RCX),
+                X64::Cqo,
+                X64::Idiv(X64R::RAX, X64R::R15),
+                X64::MovReg(X64R::RCX, X64R::RDX),
+                X64::MovReg(X64R::RAX, X64R::RCX),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_params() {
+        // Parameters are bound by their `Param` copies, whatever their numbers.
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 2,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Param(Register::VirtualXmm(7), 0),
+                X64::Param(Register::Virtual(3), 1),
+                X64::Cvttsd2si(Register::Virtual(3), Register::VirtualXmm(7)),
+                X64::Ret(Some(Register::Virtual(3))),
+            ],
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        assert_eq!(asm[0].body[0], X64::Cvttsd2si(X64R::RDX, X64R::XMM0));
+    }
+
+    #[test]
+    fn variadic_call() {
+        let vreg = Register::Virtual;
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(vreg(0), 1),
+                X64::MovReg(Register::VirtualXmm(1), vreg(0)),
+                X64::Call(
+                    String::from("printf"),
+                    vec![vreg(0), Register::VirtualXmm(1)],
+                    vreg(2),
+                    true,
+                ),
+                X64::Ret(Some(vreg(2))),
+            ],
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        // The `double` goes in XMM1 and, for the callee to find it without
+        // knowing its type, in RDX as well.
+        let call = asm[0]
+            .body
+            .iter()
+            .position(|asm| matches!(asm, X64::Call(..)))
+            .unwrap();
+        assert_eq!(asm[0].body[call - 1], X64::MovReg(X64R::RDX, X64R::XMM1));
+        assert!(asm[0].body[..call].contains(&X64::MovReg(X64R::RCX, X64R::R15)));
+    }
+
+    #[test]
+    fn arrays() {
+        let vreg = Register::Virtual;
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: vec![3, 2],
+            body: vec![
+                X64::MovNum(vreg(0), 1),
+                X64::StoreIndex(1, vreg(0), vreg(0)),
+                X64::Call(String::from("f"), Vec::new(), vreg(1), false),
+                X64::LoadIndex(vreg(2), 0, vreg(1)),
+                X64::Ret(Some(vreg(2))),
+            ],
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        // The arrays sit above the call area, padded to keep RSP aligned.
+        let local_size = X64R::CALL_AREA_SIZE + 48;
+        assert_eq!(
+            asm[0].body[8..20],
+            [
+                X64::SubNum(X64R::RSP, local_size),
+                X64::MovNum(X64R::R15, 1),
+                X64::StoreIndex(X64R::CALL_AREA_SIZE + 24, X64R::R15, X64R::R15),
+                X64::MovReg(X64R::RAX, X64R::RBP),
+                X64::MovReg(X64R::RBP, X64R::RSP),
+                X64::MovToStack(X64R::FRAME_SIZE + 8, X64R::RAX),
+                X64::Call(String::from("f"), Vec::new(), X64R::RAX, false),
+                X64::MovFromStack(X64R::RBP, X64R::FRAME_SIZE + 8),
+                X64::MovReg(X64R::R14, X64R::RAX),
+                X64::LoadIndex(X64R::R13, X64R::CALL_AREA_SIZE, X64R::R14),
+                X64::MovReg(X64R::RAX, X64R::R13),
+                X64::AddNum(X64R::RSP, local_size),
+            ]
+        );
+    }
+
+    #[test]
+    fn spill_victim() {
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..14).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend(vec![
+            X64::Tag(String::from("Loop")),
+            X64::Neg(vreg(0)),
+            X64::CmpNum(vreg(0), 0),
+            X64::Jne(String::from("Loop")),
+        ]);
+        for i in 1..14 {
+            body.push(X64::Neg(vreg(i)));
+            body.push(X64::Neg(vreg(i)));
+        }
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        // `VR0` is used least often but inside a loop, so `VR1` is spilled instead.
+        assert_eq!(
+            asm[0].body[20..22],
+            [X64::MovToStack(0, X64R::R14), X64::MovNum(X64R::R14, 13)]
+        );
+    }
+
+    #[test]
+    fn split_before_loop() {
+        // `VR0` is live across the loop but unused in it, which needs every
+        // register for the others, so it is spilled once before the loop
+        // rather than on every iteration.
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..13).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend(vec![
+            X64::Tag(String::from("Loop")),
+            X64::MovNum(vreg(13), 1),
+        ]);
+        for i in 1..13 {
+            body.push(X64::Add(vreg(i), vreg(13)));
+        }
+        body.extend(vec![
+            X64::CmpNum(vreg(1), 100),
+            X64::Jl(String::from("Loop")),
+            X64::Add(vreg(0), vreg(12)),
+            X64::Ret(Some(vreg(0))),
+        ]);
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        let body = &asm[0].body;
+        let start = body
+            .iter()
+            .position(|asm| *asm == X64::Tag(String::from("Loop")))
+            .unwrap();
+        let end = body
+            .iter()
+            .position(|asm| *asm == X64::Jl(String::from("Loop")))
+            .unwrap();
+        assert!(matches!(body[start - 1], X64::MovToStack(..)));
+        assert!(!body[start..end]
+            .iter()
+            .any(|asm| matches!(asm, X64::MovToStack(..) | X64::MovFromStack(..))));
+    }
+
+    #[test]
+    fn dead_victim() {
+        // `VR0` is never used again when `VR13` needs a register, so it is
+        // dropped without being stored.
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..14).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend((1..14).map(|i| X64::Neg(vreg(i))));
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        assert_eq!(asm[0].body[20], X64::MovNum(X64R::R15, 13));
+        assert!(!asm[0]
+            .body
+            .iter()
+            .any(|asm| matches!(asm, X64::MovToStack(..))));
+    }
+
+    #[test]
+    fn reuse_slots() {
+        // `VR0` is spilled, reloaded into the register of `VR1`, which takes
+        // the next slot, and spilled again into the slot it left. Three slots
+        // serve all six spills.
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..14).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend(vec![X64::Neg(vreg(0)), X64::MovNum(vreg(14), 14)]);
+        for i in 0..15 {
+            body.push(X64::Neg(vreg(i)));
+        }
+        for i in 1..15 {
+            body.push(X64::Neg(vreg(i)));
+            body.push(X64::Neg(vreg(i)));
+        }
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        let offsets: Vec<_> = asm[0]
+            .body
+            .iter()
+            .filter_map(|asm| match asm {
+                X64::MovToStack(offset, _) => Some(*offset),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offsets, vec![0, 8, 0, 16, 0, 8]);
+    }
+
+    #[test]
+    #[should_panic(expected = "A function cannot spill more than 128 values")]
+    fn frame_overflow() {
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..150).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend((1..150).map(|i| X64::Add(vreg(0), vreg(i))));
+        body.push(X64::Ret(Some(vreg(0))));
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        alloc(vec![func], Spill::Cost);
+    }
+
+    #[test]
+    fn spill_next_use() {
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..14).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend((0..14).rev().map(|i| X64::Neg(vreg(i))));
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::NextUse);
+        // `VR0` is the last one to be used again.
+        assert_eq!(
+            asm[0].body[20..22],
+            [X64::MovToStack(0, X64R::R15), X64::MovNum(X64R::R15, 13)]
+        );
+    }
+
+    #[test]
+    fn join_states() {
+        let vreg = Register::Virtual;
+        let mut body: Vec<_> = (0..13).map(|i| X64::MovNum(vreg(i), i as Int)).collect();
+        body.extend(vec![
+            X64::CmpNum(vreg(0), 0),
+            X64::Je(String::from("Else")),
+            X64::MovNum(vreg(13), 13),
+            X64::Jmp(String::from("End")),
+            X64::Tag(String::from("Else")),
+            X64::Tag(String::from("End")),
+        ]);
+        for i in (0..13).filter(|i| *i != 1) {
+            body.push(X64::Neg(vreg(i)));
+            body.push(X64::Neg(vreg(i)));
+        }
+        body.push(X64::Ret(Some(vreg(1))));
+        let func = X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = alloc(vec![func], Spill::Cost);
+        // `VR1` is spilled on one path only, so the other path has to store it
+        // before both paths join.
+        assert_eq!(
+            asm[0].body[20..28],
+            [
+                X64::CmpNum(X64R::R15, 0),
+                X64::Je(String::from("Else")),
+                X64::MovToStack(0, X64R::R14),
+                X64::MovNum(X64R::R14, 13),
+                X64::Jmp(String::from("End")),
+                X64::Tag(String::from("Else")),
+                X64::MovToStack(0, X64R::R14),
+                X64::Tag(String::from("End")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reconcile_on_edge() {
+        // The inner loop exits to a join whose state was set by `continue`,
+        // so its exit reloads the values the `if` arm spilled. Those moves
+        // belong to the exit alone, since the back edge of the inner loop
+        // still needs the registers they overwrite.
+        let ast = parser::parse(
+            "
+            int main(int a) {
+                int b = 2;
+                int d = 4;
+                int i = 0;
+                int k;
+                do {
+                    i = i + 1;
+                    if (d) {
+                        d = a + b - b;
+                    } else {
+                        continue;
+                    }
+                    k = 0;
+                    do {
+                        k = k + 1;
+                    } while (k < 5);
+                } while (i < 2);
+                return d * 10 + i;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(ssa::destruct(ssa::construct(ast))));
+        for spill in [Spill::Cost, Spill::NextUse] {
+            let (asm, _) = peephole::cleanup(alloc(vasm.clone(), spill));
+            assert_eq!(emulator::run(&asm, &[3]), Ok(32));
+        }
+    }
+
     #[test]
     fn register_spilling() {
         let ast = parser::parse(
@@ -248,56 +831,54 @@ mod tests {
             }
         ",
         );
-        let (ssa, prog_leaves) = ssa::construct(ast);
-        let cfg = ssa::destruct(ssa, prog_leaves);
-        let asm = X64Builder::new().build(cfg);
-        let asm = alloc(asm);
-        if let X64::MovToStack(_, reg) = &asm[0].body[26] {
-            let expected = vec![X64Function {
-                name: String::from("main"),
-                param_cnt: 0,
-                body: vec![
-                    X64::Push(X64R::RBX),
-                    X64::Push(X64R::RSI),
-                    X64::Push(X64R::RDI),
-                    X64::Push(X64R::R12),
-                    X64::Push(X64R::R13),
-                    X64::Push(X64R::R14),
-                    X64::Push(X64R::R15),
-                    X64::MovNum(X64R::R15, 1),
-                    X64::MovNum(X64R::R14, 2),
-                    X64::MovReg(X64R::R13, X64R::R15),
-                    X64::Add(X64R::R13, X64R::R14),
-                    X64::MovNum(X64R::R12, 3),
-                    X64::MovReg(X64R::R11, X64R::R13),
-                    X64::Add(X64R::R11, X64R::R12),
-                    X64::MovNum(X64R::R10, 4),
-                    X64::MovReg(X64R::R9, X64R::R11),
-                    X64::Add(X64R::R9, X64R::R10),
-                    X64::MovNum(X64R::R8, 5),
-                    X64::MovReg(X64R::RDI, X64R::R9),
-                    X64::Add(X64R::RDI, X64R::R8),
-                    X64::MovNum(X64R::RSI, 6),
-                    X64::MovReg(X64R::RDX, X64R::RDI),
-                    X64::Add(X64R::RDX, X64R::RSI),
-                    X64::MovNum(X64R::RCX, 7),
-                    X64::MovReg(X64R::RBX, X64R::RDX),
-                    X64::Add(X64R::RBX, X64R::RCX),
-                    X64::MovToStack(0, *reg),
-                    X64::MovNum(*reg, 1),
-                    X64::Pop(X64R::R15),
-                    X64::Pop(X64R::R14),
-                    X64::Pop(X64R::R13),
-                    X64::Pop(X64R::R12),
-                    X64::Pop(X64R::RDI),
-                    X64::Pop(X64R::RSI),
-                    X64::Pop(X64R::RBX),
-                    X64::Ret(None),
-                ],
-            }];
-            assert_eq!(asm, expected);
-        } else {
-            panic!()
-        }
+        let ssa = ssa::construct(ast);
+        let cfg = ssa::destruct(ssa);
+        let asm = X64Builder::new().build(lir::lower(cfg));
+        let asm = alloc(asm, Spill::Cost);
+        // Every value but the last is dead by the time it runs out of
+        // registers, so one is dropped without being stored.
+        let expected = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Push(X64R::RBX),
+                X64::Push(X64R::RSI),
+                X64::Push(X64R::RDI),
+                X64::Push(X64R::R12),
+                X64::Push(X64R::R13),
+                X64::Push(X64R::R14),
+                X64::Push(X64R::R15),
+                X64::MovNum(X64R::R15, 1),
+                X64::MovNum(X64R::R14, 2),
+                X64::MovReg(X64R::R13, X64R::R15),
+                X64::Add(X64R::R13, X64R::R14),
+                X64::MovNum(X64R::R12, 3),
+                X64::MovReg(X64R::R11, X64R::R13),
+                X64::Add(X64R::R11, X64R::R12),
+                X64::MovNum(X64R::R10, 4),
+                X64::MovReg(X64R::R9, X64R::R11),
+                X64::Add(X64R::R9, X64R::R10),
+                X64::MovNum(X64R::R8, 5),
+                X64::MovReg(X64R::RDI, X64R::R9),
+                X64::Add(X64R::RDI, X64R::R8),
+                X64::MovNum(X64R::RSI, 6),
+                X64::MovReg(X64R::RDX, X64R::RDI),
+                X64::Add(X64R::RDX, X64R::RSI),
+                X64::MovNum(X64R::RCX, 7),
+                X64::MovReg(X64R::RBX, X64R::RDX),
+                X64::Add(X64R::RBX, X64R::RCX),
+                X64::MovNum(X64R::R15, 1),
+                X64::Pop(X64R::R15),
+                X64::Pop(X64R::R14),
+                X64::Pop(X64R::R13),
+                X64::Pop(X64R::R12),
+                X64::Pop(X64R::RDI),
+                X64::Pop(X64R::RSI),
+                X64::Pop(X64R::RBX),
+                X64::Ret(None),
+            ],
+        }];
+        assert_eq!(asm, expected);
     }
 }
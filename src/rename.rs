@@ -0,0 +1,340 @@
+use crate::ir::{Expression, Function, Program, Prototype, SSAProgram, Statement};
+use std::collections::{HashMap, HashSet};
+
+// Rename functions and variables consistently across a program, such as to
+// give them names safe for any linker, to shrink a test case, or to obscure a
+// demo. `functions` renames definitions, prototypes, and calls, and
+// `variables` renames the parameters and the local variables of every
+// function, keeping the suffix SSA tells shadowed variables apart with. A new
+// name must not be taken by a function or a variable left as it is, or by
+// another renamed one, so the program means what it meant before.
+pub type Names = HashMap<String, String>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Kind {
+    Function,
+    Variable,
+}
+
+pub fn rename(
+    program: &mut Program,
+    prototypes: &mut [Prototype],
+    functions: &Names,
+    variables: &Names,
+) {
+    let mut walk = |f: &mut dyn FnMut(&mut String, Kind)| {
+        for proto in prototypes.iter_mut() {
+            f(&mut proto.name, Kind::Function);
+        }
+        for func in program.iter_mut() {
+            walk_function(func, f);
+        }
+    };
+    let mut taken = Taken::new();
+    walk(&mut |name, kind| taken.insert(name, kind));
+    taken.check(functions, variables);
+    walk(&mut |name, kind| taken.rename(name, kind, functions, variables));
+}
+
+// `rename` on a program in SSA form.
+pub fn rename_ssa(program: &mut SSAProgram, functions: &Names, variables: &Names) {
+    let mut walk = |f: &mut dyn FnMut(&mut String, Kind)| {
+        for func in program.iter_mut() {
+            f(&mut func.name, Kind::Function);
+            for param in &mut func.parameters {
+                f(&mut param.name, Kind::Variable);
+            }
+            for stmt in func.body.iter_mut().flat_map(|block| &mut block.statements) {
+                walk_stmt(stmt, f);
+            }
+            func.doubles = rename_doubles(&func.doubles, f);
+        }
+    };
+    let mut taken = Taken::new();
+    walk(&mut |name, kind| taken.insert(name, kind));
+    taken.check(functions, variables);
+    walk(&mut |name, kind| taken.rename(name, kind, functions, variables));
+}
+
+// The names of every function and variable in the program, without suffixes.
+struct Taken {
+    functions: HashSet<String>,
+    variables: HashSet<String>,
+}
+
+impl Taken {
+    fn new() -> Self {
+        Taken {
+            functions: HashSet::new(),
+            variables: HashSet::new(),
+        }
+    }
+
+    fn insert(&mut self, name: &str, kind: Kind) {
+        match kind {
+            Kind::Function => self.functions.insert(name.to_string()),
+            Kind::Variable => self.variables.insert(base(name).to_string()),
+        };
+    }
+
+    // Panic if a renaming would make two names one.
+    fn check(&self, functions: &Names, variables: &Names) {
+        // The names left as they are, and the new names, of both kinds.
+        let mut kept: HashMap<&str, Kind> = HashMap::new();
+        for (names, taken, kind) in [
+            (functions, &self.functions, Kind::Function),
+            (variables, &self.variables, Kind::Variable),
+        ] {
+            for name in taken.iter().filter(|name| !names.contains_key(*name)) {
+                kept.insert(name, kind);
+            }
+        }
+        let mut renamed: HashMap<&str, &str> = HashMap::new();
+        for (names, kind) in [(functions, Kind::Function), (variables, Kind::Variable)] {
+            // In order, so the same collision is always reported.
+            let mut names: Vec<_> = names.iter().collect();
+            names.sort_unstable();
+            for (old, new) in names {
+                // SSA returns through the variable `return`, and the driver
+                // defines the stubs of `assert`.
+                let reserved = |name: &str| match kind {
+                    Kind::Function => name.starts_with("eac_assert_"),
+                    Kind::Variable => name == "return",
+                };
+                if reserved(old) || reserved(new) || !is_name(new) {
+                    panic!("Cannot rename `{}` to `{}`.", old, new);
+                }
+                if let Some(other) = renamed.insert(new, old) {
+                    panic!(
+                        "Both `{}` and `{}` would be renamed to `{}`.",
+                        other, old, new
+                    );
+                }
+                match kept.get(new.as_str()) {
+                    Some(Kind::Function) => panic!(
+                        "Renaming `{}` to `{}` collides with the function `{}`.",
+                        old, new, new
+                    ),
+                    Some(Kind::Variable) => panic!(
+                        "Renaming `{}` to `{}` collides with the variable `{}`.",
+                        old, new, new
+                    ),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn rename(&self, name: &mut String, kind: Kind, functions: &Names, variables: &Names) {
+        match kind {
+            Kind::Function => {
+                if let Some(new) = functions.get(name) {
+                    *name = new.clone();
+                }
+            }
+            Kind::Variable => {
+                if let Some(new) = variables.get(base(name)) {
+                    *name = format!("{}{}", new, &name[base(name).len()..]);
+                }
+            }
+        }
+    }
+}
+
+fn walk_function(func: &mut Function, f: &mut dyn FnMut(&mut String, Kind)) {
+    f(&mut func.name, Kind::Function);
+    for param in &mut func.parameters {
+        f(&mut param.name, Kind::Variable);
+    }
+    walk_stmt(&mut func.body, f);
+    func.doubles = rename_doubles(&func.doubles, f);
+}
+
+fn rename_doubles(
+    doubles: &HashSet<String>,
+    f: &mut dyn FnMut(&mut String, Kind),
+) -> HashSet<String> {
+    doubles
+        .iter()
+        .map(|name| {
+            let mut name = name.clone();
+            f(&mut name, Kind::Variable);
+            name
+        })
+        .collect()
+}
+
+fn walk_stmt(stmt: &mut Statement, f: &mut dyn FnMut(&mut String, Kind)) {
+    match stmt {
+        Statement::Nop | Statement::Break | Statement::Continue | Statement::Return(None) => {}
+        Statement::Phi(var, args) => {
+            f(&mut var.name, Kind::Variable);
+            for (_, arg) in args {
+                f(&mut arg.name, Kind::Variable);
+            }
+        }
+        Statement::Declaration(var) | Statement::ArrayDeclaration(var, _) => {
+            f(&mut var.name, Kind::Variable)
+        }
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                walk_stmt(stmt, f);
+            }
+        }
+        Statement::Expression(expr) | Statement::Return(Some(expr)) => walk_expr(expr, f),
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => {
+            walk_expr(condition, f);
+            walk_stmt(body, f);
+            if let Some(alternative) = alternative {
+                walk_stmt(alternative, f);
+            }
+        }
+        Statement::While { condition, body } | Statement::DoWhile { body, condition } => {
+            walk_expr(condition, f);
+            walk_stmt(body, f);
+        }
+    }
+}
+
+fn walk_expr(expr: &mut Expression, f: &mut dyn FnMut(&mut String, Kind)) {
+    match expr {
+        Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Identifier(var) => f(&mut var.name, Kind::Variable),
+        Expression::Call {
+            function,
+            arguments,
+        } => {
+            match &mut **function {
+                Expression::Identifier(var) => f(&mut var.name, Kind::Function),
+                function => walk_expr(function, f),
+            }
+            walk_expr(arguments, f);
+        }
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                walk_expr(expr, f);
+            }
+        }
+        Expression::Index { array, indices } => {
+            f(&mut array.name, Kind::Variable);
+            for expr in indices {
+                walk_expr(expr, f);
+            }
+        }
+        Expression::Prefix { expression, .. } => walk_expr(expression, f),
+        Expression::Infix { left, right, .. } => {
+            walk_expr(left, f);
+            walk_expr(right, f);
+        }
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => {
+            walk_expr(condition, f);
+            walk_expr(consequent, f);
+            walk_expr(alternative, f);
+        }
+    }
+}
+
+// Drop the suffix that tells shadowed variables apart.
+fn base(name: &str) -> &str {
+    name.split('.').next().unwrap()
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::ssa;
+
+    fn names(pairs: &[(&str, &str)]) -> Names {
+        pairs
+            .iter()
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn program() {
+        let source = "
+            double scale(double x, int n);
+            int main() {
+                double x = 1.5;
+                int a[2];
+                a[0] = 2;
+                { double x = 0.5; a[1] = a[0]; }
+                return scale(x, a[1]);
+            }
+        ";
+        let mut prototypes = Vec::new();
+        let mut ast = parser::parse_unit(source, "main.c", &mut Vec::new(), &mut prototypes);
+        let functions = names(&[("scale", "f0"), ("main", "main")]);
+        let variables = names(&[("x", "v0"), ("a", "v1"), ("n", "v2")]);
+        rename(&mut ast, &mut prototypes, &functions, &variables);
+        let renamed = "
+            double f0(double v0, int v2);
+            int main() {
+                double v0 = 1.5;
+                int v1[2];
+                v1[0] = 2;
+                { double v0 = 0.5; v1[1] = v1[0]; }
+                return f0(v0, v1[1]);
+            }
+        ";
+        let mut expected_prototypes = Vec::new();
+        let expected =
+            parser::parse_unit(renamed, "main.c", &mut Vec::new(), &mut expected_prototypes);
+        assert_eq!(ast, expected);
+        assert_eq!(prototypes, expected_prototypes);
+
+        let mut ssa = ssa::construct(ast);
+        let mut expected = ssa.clone();
+        rename_ssa(
+            &mut ssa,
+            &names(&[("main", "start")]),
+            &names(&[("v0", "x")]),
+        );
+        rename_ssa(
+            &mut ssa,
+            &names(&[("start", "main")]),
+            &names(&[("x", "v0")]),
+        );
+        assert_eq!(ssa, expected);
+        expected[0].name = String::from("start");
+        rename_ssa(&mut ssa, &names(&[("main", "start")]), &Names::new());
+        assert_eq!(ssa, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Renaming `a` to `b` collides with the variable `b`.")]
+    fn collision() {
+        let mut ast = parser::parse("int main() { int a = 1; int b = 2; return a + b; }");
+        rename(&mut ast, &mut [], &Names::new(), &names(&[("a", "b")]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both `f` and `g` would be renamed to `h`.")]
+    fn merge() {
+        let mut ast = parser::parse("int f() { return 1; } int g() { return f(); }");
+        let functions = names(&[("f", "h"), ("g", "h")]);
+        rename(&mut ast, &mut [], &functions, &Names::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot rename `x` to `return`.")]
+    fn reserved() {
+        let mut ssa = ssa::construct(parser::parse("int main() { int x = 1; return x; }"));
+        rename_ssa(&mut ssa, &Names::new(), &names(&[("x", "return")]));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::ir::{Expression, Function, Program, Prototype, Statement};
+use std::collections::{BTreeSet, HashMap};
+
+// Check that every call resolves to a function defined in the program or
+// declared by a prototype, and passes as many arguments as it has parameters,
+// so a function may be called before it is defined, or from another unit.
+// Prototypes of one function must agree with each other and with its
+// definition. The stubs of `assert` resolve to the driver. Returns the
+// prototype of every function called or defined, by name.
+pub fn resolve(program: &Program, prototypes: &[Prototype]) -> HashMap<String, Prototype> {
+    let mut signatures: HashMap<String, Prototype> = HashMap::new();
+    for proto in prototypes {
+        match signatures.get(&proto.name) {
+            Some(other) if !other.agrees(proto) => panic!(
+                "The prototypes of `{}` on lines {} and {} do not agree.",
+                proto.name, other.line, proto.line
+            ),
+            Some(_) => {}
+            None => {
+                signatures.insert(proto.name.clone(), proto.clone());
+            }
+        }
+    }
+    for def in program.iter().map(Function::prototype) {
+        if let Some(proto) = signatures.get(&def.name) {
+            if !proto.agrees(&def) {
+                panic!(
+                    "The prototype of `{}` on line {} does not agree with its definition on line {}.",
+                    def.name, proto.line, def.line
+                );
+            }
+        }
+        signatures.insert(def.name.clone(), def);
+    }
+    for func in program {
+        let mut calls = Vec::new();
+        find_stmt_calls(&func.body, &mut calls);
+        for (name, args) in calls {
+            if name.starts_with("eac_assert_") {
+                continue;
+            }
+            match signatures.get(name) {
+                None => panic!(
+                    "Function `{}` is called in `{}` but is neither defined nor declared.",
+                    name, func.name
+                ),
+                Some(sig) if sig.variadic && sig.parameters.len() > args => panic!(
+                    "Function `{}` takes at least {} argument(s) but is called with {} in `{}`.",
+                    name,
+                    sig.parameters.len(),
+                    args,
+                    func.name
+                ),
+                Some(sig) if !sig.variadic && sig.parameters.len() != args => panic!(
+                    "Function `{}` takes {} argument(s) but is called with {} in `{}`.",
+                    name,
+                    sig.parameters.len(),
+                    args,
+                    func.name
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+    signatures
+}
+
+// The functions `program` calls without defining them, which the linker finds
+// elsewhere, in name order. The stubs of `assert` are not among them.
+pub fn externs(program: &Program) -> BTreeSet<String> {
+    let defined: Vec<_> = program.iter().map(|func| &func.name).collect();
+    let mut calls = Vec::new();
+    for func in program {
+        find_stmt_calls(&func.body, &mut calls);
+    }
+    calls
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| !name.starts_with("eac_assert_"))
+        .filter(|name| !defined.iter().any(|defined| defined == name))
+        .map(String::from)
+        .collect()
+}
+
+// Every call in the statement, as the name called and the number of arguments.
+fn find_stmt_calls<'a>(stmt: &'a Statement, calls: &mut Vec<(&'a str, usize)>) {
+    match stmt {
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                find_stmt_calls(stmt, calls);
+            }
+        }
+        Statement::Expression(expr) | Statement::Return(Some(expr)) => find_calls(expr, calls),
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => {
+            find_calls(condition, calls);
+            find_stmt_calls(body, calls);
+            if let Some(alternative) = alternative {
+                find_stmt_calls(alternative, calls);
+            }
+        }
+        Statement::While { condition, body } | Statement::DoWhile { body, condition } => {
+            find_calls(condition, calls);
+            find_stmt_calls(body, calls);
+        }
+        Statement::Nop
+        | Statement::Phi(..)
+        | Statement::Declaration(_)
+        | Statement::ArrayDeclaration(..)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Return(None) => {}
+    }
+}
+
+fn find_calls<'a>(expr: &'a Expression, calls: &mut Vec<(&'a str, usize)>) {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Call {
+            function,
+            arguments,
+        } => {
+            if let (Expression::Identifier(var), Expression::Arguments(args)) =
+                (&**function, &**arguments)
+            {
+                calls.push((&var.name, args.len()));
+            }
+            find_calls(arguments, calls);
+        }
+        Expression::Arguments(exprs) | Expression::Index { indices: exprs, .. } => {
+            for expr in exprs {
+                find_calls(expr, calls);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_calls(expression, calls),
+        Expression::Infix { left, right, .. } => {
+            find_calls(left, calls);
+            find_calls(right, calls);
+        }
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => {
+            find_calls(condition, calls);
+            find_calls(consequent, calls);
+            find_calls(alternative, calls);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn parse(source: &str) -> (Program, Vec<Prototype>) {
+        let mut prototypes = Vec::new();
+        let ast = parser::parse_unit(source, "main.c", &mut Vec::new(), &mut prototypes);
+        (ast, prototypes)
+    }
+
+    #[test]
+    fn prototypes() {
+        let (ast, prototypes) = parse(
+            "
+            int is_odd(int);
+            double scale(double x, int);
+            void log(void);
+            int print(int, ...);
+
+            int is_even(int n) {
+                if (n == 0) { return 1; }
+                return is_odd(n - 1);
+            }
+
+            int is_odd(int n) {
+                if (n == 0) { return 0; }
+                log();
+                print(1);
+                print(2, 3.5, n);
+                return is_even(n - 1) + scale(1.5, 2);
+            }
+        ",
+        );
+        let signatures = resolve(&ast, &prototypes);
+        let scale = Prototype {
+            line: 3,
+            void: false,
+            double: true,
+            name: String::from("scale"),
+            parameters: vec![true, false],
+            variadic: false,
+        };
+        assert_eq!(signatures["scale"], scale);
+        assert_eq!(signatures["log"].parameters, Vec::<bool>::new());
+        assert!(signatures["print"].variadic);
+        assert_eq!(signatures["is_odd"].line, 12);
+    }
+
+    #[test]
+    fn externs() {
+        let (ast, _) = parse(
+            "
+            int print(int, ...);
+            int f(int n) {
+                assert(n);
+                return print(n) + print(f(n - 1));
+            }
+        ",
+        );
+        assert_eq!(
+            super::externs(&ast),
+            BTreeSet::from([String::from("print")])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Function `g` is called in `f` but is neither defined nor declared.")]
+    fn undeclared() {
+        let (ast, prototypes) = parse(
+            "
+            int f() {
+                return g();
+            }
+        ",
+        );
+        resolve(&ast, &prototypes);
+    }
+
+    #[test]
+    #[should_panic(expected = "Function `g` takes 1 argument(s) but is called with 2 in `f`.")]
+    fn argument_count() {
+        let (ast, prototypes) = parse(
+            "
+            int g(int a);
+            int f() {
+                return g(1, 2);
+            }
+        ",
+        );
+        resolve(&ast, &prototypes);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Function `g` takes at least 2 argument(s) but is called with 1 in `f`."
+    )]
+    fn variadic_argument_count() {
+        let (ast, prototypes) = parse(
+            "
+            int g(int a, double, ...);
+            int f() {
+                return g(1);
+            }
+        ",
+        );
+        resolve(&ast, &prototypes);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The prototype of `g` on line 2 does not agree with its definition on line 3."
+    )]
+    fn disagreement() {
+        let (ast, prototypes) = parse(
+            "
+            int g(double);
+            int g(int a) {
+                return a;
+            }
+        ",
+        );
+        resolve(&ast, &prototypes);
+    }
+}
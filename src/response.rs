@@ -0,0 +1,108 @@
+// Windows rejects command lines longer than 32767 characters, and `cmd.exe`
+// those longer than 8191, so longer argument lists go to a response file that
+// the tool reads as `@file`.
+pub const COMMAND_LINE_LIMIT: usize = 8191;
+
+// How a tool splits a response file back into arguments.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Quoting {
+    Msvc, // `ml64` and `link`, by the rules of `CommandLineToArgvW`.
+    Gnu,  // `gcc` and `clang`, where a backslash escapes any character.
+}
+
+impl Quoting {
+    pub fn quote(&self, arg: &str) -> String {
+        match self {
+            Quoting::Msvc => quote_msvc(arg),
+            Quoting::Gnu => quote_gnu(arg),
+        }
+    }
+}
+
+// The length of the command line running `program` with `args`.
+pub fn command_line_len(program: &str, args: &[String]) -> usize {
+    args.iter()
+        .map(|arg| Quoting::Msvc.quote(arg).len() + 1)
+        .sum::<usize>()
+        + program.len()
+}
+
+// The content of a response file holding `args`, one per line.
+pub fn response_file(args: &[String], quoting: Quoting) -> String {
+    let mut file: String = args
+        .iter()
+        .map(|arg| quoting.quote(arg))
+        .collect::<Vec<_>>()
+        .join("\n");
+    file.push('\n');
+    file
+}
+
+fn quote_msvc(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            // Backslashes are literal unless they precede a quote.
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+fn quote_gnu(arg: &str) -> String {
+    if arg.is_empty() {
+        return String::from("''");
+    }
+    let mut quoted = String::new();
+    for c in arg.chars() {
+        if c.is_whitespace() || matches!(c, '\\' | '"' | '\'') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        let args: Vec<_> = ["main.exe", r"C:\Program Files\lib", r#"a\"b"#, r"dir\", ""]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let msvc = "main.exe
+\"C:\\Program Files\\lib\"
+\"a\\\\\\\"b\"
+dir\\
+\"\"
+";
+        assert_eq!(response_file(&args, Quoting::Msvc), msvc);
+        let gnu = "main.exe
+C:\\\\Program\\ Files\\\\lib
+a\\\\\\\"b
+dir\\\\
+''
+";
+        assert_eq!(response_file(&args, Quoting::Gnu), gnu);
+        assert_eq!(command_line_len("gcc", &args[..2]), 3 + 9 + 23);
+    }
+}
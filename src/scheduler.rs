@@ -0,0 +1,217 @@
+use crate::x64::{Register, X64Function, X64Program, X64};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+// List scheduling over the virtual assembly (Chapter 12.3). Within each run of
+// instructions that only compute in registers or move to and from the stack,
+// instructions are reordered so that those starting the longest chains of
+// latency go first. Everything else (tags, jumps, comparisons, calls, and
+// accesses through pointers, which may reach any array) stays in place and
+// ends the run, so flags, control flow, and memory are untouched. A run is
+// also cut every `REGION_SIZE` instructions, as hoisting the starts of chains
+// keeps their values live, which in a long block would need more stack slots
+// than the frame has. Returns the number of instructions moved.
+pub fn schedule(asm: &mut X64Program) -> usize {
+    asm.iter_mut().map(schedule_function).sum()
+}
+
+const REGION_SIZE: usize = 64;
+
+// A register, a stack slot, or a whole array, any of which an instruction may
+// depend on.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Location {
+    Register(Register),
+    Slot(usize),
+    Array(usize),
+}
+
+fn schedule_function(X64Function { body, .. }: &mut X64Function) -> usize {
+    let mut moved = 0;
+    let mut scheduled = Vec::with_capacity(body.len());
+    let mut region = Vec::new();
+    for asm in body.drain(..) {
+        if region.len() == REGION_SIZE {
+            moved += schedule_region(&mut region, &mut scheduled);
+        }
+        if accesses(&asm).is_some() {
+            region.push(asm);
+            continue;
+        }
+        moved += schedule_region(&mut region, &mut scheduled);
+        scheduled.push(asm);
+    }
+    moved += schedule_region(&mut region, &mut scheduled);
+    *body = scheduled;
+    moved
+}
+
+fn schedule_region(region: &mut Vec<X64>, scheduled: &mut Vec<X64>) -> usize {
+    let succs = dependences(region);
+    let mut pred_cnts = vec![0; region.len()];
+    for succ in succs.iter().flatten() {
+        pred_cnts[*succ] += 1;
+    }
+    // The priority of an instruction is the latency of the longest chain it starts.
+    let mut priorities = vec![0; region.len()];
+    for i in (0..region.len()).rev() {
+        let longest = succs[i].iter().map(|succ| priorities[*succ]).max();
+        priorities[i] = latency(&region[i]) + longest.unwrap_or(0);
+    }
+    let mut ready: Vec<_> = (0..region.len()).filter(|i| pred_cnts[*i] == 0).collect();
+    let mut active: Vec<(usize, usize)> = Vec::new();
+    let mut order = Vec::with_capacity(region.len());
+    let mut cycle = 0;
+    while !ready.is_empty() || !active.is_empty() {
+        // Ties go to the earlier instruction, which keeps the order stable.
+        let best =
+            (0..ready.len()).max_by_key(|pos| (priorities[ready[*pos]], Reverse(ready[*pos])));
+        if let Some(pos) = best {
+            let i = ready.swap_remove(pos);
+            order.push(i);
+            active.push((i, cycle + latency(&region[i])));
+        }
+        cycle += 1;
+        active.retain(|&(i, finish)| {
+            if finish > cycle {
+                return true;
+            }
+            for succ in &succs[i] {
+                pred_cnts[*succ] -= 1;
+                if pred_cnts[*succ] == 0 {
+                    ready.push(*succ);
+                }
+            }
+            false
+        });
+    }
+    let moved = order.iter().enumerate().filter(|(k, i)| k != *i).count();
+    let mut region: Vec<_> = region.drain(..).map(Some).collect();
+    scheduled.extend(order.into_iter().map(|i| region[i].take().unwrap()));
+    moved
+}
+
+// The successors of every instruction in the dependence graph: an instruction
+// follows the last write of every location it reads or writes, and every read
+// since then of a location it writes.
+fn dependences(region: &[X64]) -> Vec<Vec<usize>> {
+    let mut succs = vec![Vec::new(); region.len()];
+    let mut last_writes: HashMap<Location, usize> = HashMap::new();
+    let mut reads: HashMap<Location, Vec<usize>> = HashMap::new();
+    for (j, asm) in region.iter().enumerate() {
+        let (writes, uses) = accesses(asm).unwrap();
+        let mut preds: Vec<usize> = Vec::new();
+        for loc in uses.iter().chain(&writes) {
+            preds.extend(last_writes.get(loc));
+        }
+        for loc in &writes {
+            preds.extend(reads.get(loc).into_iter().flatten());
+        }
+        preds.sort_unstable();
+        preds.dedup();
+        for i in preds {
+            if i != j {
+                succs[i].push(j);
+            }
+        }
+        for loc in uses {
+            reads.entry(loc).or_default().push(j);
+        }
+        for loc in writes {
+            last_writes.insert(loc, j);
+            reads.remove(&loc);
+        }
+    }
+    succs
+}
+
+// The locations the instruction writes and reads, or `None` if it must not move.
+fn accesses(asm: &X64) -> Option<(Vec<Location>, Vec<Location>)> {
+    use Location::{Array, Register as Reg, Slot};
+    match *asm {
+        X64::MovNum(reg, _) => Some((vec![Reg(reg)], Vec::new())),
+        X64::MovReg(left, right) => Some((vec![Reg(left)], vec![Reg(right)])),
+        X64::MovToStack(offset, reg) => Some((vec![Slot(offset)], vec![Reg(reg)])),
+        X64::MovFromStack(reg, offset) => Some((vec![Reg(reg)], vec![Slot(offset)])),
+        X64::LoadIndex(reg, array, index) => Some((vec![Reg(reg)], vec![Array(array), Reg(index)])),
+        X64::StoreIndex(array, index, reg) => {
+            Some((vec![Array(array)], vec![Reg(index), Reg(reg)]))
+        }
+        X64::LeaIndex(reg, _, index) => Some((vec![Reg(reg)], vec![Reg(index)])),
+        X64::Lea(reg, base, index, _) => Some((vec![Reg(reg)], vec![Reg(base), Reg(index)])),
+        X64::LoadField(reg, array, _) => Some((vec![Reg(reg)], vec![Array(array)])),
+        X64::StoreField(array, _, reg) => Some((vec![Array(array)], vec![Reg(reg)])),
+        X64::Neg(reg) => Some((vec![Reg(reg)], vec![Reg(reg)])),
+        X64::Cvtsi2sd(left, right) | X64::Cvttsd2si(left, right) => {
+            Some((vec![Reg(left)], vec![Reg(right)]))
+        }
+        X64::Imul(left, right)
+        | X64::Idiv(left, right)
+        | X64::Irem(left, right)
+        | X64::Add(left, right)
+        | X64::Sub(left, right)
+        | X64::And(left, right)
+        | X64::Or(left, right)
+        | X64::Addsd(left, right)
+        | X64::Subsd(left, right)
+        | X64::Mulsd(left, right)
+        | X64::Divsd(left, right) => Some((vec![Reg(left)], vec![Reg(left), Reg(right)])),
+        _ => None,
+    }
+}
+
+// Rough cycle counts on current x64 cores.
+fn latency(asm: &X64) -> usize {
+    match asm {
+        X64::Idiv(..) | X64::Irem(..) => 20,
+        X64::Divsd(..) => 14,
+        X64::Addsd(..)
+        | X64::Subsd(..)
+        | X64::Mulsd(..)
+        | X64::Cvtsi2sd(..)
+        | X64::Cvttsd2si(..) => 4,
+        X64::Imul(..) | X64::MovFromStack(..) | X64::LoadIndex(..) | X64::LoadField(..) => 3,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x64::Register::Virtual;
+
+    #[test]
+    fn latencies() {
+        let mut asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(Virtual(0), 1),
+                X64::MovNum(Virtual(1), 2),
+                X64::MovReg(Virtual(2), Virtual(0)),
+                X64::Add(Virtual(2), Virtual(1)),
+                X64::MovReg(Virtual(3), Virtual(0)),
+                X64::Idiv(Virtual(3), Virtual(1)),
+                X64::CmpReg(Virtual(2), Virtual(3)),
+                X64::MovReg(Virtual(0), Virtual(2)),
+                X64::MovReg(Virtual(2), Virtual(3)),
+                X64::Ret(Some(Virtual(0))),
+            ],
+        }];
+        assert_eq!(schedule(&mut asm), 4);
+        let expected = vec![
+            X64::MovNum(Virtual(0), 1),
+            X64::MovNum(Virtual(1), 2),
+            X64::MovReg(Virtual(3), Virtual(0)),
+            X64::Idiv(Virtual(3), Virtual(1)),
+            X64::MovReg(Virtual(2), Virtual(0)),
+            X64::Add(Virtual(2), Virtual(1)),
+            X64::CmpReg(Virtual(2), Virtual(3)),
+            X64::MovReg(Virtual(0), Virtual(2)),
+            X64::MovReg(Virtual(2), Virtual(3)),
+            X64::Ret(Some(Virtual(0))),
+        ];
+        assert_eq!(asm[0].body, expected);
+    }
+}
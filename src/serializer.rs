@@ -1,9 +1,93 @@
-use crate::x64::{X64Function, X64Program};
+use crate::lines::Lines;
+use crate::x64::{Register, RegisterClass, X64Function, X64Program, X64};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::str::FromStr;
 
 const INDENT_SIZE: usize = 4;
 
-pub fn run(asm: X64Program) -> String {
-    let mut file = String::from(".code\n");
+// The assembler and linker that turn the output into an executable.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Toolchain {
+    #[default]
+    Masm, // MASM syntax for `ml64`.
+    Mingw, // GAS syntax for MinGW-w64 `gcc`.
+    Macos, // GAS syntax with Mach-O symbols for `clang` on macOS.
+}
+
+impl Toolchain {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Toolchain::Masm => "asm",
+            Toolchain::Mingw | Toolchain::Macos => "s",
+        }
+    }
+
+    // The linker argument for the library `lib`, which may be given as
+    // `user32`, `user32.lib`, or `-luser32` whatever the toolchain.
+    pub fn library_arg(&self, lib: &str) -> String {
+        let name = lib.strip_prefix("-l").unwrap_or(lib);
+        let name = name.strip_suffix(".lib").unwrap_or(name);
+        match self {
+            Toolchain::Masm => format!("{}.lib", name),
+            Toolchain::Mingw | Toolchain::Macos => format!("-l{}", name),
+        }
+    }
+}
+
+impl FromStr for Toolchain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "masm" => Ok(Toolchain::Masm),
+            "mingw" => Ok(Toolchain::Mingw),
+            "macos" => Ok(Toolchain::Macos),
+            s => Err(format!("Unknown toolchain `{}`.", s)),
+        }
+    }
+}
+
+pub fn run(asm: X64Program, assertions: &[String]) -> String {
+    let mut file = String::new();
+    // Functions called but defined nowhere in the program come from the driver.
+    let defined: HashSet<_> = asm
+        .iter()
+        .map(|func| func.name.clone())
+        .chain((0..assertions.len()).map(|i| format!("eac_assert_{}", i)))
+        .collect();
+    let mut externs = BTreeSet::new();
+    for func in &asm {
+        for asm in &func.body {
+            if let X64::Call(name, ..) = asm {
+                if !defined.contains(name) {
+                    externs.insert(name.clone());
+                }
+            }
+        }
+    }
+    if !assertions.is_empty() {
+        externs.insert(String::from("eac_assert"));
+    }
+    for name in &externs {
+        file += &format!("extern {}: proc\n", name);
+    }
+    if !externs.is_empty() {
+        file.push('\n');
+    }
+    if !assertions.is_empty() {
+        file += ".data\n";
+        for (i, message) in assertions.iter().enumerate() {
+            file += &format!(
+                "{}eac_assert_message_{} byte \"{}\", 10\n",
+                indent(1),
+                i,
+                message.replace('"', "\"\"")
+            );
+        }
+        file.push('\n');
+    }
+    file += ".code\n";
     let mut indent_level = 1;
     for X64Function { name, body, .. } in asm {
         file += &format!("{}{} proc\n", indent(indent_level), name);
@@ -14,10 +98,179 @@ pub fn run(asm: X64Program) -> String {
         indent_level -= 1;
         file += &format!("{}{} endp\n\n", indent(indent_level), name);
     }
+    // Every `assert` calls a stub that passes the condition in RCX on to
+    // `eac_assert` in the driver, together with the address and length of its
+    // failure message. `eac_assert` returns to the caller if the condition holds.
+    for (i, message) in assertions.iter().enumerate() {
+        file += &format!("{}eac_assert_{} proc\n", indent(1), i);
+        file += &format!("{}lea RDX, eac_assert_message_{}\n", indent(2), i);
+        file += &format!("{}mov R8, {}\n", indent(2), message.len() + 1);
+        file += &format!("{}jmp eac_assert\n", indent(2));
+        file += &format!("{}eac_assert_{} endp\n\n", indent(1), i);
+    }
     file += "end\n";
     file
 }
 
+// Emit GAS assembly in AT&T syntax, where the source operand comes first,
+// registers are prefixed with `%`, and immediates with `$`. Mach-O puts code
+// in the `__TEXT,__text` section and prefixes C symbols with an underscore.
+pub fn run_gas(asm: X64Program, mach_o: bool, assertions: &[String]) -> String {
+    let (data, section, prefix) = match mach_o {
+        true => (".section __DATA,__data", ".section __TEXT,__text", "_"),
+        false => (".data", ".text", ""),
+    };
+    let mut file = String::new();
+    if !assertions.is_empty() {
+        file += &format!("    {}\n", data);
+        for (i, message) in assertions.iter().enumerate() {
+            let message = message.replace('\\', "\\\\").replace('"', "\\\"");
+            file += &format!(
+                "eac_assert_message_{}:\n{}.ascii \"{}\\n\"\n",
+                i,
+                indent(1),
+                message
+            );
+        }
+        file.push('\n');
+    }
+    file += &format!("    {}\n", section);
+    for X64Function { name, body, .. } in asm {
+        file += &format!("\n    .globl {}{}\n{}{}:\n", prefix, name, prefix, name);
+        for asm in body {
+            match asm {
+                X64::Tag(tag) => file += &format!("{}:\n", tag),
+                X64::Call(name, ..) => file += &format!("{}call {}{}\n", indent(1), prefix, name),
+                asm => file += &format!("{}{}\n", indent(1), att(&asm)),
+            }
+        }
+    }
+    for (i, message) in assertions.iter().enumerate() {
+        file += &format!("\n{}eac_assert_{}:\n", prefix, i);
+        file += &format!("{}leaq eac_assert_message_{}(%rip), %rdx\n", indent(1), i);
+        file += &format!("{}movq ${}, %r8\n", indent(1), message.len() + 1);
+        file += &format!("{}jmp {}eac_assert\n", indent(1), prefix);
+    }
+    file
+}
+
+fn att(asm: &X64) -> String {
+    let reg = |reg: &Register| format!("%{}", reg).to_lowercase();
+    let binary = |op, left, right| format!("{} {}, {}", op, reg(right), reg(left));
+    let mov = |r: &Register| match r.class() {
+        RegisterClass::General => "movq",
+        RegisterClass::Xmm => "movsd",
+    };
+    match asm {
+        // Only `movabs` takes an immediate that does not fit in 32 bits.
+        X64::MovNum(r, num) if i32::try_from(*num).is_err() => {
+            format!("movabsq ${}, {}", num, reg(r))
+        }
+        X64::MovNum(r, num) => format!("movq ${}, {}", num, reg(r)),
+        // `movq` also moves bits between general-purpose and XMM registers.
+        X64::MovReg(left, right) => match (left.class(), right.class()) {
+            (RegisterClass::Xmm, RegisterClass::Xmm) => binary("movsd", left, right),
+            _ => binary("movq", left, right),
+        },
+        X64::MovToStack(offset, r) => format!("{} {}, {}(%rbp)", mov(r), reg(r), offset),
+        X64::MovFromStack(r, offset) => format!("{} {}(%rbp), {}", mov(r), offset, reg(r)),
+        X64::LoadIndex(r, offset, index) => {
+            format!("movq {}(%rsp,{},8), {}", offset, reg(index), reg(r))
+        }
+        X64::StoreIndex(offset, index, r) => {
+            format!("movq {}, {}(%rsp,{},8)", reg(r), offset, reg(index))
+        }
+        X64::LeaIndex(r, offset, index) => {
+            format!("leaq {}(%rsp,{},8), {}", offset, reg(index), reg(r))
+        }
+        X64::Lea(r, base, index, scale) => {
+            format!("leaq ({},{},{}), {}", reg(base), reg(index), scale, reg(r))
+        }
+        X64::LoadField(r, array, offset) => format!("movq {}(%rsp), {}", array + offset, reg(r)),
+        X64::StoreField(array, offset, r) => format!("movq {}, {}(%rsp)", reg(r), array + offset),
+        X64::Load(r, address) => format!("movq ({}), {}", reg(address), reg(r)),
+        X64::Store(address, r) => format!("movq {}, ({})", reg(r), reg(address)),
+        X64::Neg(r) => format!("negq {}", reg(r)),
+        X64::CmpNum(r, num) => format!("cmpq ${}, {}", num, reg(r)),
+        X64::CmpReg(left, right) => binary("cmpq", left, right),
+        X64::Imul(left, right) => binary("imulq", left, right),
+        X64::Idiv(_, right) => format!("idivq {}", reg(right)),
+        X64::Cqo => String::from("cqto"),
+        X64::Add(left, right) => binary("addq", left, right),
+        X64::AddNum(r, num) => format!("addq ${}, {}", num, reg(r)),
+        X64::Sub(left, right) => binary("subq", left, right),
+        X64::SubNum(r, num) => format!("subq ${}, {}", num, reg(r)),
+        X64::And(left, right) => binary("andq", left, right),
+        X64::Or(left, right) => binary("orq", left, right),
+        X64::Xor(left, right) => binary("xorq", left, right),
+        X64::Test(left, right) => binary("testq", left, right),
+        X64::Cvtsi2sd(left, right) => binary("cvtsi2sdq", left, right),
+        X64::Cvttsd2si(left, right) => binary("cvttsd2siq", left, right),
+        X64::Addsd(left, right) => binary("addsd", left, right),
+        X64::Subsd(left, right) => binary("subsd", left, right),
+        X64::Mulsd(left, right) => binary("mulsd", left, right),
+        X64::Divsd(left, right) => binary("divsd", left, right),
+        X64::Ucomisd(left, right) => binary("ucomisd", left, right),
+        X64::Push(r) => format!("pushq {}", reg(r)),
+        X64::Pop(r) => format!("popq {}", reg(r)),
+        // Calls, jumps, tags, and `ret` read the same in both syntaxes.
+        asm => format!("{}", asm),
+    }
+}
+
+// Emit assembly in the format Compiler Explorer expects: no `.code`/`proc`/`end`
+// directives, and function names as top-level labels. Local tags are already
+// prefixed with their function name, so they never collide.
+// The second returned value is a JSON sidecar mapping every assembly line to the
+// source line in `lines` of the instruction it came from, and the name of every
+// function to its line in `functions`.
+pub fn run_explorer(
+    asm: X64Program,
+    functions: &HashMap<String, usize>,
+    lines: &Lines,
+) -> (String, String) {
+    let mut file = String::new();
+    let mut source_map = Vec::new();
+    for X64Function { name, body, .. } in asm {
+        let line = functions.get(&name).copied();
+        let asm_lines = lines.get(&name).map_or(&[][..], Vec::as_slice);
+        let mut emit = |text: String, line| {
+            file += &text;
+            file.push('\n');
+            source_map.push((text, line));
+        };
+        emit(format!("{}:", name), line);
+        for (index, asm) in body.into_iter().enumerate() {
+            let text = match asm {
+                tag @ X64::Tag(_) => format!("{}", tag),
+                asm => format!("{}{}", indent(1), asm),
+            };
+            emit(text, asm_lines.get(index).copied().or(line));
+        }
+        file.push('\n');
+        source_map.push((String::new(), None));
+    }
+    let entries: Vec<_> = source_map
+        .into_iter()
+        .map(|(text, line)| {
+            let source = match line {
+                Some(line) => format!("{{\"file\": null, \"line\": {}}}", line),
+                None => String::from("null"),
+            };
+            format!(
+                "{{\"text\": \"{}\", \"source\": {}}}",
+                escape_json(&text),
+                source
+            )
+        })
+        .collect();
+    (file, format!("[{}]\n", entries.join(", ")))
+}
+
+pub(crate) fn escape_json(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 fn indent(indent_level: usize) -> String {
     String::from_utf8(vec![32; indent_level * INDENT_SIZE]).unwrap()
 }
@@ -32,12 +285,13 @@ mod tests {
         let program = vec![X64Function {
             name: String::from("main"),
             param_cnt: 0,
+            arrays: Vec::new(),
             body: vec![
                 X64::MovNum(X64R::RSP, 0),
                 X64::MovReg(X64R::RSP, X64R::RSP),
                 X64::MovToStack(0, X64R::RSP),
                 X64::MovFromStack(X64R::RSP, 0),
-                X64::Call(String::from("Tag"), Vec::new(), X64R::RSP),
+                X64::Call(String::from("Tag"), Vec::new(), X64R::RSP, false),
                 X64::Neg(X64R::RSP),
                 X64::CmpNum(X64R::RSP, 0),
                 X64::CmpReg(X64R::RSP, X64R::RSP),
@@ -50,19 +304,24 @@ mod tests {
                 X64::Jmp(String::from("Tag")),
                 X64::Tag(String::from("Tag")),
                 X64::Imul(X64R::RSP, X64R::RSP),
-                X64::Idiv(X64R::RSP, X64R::RSP),
+                X64::Cqo,
+                X64::Idiv(X64R::RAX, X64R::RSP),
                 X64::Add(X64R::RSP, X64R::RSP),
                 X64::Sub(X64R::RSP, X64R::RSP),
                 X64::SubNum(X64R::RSP, 0),
                 X64::And(X64R::RSP, X64R::RSP),
                 X64::Or(X64R::RSP, X64R::RSP),
+                X64::Xor(X64R::RSP, X64R::RSP),
+                X64::Test(X64R::RSP, X64R::RSP),
                 X64::Ret(None),
                 X64::Push(X64R::RSP),
                 X64::Pop(X64R::RSP),
             ],
         }];
-        let file = run(program);
-        let expected = ".code
+        let file = run(program, &[]);
+        let expected = "extern Tag: proc
+
+.code
     main proc
         mov RSP, 0
         mov RSP, RSP
@@ -81,12 +340,15 @@ mod tests {
         jmp Tag
         Tag:
         imul RSP, RSP
-        idiv RSP, RSP
+        cqo
+        idiv RSP
         add RSP, RSP
         sub RSP, RSP
         sub RSP, 0
         and RSP, RSP
         or RSP, RSP
+        xor RSP, RSP
+        test RSP, RSP
         ret
         push RSP
         pop RSP
@@ -96,4 +358,166 @@ end
 ";
         assert_eq!(file, expected);
     }
+
+    #[test]
+    fn serialize_gas() {
+        let (a, b) = (X64R::RAX, X64R::RCX);
+        let program = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::MovNum(a, 1),
+                X64::MovNum(a, 4294967296),
+                X64::MovReg(a, b),
+                X64::MovToStack(8, a),
+                X64::MovFromStack(a, 8),
+                X64::CmpReg(a, b),
+                X64::Jl(String::from("main_L1")),
+                X64::Sub(a, b),
+                X64::SubNum(X64R::RSP, 512),
+                X64::Tag(String::from("main_L1")),
+                X64::Push(a),
+                X64::Ret(None),
+            ],
+        }];
+        let file = run_gas(program, false, &[]);
+        let expected = "    .text
+
+    .globl main
+main:
+    movq $1, %rax
+    movabsq $4294967296, %rax
+    movq %rcx, %rax
+    movq %rax, 8(%rbp)
+    movq 8(%rbp), %rax
+    cmpq %rcx, %rax
+    jl main_L1
+    subq %rcx, %rax
+    subq $512, %rsp
+main_L1:
+    pushq %rax
+    ret
+";
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn serialize_explorer() {
+        let program = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Je(String::from("main_L1")),
+                X64::Tag(String::from("main_L1")),
+                X64::Ret(None),
+            ],
+        }];
+        let functions = vec![(String::from("main"), 3)].into_iter().collect();
+        let lines = vec![(String::from("main"), vec![4, 6, 6])]
+            .into_iter()
+            .collect();
+        let (file, source_map) = run_explorer(program, &functions, &lines);
+        let expected = "main:
+    je main_L1
+main_L1:
+    ret
+
+";
+        assert_eq!(file, expected);
+        let expected = concat!(
+            "[{\"text\": \"main:\", \"source\": {\"file\": null, \"line\": 3}}, ",
+            "{\"text\": \"    je main_L1\", \"source\": {\"file\": null, \"line\": 4}}, ",
+            "{\"text\": \"main_L1:\", \"source\": {\"file\": null, \"line\": 6}}, ",
+            "{\"text\": \"    ret\", \"source\": {\"file\": null, \"line\": 6}}, ",
+            "{\"text\": \"\", \"source\": null}]\n",
+        );
+        assert_eq!(source_map, expected);
+    }
+
+    #[test]
+    fn serialize_mach_o() {
+        let program = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Call(String::from("fib"), Vec::new(), X64R::RAX, false),
+                X64::Ret(None),
+            ],
+        }];
+        let file = run_gas(program, true, &[]);
+        let expected = "    .section __TEXT,__text
+
+    .globl _main
+_main:
+    call _fib
+    ret
+";
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn serialize_assertions() {
+        let program = || {
+            vec![X64Function {
+                name: String::from("main"),
+                param_cnt: 0,
+                arrays: Vec::new(),
+                body: vec![
+                    X64::Call(String::from("eac_assert_0"), Vec::new(), X64R::RAX, false),
+                    X64::Ret(None),
+                ],
+            }]
+        };
+        let assertions = vec![String::from("main.c:2: assertion `n > 0` failed")];
+        let file = run(program(), &assertions);
+        let expected = "extern eac_assert: proc
+
+.data
+    eac_assert_message_0 byte \"main.c:2: assertion `n > 0` failed\", 10
+
+.code
+    main proc
+        call eac_assert_0
+        ret
+    main endp
+
+    eac_assert_0 proc
+        lea RDX, eac_assert_message_0
+        mov R8, 35
+        jmp eac_assert
+    eac_assert_0 endp
+
+end
+";
+        assert_eq!(file, expected);
+        let file = run_gas(program(), true, &assertions);
+        let expected = "    .section __DATA,__data
+eac_assert_message_0:
+    .ascii \"main.c:2: assertion `n > 0` failed\\n\"
+
+    .section __TEXT,__text
+
+    .globl _main
+_main:
+    call _eac_assert_0
+    ret
+
+_eac_assert_0:
+    leaq eac_assert_message_0(%rip), %rdx
+    movq $35, %r8
+    jmp _eac_assert
+";
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn library_args() {
+        for lib in ["user32", "user32.lib", "-luser32"] {
+            assert_eq!(Toolchain::Masm.library_arg(lib), "user32.lib");
+            assert_eq!(Toolchain::Mingw.library_arg(lib), "-luser32");
+        }
+    }
 }
@@ -0,0 +1,137 @@
+// Compile a program one function at a time, so consumers such as editors can
+// show the result of every stage for a function as soon as it is ready. Only
+// parsing covers the whole program up front; each call to `next` runs the rest
+// of the pipeline on the next function. Like `bench`, the session keeps the IR
+// types private and hands out the same dumps as `--ssa`, `--cfg`, `--vasm`,
+// and `--asm`.
+use crate::cancel::CancellationToken;
+use crate::ir::Function;
+use crate::lint::Lint;
+use crate::range::Overflow;
+use crate::x64::Spill;
+use crate::{parse_units, Context, Opt, Pipeline};
+use std::path::Path;
+use std::vec::IntoIter;
+
+pub struct CompilationSession {
+    functions: IntoIter<Function>,
+    context: Context,
+    opt: Opt,
+}
+
+// What every stage produced for one function.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionArtifacts {
+    pub name: String,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>, // The lints denied.
+    pub ssa: String,         // After SSA construction and range folding.
+    pub cfg: String,         // After SSA destruction.
+    pub vasm: String,        // After instruction selection, numbering, and scheduling.
+    pub asm: String,         // After register allocation and cleanup.
+}
+
+impl CompilationSession {
+    // Lints in `allow` are off and those in `deny` are errors, as with
+    // `--allow` and `--deny`.
+    pub fn new(
+        sources: &[(&Path, &str)],
+        spill: Spill,
+        overflow: Overflow,
+        stack_guard: bool,
+        allow: &[Lint],
+        deny: &[Lint],
+    ) -> Self {
+        let (ast, context) = parse_units(sources, false);
+        let opt = Opt {
+            spill,
+            overflow,
+            stack_guard,
+            allow: allow.to_vec(),
+            deny: deny.to_vec(),
+            ..Opt::default()
+        };
+        CompilationSession {
+            functions: ast.into_iter(),
+            context,
+            opt,
+        }
+    }
+}
+
+impl Iterator for CompilationSession {
+    type Item = FunctionArtifacts;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let func = self.functions.next()?;
+        let name = func.name.clone();
+        // Every function is built alone, but the pipeline knows the types of
+        // the others from the context.
+        let mut pipeline = Pipeline::new(&self.opt, &self.context);
+        let cancel = CancellationToken::new();
+        let (mut ssa, warnings, errors) = pipeline.construct(vec![func], &cancel).unwrap();
+        pipeline.optimize(&mut ssa);
+        let ssa_dump = format!("{:#?}", ssa[0]);
+        let cfg = pipeline.destruct(ssa);
+        let cfg_dump = format!("{:#?}", cfg[0]);
+        let lir = pipeline.lower(cfg);
+        let vasm = pipeline.select(lir);
+        let vasm_dump = format!("{:#?}", vasm[0]);
+        let asm = pipeline.allocate(vasm);
+        let asm = pipeline.cleanup(asm);
+        Some(FunctionArtifacts {
+            name,
+            warnings,
+            errors,
+            ssa: ssa_dump,
+            cfg: cfg_dump,
+            vasm: vasm_dump,
+            asm: format!("{:#?}", asm[0]),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.functions.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions() {
+        let main = "int main() { int a; return f(a); }";
+        let f = "int f(int n) { while (n) {} return n; 1; }";
+        let sources = [(Path::new("main.c"), main), (Path::new("f.c"), f)];
+        let deny = [Lint::EmptyLoopBody];
+        let mut session = CompilationSession::new(
+            &sources,
+            Spill::default(),
+            Overflow::default(),
+            false,
+            &[],
+            &deny,
+        );
+        assert_eq!(session.size_hint(), (2, Some(2)));
+        let main = session.next().unwrap();
+        assert_eq!(main.name, "main");
+        assert_eq!(
+            main.warnings,
+            vec!["line 1: `a` may be used before it is assigned in function `main`"]
+        );
+        assert!(main.errors.is_empty());
+        assert!(main.vasm.contains("Call("));
+        let f = session.next().unwrap();
+        assert_eq!(f.name, "f");
+        assert_eq!(
+            f.warnings,
+            vec!["line 1: 1 unreachable statement(s) dropped in function `f`"]
+        );
+        assert_eq!(
+            f.errors,
+            vec!["line 1: the body of `while` is empty in function `f` [empty-loop-body]"]
+        );
+        assert!(session.next().is_none());
+    }
+}
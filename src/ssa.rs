@@ -1,150 +1,871 @@
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::dataflow::{self, DataflowAnalysis, Direction};
 use crate::ir::{
-    Block, CFGBuilder, Expression, Function, Program, SSAFunction, SSAProgram, SSAVar, Statement,
-    CFG,
+    declared_double, Block, CFGBuilder, Edge, Expression, Function, Program, Prototype,
+    SSAFunction, SSAProgram, SSAVar, Statement, CFG,
 };
+use crate::lines::{self, BlockLines, Lines};
+use crate::traversal;
 use std::collections::{HashMap, HashSet};
+use std::iter;
 
-pub fn construct(ast: Program) -> (SSAProgram, Vec<Vec<LeavingMap>>) {
+// Construct the SSA form of a program that only calls the functions it defines.
+#[cfg(test)]
+pub fn construct(ast: Program) -> SSAProgram {
+    let signatures = ast
+        .iter()
+        .map(|func| (func.name.clone(), func.prototype()))
+        .collect();
+    let cancel = CancellationToken::new();
+    construct_cancellable(ast, &signatures, &Lines::new(), &mut Vec::new(), &cancel)
+        .unwrap()
+        .0
+}
+
+// Like `construct`, but gives up with `Cancelled` once `cancel` is cancelled.
+// The functions called are those of `signatures`. The statements of every
+// function are at `lines`, as the parser found them, or at the line of the
+// function if it has none, and the lines of the statements of its CFG come
+// along with it. A warning is added to `warnings` for every function with
+// unreachable statements, which are dropped from its CFG, at the line of the
+// first of them.
+pub fn construct_cancellable(
+    ast: Program,
+    signatures: &HashMap<String, Prototype>,
+    lines: &Lines,
+    warnings: &mut Vec<String>,
+    cancel: &CancellationToken,
+) -> Result<(SSAProgram, BlockLines), Cancelled> {
+    let returns = signatures
+        .values()
+        .filter(|proto| proto.double)
+        .map(|proto| proto.name.clone())
+        .collect();
     let mut program = Vec::new();
-    let mut prog_leaves = Vec::new();
-    for (func, leaving_maps) in ast.into_iter().map(construct_cfg).map(construct_ssa) {
+    let mut block_lines = BlockLines::new();
+    for func in ast {
+        let line = func.line;
+        let stmt_lines = lines.get(&func.name).map_or(&[][..], Vec::as_slice);
+        let (func, mut func_lines) = construct_cfg(func, &returns, stmt_lines, warnings);
+        let func = construct_ssa(func, cancel)?;
+        // Phis go first in their blocks, at the line of the statement after them.
+        for (lines, block) in func_lines.iter_mut().zip(&func.body) {
+            let phis = block.statements.len() - lines.len();
+            let first = lines.first().copied().unwrap_or(line);
+            lines.splice(0..0, iter::repeat_n(first, phis));
+        }
+        block_lines.insert(func.name.clone(), func_lines);
         program.push(func);
-        prog_leaves.push(leaving_maps);
     }
-    (program, prog_leaves)
+    Ok((program, block_lines))
 }
 
-pub fn destruct(ssa: SSAProgram, prog_leaving_maps: Vec<Vec<LeavingMap>>) -> SSAProgram {
+pub fn destruct(ssa: SSAProgram) -> SSAProgram {
     ssa.into_iter()
-        .zip(prog_leaving_maps)
-        .map(
-            |(
-                SSAFunction {
-                    void,
-                    name,
-                    parameters,
-                    body,
-                },
-                leaving_maps,
-            )| {
-                SSAFunction {
-                    void,
-                    name,
-                    parameters,
-                    body: destruct_ssa(body, leaving_maps),
-                }
-            },
-        )
+        .map(|func| SSAFunction {
+            body: destruct_ssa(func.body),
+            ..func
+        })
         .collect()
 }
 
+// Build the CFG of a function, along with the line of every statement in it,
+// given those of its AST. The functions in `returns` return a `double`.
 fn construct_cfg(
     Function {
         void,
+        double,
         name,
-        parameters,
-        body,
+        mut parameters,
+        doubles,
+        mut body,
+        line,
     }: Function,
-) -> SSAFunction {
+    returns: &HashSet<String>,
+    lines: &[usize],
+    warnings: &mut Vec<String>,
+) -> (SSAFunction, Vec<Vec<usize>>) {
+    resolve_scopes(&parameters, &mut body);
+    demote_addressed(&mut parameters, &mut body, &doubles);
+    let mut doubles = doubles;
+    // The statements added in front of the body, and every statement if none
+    // has a line, are at the line of the function.
+    let mut lines = lines.to_vec();
+    let start = lines.len().min(1);
+    let added = lines::span(&body) - lines.len();
+    lines.splice(start..start, iter::repeat_n(line, added));
     let mut cfg_builder = CFGBuilder::new();
-    _construct_cfg(body, &mut cfg_builder);
-    SSAFunction {
+    let mut dropped = Vec::new();
+    let mut conditionals = Conditionals {
+        count: 0,
+        doubles: &mut doubles,
+        returns,
+    };
+    _construct_cfg(
+        body,
+        &mut cfg_builder,
+        &mut lines.into_iter(),
+        &mut dropped,
+        &mut conditionals,
+    );
+    if !dropped.is_empty() {
+        warnings.push(format!(
+            "line {}: {} unreachable statement(s) dropped in function `{}`",
+            dropped[0],
+            dropped.len(),
+            name
+        ));
+    }
+    let mut lines = cfg_builder.take_lines();
+    let mut body = cfg_builder.get_cfg();
+    merge_exits(&mut body, void, double, &mut doubles);
+    // So are the statements `merge_exits` adds at the end of a block.
+    lines.resize(body.len(), Vec::new());
+    for (lines, block) in lines.iter_mut().zip(&body) {
+        lines.resize(block.statements.len(), line);
+    }
+    let func = SSAFunction {
         void,
+        double,
         name,
         parameters,
-        body: cfg_builder.get_cfg(),
+        doubles,
+        body,
+    };
+    (func, lines)
+}
+
+// Give the CFG a single exit block, the only one without successors, so the
+// epilog is emitted once. Every block that used to leave the function assigns
+// what it returns to the variable `return`, which no identifier can shadow,
+// and jumps to the new exit block, where SSA construction merges the values
+// with a phi. Falling off the end of a function that returns a value returns
+// 0, as `main` does.
+fn merge_exits(cfg: &mut CFG, void: bool, double: bool, doubles: &mut HashSet<String>) {
+    let exits: Vec<_> = traversal::postorder(cfg, 0)
+        .filter(|&i| cfg[i].successors.is_empty())
+        .collect();
+    if exits.len() < 2 {
+        return;
+    }
+    let var = || Box::new(Expression::Identifier(SSAVar::new("return")));
+    if double {
+        doubles.insert(String::from("return"));
+    }
+    let exit = cfg.len();
+    cfg.push(Block::default());
+    for i in exits {
+        let value = match cfg[i].statements.last() {
+            Some(Statement::Return(_)) => match cfg[i].statements.pop() {
+                Some(Statement::Return(value)) => value,
+                _ => unreachable!(),
+            },
+            _ if void => None,
+            _ => Some(Expression::Number(0)),
+        };
+        let stmt = match (void, value) {
+            (_, None) => None,
+            (true, Some(value)) => Some(Statement::Expression(value)),
+            (false, Some(value)) => Some(Statement::Expression(Expression::Infix {
+                left: var(),
+                operator: "=",
+                right: Box::new(value),
+            })),
+        };
+        cfg[i].statements.extend(stmt);
+        cfg[i].successors.insert(exit);
+        cfg[i].edges.insert(exit, Edge::Return);
+        cfg[exit].predecessors.insert(i);
+    }
+    let value = match void {
+        true => None,
+        false => Some(*var()),
+    };
+    cfg[exit].statements.push(Statement::Return(value));
+}
+
+// Each scope maps a declared name to the name of the variable it refers to.
+type Scope = HashMap<String, String>;
+
+// Rename every variable that is declared again in a nested block, so that it
+// becomes a distinct variable and does not leak into the enclosing scope.
+// Parameters share the outermost scope with the function body.
+fn resolve_scopes(parameters: &[SSAVar], body: &mut Statement) {
+    let params = parameters
+        .iter()
+        .map(|param| (param.name.to_string(), param.name.to_string()))
+        .collect();
+    let mut scopes = vec![params];
+    let mut declared = parameters
+        .iter()
+        .map(|param| (param.name.to_string(), 0))
+        .collect();
+    match body {
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                resolve_stmt_scopes(stmt, &mut scopes, &mut declared);
+            }
+        }
+        stmt => resolve_stmt_scopes(stmt, &mut scopes, &mut declared),
+    }
+}
+
+fn resolve_stmt_scopes(
+    stmt: &mut Statement,
+    scopes: &mut Vec<Scope>,
+    declared: &mut HashMap<String, usize>,
+) {
+    match stmt {
+        Statement::Nop | Statement::Break | Statement::Continue => {}
+        Statement::Phi(_, _) => unreachable!(),
+        Statement::Declaration(SSAVar { name, .. })
+        | Statement::ArrayDeclaration(SSAVar { name, .. }, _) => {
+            let scope = scopes.last_mut().unwrap();
+            let resolved = match (scope.get(name), declared.get_mut(name)) {
+                // Redeclaration in the same scope.
+                (Some(resolved), _) => resolved.to_string(),
+                (None, Some(cnt)) => {
+                    *cnt += 1;
+                    format!("{}.{}", name, cnt)
+                }
+                (None, None) => {
+                    declared.insert(name.to_string(), 0);
+                    name.to_string()
+                }
+            };
+            scope.insert(name.to_string(), resolved.to_string());
+            *name = resolved;
+        }
+        Statement::Compound(stmts) => {
+            scopes.push(Scope::new());
+            for stmt in stmts {
+                resolve_stmt_scopes(stmt, scopes, declared);
+            }
+            scopes.pop();
+        }
+        Statement::Expression(expr) => resolve_expr_scopes(expr, scopes),
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => {
+            resolve_expr_scopes(condition, scopes);
+            resolve_stmt_scopes(body, scopes, declared);
+            if let Some(alt) = alternative {
+                resolve_stmt_scopes(alt, scopes, declared);
+            }
+        }
+        Statement::While { condition, body } => {
+            resolve_expr_scopes(condition, scopes);
+            resolve_stmt_scopes(body, scopes, declared);
+        }
+        Statement::DoWhile { body, condition } => {
+            resolve_stmt_scopes(body, scopes, declared);
+            resolve_expr_scopes(condition, scopes);
+        }
+        Statement::Return(Some(expr)) => resolve_expr_scopes(expr, scopes),
+        Statement::Return(None) => {}
+    }
+}
+
+fn resolve_expr_scopes(expr: &mut Expression, scopes: &[Scope]) {
+    match expr {
+        Expression::Identifier(SSAVar { name, .. }) => {
+            if let Some(resolved) = scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                *name = resolved.to_string();
+            }
+        }
+        Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { array, indices } => {
+            let name = &mut array.name;
+            if let Some(resolved) = scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                *name = resolved.to_string();
+            }
+            for index in indices {
+                resolve_expr_scopes(index, scopes);
+            }
+        }
+        Expression::Call { arguments, .. } => resolve_expr_scopes(arguments, scopes),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                resolve_expr_scopes(expr, scopes);
+            }
+        }
+        Expression::Prefix { expression, .. } => resolve_expr_scopes(expression, scopes),
+        Expression::Infix { left, right, .. } => {
+            resolve_expr_scopes(left, scopes);
+            resolve_expr_scopes(right, scopes);
+        }
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => {
+            resolve_expr_scopes(condition, scopes);
+            resolve_expr_scopes(consequent, scopes);
+            resolve_expr_scopes(alternative, scopes);
+        }
+    }
+}
+
+// A variable whose address is taken may change through a pointer, so it lives
+// in memory as an array of one element instead of in SSA variables. `&x`
+// becomes `&x[0]`, and every other use of `x` reads or writes `x[0]`. A
+// parameter is renamed `x.0` and copied into memory on entry. Memory only
+// holds integers, so the address of a `double` cannot be taken.
+fn demote_addressed(parameters: &mut [SSAVar], body: &mut Statement, doubles: &HashSet<String>) {
+    let mut addressed = HashSet::new();
+    for expr in stmt_exprs(body) {
+        find_addressed(expr, &mut addressed);
+    }
+    for name in &addressed {
+        let name = name.split('.').next().unwrap();
+        if doubles.contains(name) {
+            panic!("The address of `double` `{}` cannot be taken.", name);
+        }
+    }
+    if addressed.is_empty() {
+        return;
+    }
+    // Arrays and functions are left alone.
+    let mut demoted = HashSet::new();
+    demote_declarations(body, &addressed, &mut demoted);
+    let mut copies = Vec::new();
+    for param in parameters.iter_mut() {
+        if addressed.contains(&param.name) {
+            demoted.insert(param.name.to_string());
+            let var = SSAVar::new(&param.name);
+            param.name = format!("{}.0", param.name);
+            copies.push(Statement::ArrayDeclaration(var.clone(), vec![1]));
+            copies.push(Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Index {
+                    array: var,
+                    indices: vec![Expression::Number(0)],
+                }),
+                operator: "=",
+                right: Box::new(Expression::Identifier(param.clone())),
+            }));
+        }
+    }
+    for expr in stmt_exprs(body) {
+        demote_expr(expr, &demoted);
+    }
+    if let Statement::Compound(stmts) = body {
+        stmts.splice(0..0, copies);
+    }
+}
+
+// The expressions directly held by `stmt` and the statements nested in it.
+fn stmt_exprs(stmt: &mut Statement) -> Vec<&mut Expression> {
+    match stmt {
+        Statement::Compound(stmts) => stmts.iter_mut().flat_map(stmt_exprs).collect(),
+        Statement::Expression(expr) | Statement::Return(Some(expr)) => vec![expr],
+        Statement::If {
+            condition,
+            body,
+            alternative,
+        } => {
+            let mut exprs = vec![condition];
+            exprs.extend(stmt_exprs(body));
+            if let Some(alt) = alternative {
+                exprs.extend(stmt_exprs(alt));
+            }
+            exprs
+        }
+        Statement::While { condition, body } | Statement::DoWhile { body, condition } => {
+            let mut exprs = vec![condition];
+            exprs.extend(stmt_exprs(body));
+            exprs
+        }
+        _ => Vec::new(),
+    }
+}
+
+// The operands of `expr`. The callee of a call is a function, not an operand.
+fn operands(expr: &mut Expression) -> Vec<&mut Expression> {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => Vec::new(),
+        Expression::Index { indices, .. } => indices.iter_mut().collect(),
+        Expression::Call { arguments, .. } => vec![arguments],
+        Expression::Arguments(exprs) => exprs.iter_mut().collect(),
+        Expression::Prefix { expression, .. } => vec![expression],
+        Expression::Infix { left, right, .. } => vec![left, right],
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => vec![condition, consequent, alternative],
+    }
+}
+
+fn find_addressed(expr: &mut Expression, addressed: &mut HashSet<String>) {
+    if let Expression::Prefix {
+        operator: "&",
+        expression,
+    } = expr
+    {
+        if let Expression::Identifier(var) = &**expression {
+            addressed.insert(var.name.to_string());
+        }
+    }
+    for operand in operands(expr) {
+        find_addressed(operand, addressed);
+    }
+}
+
+fn demote_declarations(
+    stmt: &mut Statement,
+    addressed: &HashSet<String>,
+    demoted: &mut HashSet<String>,
+) {
+    match stmt {
+        Statement::Declaration(var) if addressed.contains(&var.name) => {
+            demoted.insert(var.name.to_string());
+            *stmt = Statement::ArrayDeclaration(var.clone(), vec![1]);
+        }
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                demote_declarations(stmt, addressed, demoted);
+            }
+        }
+        Statement::If {
+            body, alternative, ..
+        } => {
+            demote_declarations(body, addressed, demoted);
+            if let Some(alt) = alternative {
+                demote_declarations(alt, addressed, demoted);
+            }
+        }
+        Statement::While { body, .. } | Statement::DoWhile { body, .. } => {
+            demote_declarations(body, addressed, demoted)
+        }
+        _ => {}
     }
 }
 
-fn _construct_cfg(stmt: Statement, cfg: &mut CFGBuilder) -> bool {
-    let mut early_return = false;
+fn demote_expr(expr: &mut Expression, demoted: &HashSet<String>) {
+    match expr {
+        Expression::Identifier(var) if demoted.contains(&var.name) => {
+            *expr = Expression::Index {
+                array: var.clone(),
+                indices: vec![Expression::Number(0)],
+            };
+        }
+        expr => {
+            for operand in operands(expr) {
+                demote_expr(operand, demoted);
+            }
+        }
+    }
+}
+
+// Statements following a `return`, `break`, or `continue` in the same compound
+// statement can never run, so they are dropped, and their lines are pushed to
+// `dropped`. `lines` goes through the lines of `stmt` in pre-order. Every `?:`
+// is lowered into blocks of its own ahead of the statement it is in.
+fn _construct_cfg(
+    stmt: Statement,
+    cfg: &mut CFGBuilder,
+    lines: &mut impl Iterator<Item = usize>,
+    dropped: &mut Vec<usize>,
+    conditionals: &mut Conditionals,
+) {
+    let line = lines.next().unwrap();
+    cfg.set_line(line);
     match stmt {
         Statement::Nop => unreachable!(),
         Statement::Phi(_, _) => unreachable!(),
-        stmt @ Statement::Declaration(_) => cfg.push(stmt),
+        stmt @ (Statement::Declaration(_) | Statement::ArrayDeclaration(..)) => cfg.push(stmt),
         Statement::Compound(stmts) => {
             cfg.enter_new_block();
-            early_return |= stmts.into_iter().any(|stmt| _construct_cfg(stmt, cfg));
-            cfg.enter_new_block();
+            let mut stmts = stmts.into_iter();
+            while cfg.reachable() {
+                match stmts.next() {
+                    Some(stmt) => _construct_cfg(stmt, cfg, lines, dropped, conditionals),
+                    None => break,
+                }
+            }
+            for stmt in stmts {
+                let mut skipped = lines.by_ref().take(lines::span(&stmt));
+                dropped.extend(skipped.next());
+                skipped.for_each(drop);
+            }
         }
-        stmt @ Statement::Expression(_) => cfg.push(stmt),
+        Statement::Expression(expr) => lower_statement(expr, cfg, conditionals),
         Statement::If {
-            condition,
+            mut condition,
             body,
             alternative,
         } => {
+            lower_conditionals(&mut condition, cfg, conditionals);
             cfg.enter_if(condition, alternative.is_some());
-            cfg.enter_if_body();
-            let body_return = _construct_cfg(*body, cfg);
+            _construct_cfg(*body, cfg, lines, dropped, conditionals);
             cfg.exit_if_body();
-            let alt_return = alternative
-                .map(|alt| {
-                    cfg.enter_if_alt();
-                    let alt_return = _construct_cfg(*alt, cfg);
-                    cfg.exit_if_alt();
-                    alt_return
-                })
-                .unwrap_or_default();
+            if let Some(alt) = alternative {
+                cfg.enter_if_alt();
+                _construct_cfg(*alt, cfg, lines, dropped, conditionals);
+            }
             cfg.exit_if();
-            early_return |= body_return && alt_return;
         }
-        Statement::While { condition, body } => {
-            cfg.enter_while(condition);
-            let body_return = _construct_cfg(*body, cfg);
-            cfg.exit_while(body_return);
+        Statement::While {
+            mut condition,
+            body,
+        } => {
+            cfg.enter_while();
+            lower_conditionals(&mut condition, cfg, conditionals);
+            cfg.enter_while_body(condition);
+            _construct_cfg(*body, cfg, lines, dropped, conditionals);
+            cfg.exit_while();
+        }
+        Statement::DoWhile {
+            body,
+            mut condition,
+        } => {
+            cfg.enter_do();
+            _construct_cfg(*body, cfg, lines, dropped, conditionals);
+            cfg.set_line(line);
+            cfg.enter_do_condition();
+            if cfg.reachable() {
+                lower_conditionals(&mut condition, cfg, conditionals);
+            }
+            cfg.exit_do(condition);
+        }
+        Statement::Break => cfg.push_break(),
+        Statement::Continue => cfg.push_continue(),
+        Statement::Return(mut expr) => {
+            if let Some(expr) = &mut expr {
+                lower_conditionals(expr, cfg, conditionals);
+            }
+            cfg.push(Statement::Return(expr));
+        }
+    }
+}
+
+// The variables holding the values of the `?:` lowered in a function, which
+// are `%cond` and a number, as no identifier can be, and what tells their
+// types apart.
+struct Conditionals<'a> {
+    count: usize,
+    doubles: &'a mut HashSet<String>,
+    returns: &'a HashSet<String>, // The functions returning a `double`.
+}
+
+impl Conditionals<'_> {
+    // A new variable for the value of `expr`, which is a `double` if either
+    // of its arms is.
+    fn create(&mut self, expr: &Expression) -> SSAVar {
+        let name = format!("%cond{}", self.count);
+        self.count += 1;
+        if self.is_double(expr) {
+            self.doubles.insert(name.clone());
+        }
+        SSAVar::new(&name)
+    }
+
+    // Whether the LIR computes `expr` in a `double` register.
+    fn is_double(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Identifier(var) => declared_double(self.doubles, var),
+            Expression::Float(_) => true,
+            Expression::Number(_) | Expression::Index { .. } | Expression::Arguments(_) => false,
+            Expression::Call { function, .. } => match &**function {
+                Expression::Identifier(var) => self.returns.contains(&var.name),
+                _ => false,
+            },
+            Expression::Prefix {
+                operator: "+" | "-",
+                expression,
+            } => self.is_double(expression),
+            Expression::Prefix { .. } => false,
+            // An assignment gives the type of its target.
+            Expression::Infix {
+                left,
+                operator: "=",
+                ..
+            } => self.is_double(left),
+            Expression::Infix {
+                left,
+                operator: "+" | "-" | "*" | "/" | "%",
+                right,
+            } => self.is_double(left) || self.is_double(right),
+            Expression::Infix { .. } => false,
+            Expression::Conditional {
+                consequent,
+                alternative,
+                ..
+            } => self.is_double(consequent) || self.is_double(alternative),
+        }
+    }
+}
+
+// An expression statement. Its value is not used, so a `?:` at its top becomes
+// an `if` whose arms are statements of their own.
+fn lower_statement(mut expr: Expression, cfg: &mut CFGBuilder, conditionals: &mut Conditionals) {
+    if let Expression::Conditional { .. } = expr {
+        lower_conditional(expr, None, cfg, conditionals);
+    } else {
+        lower_conditionals(&mut expr, cfg, conditionals);
+        cfg.push(Statement::Expression(expr));
+    }
+}
+
+// Only one arm of `?:` runs, so every `?:` in `expr` is lowered into an `if`
+// ahead of the rest of `expr`, as `if (c) t = x; else t = y;`, and `t` takes
+// its place. An assignment in an arm is then in a block of its own, and a phi
+// joins the versions of the arms, as after any `if`. The rest of `expr` may
+// run after the `?:`, where C leaves the order of evaluation unspecified,
+// except past `&&` and `||`: one whose right operand holds a `?:` or an
+// assignment becomes a `?:` itself, so the right operand is only evaluated
+// when C evaluates it, and a phi joins what it assigns with what came before.
+fn lower_conditionals(
+    expr: &mut Expression,
+    cfg: &mut CFGBuilder,
+    conditionals: &mut Conditionals,
+) {
+    if let Expression::Infix {
+        operator: "&&" | "||",
+        right,
+        ..
+    } = expr
+    {
+        if branches(right) {
+            let short_circuit = std::mem::replace(expr, Expression::Number(0));
+            *expr = short_circuit_conditional(short_circuit);
+        }
+    }
+    if let Expression::Conditional { .. } = expr {
+        let var = conditionals.create(expr);
+        let conditional = std::mem::replace(expr, Expression::Identifier(var.clone()));
+        lower_conditional(conditional, Some(&var), cfg, conditionals);
+    } else {
+        for operand in operands(expr) {
+            lower_conditionals(operand, cfg, conditionals);
         }
-        stmt @ Statement::Return(_) => {
-            cfg.push(stmt);
-            early_return = true;
+    }
+}
+
+// Whether `expr` holds a `?:` or an assignment, which only belong in the
+// right operand of `&&` and `||` in a block of their own.
+fn branches(expr: &mut Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Conditional { .. } | Expression::Infix { operator: "=", .. }
+    ) || operands(expr).into_iter().any(branches)
+}
+
+// `l && r` as `l ? r != 0 : 0`, and `l || r` as `l ? 1 : r != 0`.
+fn short_circuit_conditional(expr: Expression) -> Expression {
+    let (left, operator, right) = match expr {
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => (left, operator, right),
+        _ => unreachable!(),
+    };
+    let truth = Box::new(Expression::Infix {
+        left: right,
+        operator: "!=",
+        right: Box::new(Expression::Number(0)),
+    });
+    let (consequent, alternative) = match operator {
+        "&&" => (truth, Box::new(Expression::Number(0))),
+        _ => (Box::new(Expression::Number(1)), truth),
+    };
+    Expression::Conditional {
+        condition: left,
+        consequent,
+        alternative,
+    }
+}
+
+// Lower `conditional` into an `if` whose arms assign its value to `var`, or
+// only evaluate it without one. A `?:` in an arm assigns `var` in its own arms.
+fn lower_conditional(
+    conditional: Expression,
+    var: Option<&SSAVar>,
+    cfg: &mut CFGBuilder,
+    conditionals: &mut Conditionals,
+) {
+    let (mut condition, consequent, alternative) = match conditional {
+        Expression::Conditional {
+            condition,
+            consequent,
+            alternative,
+        } => (condition, consequent, alternative),
+        _ => unreachable!(),
+    };
+    lower_conditionals(&mut condition, cfg, conditionals);
+    cfg.enter_if(*condition, true);
+    lower_arm(*consequent, var, cfg, conditionals);
+    cfg.exit_if_body();
+    cfg.enter_if_alt();
+    lower_arm(*alternative, var, cfg, conditionals);
+    cfg.exit_if();
+}
+
+fn lower_arm(
+    mut arm: Expression,
+    var: Option<&SSAVar>,
+    cfg: &mut CFGBuilder,
+    conditionals: &mut Conditionals,
+) {
+    match var {
+        None => lower_statement(arm, cfg, conditionals),
+        Some(_) if matches!(arm, Expression::Conditional { .. }) => {
+            lower_conditional(arm, var, cfg, conditionals)
+        }
+        Some(var) => {
+            lower_conditionals(&mut arm, cfg, conditionals);
+            cfg.push(Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Identifier(var.clone())),
+                operator: "=",
+                right: Box::new(arm),
+            }));
         }
     }
-    early_return
 }
 
 fn construct_ssa(
     SSAFunction {
         void,
+        double,
         name,
         mut parameters,
+        doubles,
         mut body,
     }: SSAFunction,
-) -> (SSAFunction, Vec<LeavingMap>) {
-    insert_phi(&mut body);
-    let (reaching_maps, leaves) = find_inout_defs(&mut parameters, &mut body);
-    rename_ssa(&reaching_maps, &mut body);
-    (
-        SSAFunction {
-            void,
-            name,
-            parameters,
-            body,
-        },
-        leaves,
-    )
+    cancel: &CancellationToken,
+) -> Result<SSAFunction, Cancelled> {
+    cancel.check()?;
+    let idoms = dataflow::dominators_cancellable(&body, cancel)?;
+    insert_phi(&parameters, &mut body, &idoms, cancel)?;
+    rename_ssa(&mut parameters, &mut body, &idoms);
+    Ok(SSAFunction {
+        void,
+        double,
+        name,
+        parameters,
+        doubles,
+        body,
+    })
 }
 
-fn insert_phi(body: &mut CFG) {
-    for block in body {
-        if block.predecessors.len() > 1 {
-            let mut vars = Vec::new();
-            for stmt in &block.statements {
-                find_stmt_vars(stmt, &mut vars);
+// Place phis at the iterated dominance frontier of the blocks that define
+// each variable, where its parameter, declarations, and assignments meet.
+// A variable only needs a phi in the blocks it is live into.
+fn insert_phi(
+    parameters: &[SSAVar],
+    body: &mut CFG,
+    idoms: &[Option<usize>],
+    cancel: &CancellationToken,
+) -> Result<(), Cancelled> {
+    let frontiers = dominance_frontiers(body, idoms);
+    let live = dataflow::solve_cancellable(&Liveness, body, cancel)?.ins;
+    let mut def_blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for param in parameters {
+        def_blocks
+            .entry(param.name.to_string())
+            .or_default()
+            .push(0);
+    }
+    for (i, block) in body.iter().enumerate() {
+        for stmt in &block.statements {
+            let mut defs = Vec::new();
+            find_stmt_defs(stmt, &mut defs);
+            for def in defs {
+                let blocks = def_blocks.entry(def).or_default();
+                if blocks.last() != Some(&i) {
+                    blocks.push(i);
+                }
+            }
+        }
+    }
+    // Insert the phis of every block in the order of their variables.
+    let mut def_blocks: Vec<_> = def_blocks.into_iter().collect();
+    def_blocks.sort();
+    def_blocks.reverse();
+    for (var, mut work) in def_blocks {
+        let mut placed = HashSet::new();
+        while let Some(block) = work.pop() {
+            for &frontier in &frontiers[block] {
+                if live[frontier].contains(&var) && placed.insert(frontier) {
+                    let phi = Statement::Phi(SSAVar::new(&var), Vec::new());
+                    body[frontier].statements.insert(0, phi);
+                    work.push(frontier);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The variables that may be read before being defined again on some path from
+// a block, computed before renaming.
+struct Liveness;
+
+impl DataflowAnalysis for Liveness {
+    type Fact = HashSet<String>;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn boundary(&self) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn top(&self) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn meet(&self, left: &Self::Fact, right: &Self::Fact) -> Self::Fact {
+        left.union(right).cloned().collect()
+    }
+
+    fn transfer(&self, _: usize, block: &Block, live: &Self::Fact) -> Self::Fact {
+        let mut live = live.clone();
+        for stmt in block.statements.iter().rev() {
+            let mut defs = Vec::new();
+            find_stmt_defs(stmt, &mut defs);
+            for def in defs {
+                live.remove(&def);
             }
-            for var in vars {
-                let phi = Statement::Phi(SSAVar::new(&var), HashSet::new());
-                block.statements.insert(0, phi);
+            let mut uses = Vec::new();
+            find_stmt_vars(stmt, &mut uses);
+            live.extend(uses);
+        }
+        live
+    }
+}
+
+// The blocks where the dominance of each block ends: those it does not
+// strictly dominate but which have a predecessor it dominates.
+fn dominance_frontiers(body: &CFG, idoms: &[Option<usize>]) -> Vec<HashSet<usize>> {
+    let mut frontiers = vec![HashSet::new(); body.len()];
+    for (i, block) in body.iter().enumerate() {
+        if block.predecessors.len() < 2 || idoms[i].is_none() {
+            continue;
+        }
+        for &pred in &block.predecessors {
+            let mut runner = pred;
+            while Some(runner) != idoms[i] {
+                frontiers[runner].insert(i);
+                runner = idoms[runner].unwrap();
             }
         }
     }
+    frontiers
 }
 
 fn find_stmt_vars(stmt: &Statement, vars: &mut Vec<String>) {
     match stmt {
-        Statement::Nop => {}
+        // A declaration defines its variable without reading it, and arrays
+        // stay in memory and need no phi.
+        Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Declaration(_)
+        | Statement::ArrayDeclaration(..) => {}
         Statement::Phi(_, _) => unreachable!(),
-        Statement::Declaration(SSAVar { name, .. }) => {
-            vars.push(name.to_string());
-        }
         Statement::Compound(stmts) => {
             for stmt in stmts {
                 find_stmt_vars(stmt, vars);
@@ -162,7 +883,7 @@ fn find_stmt_vars(stmt: &Statement, vars: &mut Vec<String>) {
                 find_stmt_vars(alt, vars);
             }
         }
-        Statement::While { condition, body } => {
+        Statement::While { condition, body } | Statement::DoWhile { body, condition } => {
             find_expr_vars(condition, vars);
             find_stmt_vars(body, vars);
         }
@@ -171,10 +892,21 @@ fn find_stmt_vars(stmt: &Statement, vars: &mut Vec<String>) {
     }
 }
 
+// The variables read in `expr`. The variable an assignment stores to is not.
 fn find_expr_vars(expr: &Expression, vars: &mut Vec<String>) {
     match expr {
+        Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        } if matches!(**left, Expression::Identifier(_)) => find_expr_vars(right, vars),
         Expression::Identifier(var) => vars.push(var.name.to_string()),
-        Expression::Number(_) => {}
+        Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_expr_vars(index, vars);
+            }
+        }
         Expression::Call { arguments, .. } => find_expr_vars(arguments, vars),
         Expression::Arguments(exprs) => {
             for expr in exprs {
@@ -186,214 +918,226 @@ fn find_expr_vars(expr: &Expression, vars: &mut Vec<String>) {
             find_expr_vars(left, vars);
             find_expr_vars(right, vars);
         }
+        Expression::Conditional { .. } => unreachable!(),
     }
 }
 
-type ReachingMap = HashMap<String, HashSet<usize>>;
-type LeavingMap = HashMap<String, usize>;
-
-fn find_inout_defs(
-    parameters: &mut Vec<SSAVar>,
-    body: &mut CFG,
-) -> (Vec<ReachingMap>, Vec<LeavingMap>) {
-    let mut def_map = HashMap::new();
-    let mut reaches = vec![ReachingMap::new(); body.len()];
-    let mut de_defs = vec![HashMap::new(); body.len()];
-    let mut def_kills = vec![HashMap::new(); body.len()];
-    // Process function arguments.
-    for SSAVar { name, subscript } in parameters {
-        let sub = def_map.entry(name.to_string()).or_default();
-        *subscript = Some(*sub);
-        reaches[0].insert(name.to_string(), vec![*sub].into_iter().collect());
-        *sub += 1;
+// Collect the variables a statement defines: the one it declares, or the ones
+// it assigns.
+fn find_stmt_defs(stmt: &Statement, defs: &mut Vec<String>) {
+    match stmt {
+        Statement::Declaration(var) => defs.push(var.name.to_string()),
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => find_assigned(expr, defs),
+        _ => {}
     }
-    // Compute `de_def` and `def_kill`.
-    for i in 0..body.len() {
-        let de_def = &mut de_defs[i];
-        let def_kill = &mut def_kills[i];
-        for stmt in &mut body[i].statements {
-            if let Statement::Phi(SSAVar { name, subscript }, ..)
-            | Statement::Declaration(SSAVar { name, subscript }) = stmt
-            {
-                let sub = def_map.entry(name.to_string()).or_default();
-                *subscript = Some(*sub);
-                match de_def.get_mut(name) {
-                    Some(sub @ Some(_)) => *sub = None, // def killed
-                    Some(None) => {}
-                    None => {
-                        de_def.insert(name.to_string(), Some(*sub));
-                    }
-                };
-                def_kill.insert(name.to_string(), *sub);
-                *sub += 1;
-            }
-        }
-    }
-    // Solve the data-flow equation.
-    let mut old_reaches = Vec::new();
-    while old_reaches != reaches {
-        old_reaches = reaches.clone();
-        for i in 0..body.len() {
-            let preds = find_predecessors(body, i);
-            for pred in preds {
-                let mut fall_through = reaches[pred].clone();
-                for name in def_kills[pred].keys() {
-                    fall_through.remove(name);
-                }
-                for (name, sub) in fall_through {
-                    reaches[i].entry(name).or_default().extend(sub);
-                }
-                for (name, sub) in &de_defs[pred] {
-                    if let Some(sub) = sub {
-                        reaches[i].entry(name.clone()).or_default().insert(*sub);
-                    }
-                }
+}
+
+// The variables that `expr` assigns to, which `rename_expr_vars` gives new
+// versions.
+fn find_assigned(expr: &Expression, vars: &mut Vec<String>) {
+    match expr {
+        Expression::Identifier(_) | Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                find_assigned(index, vars);
             }
         }
+        Expression::Call { arguments, .. } => find_assigned(arguments, vars),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                find_assigned(expr, vars);
+            }
+        }
+        Expression::Prefix { expression, .. } => find_assigned(expression, vars),
+        Expression::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            find_assigned(left, vars);
+            find_assigned(right, vars);
+            if let (Expression::Identifier(var), "=") = (&**left, *operator) {
+                vars.push(var.name.to_string());
+            }
+        }
+        Expression::Conditional { .. } => unreachable!(),
     }
-    // Compute leaving maps.
-    let mut leaves = vec![LeavingMap::new(); body.len()];
-    for (reach, leave) in reaches.iter().zip(leaves.iter_mut()) {
-        for (name, subs) in reach {
-            leave.insert(name.clone(), *subs.iter().next().unwrap());
+}
+
+// Every definition of a variable gets the next subscript of its name, and every
+// use gets the subscript of the definition that dominates it most closely.
+fn rename_ssa(parameters: &mut [SSAVar], body: &mut CFG, idoms: &[Option<usize>]) {
+    let mut children = vec![Vec::new(); body.len()];
+    for (i, idom) in idoms.iter().enumerate() {
+        if let Some(idom) = idom {
+            children[*idom].push(i);
         }
     }
-    for (leave, def_kill) in leaves.iter_mut().zip(def_kills) {
-        for (name, sub) in def_kill {
-            leave.insert(name, sub);
+    let mut versions = Versions::default();
+    for param in parameters {
+        versions.define(param);
+    }
+    rename_block(0, body, &children, &mut versions);
+    for block in body {
+        for stmt in &mut block.statements {
+            if let Statement::Phi(_, values) = stmt {
+                values.sort_by_key(|(pred, _)| *pred);
+            }
         }
     }
-    (reaches, leaves)
 }
 
-fn find_predecessors(body: &[Block], index: usize) -> HashSet<usize> {
-    let mut predecessors = HashSet::new();
-    let mut stack = vec![index];
-    while let Some(pred) = stack.pop() {
-        let preds = &body[pred].predecessors;
-        if preds.is_subset(&predecessors) {
-            break;
+// Walking the dominator tree, the subscripts given out so far, and for every
+// name, a stack of the subscripts of the definitions in scope.
+#[derive(Default)]
+struct Versions {
+    subs: HashMap<String, usize>,
+    stacks: HashMap<String, Vec<usize>>,
+    defined: Vec<String>, // The names defined on the way to the current block.
+}
+
+impl Versions {
+    fn define(&mut self, var: &mut SSAVar) {
+        let sub = self.subs.entry(var.name.to_string()).or_default();
+        var.subscript = Some(*sub);
+        self.stacks
+            .entry(var.name.to_string())
+            .or_default()
+            .push(*sub);
+        self.defined.push(var.name.to_string());
+        *sub += 1;
+    }
+
+    fn current(&self, name: &str) -> Option<usize> {
+        self.stacks.get(name).and_then(|subs| subs.last()).copied()
+    }
+
+    // Forget the definitions made since `defined` had `len` names.
+    fn truncate(&mut self, len: usize) {
+        for name in self.defined.drain(len..) {
+            self.stacks.get_mut(&name).unwrap().pop();
         }
-        predecessors.extend(preds.clone());
-        stack.extend(preds.clone());
     }
-    predecessors
 }
 
-fn rename_ssa(reaching_maps: &[ReachingMap], body: &mut CFG) {
-    for (block, reaching_map) in body.iter_mut().zip(reaching_maps) {
-        let mut var_map = HashMap::new();
-        for stmt in &mut block.statements {
-            rename_stmt_vars(stmt, reaching_map, &mut var_map);
+fn rename_block(index: usize, body: &mut CFG, children: &[Vec<usize>], versions: &mut Versions) {
+    let len = versions.defined.len();
+    for stmt in &mut body[index].statements {
+        match stmt {
+            Statement::Phi(var, _) | Statement::Declaration(var) => versions.define(var),
+            stmt => rename_stmt_vars(stmt, versions),
+        }
+    }
+    let mut succs: Vec<_> = body[index].successors.iter().copied().collect();
+    succs.sort_unstable();
+    for succ in succs {
+        for stmt in &mut body[succ].statements {
+            if let Statement::Phi(var, values) = stmt {
+                if let Some(sub) = versions.current(&var.name) {
+                    let value = SSAVar {
+                        name: var.name.to_string(),
+                        subscript: Some(sub),
+                    };
+                    values.push((index, value));
+                }
+            }
         }
     }
+    for &child in &children[index] {
+        rename_block(child, body, children, versions);
+    }
+    versions.truncate(len);
 }
 
-fn rename_stmt_vars(
-    stmt: &mut Statement,
-    reaching_map: &ReachingMap,
-    var_map: &mut HashMap<String, usize>,
-) {
+fn rename_stmt_vars(stmt: &mut Statement, versions: &mut Versions) {
     match stmt {
-        Statement::Nop => {}
-        Statement::Phi(var, values) => {
-            let subs = reaching_map
-                .get(&var.name)
-                .unwrap_or_else(|| panic!("Undefined variable `{}`.", var.name));
-            for sub in subs {
-                let value = SSAVar {
-                    name: var.name.to_string(),
-                    subscript: Some(*sub),
-                };
-                values.insert(value);
-            }
-            var_map.insert(var.name.to_string(), var.subscript.unwrap());
-        }
-        Statement::Declaration(SSAVar { name, subscript }) => {
-            var_map.insert(name.to_string(), subscript.unwrap());
-        }
+        Statement::Nop | Statement::Break | Statement::Continue => {}
+        Statement::Phi(..) | Statement::Declaration(_) => unreachable!(),
+        Statement::ArrayDeclaration(..) => {}
         Statement::Compound(stmts) => {
             for stmt in stmts {
-                rename_stmt_vars(stmt, reaching_map, var_map);
+                rename_stmt_vars(stmt, versions);
             }
         }
-        Statement::Expression(expr) => {
-            rename_expr_vars(expr, reaching_map, var_map);
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
         }
-        Statement::If {
-            condition,
-            body,
-            alternative,
-        } => {
-            rename_expr_vars(condition, reaching_map, var_map);
-            rename_stmt_vars(body, reaching_map, var_map);
-            if let Some(alt) = alternative {
-                rename_stmt_vars(alt, reaching_map, var_map);
-            }
-        }
-        Statement::While { condition, body } => {
-            rename_expr_vars(condition, reaching_map, var_map);
-            rename_stmt_vars(body, reaching_map, var_map);
+        | Statement::While {
+            condition: expr, ..
         }
-        Statement::Return(Some(expr)) => {
-            rename_expr_vars(expr, reaching_map, var_map);
+        | Statement::DoWhile {
+            condition: expr, ..
         }
+        | Statement::Return(Some(expr)) => rename_expr_vars(expr, versions),
         Statement::Return(None) => {}
     }
 }
 
-fn rename_expr_vars(
-    expr: &mut Expression,
-    reaching_map: &ReachingMap,
-    var_map: &mut HashMap<String, usize>,
-) {
+// Operands are renamed in the order they are evaluated. An assignment to a
+// variable defines a new version of it.
+fn rename_expr_vars(expr: &mut Expression, versions: &mut Versions) {
     match expr {
         Expression::Identifier(SSAVar { name, subscript }) => {
-            *subscript = match var_map.get(name) {
-                sub @ Some(_) => sub.cloned(),
-                None => {
-                    let reach = reaching_map
-                        .get(name)
-                        .unwrap_or_else(|| panic!("Undefined variable `{}`.", name));
-                    reach.iter().next().cloned()
-                }
-            };
+            let sub = versions.current(name);
+            *subscript = Some(sub.unwrap_or_else(|| panic!("Undefined variable `{}`.", name)));
+        }
+        Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                rename_expr_vars(index, versions);
+            }
         }
-        Expression::Number(_) => {}
         Expression::Call { arguments, .. } => {
-            rename_expr_vars(arguments, reaching_map, var_map);
+            rename_expr_vars(arguments, versions);
         }
         Expression::Arguments(exprs) => {
             for expr in exprs {
-                rename_expr_vars(expr, reaching_map, var_map);
+                rename_expr_vars(expr, versions);
             }
         }
         Expression::Prefix { expression, .. } => {
-            rename_expr_vars(expression, reaching_map, var_map);
+            rename_expr_vars(expression, versions);
+        }
+        Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        } if matches!(**left, Expression::Identifier(_)) => {
+            rename_expr_vars(right, versions);
+            if let Expression::Identifier(var) = &mut **left {
+                versions.define(var);
+            }
         }
         Expression::Infix { left, right, .. } => {
-            rename_expr_vars(left, reaching_map, var_map);
-            rename_expr_vars(right, reaching_map, var_map);
+            rename_expr_vars(left, versions);
+            rename_expr_vars(right, versions);
         }
+        Expression::Conditional { .. } => unreachable!(),
     }
 }
 
-fn destruct_ssa(mut body: CFG, leaves: Vec<LeavingMap>) -> CFG {
+fn destruct_ssa(mut body: CFG) -> CFG {
     for i in 0..body.len() {
-        while let Some(Statement::Phi(var, _)) = body[i].statements.first().cloned() {
+        while let Some(Statement::Phi(var, values)) = body[i].statements.first().cloned() {
             body[i].statements.remove(0);
-            for pred in body[i].predecessors.clone() {
-                if let Some(sub) = leaves[pred].get(&var.name) {
-                    let copy = Statement::Expression(Expression::Infix {
-                        left: Box::new(Expression::Identifier(var.clone())),
-                        operator: "=",
-                        right: Box::new(Expression::Identifier(SSAVar {
-                            name: var.name.to_string(),
-                            subscript: Some(*sub),
-                        })),
-                    });
-                    body[pred].statements.push(copy);
-                }
+            for (pred, value) in values {
+                let copy = Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(var.clone())),
+                    operator: "=",
+                    right: Box::new(Expression::Identifier(value)),
+                });
+                body[pred].statements.push(copy);
             }
         }
     }
@@ -403,8 +1147,16 @@ fn destruct_ssa(mut body: CFG, leaves: Vec<LeavingMap>) -> CFG {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ir::{Block, Expression};
-    use crate::parser;
+    use crate::asm::X64Builder;
+    use crate::ir::{Block, Edge, Expression};
+    use crate::lir;
+    use crate::x64::Spill;
+    use crate::{emulator, parser, peephole, reg_allocator};
+
+    // The CFG of `func`, without the lines of its statements.
+    fn construct_cfg(func: Function, warnings: &mut Vec<String>) -> SSAFunction {
+        super::construct_cfg(func, &HashSet::new(), &[], warnings).0
+    }
 
     #[test]
     fn cfg_linear() {
@@ -419,26 +1171,25 @@ mod tests {
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
         let expected = SSAFunction {
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: vec![
                 Block {
                     statements: vec![Statement::Declaration(SSAVar::new("a"))],
                     predecessors: vec![].into_iter().collect(),
                     successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::Expression(Expression::Number(1))],
                     predecessors: vec![0].into_iter().collect(),
-                    successors: vec![2].into_iter().collect(),
-                },
-                Block {
-                    statements: vec![],
-                    predecessors: vec![1].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
@@ -463,11 +1214,13 @@ mod tests {
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
         let expected = SSAFunction {
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: vec![
                 Block {
                     statements: vec![Statement::If {
@@ -477,16 +1230,19 @@ mod tests {
                     }],
                     predecessors: vec![].into_iter().collect(),
                     successors: vec![1, 2].into_iter().collect(),
+                    edges: vec![(1, Edge::Then), (2, Edge::Else)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::Expression(Expression::Number(1))],
                     predecessors: vec![0].into_iter().collect(),
                     successors: vec![3].into_iter().collect(),
+                    edges: vec![(3, Edge::Skip)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::Expression(Expression::Number(2))],
                     predecessors: vec![0].into_iter().collect(),
                     successors: vec![3].into_iter().collect(),
+                    edges: vec![(3, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::If {
@@ -496,11 +1252,13 @@ mod tests {
                     }],
                     predecessors: vec![1, 2].into_iter().collect(),
                     successors: vec![4, 5].into_iter().collect(),
+                    edges: vec![(4, Edge::Then), (5, Edge::Else)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::Expression(Expression::Number(4))],
                     predecessors: vec![3].into_iter().collect(),
                     successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::If {
@@ -509,7 +1267,20 @@ mod tests {
                         alternative: Some(Box::new(Statement::Nop)),
                     }],
                     predecessors: vec![3, 4].into_iter().collect(),
-                    successors: vec![6].into_iter().collect(),
+                    successors: vec![6, 7].into_iter().collect(),
+                    edges: vec![(6, Edge::Then), (7, Edge::Else)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![5].into_iter().collect(),
+                    successors: vec![8].into_iter().collect(),
+                    edges: vec![(8, Edge::Skip)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![5].into_iter().collect(),
+                    successors: vec![8].into_iter().collect(),
+                    edges: vec![(8, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::If {
@@ -517,13 +1288,23 @@ mod tests {
                         body: Box::new(Statement::Nop),
                         alternative: None,
                     }],
-                    predecessors: vec![5].into_iter().collect(),
-                    successors: vec![7].into_iter().collect(),
+                    predecessors: vec![6, 7].into_iter().collect(),
+                    successors: vec![9, 10].into_iter().collect(),
+                    edges: vec![(9, Edge::Then), (10, Edge::Else)]
+                        .into_iter()
+                        .collect(),
                 },
                 Block {
                     statements: vec![],
-                    predecessors: vec![6].into_iter().collect(),
+                    predecessors: vec![8].into_iter().collect(),
+                    successors: vec![10].into_iter().collect(),
+                    edges: vec![(10, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![8, 9].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
@@ -542,43 +1323,284 @@ mod tests {
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
         let expected = SSAFunction {
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: vec![
+                Block {
+                    statements: vec![],
+                    predecessors: vec![].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
+                },
                 Block {
                     statements: vec![Statement::While {
                         condition: Expression::Number(0),
                         body: Box::new(Statement::Nop),
                     }],
+                    predecessors: vec![0, 2].into_iter().collect(),
+                    successors: vec![2, 3].into_iter().collect(),
+                    edges: vec![(2, Edge::Body), (3, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::Expression(Expression::Number(1))],
+                    predecessors: vec![1].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Back)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::While {
+                        condition: Expression::Number(2),
+                        body: Box::new(Statement::Nop),
+                    }],
+                    predecessors: vec![1, 4].into_iter().collect(),
+                    successors: vec![4, 5].into_iter().collect(),
+                    edges: vec![(4, Edge::Body), (5, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![3].into_iter().collect(),
+                    successors: vec![3].into_iter().collect(),
+                    edges: vec![(3, Edge::Back)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![3].into_iter().collect(),
+                    successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
+                },
+            ],
+        };
+        assert_eq!(cfg, expected);
+    }
+
+    #[test]
+    fn cfg_for() {
+        let mut ast = parser::parse(
+            "
+            int main() {
+                for (1; 2; 3) {
+                    for (; 4;) {}
+                }
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let expected = SSAFunction {
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: vec![
+                Block {
+                    statements: vec![Statement::Expression(Expression::Number(1))],
+                    predecessors: vec![].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::While {
+                        condition: Expression::Number(2),
+                        body: Box::new(Statement::Nop),
+                    }],
+                    predecessors: vec![0, 4].into_iter().collect(),
+                    successors: vec![2, 5].into_iter().collect(),
+                    edges: vec![(2, Edge::Body), (5, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::While {
+                        condition: Expression::Number(4),
+                        body: Box::new(Statement::Nop),
+                    }],
+                    predecessors: vec![1, 3].into_iter().collect(),
+                    successors: vec![3, 4].into_iter().collect(),
+                    edges: vec![(3, Edge::Body), (4, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![2].into_iter().collect(),
+                    edges: vec![(2, Edge::Back)].into_iter().collect(),
+                },
+                // The step ends the body of the outer loop.
+                Block {
+                    statements: vec![Statement::Expression(Expression::Number(3))],
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Back)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![1].into_iter().collect(),
+                    successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
+                },
+            ],
+        };
+        assert_eq!(cfg, expected);
+    }
+
+    #[test]
+    fn cfg_do_while() {
+        let mut ast = parser::parse(
+            "
+            int main() {
+                0;
+                do {
+                    1;
+                } while (2);
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let expected = SSAFunction {
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: vec![
+                Block {
+                    statements: vec![Statement::Expression(Expression::Number(0))],
+                    predecessors: vec![].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::Expression(Expression::Number(1))],
+                    predecessors: vec![0, 2].into_iter().collect(),
+                    successors: vec![2].into_iter().collect(),
+                    edges: vec![(2, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::DoWhile {
+                        body: Box::new(Statement::Nop),
+                        condition: Expression::Number(2),
+                    }],
+                    predecessors: vec![1].into_iter().collect(),
+                    successors: vec![1, 3].into_iter().collect(),
+                    edges: vec![(1, Edge::Back), (3, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![],
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
+                },
+            ],
+        };
+        assert_eq!(cfg, expected);
+    }
+
+    #[test]
+    fn cfg_break_continue() {
+        let mut ast = parser::parse(
+            "
+            int main() {
+                while (0) {
+                    if (1) break;
+                    continue;
+                }
+                do {
+                    break;
+                } while (2);
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let expected = SSAFunction {
+            void: false,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: vec![
+                Block {
+                    statements: vec![],
+                    predecessors: vec![].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::While {
+                        condition: Expression::Number(0),
+                        body: Box::new(Statement::Nop),
+                    }],
+                    predecessors: vec![0, 4].into_iter().collect(),
+                    successors: vec![2, 5].into_iter().collect(),
+                    edges: vec![(2, Edge::Body), (5, Edge::Exit)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![Statement::If {
+                        condition: Expression::Number(1),
+                        body: Box::new(Statement::Nop),
+                        alternative: None,
+                    }],
                     predecessors: vec![1].into_iter().collect(),
-                    successors: vec![1, 2].into_iter().collect(),
+                    successors: vec![3, 4].into_iter().collect(),
+                    edges: vec![(3, Edge::Then), (4, Edge::Else)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![Statement::Expression(Expression::Number(1))],
-                    predecessors: vec![0].into_iter().collect(),
-                    successors: vec![0].into_iter().collect(),
+                    statements: vec![Statement::Break],
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Break)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![Statement::While {
-                        condition: Expression::Number(2),
-                        body: Box::new(Statement::Nop),
-                    }],
-                    predecessors: vec![0, 2].into_iter().collect(),
-                    successors: vec![2, 3].into_iter().collect(),
+                    statements: vec![Statement::Continue],
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Continue)].into_iter().collect(),
+                },
+                // The body of the `do` never reaches its condition.
+                Block {
+                    statements: vec![Statement::Break],
+                    predecessors: vec![1, 3].into_iter().collect(),
+                    successors: vec![6].into_iter().collect(),
+                    edges: vec![(6, Edge::Break)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![],
-                    predecessors: vec![2].into_iter().collect(),
+                    predecessors: vec![5].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
         assert_eq!(cfg, expected);
     }
 
+    #[test]
+    fn unreachable_line() {
+        let source = "
+            int main() {
+                return 1;
+                2;
+                3;
+            }
+        ";
+        let mut lines = Lines::new();
+        let events = parser::events(source);
+        let mut ast =
+            parser::replay_unit(events, "", &mut Vec::new(), &mut Vec::new(), &mut lines).unwrap();
+        let mut warnings = Vec::new();
+        super::construct_cfg(
+            ast.remove(0),
+            &HashSet::new(),
+            &lines["main"],
+            &mut warnings,
+        );
+        assert_eq!(
+            warnings,
+            ["line 4: 2 unreachable statement(s) dropped in function `main`"]
+        );
+    }
+
     #[test]
     fn cfg_return() {
         let mut ast = parser::parse(
@@ -597,11 +1619,27 @@ mod tests {
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
+        let mut warnings = Vec::new();
+        let cfg = construct_cfg(ast.remove(0), &mut warnings);
+        assert_eq!(
+            warnings,
+            vec![String::from(
+                "line 2: 1 unreachable statement(s) dropped in function `main`"
+            )]
+        );
+        let returns = |value| {
+            Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Identifier(SSAVar::new("return"))),
+                operator: "=",
+                right: Box::new(Expression::Number(value)),
+            })
+        };
         let expected = SSAFunction {
             void: false,
+            double: false,
             name: String::from("main"),
             parameters: vec![],
+            doubles: HashSet::new(),
             body: vec![
                 Block {
                     statements: vec![Statement::If {
@@ -611,34 +1649,43 @@ mod tests {
                     }],
                     predecessors: vec![].into_iter().collect(),
                     successors: vec![1, 2].into_iter().collect(),
+                    edges: vec![(1, Edge::Then), (2, Edge::Else)].into_iter().collect(),
                 },
+                // `return` ends the block, which leads to the only exit.
                 Block {
-                    statements: vec![Statement::Return(Some(Expression::Number(1)))],
+                    statements: vec![returns(1)],
                     predecessors: vec![0].into_iter().collect(),
-                    successors: vec![2].into_iter().collect(),
+                    successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Return)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::While {
                         condition: Expression::Number(2),
                         body: Box::new(Statement::Nop),
                     }],
-                    predecessors: vec![0, 1].into_iter().collect(),
+                    predecessors: vec![0].into_iter().collect(),
                     successors: vec![3, 4].into_iter().collect(),
+                    edges: vec![(3, Edge::Body), (4, Edge::Exit)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![Statement::Return(Some(Expression::Number(3)))],
+                    statements: vec![returns(3)],
                     predecessors: vec![2].into_iter().collect(),
-                    successors: vec![].into_iter().collect(),
+                    successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Return)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![Statement::Return(Some(Expression::Number(4)))],
+                    statements: vec![returns(4)],
                     predecessors: vec![2].into_iter().collect(),
                     successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Return)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![],
-                    predecessors: vec![4].into_iter().collect(),
+                    statements: vec![Statement::Return(Some(Expression::Identifier(
+                        SSAVar::new("return"),
+                    )))],
+                    predecessors: vec![1, 3, 4].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
@@ -646,7 +1693,90 @@ mod tests {
     }
 
     #[test]
-    fn reaching_def() {
+    fn scopes() {
+        let mut ast = parser::parse(
+            "
+            int main(int a) {
+                {
+                    int a;
+                    a;
+                }
+                {
+                    int a;
+                }
+                a;
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let stmts: Vec<_> = cfg
+            .body
+            .into_iter()
+            .flat_map(|block| block.statements)
+            .collect();
+        let expected = vec![
+            Statement::Declaration(SSAVar::new("a.1")),
+            Statement::Expression(Expression::Identifier(SSAVar::new("a.1"))),
+            Statement::Declaration(SSAVar::new("a.2")),
+            Statement::Expression(Expression::Identifier(SSAVar::new("a"))),
+        ];
+        assert_eq!(stmts, expected);
+    }
+
+    #[test]
+    fn addressed() {
+        let mut ast = parser::parse(
+            "
+            int main(int n) {
+                int a, b;
+                *&a = &n;
+                return a + b;
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        assert_eq!(cfg.parameters, vec![SSAVar::new("n.0")]);
+        let stmts: Vec<_> = cfg
+            .body
+            .into_iter()
+            .flat_map(|block| block.statements)
+            .collect();
+        let element = |name| Expression::Index {
+            array: SSAVar::new(name),
+            indices: vec![Expression::Number(0)],
+        };
+        let address = |name| Expression::Prefix {
+            operator: "&",
+            expression: Box::new(element(name)),
+        };
+        let expected = vec![
+            Statement::ArrayDeclaration(SSAVar::new("n"), vec![1]),
+            Statement::Expression(Expression::Infix {
+                left: Box::new(element("n")),
+                operator: "=",
+                right: Box::new(Expression::Identifier(SSAVar::new("n.0"))),
+            }),
+            Statement::ArrayDeclaration(SSAVar::new("a"), vec![1]),
+            Statement::Declaration(SSAVar::new("b")),
+            Statement::Expression(Expression::Infix {
+                left: Box::new(Expression::Prefix {
+                    operator: "*",
+                    expression: Box::new(address("a")),
+                }),
+                operator: "=",
+                right: Box::new(address("n")),
+            }),
+            Statement::Return(Some(Expression::Infix {
+                left: Box::new(element("a")),
+                operator: "+",
+                right: Box::new(Expression::Identifier(SSAVar::new("b"))),
+            })),
+        ];
+        assert_eq!(stmts, expected);
+    }
+
+    #[test]
+    fn rename() {
         let mut ast = parser::parse(
             "
             void main(int a) {
@@ -658,15 +1788,18 @@ mod tests {
             }
         ",
         );
-        let mut ssa = construct_cfg(ast.remove(0));
-        find_inout_defs(&mut ssa.parameters, &mut ssa.body);
+        let mut ssa = construct_cfg(ast.remove(0), &mut Vec::new());
+        let idoms = dataflow::dominators(&ssa.body);
+        rename_ssa(&mut ssa.parameters, &mut ssa.body, &idoms);
         let expected = SSAFunction {
             void: true,
+            double: false,
             name: String::from("main"),
             parameters: vec![SSAVar {
                 name: "a".to_string(),
                 subscript: Some(0),
             }],
+            doubles: HashSet::new(),
             body: vec![
                 Block {
                     statements: vec![
@@ -681,6 +1814,7 @@ mod tests {
                     ],
                     predecessors: vec![].into_iter().collect(),
                     successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::If {
@@ -690,19 +1824,22 @@ mod tests {
                     }],
                     predecessors: vec![0].into_iter().collect(),
                     successors: vec![2, 3].into_iter().collect(),
+                    edges: vec![(2, Edge::Then), (3, Edge::Else)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::Declaration(SSAVar {
-                        name: "a".to_string(),
-                        subscript: Some(2),
+                        name: "a.1".to_string(),
+                        subscript: Some(0),
                     })],
                     predecessors: vec![1].into_iter().collect(),
                     successors: vec![3].into_iter().collect(),
+                    edges: vec![(3, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![],
                     predecessors: vec![1, 2].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
@@ -714,112 +1851,139 @@ mod tests {
         let mut ast = parser::parse(
             "
             void main(int a) {
-                int b;
-                if (0) {
+                while (a) {
                     int b;
+                    if (a) {
+                        a = b;
+                    }
                 }
-                main(a);
-                b;
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
-        let ssa = construct_ssa(cfg).0;
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let ssa = construct_ssa(cfg, &CancellationToken::new()).unwrap();
         let expected = SSAFunction {
             void: true,
+            double: false,
             name: String::from("main"),
             parameters: vec![SSAVar {
                 name: "a".to_string(),
                 subscript: Some(0),
             }],
+            doubles: HashSet::new(),
             body: vec![
+                Block {
+                    statements: vec![],
+                    predecessors: vec![].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Next)].into_iter().collect(),
+                },
+                Block {
+                    statements: vec![
+                        Statement::Phi(
+                            SSAVar {
+                                name: "a".to_string(),
+                                subscript: Some(1),
+                            },
+                            vec![
+                                (
+                                    0,
+                                    SSAVar {
+                                        name: "a".to_string(),
+                                        subscript: Some(0),
+                                    },
+                                ),
+                                (
+                                    5,
+                                    SSAVar {
+                                        name: "a".to_string(),
+                                        subscript: Some(3),
+                                    },
+                                ),
+                            ],
+                        ),
+                        Statement::While {
+                            condition: Expression::Identifier(SSAVar {
+                                name: "a".to_string(),
+                                subscript: Some(1),
+                            }),
+                            body: Box::new(Statement::Nop),
+                        },
+                    ],
+                    predecessors: vec![0, 5].into_iter().collect(),
+                    successors: vec![2, 6].into_iter().collect(),
+                    edges: vec![(2, Edge::Body), (6, Edge::Exit)].into_iter().collect(),
+                },
                 Block {
                     statements: vec![Statement::Declaration(SSAVar {
                         name: "b".to_string(),
                         subscript: Some(0),
                     })],
-                    predecessors: vec![].into_iter().collect(),
-                    successors: vec![1].into_iter().collect(),
+                    predecessors: vec![1].into_iter().collect(),
+                    successors: vec![3].into_iter().collect(),
+                    edges: vec![(3, Edge::Next)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![Statement::If {
-                        condition: Expression::Number(0),
+                        condition: Expression::Identifier(SSAVar {
+                            name: "a".to_string(),
+                            subscript: Some(1),
+                        }),
                         body: Box::new(Statement::Nop),
                         alternative: None,
                     }],
-                    predecessors: vec![0].into_iter().collect(),
-                    successors: vec![2, 3].into_iter().collect(),
+                    predecessors: vec![2].into_iter().collect(),
+                    successors: vec![4, 5].into_iter().collect(),
+                    edges: vec![(4, Edge::Then), (5, Edge::Else)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![Statement::Declaration(SSAVar {
-                        name: "b".to_string(),
-                        subscript: Some(1),
+                    statements: vec![Statement::Expression(Expression::Infix {
+                        left: Box::new(Expression::Identifier(SSAVar {
+                            name: "a".to_string(),
+                            subscript: Some(2),
+                        })),
+                        operator: "=",
+                        right: Box::new(Expression::Identifier(SSAVar {
+                            name: "b".to_string(),
+                            subscript: Some(0),
+                        })),
                     })],
-                    predecessors: vec![1].into_iter().collect(),
-                    successors: vec![3].into_iter().collect(),
+                    predecessors: vec![3].into_iter().collect(),
+                    successors: vec![5].into_iter().collect(),
+                    edges: vec![(5, Edge::Next)].into_iter().collect(),
                 },
                 Block {
-                    statements: vec![
-                        Statement::Phi(
-                            SSAVar {
-                                name: "b".to_string(),
-                                subscript: Some(2),
-                            },
-                            vec![
-                                SSAVar {
-                                    name: "b".to_string(),
-                                    subscript: Some(0),
-                                },
-                                SSAVar {
-                                    name: "b".to_string(),
-                                    subscript: Some(1),
-                                },
-                            ]
-                            .into_iter()
-                            .collect(),
-                        ),
-                        Statement::Phi(
-                            SSAVar {
-                                name: "a".to_string(),
-                                subscript: Some(1),
-                            },
-                            vec![
+                    statements: vec![Statement::Phi(
+                        SSAVar {
+                            name: "a".to_string(),
+                            subscript: Some(3),
+                        },
+                        vec![
+                            (
+                                3,
                                 SSAVar {
                                     name: "a".to_string(),
-                                    subscript: Some(0),
+                                    subscript: Some(1),
                                 },
+                            ),
+                            (
+                                4,
                                 SSAVar {
                                     name: "a".to_string(),
-                                    subscript: Some(0),
+                                    subscript: Some(2),
                                 },
-                            ]
-                            .into_iter()
-                            .collect(),
-                        ),
-                        Statement::Expression(Expression::Call {
-                            function: Box::new(Expression::Identifier(SSAVar {
-                                name: "main".to_string(),
-                                subscript: None,
-                            })),
-                            arguments: Box::new(Expression::Arguments(vec![
-                                Expression::Identifier(SSAVar {
-                                    name: "a".to_string(),
-                                    subscript: Some(1),
-                                }),
-                            ])),
-                        }),
-                        Statement::Expression(Expression::Identifier(SSAVar {
-                            name: "b".to_string(),
-                            subscript: Some(2),
-                        })),
-                    ],
-                    predecessors: vec![1, 2].into_iter().collect(),
-                    successors: vec![4].into_iter().collect(),
+                            ),
+                        ],
+                    )],
+                    predecessors: vec![3, 4].into_iter().collect(),
+                    successors: vec![1].into_iter().collect(),
+                    edges: vec![(1, Edge::Back)].into_iter().collect(),
                 },
                 Block {
                     statements: vec![],
-                    predecessors: vec![3].into_iter().collect(),
+                    predecessors: vec![1].into_iter().collect(),
                     successors: vec![].into_iter().collect(),
+                    edges: HashMap::new(),
                 },
             ],
         };
@@ -831,120 +1995,264 @@ mod tests {
         let mut ast = parser::parse(
             "
             void main(int a) {
-                int b;
-                if (0) {
+                while (a) {
                     int b;
+                    if (a) {
+                        a = b;
+                    }
                 }
-                main(a);
-                b;
             }
         ",
         );
-        let cfg = construct_cfg(ast.remove(0));
-        let (ssa, leaves) = construct_ssa(cfg);
-        let body = destruct_ssa(ssa.body, leaves);
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let ssa = construct_ssa(cfg, &CancellationToken::new()).unwrap();
+        let body = destruct_ssa(ssa.body);
         let expected = vec![
+            Block {
+                statements: vec![Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar {
+                        name: "a".to_string(),
+                        subscript: Some(1),
+                    })),
+                    operator: "=",
+                    right: Box::new(Expression::Identifier(SSAVar {
+                        name: "a".to_string(),
+                        subscript: Some(0),
+                    })),
+                })],
+                predecessors: vec![].into_iter().collect(),
+                successors: vec![1].into_iter().collect(),
+                edges: vec![(1, Edge::Next)].into_iter().collect(),
+            },
+            Block {
+                statements: vec![Statement::While {
+                    condition: Expression::Identifier(SSAVar {
+                        name: "a".to_string(),
+                        subscript: Some(1),
+                    }),
+                    body: Box::new(Statement::Nop),
+                }],
+                predecessors: vec![0, 5].into_iter().collect(),
+                successors: vec![2, 6].into_iter().collect(),
+                edges: vec![(2, Edge::Body), (6, Edge::Exit)].into_iter().collect(),
+            },
             Block {
                 statements: vec![Statement::Declaration(SSAVar {
                     name: "b".to_string(),
                     subscript: Some(0),
                 })],
-                predecessors: vec![].into_iter().collect(),
-                successors: vec![1].into_iter().collect(),
+                predecessors: vec![1].into_iter().collect(),
+                successors: vec![3].into_iter().collect(),
+                edges: vec![(3, Edge::Next)].into_iter().collect(),
             },
             Block {
                 statements: vec![
                     Statement::If {
-                        condition: Expression::Number(0),
+                        condition: Expression::Identifier(SSAVar {
+                            name: "a".to_string(),
+                            subscript: Some(1),
+                        }),
                         body: Box::new(Statement::Nop),
                         alternative: None,
                     },
-                    Statement::Expression(Expression::Infix {
-                        left: Box::new(Expression::Identifier(SSAVar {
-                            name: "b".to_string(),
-                            subscript: Some(2),
-                        })),
-                        operator: "=",
-                        right: Box::new(Expression::Identifier(SSAVar {
-                            name: "b".to_string(),
-                            subscript: Some(0),
-                        })),
-                    }),
                     Statement::Expression(Expression::Infix {
                         left: Box::new(Expression::Identifier(SSAVar {
                             name: "a".to_string(),
-                            subscript: Some(1),
+                            subscript: Some(3),
                         })),
                         operator: "=",
                         right: Box::new(Expression::Identifier(SSAVar {
                             name: "a".to_string(),
-                            subscript: Some(0),
+                            subscript: Some(1),
                         })),
                     }),
                 ],
-                predecessors: vec![0].into_iter().collect(),
-                successors: vec![2, 3].into_iter().collect(),
+                predecessors: vec![2].into_iter().collect(),
+                successors: vec![4, 5].into_iter().collect(),
+                edges: vec![(4, Edge::Then), (5, Edge::Else)].into_iter().collect(),
             },
             Block {
                 statements: vec![
-                    Statement::Declaration(SSAVar {
-                        name: "b".to_string(),
-                        subscript: Some(1),
-                    }),
                     Statement::Expression(Expression::Infix {
                         left: Box::new(Expression::Identifier(SSAVar {
-                            name: "b".to_string(),
+                            name: "a".to_string(),
                             subscript: Some(2),
                         })),
                         operator: "=",
                         right: Box::new(Expression::Identifier(SSAVar {
                             name: "b".to_string(),
-                            subscript: Some(1),
+                            subscript: Some(0),
                         })),
                     }),
                     Statement::Expression(Expression::Infix {
                         left: Box::new(Expression::Identifier(SSAVar {
                             name: "a".to_string(),
-                            subscript: Some(1),
+                            subscript: Some(3),
                         })),
                         operator: "=",
                         right: Box::new(Expression::Identifier(SSAVar {
                             name: "a".to_string(),
-                            subscript: Some(0),
+                            subscript: Some(2),
                         })),
                     }),
                 ],
-                predecessors: vec![1].into_iter().collect(),
-                successors: vec![3].into_iter().collect(),
+                predecessors: vec![3].into_iter().collect(),
+                successors: vec![5].into_iter().collect(),
+                edges: vec![(5, Edge::Next)].into_iter().collect(),
             },
             Block {
-                statements: vec![
-                    Statement::Expression(Expression::Call {
-                        function: Box::new(Expression::Identifier(SSAVar {
-                            name: "main".to_string(),
-                            subscript: None,
-                        })),
-                        arguments: Box::new(Expression::Arguments(vec![Expression::Identifier(
-                            SSAVar {
-                                name: "a".to_string(),
-                                subscript: Some(1),
-                            },
-                        )])),
-                    }),
-                    Statement::Expression(Expression::Identifier(SSAVar {
-                        name: "b".to_string(),
-                        subscript: Some(2),
+                statements: vec![Statement::Expression(Expression::Infix {
+                    left: Box::new(Expression::Identifier(SSAVar {
+                        name: "a".to_string(),
+                        subscript: Some(1),
                     })),
-                ],
-                predecessors: vec![1, 2].into_iter().collect(),
-                successors: vec![4].into_iter().collect(),
+                    operator: "=",
+                    right: Box::new(Expression::Identifier(SSAVar {
+                        name: "a".to_string(),
+                        subscript: Some(3),
+                    })),
+                })],
+                predecessors: vec![3, 4].into_iter().collect(),
+                successors: vec![1].into_iter().collect(),
+                edges: vec![(1, Edge::Back)].into_iter().collect(),
             },
             Block {
                 statements: vec![],
-                predecessors: vec![3].into_iter().collect(),
+                predecessors: vec![1].into_iter().collect(),
                 successors: vec![].into_iter().collect(),
+                edges: HashMap::new(),
             },
         ];
         assert_eq!(body, expected);
     }
+
+    #[test]
+    fn assignments() {
+        let mut ast = parser::parse(
+            "
+            int main(int a) {
+                a = a + 1;
+                a ? (a = 2) : 3;
+                return a;
+            }
+        ",
+        );
+        let cfg = construct_cfg(ast.remove(0), &mut Vec::new());
+        let ssa = construct_ssa(cfg, &CancellationToken::new()).unwrap();
+        let a = |subscript| SSAVar {
+            name: "a".to_string(),
+            subscript: Some(subscript),
+        };
+        let var = |subscript| Box::new(Expression::Identifier(a(subscript)));
+        let expected = vec![Statement::Expression(Expression::Infix {
+            left: var(1),
+            operator: "=",
+            right: Box::new(Expression::Infix {
+                left: var(0),
+                operator: "+",
+                right: Box::new(Expression::Number(1)),
+            }),
+        })];
+        assert_eq!(ssa.body[0].statements, expected);
+        // `?:` is an `if` whose arms are blocks of their own, so the assignment
+        // in one defines `a2`, and a phi joins it with `a1`.
+        let expected = vec![Statement::If {
+            condition: *var(1),
+            body: Box::new(Statement::Nop),
+            alternative: Some(Box::new(Statement::Nop)),
+        }];
+        assert_eq!(ssa.body[1].statements, expected);
+        let expected = vec![Statement::Expression(Expression::Infix {
+            left: var(2),
+            operator: "=",
+            right: Box::new(Expression::Number(2)),
+        })];
+        assert_eq!(ssa.body[2].statements, expected);
+        let expected = vec![
+            Statement::Phi(a(3), vec![(2, a(2)), (3, a(1))]),
+            Statement::Return(Some(*var(3))),
+        ];
+        assert_eq!(ssa.body[4].statements, expected);
+    }
+
+    #[test]
+    fn nested_loops() {
+        // The join after the inner loop has no phi of `i`, so its uses must
+        // see the definition that dominates them.
+        let ast = parser::parse(
+            "
+            int main() {
+                int i = 0;
+                int n = 0;
+                while (n < 3) {
+                    if (n < 10) {
+                        while (i < 5) { i = i + 1; }
+                    }
+                    n = n + 1;
+                    i = i + 10;
+                }
+                return i;
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(super::destruct(super::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(emulator::run(&asm, &[]), Ok(35));
+    }
+
+    #[test]
+    fn chained_assignment() {
+        // Every assignment yields the value it stored, converted to the type
+        // of its target.
+        let ast = parser::parse(
+            "
+            int main(int n) {
+                int x[2];
+                int a;
+                int b;
+                int *p = &x[1];
+                x[0] = a = n;
+                b = x[1] = 4;
+                a = *p = a + b;
+                double d;
+                int i;
+                d = i = 2.5;
+                return x[0] * 1000 + a * 100 + b * 10 + i + (d == 2.0) + (b = 1);
+            }
+        ",
+        );
+        let vasm = X64Builder::new().build(lir::lower(super::destruct(super::construct(ast))));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        assert_eq!(emulator::run(&asm, &[3]), Ok(3744));
+    }
+
+    #[test]
+    fn single_exit() {
+        let ast = parser::parse(
+            "
+            int main(int a) {
+                if (a < 0) {
+                    return 1;
+                }
+                if (a > 0) {
+                    return a / 2;
+                }
+            }
+        ",
+        );
+        let ssa = super::construct(ast);
+        let exits: Vec<_> = ssa[0]
+            .body
+            .iter()
+            .filter(|block| block.successors.is_empty())
+            .collect();
+        assert_eq!(exits.len(), 1);
+        assert!(matches!(exits[0].statements[0], Statement::Phi(..)));
+        let vasm = X64Builder::new().build(lir::lower(super::destruct(ssa)));
+        let (asm, _) = peephole::cleanup(reg_allocator::alloc(vasm, Spill::Cost));
+        // Falling off the end returns 0.
+        assert_eq!(emulator::run(&asm, &[-3]), Ok(1));
+        assert_eq!(emulator::run(&asm, &[9]), Ok(4));
+        assert_eq!(emulator::run(&asm, &[0]), Ok(0));
+    }
 }
@@ -0,0 +1,217 @@
+use crate::x64::{Register, X64Function, X64Program, X64Register, X64RegisterAllocator, X64};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+// A per-function summary of the final assembly: the number of instructions,
+// the stack traffic caused by spilling, an estimate of the encoded size, and
+// how often each mnemonic is used.
+pub fn report(asm: &X64Program) -> String {
+    let mut report = String::new();
+    for X64Function { name, body, .. } in asm {
+        let asms: Vec<_> = body
+            .iter()
+            .filter(|asm| !matches!(asm, X64::Tag(_)))
+            .collect();
+        let loads = asms
+            .iter()
+            .filter(|asm| matches!(asm, X64::MovFromStack(..)))
+            .count();
+        let stores = asms
+            .iter()
+            .filter(|asm| matches!(asm, X64::MovToStack(..)))
+            .count();
+        let bytes: usize = asms.iter().map(|asm| size(asm)).sum();
+        let mut mix = BTreeMap::new();
+        for asm in &asms {
+            let text = asm.to_string();
+            let mnemonic = text.split(' ').next().unwrap().to_string();
+            *mix.entry(mnemonic).or_insert(0) += 1;
+        }
+        let mix: Vec<_> = mix
+            .into_iter()
+            .map(|(mnemonic, cnt)| format!("{} {}", mnemonic, cnt))
+            .collect();
+        report += &format!(
+            "{}: {} instructions, {} stack loads, {} stack stores, ~{} bytes\n    {}\n",
+            name,
+            asms.len(),
+            loads,
+            stores,
+            bytes,
+            mix.join(", ")
+        );
+    }
+    report
+}
+
+// A map of the emitted functions in the order they are written: where each
+// starts and how long it is, by the same estimate as `report`, how many bytes
+// its prologue takes off the stack, and how many times it stores a spilled
+// register.
+pub fn map(asm: &X64Program) -> String {
+    let mut map = String::from(
+        "offset      size   frame  spills  function
+",
+    );
+    let mut offset = 0;
+    for X64Function { name, body, .. } in asm {
+        let bytes: usize = body.iter().map(size).sum();
+        let mut frame = 0;
+        let mut spills = 0;
+        // Between pointing RBP to the frame of the callee and the call, stack
+        // stores save registers and pass arguments instead.
+        let mut calling = false;
+        for asm in body {
+            match asm {
+                X64::Push(_) => frame += 8,
+                X64::SubNum(X64RegisterAllocator::RSP, size) => frame += size,
+                X64::MovReg(X64RegisterAllocator::RBP, X64RegisterAllocator::RSP) => calling = true,
+                X64::Call(..) => calling = false,
+                X64::MovToStack(offset, _)
+                    if !calling && *offset < X64RegisterAllocator::FRAME_SIZE =>
+                {
+                    spills += 1
+                }
+                _ => {}
+            }
+        }
+        map += &format!(
+            "{:#010x}  {:>5}  {:>6}  {:>6}  {}\n",
+            offset, bytes, frame, spills, name
+        );
+        offset += bytes;
+    }
+    map
+}
+
+// The estimated encoded size in bytes, assuming 64-bit operands (a REX
+// prefix) and the shortest immediate and displacement forms.
+fn size(asm: &X64) -> usize {
+    let imm = |num: i64| if (-128..128).contains(&num) { 1 } else { 4 };
+    match asm {
+        X64::MovNum(_, num) if i32::try_from(*num).is_err() => 10,
+        X64::MovNum(..) => 7,
+        X64::MovReg(..)
+        | X64::Neg(_)
+        | X64::CmpReg(..)
+        | X64::Idiv(..)
+        | X64::Add(..)
+        | X64::Sub(..)
+        | X64::And(..)
+        | X64::Or(..)
+        | X64::Xor(..)
+        | X64::Test(..) => 3,
+        X64::Imul(..)
+        | X64::Cvtsi2sd(..)
+        | X64::Cvttsd2si(..)
+        | X64::Addsd(..)
+        | X64::Subsd(..)
+        | X64::Mulsd(..)
+        | X64::Divsd(..) => 4,
+        // Without a REX prefix, as it only compares two registers.
+        X64::Ucomisd(..) => 4,
+        X64::Cqo => 2,
+        X64::Irem(..) => unreachable!(),
+        X64::MovToStack(offset, _) | X64::MovFromStack(_, offset) => 3 + imm(*offset as i64),
+        // Indexed addressing adds a SIB byte.
+        X64::LoadIndex(_, offset, _)
+        | X64::StoreIndex(offset, _, _)
+        | X64::LeaIndex(_, offset, _) => 4 + imm(*offset as i64),
+        X64::Lea(..) => 4,
+        X64::LoadField(_, array, offset) | X64::StoreField(array, offset, _) => {
+            3 + imm((array + offset) as i64)
+        }
+        X64::Load(..) | X64::Store(..) => 3,
+        X64::CmpNum(_, num) => 3 + imm(*num as i64),
+        X64::AddNum(_, num) | X64::SubNum(_, num) => 3 + imm(*num as i64),
+        X64::Call(..) | X64::Jmp(_) => 5,
+        X64::Jl(_)
+        | X64::Jg(_)
+        | X64::Jle(_)
+        | X64::Jge(_)
+        | X64::Je(_)
+        | X64::Jne(_)
+        | X64::Jb(_)
+        | X64::Ja(_)
+        | X64::Jbe(_)
+        | X64::Jae(_) => 6,
+        X64::Tag(_) | X64::Param(..) => 0,
+        X64::Ret(_) => 1,
+        X64::Push(reg) | X64::Pop(reg) => match reg {
+            Register::X64(
+                X64Register::R8
+                | X64Register::R9
+                | X64Register::R10
+                | X64Register::R11
+                | X64Register::R12
+                | X64Register::R13
+                | X64Register::R14
+                | X64Register::R15,
+            ) => 2,
+            _ => 1,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x64::X64RegisterAllocator as X64R;
+
+    #[test]
+    fn summary() {
+        let asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![
+                X64::Push(X64R::R15),
+                X64::MovNum(X64R::R15, 1),
+                X64::MovToStack(0, X64R::R15),
+                X64::Tag(String::from("Tag")),
+                X64::MovFromStack(X64R::RAX, 0),
+                X64::Pop(X64R::R15),
+                X64::Ret(None),
+            ],
+        }];
+        let expected = "main: 6 instructions, 1 stack loads, 1 stack stores, ~20 bytes
+    mov 3, pop 1, push 1, ret 1
+";
+        assert_eq!(report(&asm), expected);
+    }
+
+    #[test]
+    fn functions() {
+        let func = |name: &str, body| X64Function {
+            name: String::from(name),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body,
+        };
+        let asm = vec![
+            func(
+                "main",
+                vec![
+                    X64::Push(X64R::RBX),
+                    X64::SubNum(X64R::RSP, 1096),
+                    X64::MovToStack(8, X64R::RBX),
+                    X64::MovReg(X64R::RAX, X64R::RBP),
+                    X64::MovReg(X64R::RBP, X64R::RSP),
+                    X64::MovToStack(1032, X64R::RAX),
+                    X64::MovToStack(16, X64R::RBX),
+                    X64::Call(String::from("f"), Vec::new(), X64R::RAX, false),
+                    X64::MovFromStack(X64R::RBP, 1032),
+                    X64::AddNum(X64R::RSP, 1096),
+                    X64::Pop(X64R::RBX),
+                    X64::Ret(None),
+                ],
+            ),
+            func("f", vec![X64::Ret(None)]),
+        ];
+        let expected = "offset      size   frame  spills  function
+0x00000000     50    1104       1  main
+0x00000032      1       0       0  f
+";
+        assert_eq!(map(&asm), expected);
+    }
+}
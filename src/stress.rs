@@ -0,0 +1,126 @@
+// Deterministic generators of large programs in the supported subset of C,
+// for benchmarks and for regression tests that catch complexity blowups in
+// the data-flow solvers and the register allocator. The same seed always
+// gives the same program.
+
+// A xorshift64 generator, so programs do not depend on an external crate.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves the zero state.
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+const OPERATORS: [&str; 3] = ["+", "-", "*"];
+
+// `main(a, b)` computing `temps` temporaries in a single block, each from two
+// of the `WINDOW` values before it, and returning the last one. The window
+// keeps more values live than there are registers, but few enough for the
+// stack frame.
+pub fn straight_line(seed: u64, temps: usize) -> String {
+    const WINDOW: usize = 24;
+    let mut rng = Rng::new(seed);
+    let mut body = String::new();
+    let mut values = vec![String::from("a"), String::from("b")];
+    for i in 0..temps {
+        let expr = random_expr(&mut rng, &values[values.len().saturating_sub(WINDOW)..]);
+        body += &format!("    int t{};\n    t{} = {};\n", i, i, expr);
+        values.push(format!("t{}", i));
+    }
+    format!(
+        "int main(int a, int b) {{\n{}    return {};\n}}\n",
+        body,
+        values.last().unwrap()
+    )
+}
+
+// `main(a, b)` made of `branches` consecutive `if`/`else` statements and
+// `while` loops over a few shared variables, so the CFG has a few blocks per
+// branch and every join needs phis.
+pub fn giant_cfg(seed: u64, branches: usize) -> String {
+    let mut rng = Rng::new(seed);
+    let values: Vec<_> = ["a", "b", "x", "y", "z"]
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    let mut body =
+        String::from("    int x;\n    int y;\n    int z;\n    x = a;\n    y = b;\n    z = 0;\n");
+    for _ in 0..branches {
+        let target = &values[2 + rng.below(3)];
+        let condition = format!("{} < {}", random_expr(&mut rng, &values), rng.below(100));
+        match rng.below(3) {
+            0 => {
+                body += &format!(
+                    "    if ({}) {{\n        {} = {};\n    }}\n",
+                    condition,
+                    target,
+                    random_expr(&mut rng, &values)
+                );
+            }
+            1 => {
+                body += &format!(
+                    "    if ({}) {{\n        {} = {};\n    }} else {{\n        {} = {};\n    }}\n",
+                    condition,
+                    target,
+                    random_expr(&mut rng, &values),
+                    target,
+                    random_expr(&mut rng, &values)
+                );
+            }
+            _ => {
+                body += &format!(
+                    "    while ({} < {}) {{\n        {} = {} + 1;\n    }}\n",
+                    target,
+                    rng.below(100),
+                    target,
+                    target
+                );
+            }
+        }
+    }
+    format!(
+        "int main(int a, int b) {{\n{}    return x + y + z;\n}}\n",
+        body
+    )
+}
+
+fn random_expr(rng: &mut Rng, values: &[String]) -> String {
+    let left = &values[rng.below(values.len())];
+    let right = match rng.below(4) {
+        0 => rng.below(16).to_string(),
+        _ => values[rng.below(values.len())].clone(),
+    };
+    format!(
+        "{} {} {}",
+        left,
+        OPERATORS[rng.below(OPERATORS.len())],
+        right
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        assert_eq!(straight_line(7, 50), straight_line(7, 50));
+        assert_ne!(straight_line(7, 50), straight_line(8, 50));
+        assert_eq!(giant_cfg(7, 50), giant_cfg(7, 50));
+        let expected = "int main(int a, int b) {\n    int t0;\n    t0 = ";
+        assert!(straight_line(1, 1).starts_with(expected));
+    }
+}
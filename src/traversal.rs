@@ -0,0 +1,117 @@
+use crate::dataflow::Direction;
+use crate::ir::Block;
+use std::collections::HashSet;
+
+// Traversal utilities over a CFG. Neighbors are always visited in ascending
+// block order, so every traversal is deterministic.
+
+// A depth-first preorder walk that visits every reachable block exactly once.
+pub struct Dfs<'a> {
+    cfg: &'a [Block],
+    direction: Direction,
+    stack: Vec<usize>,
+    visited: HashSet<usize>,
+}
+
+impl<'a> Dfs<'a> {
+    pub fn new(cfg: &'a [Block], roots: Vec<usize>, direction: Direction) -> Self {
+        let mut stack = roots;
+        stack.reverse();
+        Dfs {
+            cfg,
+            direction,
+            stack,
+            visited: HashSet::new(),
+        }
+    }
+}
+
+impl Iterator for Dfs<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while let Some(index) = self.stack.pop() {
+            if self.visited.insert(index) {
+                let mut neighbors = neighbors(&self.cfg[index], self.direction);
+                neighbors.reverse();
+                self.stack.extend(neighbors);
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+// All blocks reachable from `entry`, in depth-first postorder.
+pub fn postorder(cfg: &[Block], entry: usize) -> impl DoubleEndedIterator<Item = usize> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    // Each frame holds a block and its remaining successors to explore.
+    let mut stack = vec![(entry, neighbors(&cfg[entry], Direction::Forward))];
+    visited.insert(entry);
+    while let Some((index, succs)) = stack.last_mut() {
+        let index = *index;
+        if succs.is_empty() {
+            order.push(index);
+            stack.pop();
+        } else {
+            let succ = succs.remove(0);
+            if visited.insert(succ) {
+                stack.push((succ, neighbors(&cfg[succ], Direction::Forward)));
+            }
+        }
+    }
+    order.into_iter()
+}
+
+// All blocks reachable from `entry`, in reverse postorder, so every block comes
+// before its successors except along back edges.
+pub fn reverse_postorder(cfg: &[Block], entry: usize) -> impl Iterator<Item = usize> {
+    postorder(cfg, entry).rev()
+}
+
+fn neighbors(block: &Block, direction: Direction) -> Vec<usize> {
+    let mut neighbors: Vec<_> = match direction {
+        Direction::Forward => block.successors.iter().copied().collect(),
+        Direction::Backward => block.predecessors.iter().copied().collect(),
+    };
+    neighbors.sort_unstable();
+    neighbors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::CFG;
+
+    // 0 -> 1 -> 2 -> 4
+    //      ^    |
+    //      +----3
+    fn loop_cfg() -> CFG {
+        let mut cfg: CFG = (0..5).map(|_| Block::default()).collect();
+        for (pred, succ) in [(0, 1), (1, 2), (2, 3), (3, 1), (2, 4)] {
+            cfg[pred].successors.insert(succ);
+            cfg[succ].predecessors.insert(pred);
+        }
+        cfg
+    }
+
+    #[test]
+    fn orders() {
+        let cfg = loop_cfg();
+        let preorder = Dfs::new(&cfg, vec![0], Direction::Forward);
+        assert_eq!(preorder.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(postorder(&cfg, 0).collect::<Vec<_>>(), vec![3, 4, 2, 1, 0]);
+        assert_eq!(
+            reverse_postorder(&cfg, 0).collect::<Vec<_>>(),
+            vec![0, 1, 2, 4, 3]
+        );
+    }
+
+    #[test]
+    fn visit_once() {
+        let cfg = loop_cfg();
+        let dfs = Dfs::new(&cfg, vec![3, 1, 3], Direction::Forward);
+        assert_eq!(dfs.collect::<Vec<_>>(), vec![3, 1, 2, 4]);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::dataflow::{self, DataflowAnalysis, Direction};
+use crate::ir::{Block, Expression, SSAFunction, SSAProgram, SSAVar, Statement};
+use crate::lines::BlockLines;
+use crate::traversal;
+use std::collections::{HashMap, HashSet};
+
+// Find variables that may be read before any assignment. Declarations do not
+// initialize, so a declaration is a definition that reaches its uses
+// uninitialized until an assignment kills it. A phi is uninitialized if any of
+// its operands may be. Returns one warning per variable, located at the line of
+// the statement that first reads it in `blocks`, or of its function in `lines`
+// if that is not known.
+pub fn check(ssa: &SSAProgram, lines: &HashMap<String, usize>, blocks: &BlockLines) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for func in ssa {
+        let blocks = blocks.get(&func.name).map_or(&[][..], Vec::as_slice);
+        for (name, line) in check_function(func, blocks) {
+            warnings.push(format!(
+                "line {}: `{}` may be used before it is assigned in function `{}`",
+                line.unwrap_or(lines[&func.name]),
+                name,
+                func.name
+            ));
+        }
+    }
+    warnings
+}
+
+type Uninit = HashSet<SSAVar>;
+
+// The variables possibly uninitialized at the start and the end of a block.
+struct MaybeUninit;
+
+impl DataflowAnalysis for MaybeUninit {
+    type Fact = Uninit;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self) -> Uninit {
+        Uninit::new()
+    }
+
+    fn top(&self) -> Uninit {
+        Uninit::new()
+    }
+
+    fn meet(&self, left: &Uninit, right: &Uninit) -> Uninit {
+        left.union(right).cloned().collect()
+    }
+
+    fn transfer(&self, _: usize, block: &Block, uninit: &Uninit) -> Uninit {
+        let mut uninit = uninit.clone();
+        for stmt in &block.statements {
+            check_stmt(stmt, &mut uninit, &mut Vec::new());
+        }
+        uninit
+    }
+}
+
+// The variables read uninitialized, each with the line of its first such read.
+fn check_function(func: &SSAFunction, lines: &[Vec<usize>]) -> Vec<(String, Option<usize>)> {
+    let body = &func.body;
+    let order: Vec<_> = traversal::reverse_postorder(body, 0).collect();
+    let ins = dataflow::solve(&MaybeUninit, body).ins;
+    let mut uses: Vec<(String, Option<usize>)> = Vec::new();
+    for i in order {
+        let mut uninit = ins[i].clone();
+        for (j, stmt) in body[i].statements.iter().enumerate() {
+            let mut names = Vec::new();
+            check_stmt(stmt, &mut uninit, &mut names);
+            let line = lines.get(i).and_then(|lines| lines.get(j)).copied();
+            for name in names {
+                if uses.iter().all(|(used, _)| *used != name) {
+                    uses.push((name, line));
+                }
+            }
+        }
+    }
+    uses
+}
+
+fn check_stmt(stmt: &Statement, uninit: &mut Uninit, names: &mut Vec<String>) {
+    match stmt {
+        Statement::Phi(var, values) => {
+            if values.iter().any(|(_, value)| uninit.contains(value)) {
+                uninit.insert(var.clone());
+            } else {
+                uninit.remove(var);
+            }
+        }
+        Statement::Declaration(var) => {
+            uninit.insert(var.clone());
+        }
+        Statement::Compound(stmts) => {
+            for stmt in stmts {
+                check_stmt(stmt, uninit, names);
+            }
+        }
+        Statement::Expression(expr)
+        | Statement::If {
+            condition: expr, ..
+        }
+        | Statement::While {
+            condition: expr, ..
+        }
+        | Statement::DoWhile {
+            condition: expr, ..
+        }
+        | Statement::Return(Some(expr)) => check_expr(expr, uninit, names),
+        // The elements of arrays are not tracked.
+        Statement::ArrayDeclaration(..)
+        | Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Return(None) => {}
+    }
+}
+
+fn check_expr(expr: &Expression, uninit: &mut Uninit, names: &mut Vec<String>) {
+    match expr {
+        Expression::Identifier(var) => {
+            if uninit.contains(var) {
+                // Drop the suffix that tells shadowed variables apart.
+                let name = var.name.split('.').next().unwrap().to_string();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        Expression::Number(_) | Expression::Float(_) => {}
+        Expression::Index { indices, .. } => {
+            for index in indices {
+                check_expr(index, uninit, names);
+            }
+        }
+        Expression::Call { arguments, .. } => check_expr(arguments, uninit, names),
+        Expression::Arguments(exprs) => {
+            for expr in exprs {
+                check_expr(expr, uninit, names);
+            }
+        }
+        Expression::Prefix { expression, .. } => check_expr(expression, uninit, names),
+        Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        } => {
+            check_expr(right, uninit, names);
+            match &**left {
+                Expression::Identifier(var) => {
+                    uninit.remove(var);
+                }
+                left => check_expr(left, uninit, names),
+            }
+        }
+        Expression::Infix { left, right, .. } => {
+            check_expr(left, uninit, names);
+            check_expr(right, uninit, names);
+        }
+        Expression::Conditional { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::lines::Lines;
+    use crate::{parser, ssa};
+
+    #[test]
+    fn uninitialized() {
+        let source = "
+            int main(int n) {
+                int a;
+                int b;
+                int c;
+                int d;
+                int e;
+                c = 0;
+                n ? (d = 1) : (e = 1);
+                n ? (e = 2) : (e = 3);
+                if (n) {
+                    a = 1;
+                    b = 1;
+                } else {
+                    b = 2;
+                }
+                while (c < b) {
+                    int c;
+                    c = c + 1;
+                }
+                return a + b + d + e;
+            }
+        ";
+        let mut stmt_lines = Lines::new();
+        let events = parser::events(source);
+        let ast = parser::replay_unit(
+            events,
+            "main.c",
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut stmt_lines,
+        )
+        .unwrap();
+        let lines = ast
+            .iter()
+            .map(|func| (func.name.clone(), func.line))
+            .collect();
+        let cancel = CancellationToken::new();
+        let (ssa, blocks) =
+            ssa::construct_cancellable(ast, &HashMap::new(), &stmt_lines, &mut Vec::new(), &cancel)
+                .unwrap();
+        let expected = vec![
+            "line 21: `a` may be used before it is assigned in function `main`",
+            "line 21: `d` may be used before it is assigned in function `main`",
+            "line 19: `c` may be used before it is assigned in function `main`",
+        ];
+        assert_eq!(check(&ssa, &lines, &blocks), expected);
+        // Without the lines of the statements, at the line of the function.
+        let warnings = check(&ssa, &lines, &BlockLines::new());
+        assert!(warnings
+            .iter()
+            .all(|warning| warning.starts_with("line 2:")));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::ir::{Expression, SSAFunction, SSAProgram, SSAVar, Statement, CFG};
+use crate::x64::{Register, X64Function, X64Program, X64};
+use std::collections::HashSet;
+
+pub fn verify_cfg(program: &SSAProgram) -> Result<(), String> {
+    for func in program {
+        check_edges(&func.name, &func.body)?;
+    }
+    Ok(())
+}
+
+// Besides the CFG invariants, every SSA name should be defined exactly once,
+// Phi functions should only appear at the beginning of a block, and every
+// variable use should have been renamed.
+pub fn verify_ssa(program: &SSAProgram) -> Result<(), String> {
+    verify_cfg(program)?;
+    for SSAFunction {
+        name,
+        parameters,
+        body,
+        ..
+    } in program
+    {
+        let mut defs = HashSet::new();
+        for param in parameters {
+            check_def(name, param, &mut defs)?;
+        }
+        for (index, block) in body.iter().enumerate() {
+            let mut leading = true;
+            for stmt in &block.statements {
+                match stmt {
+                    Statement::Phi(var, values) => {
+                        if !leading {
+                            return Err(format!(
+                                "`{}`: Phi of `{}` is not at the beginning of block {}.",
+                                name, var.name, index
+                            ));
+                        }
+                        check_def(name, var, &mut defs)?;
+                        for (_, value) in values {
+                            check_use(name, value)?;
+                        }
+                    }
+                    stmt => {
+                        leading = false;
+                        check_stmt(name, stmt, &mut defs)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// After SSA destruction no Phi function should remain.
+pub fn verify_destructed(program: &SSAProgram) -> Result<(), String> {
+    verify_cfg(program)?;
+    for func in program {
+        for (index, block) in func.body.iter().enumerate() {
+            if let Some(Statement::Phi(var, _)) = block
+                .statements
+                .iter()
+                .find(|stmt| matches!(stmt, Statement::Phi(..)))
+            {
+                return Err(format!(
+                    "`{}`: Phi of `{}` survives in block {}.",
+                    func.name, var.name, index
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Every jump should target a tag defined exactly once in the same function.
+pub fn verify_vasm(program: &X64Program) -> Result<(), String> {
+    for X64Function { name, body, .. } in program {
+        let entry = body.iter().take_while(|asm| matches!(asm, X64::Param(..)));
+        for asm in body.iter().skip(entry.count()) {
+            if let X64::Param(_, index) = asm {
+                return Err(format!(
+                    "`{}`: Parameter {} is defined after the entry.",
+                    name, index
+                ));
+            }
+        }
+        let mut tags = HashSet::new();
+        for asm in body {
+            if let X64::Tag(tag) = asm {
+                if !tags.insert(tag) {
+                    return Err(format!("`{}`: Tag `{}` is defined twice.", name, tag));
+                }
+            }
+        }
+        for asm in body {
+            if let Some(target) = asm.jump_target() {
+                if !tags.contains(target) {
+                    return Err(format!("`{}`: Jump to undefined tag `{}`.", name, target));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// After register allocation, only physical registers should remain.
+pub fn verify_asm(program: &X64Program) -> Result<(), String> {
+    verify_vasm(program)?;
+    for X64Function { name, body, .. } in program {
+        for asm in body {
+            if let Some(reg) = asm.registers().into_iter().find(|reg| match reg {
+                Register::Virtual(_) | Register::VirtualXmm(_) => true,
+                Register::X64(_) => false,
+            }) {
+                return Err(format!(
+                    "`{}`: Virtual register `{}` survives in `{}`.",
+                    name, reg, asm
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_edges(name: &str, body: &CFG) -> Result<(), String> {
+    for (index, block) in body.iter().enumerate() {
+        for succ in &block.successors {
+            match body.get(*succ) {
+                Some(b) if b.predecessors.contains(&index) => {}
+                _ => {
+                    return Err(format!(
+                        "`{}`: Edge {} -> {} has no matching predecessor.",
+                        name, index, succ
+                    ))
+                }
+            }
+        }
+        for pred in &block.predecessors {
+            match body.get(*pred) {
+                Some(b) if b.successors.contains(&index) => {}
+                _ => {
+                    return Err(format!(
+                        "`{}`: Edge {} -> {} has no matching successor.",
+                        name, pred, index
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_def<'a>(
+    func: &str,
+    var: &'a SSAVar,
+    defs: &mut HashSet<&'a SSAVar>,
+) -> Result<(), String> {
+    check_use(func, var)?;
+    if !defs.insert(var) {
+        return Err(format!(
+            "`{}`: `{}{}` is defined twice.",
+            func,
+            var.name,
+            var.subscript.unwrap()
+        ));
+    }
+    Ok(())
+}
+
+fn check_use(func: &str, var: &SSAVar) -> Result<(), String> {
+    match var.subscript {
+        Some(_) => Ok(()),
+        None => Err(format!("`{}`: `{}` is not renamed.", func, var.name)),
+    }
+}
+
+fn check_stmt<'a>(
+    func: &str,
+    stmt: &'a Statement,
+    defs: &mut HashSet<&'a SSAVar>,
+) -> Result<(), String> {
+    match stmt {
+        // Arrays are not SSA variables.
+        Statement::Nop
+        | Statement::Break
+        | Statement::Continue
+        | Statement::ArrayDeclaration(..) => Ok(()),
+        Statement::Phi(..) => unreachable!(),
+        Statement::Declaration(var) => check_def(func, var, defs),
+        Statement::Compound(stmts) => stmts
+            .iter()
+            .try_for_each(|stmt| check_stmt(func, stmt, defs)),
+        Statement::Expression(expr) => check_expr(func, expr, defs),
+        Statement::If { condition, .. }
+        | Statement::While { condition, .. }
+        | Statement::DoWhile { condition, .. } => check_expr(func, condition, defs),
+        Statement::Return(Some(expr)) => check_expr(func, expr, defs),
+        Statement::Return(None) => Ok(()),
+    }
+}
+
+// An assignment to a variable defines it. Every `?:` is lowered into blocks.
+fn check_expr<'a>(
+    func: &str,
+    expr: &'a Expression,
+    defs: &mut HashSet<&'a SSAVar>,
+) -> Result<(), String> {
+    match expr {
+        Expression::Identifier(var) => check_use(func, var),
+        Expression::Number(_) | Expression::Float(_) => Ok(()),
+        Expression::Index { indices, .. } => indices
+            .iter()
+            .try_for_each(|index| check_expr(func, index, defs)),
+        Expression::Call { arguments, .. } => check_expr(func, arguments, defs),
+        Expression::Arguments(exprs) => exprs
+            .iter()
+            .try_for_each(|expr| check_expr(func, expr, defs)),
+        Expression::Prefix { expression, .. } => check_expr(func, expression, defs),
+        Expression::Infix {
+            left,
+            operator: "=",
+            right,
+        } if matches!(**left, Expression::Identifier(_)) => {
+            check_expr(func, right, defs)?;
+            match &**left {
+                Expression::Identifier(var) => check_def(func, var, defs),
+                _ => unreachable!(),
+            }
+        }
+        Expression::Infix { left, right, .. } => {
+            check_expr(func, left, defs)?;
+            check_expr(func, right, defs)
+        }
+        Expression::Conditional { .. } => Err(format!("`{}`: `?:` is not lowered.", func)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::X64Builder;
+    use crate::ir::Block;
+    use crate::lir;
+    use crate::parser;
+    use crate::reg_allocator;
+    use crate::ssa;
+    use crate::x64::Spill;
+
+    #[test]
+    fn pipeline() {
+        let ast = parser::parse(
+            "
+            int f(int a) {
+                int b;
+                if (a < 1) {
+                    int b;
+                }
+                while (a) {
+                    a = a - 1;
+                }
+                return b;
+            }
+        ",
+        );
+        let ssa = ssa::construct(ast);
+        assert_eq!(verify_ssa(&ssa), Ok(()));
+        let cfg = ssa::destruct(ssa);
+        assert_eq!(verify_destructed(&cfg), Ok(()));
+        let vasm = X64Builder::new().build(lir::lower(cfg));
+        assert_eq!(verify_vasm(&vasm), Ok(()));
+        let asm = reg_allocator::alloc(vasm, Spill::Cost);
+        assert_eq!(verify_asm(&asm), Ok(()));
+    }
+
+    #[test]
+    fn broken_invariants() {
+        let mut block = Block::default();
+        block.successors.insert(1);
+        block.statements = vec![
+            Statement::Declaration(SSAVar::new("a")),
+            Statement::Phi(SSAVar::new("a"), Vec::new()),
+        ];
+        let mut func = SSAFunction {
+            void: true,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: vec![block, Block::default()],
+        };
+        assert_eq!(
+            verify_cfg(&vec![func]),
+            Err(String::from(
+                "`main`: Edge 0 -> 1 has no matching predecessor."
+            ))
+        );
+        func = SSAFunction {
+            void: true,
+            double: false,
+            name: String::from("main"),
+            parameters: vec![],
+            doubles: HashSet::new(),
+            body: vec![Block {
+                statements: vec![Statement::Declaration(SSAVar::new("a"))],
+                ..Block::default()
+            }],
+        };
+        assert_eq!(
+            verify_ssa(&vec![func]),
+            Err(String::from("`main`: `a` is not renamed."))
+        );
+        let vasm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![X64::Jmp(String::from("End"))],
+        }];
+        assert_eq!(
+            verify_vasm(&vasm),
+            Err(String::from("`main`: Jump to undefined tag `End`."))
+        );
+        let vasm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 1,
+            arrays: Vec::new(),
+            body: vec![X64::Cqo, X64::Param(Register::Virtual(0), 0)],
+        }];
+        assert_eq!(
+            verify_vasm(&vasm),
+            Err(String::from(
+                "`main`: Parameter 0 is defined after the entry."
+            ))
+        );
+        let asm = vec![X64Function {
+            name: String::from("main"),
+            param_cnt: 0,
+            arrays: Vec::new(),
+            body: vec![X64::Neg(Register::Virtual(0))],
+        }];
+        assert_eq!(
+            verify_asm(&asm),
+            Err(String::from(
+                "`main`: Virtual register `VR0` survives in `neg VR0`."
+            ))
+        );
+    }
+}
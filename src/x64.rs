@@ -1,174 +1,103 @@
-use crate::ir::SSAVar;
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter, Result as FmtResult};
+// The x64 types live in the `eac-ir` crate; the allocator of physical
+// registers stays here.
+pub use eac_ir::x64::*;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub enum Register {
-    Virtual(VRegister),
-    X64(X64Register),
-}
-
-impl Display for Register {
-    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        match self {
-            Register::Virtual(i) => write!(f, "VR{}", i),
-            Register::X64(reg) => write!(f, "{:?}", reg),
-        }
-    }
-}
-
-pub type VRegister = usize;
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub enum X64Register {
-    RAX,
-    RBX,
-    RCX,
-    RDX,
-    RBP,
-    RSI,
-    RDI,
-    RSP,
-    R8,
-    R9,
-    R10,
-    R11,
-    R12,
-    R13,
-    R14,
-    R15,
-}
-
-#[derive(Debug, PartialEq)]
-pub enum X64 {
-    MovNum(Register, i32),
-    MovReg(Register, Register),
-    MovToStack(usize, Register),           // MovToStack(offset, reg)
-    MovFromStack(Register, usize),         // MovFromStack(reg, offset)
-    Call(String, Vec<Register>, Register), // Call(name, args, ret_reg)
-    Neg(Register),
-    CmpNum(Register, i32),
-    CmpReg(Register, Register),
-    Jl(String),
-    Jg(String),
-    Jle(String),
-    Jge(String),
-    Je(String),
-    Jne(String),
-    Jmp(String),
-    Tag(String),
-    Imul(Register, Register),
-    Idiv(Register, Register),
-    Add(Register, Register),
-    AddNum(Register, usize), // Used only in stack manipulation.
-    Sub(Register, Register),
-    SubNum(Register, usize), // Used only in stack manipulation.
-    And(Register, Register),
-    Or(Register, Register),
-    Ret(Option<Register>),
-    Push(Register),
-    Pop(Register),
-}
-
-impl Display for X64 {
-    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        match self {
-            X64::MovNum(reg, num) => write!(f, "mov {}, {}", reg, num),
-            X64::MovReg(left, right) => write!(f, "mov {}, {}", left, right),
-            X64::MovToStack(offset, reg) => write!(f, "mov {}[RBP], {}", offset, reg),
-            X64::MovFromStack(reg, offset) => write!(f, "mov {}, {}[RBP]", reg, offset),
-            X64::Call(name, _, _) => write!(f, "call {}", name),
-            X64::Neg(reg) => write!(f, "neg {}", reg),
-            X64::CmpNum(reg, num) => write!(f, "cmp {}, {}", reg, num),
-            X64::CmpReg(left, right) => write!(f, "cmp {}, {}", left, right),
-            X64::Jl(tag) => write!(f, "jl {}", tag),
-            X64::Jg(tag) => write!(f, "jg {}", tag),
-            X64::Jle(tag) => write!(f, "jle {}", tag),
-            X64::Jge(tag) => write!(f, "jge {}", tag),
-            X64::Je(tag) => write!(f, "je {}", tag),
-            X64::Jne(tag) => write!(f, "jne {}", tag),
-            X64::Jmp(tag) => write!(f, "jmp {}", tag),
-            X64::Tag(tag) => write!(f, "{}:", tag),
-            X64::Imul(left, right) => write!(f, "imul {}, {}", left, right),
-            X64::Idiv(left, right) => write!(f, "idiv {}, {}", left, right),
-            X64::Add(left, right) => write!(f, "add {}, {}", left, right),
-            X64::AddNum(reg, offset) => write!(f, "add {}, {}", reg, offset),
-            X64::Sub(left, right) => write!(f, "sub {}, {}", left, right),
-            X64::SubNum(reg, offset) => write!(f, "sub {}, {}", reg, offset),
-            X64::And(left, right) => write!(f, "and {}, {}", left, right),
-            X64::Or(left, right) => write!(f, "or {}, {}", left, right),
-            X64::Ret(_) => write!(f, "ret"),
-            X64::Push(reg) => write!(f, "push {}", reg),
-            X64::Pop(reg) => write!(f, "pop {}", reg),
-        }
-    }
-}
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
-pub struct X64Function {
-    pub name: String,
-    pub param_cnt: usize,
-    pub body: Vec<X64>,
+// How the register allocator picks a register to spill.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Spill {
+    #[default]
+    Cost, // The value with the cheapest loop-weighted remaining uses.
+    NextUse, // The value whose next use is furthest away (Belady).
 }
 
-pub type X64Program = Vec<X64Function>;
-
-pub struct VRegisterAllocator {
-    count: usize,
-    var_map: HashMap<SSAVar, Register>,
-}
+impl FromStr for Spill {
+    type Err = String;
 
-impl VRegisterAllocator {
-    pub fn new() -> Self {
-        VRegisterAllocator {
-            count: 0,
-            var_map: HashMap::new(),
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cost" => Ok(Spill::Cost),
+            "next-use" => Ok(Spill::NextUse),
+            s => Err(format!("Unknown spill heuristic `{}`.", s)),
         }
     }
-
-    pub fn from_var(&mut self, var: SSAVar) -> Register {
-        match self.var_map.get(&var) {
-            Some(reg) => *reg,
-            None => {
-                let reg = Register::Virtual(self.count);
-                self.count += 1;
-                self.var_map.insert(var, reg);
-                reg
-            }
-        }
-    }
-
-    pub fn create_temp(&mut self) -> Register {
-        let reg = Register::Virtual(self.count);
-        self.count += 1;
-        reg
-    }
-
-    pub fn clear(&mut self) {
-        self.count = 0;
-        self.var_map.clear();
-    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum RegStatus {
     Reg(Register),
     Stack(usize), // offset
 }
 
+// Where every virtual register lives at some point of the program.
+#[derive(Debug, Clone)]
+pub struct AllocState {
+    vreg_map: HashMap<Register, RegStatus>,
+    x64regs: Vec<Register>,
+}
+
 // `rsp` is the stack pointer (from 2^64-1 to 0).
 // `rbp` is the stack frame pointer (callee-saved).
 #[derive(Debug)]
 pub struct X64RegisterAllocator {
     vreg_map: HashMap<Register, RegStatus>,
-    last: Register,
-    stack: usize,
+    spill: Spill,
+    uses: HashMap<Register, Vec<(usize, usize)>>, // (position, weight) of every use.
+    position: usize,                              // Position of the current instruction.
+    busy: Vec<Register>,                          // Operands of the current instruction.
+    live: Vec<HashSet<Register>>,                 // Values live after every instruction.
+    stack: usize, // Offset of the first slot above the shadow space.
     x64regs: Vec<Register>,
+    arrays: Vec<usize>, // The offset of every array from RSP.
+    local_size: usize,  // The bytes the prologue allocates below the pushes.
 }
 
 impl X64RegisterAllocator {
-    pub const INT_SIZE: usize = 4;
-    pub const FRAME_SIZE: usize = Self::INT_SIZE * 128;
+    // Every stack slot holds a whole register, as `int` and `long` both do.
+    pub const WORD_SIZE: usize = 8;
+    pub const FRAME_SIZE: usize = Self::WORD_SIZE * 128;
+    // The registers values are allocated to, in the order they are taken,
+    // from the last.
+    pub const ALLOCATABLE: [Register; 18] = [
+        Self::RBX,
+        Self::RCX,
+        Self::RDX,
+        Self::RSI,
+        Self::RDI,
+        Self::R8,
+        Self::R9,
+        Self::R10,
+        Self::R11,
+        Self::R12,
+        Self::R13,
+        Self::R14,
+        Self::R15,
+        Self::XMM0,
+        Self::XMM1,
+        Self::XMM2,
+        Self::XMM3,
+        Self::XMM4,
+    ];
+    // A function that calls allocates once, in its prologue, the frame of its
+    // callees followed by the slots where every call saves the frame pointer
+    // and the caller-saved registers. The slots start a word past the frame,
+    // so that eight pushes and this area keep RSP 16-byte aligned at every call.
+    pub const CALL_AREA_SIZE: usize = Self::FRAME_SIZE + 8 * 9;
+    // Arrays are laid out above the call area, with 8-byte elements.
+    pub const ELEMENT_SIZE: usize = 8;
+    const SAVE_AREA: usize = Self::FRAME_SIZE + 8;
+    const ARG_REGS: [Register; 4] = [Self::RCX, Self::RDX, Self::R8, Self::R9];
+    const XMM_ARG_REGS: [Register; 4] = [Self::XMM0, Self::XMM1, Self::XMM2, Self::XMM3];
+    const CALLER_SAVED: [Register; 6] = [
+        Self::RCX,
+        Self::RDX,
+        Self::R8,
+        Self::R9,
+        Self::R10,
+        Self::R11,
+    ];
     pub const RAX: Register = Register::X64(X64Register::RAX);
     pub const RBX: Register = Register::X64(X64Register::RBX);
     pub const RCX: Register = Register::X64(X64Register::RCX);
@@ -185,61 +114,73 @@ impl X64RegisterAllocator {
     pub const R13: Register = Register::X64(X64Register::R13);
     pub const R14: Register = Register::X64(X64Register::R14);
     pub const R15: Register = Register::X64(X64Register::R15);
+    pub const XMM0: Register = Register::X64(X64Register::XMM0);
+    pub const XMM1: Register = Register::X64(X64Register::XMM1);
+    pub const XMM2: Register = Register::X64(X64Register::XMM2);
+    pub const XMM3: Register = Register::X64(X64Register::XMM3);
+    pub const XMM4: Register = Register::X64(X64Register::XMM4);
+    // The scratch register of `double` moves, never allocated.
+    pub const XMM5: Register = Register::X64(X64Register::XMM5);
 
-    pub fn new(param_cnt: usize) -> Self {
+    // `params` holds the register of every parameter, by position, as defined
+    // by the `Param` instructions of the function.
+    pub fn new(
+        param_cnt: usize,
+        params: &[Option<Register>],
+        arrays: &[usize],
+        uses: HashMap<Register, Vec<(usize, usize)>>,
+        live: Vec<HashSet<Register>>,
+        spill: Spill,
+        calls: bool,
+    ) -> Self {
+        let call_area = if calls { Self::CALL_AREA_SIZE } else { 0 };
+        let mut offsets = Vec::new();
+        let mut array_size = 0;
+        for len in arrays {
+            offsets.push(call_area + array_size);
+            array_size += len * Self::ELEMENT_SIZE;
+        }
+        // Round up to keep RSP 16-byte aligned at every call.
+        let local_size = call_area + array_size.div_ceil(16) * 16;
         let mut allocator = X64RegisterAllocator {
             vreg_map: HashMap::new(),
-            last: Self::RSP,
-            stack: param_cnt * Self::INT_SIZE, // Allocate the shadow space.
-            x64regs: vec![
-                Self::RBX,
-                Self::RCX,
-                Self::RDX,
-                Self::RSI,
-                Self::RDI,
-                Self::R8,
-                Self::R9,
-                Self::R10,
-                Self::R11,
-                Self::R12,
-                Self::R13,
-                Self::R14,
-                Self::R15,
-            ],
+            spill,
+            uses,
+            position: 0,
+            busy: Vec::new(),
+            live,
+            stack: param_cnt * Self::WORD_SIZE, // Allocate the shadow space.
+            x64regs: Self::ALLOCATABLE.to_vec(),
+            arrays: offsets,
+            local_size,
         };
-        // Allocate arguments.
-        for i in 0..param_cnt {
-            let vreg = Register::Virtual(i);
-            match i {
-                0 => {
-                    let rcx = allocator.x64regs.remove(1);
-                    allocator.vreg_map.insert(vreg, RegStatus::Reg(rcx));
-                }
-                1 => {
-                    let rdx = allocator.x64regs.remove(1);
-                    allocator.vreg_map.insert(vreg, RegStatus::Reg(rdx));
-                }
-                2 => {
-                    let r8 = allocator.x64regs.remove(3);
-                    allocator.vreg_map.insert(vreg, RegStatus::Reg(r8));
+        // Allocate arguments. A `double` parameter arrives in the XMM register
+        // of its position.
+        for (i, vreg) in params.iter().enumerate() {
+            let vreg = match vreg {
+                Some(vreg) => *vreg,
+                None => continue,
+            };
+            let arg_regs = match vreg.class() {
+                RegisterClass::General => Self::ARG_REGS,
+                RegisterClass::Xmm => Self::XMM_ARG_REGS,
+            };
+            let status = match arg_regs.get(i) {
+                Some(reg) => {
+                    allocator.x64regs.retain(|r| r != reg);
+                    RegStatus::Reg(*reg)
                 }
-                3 => {
-                    let r9 = allocator.x64regs.remove(3);
-                    allocator.vreg_map.insert(vreg, RegStatus::Reg(r9));
-                }
-                i => {
-                    allocator
-                        .vreg_map
-                        .insert(vreg, RegStatus::Stack(i * Self::INT_SIZE));
-                }
-            }
+                None => RegStatus::Stack(i * Self::WORD_SIZE),
+            };
+            allocator.vreg_map.insert(vreg, status);
         }
         allocator
     }
 
     pub fn prolog(&self) -> Vec<X64> {
-        // Save callee-saved registers.
-        vec![
+        // Save callee-saved registers, including the frame pointer every call
+        // points to the frame of the callee.
+        let mut asms = vec![
             X64::Push(Self::RBX),
             X64::Push(Self::RSI),
             X64::Push(Self::RDI),
@@ -247,12 +188,22 @@ impl X64RegisterAllocator {
             X64::Push(Self::R13),
             X64::Push(Self::R14),
             X64::Push(Self::R15),
-        ]
+            X64::Push(Self::RBP),
+        ];
+        if self.local_size > 0 {
+            asms.push(X64::SubNum(Self::RSP, self.local_size));
+        }
+        asms
     }
 
     pub fn epilog(&self) -> Vec<X64> {
         // Restore callee-saved registers before returning.
-        vec![
+        let mut asms = Vec::new();
+        if self.local_size > 0 {
+            asms.push(X64::AddNum(Self::RSP, self.local_size));
+        }
+        asms.extend(vec![
+            X64::Pop(Self::RBP),
             X64::Pop(Self::R15),
             X64::Pop(Self::R14),
             X64::Pop(Self::R13),
@@ -261,53 +212,301 @@ impl X64RegisterAllocator {
             X64::Pop(Self::RSI),
             X64::Pop(Self::RBX),
             X64::Ret(None),
-        ]
+        ]);
+        asms
     }
 
-    pub fn call_prolog(&mut self, args: Vec<Register>) -> Vec<X64> {
-        // Save caller-saved registers and set up the stack frame.
-        let mut assemblies = vec![
-            X64::Push(Self::RCX),
-            X64::Push(Self::RDX),
-            X64::Push(Self::R8),
-            X64::Push(Self::R9),
-            X64::Push(Self::R10),
-            X64::Push(Self::R11),
-            X64::SubNum(Self::RSP, Self::FRAME_SIZE),
-            X64::MovReg(Self::RBP, Self::RSP),
-        ];
-        // Push arguments.
-        for (i, arg) in args.into_iter().enumerate() {
+    pub fn call_prolog(&mut self, args: Vec<Register>, variadic: bool) -> Vec<X64> {
+        // Load the arguments while RBP still points to the frame of this function.
+        let mut assemblies = Vec::new();
+        let mut regs = Vec::new();
+        for arg in args {
             let (asms, reg) = self.alloc(arg);
             assemblies.extend(asms);
-            assemblies.push(X64::MovToStack(i * Self::INT_SIZE, reg));
-            match i {
-                0 => assemblies.push(X64::MovReg(Self::RCX, reg)),
-                1 => assemblies.push(X64::MovReg(Self::RDX, reg)),
-                2 => assemblies.push(X64::MovReg(Self::R8, reg)),
-                3 => assemblies.push(X64::MovReg(Self::R9, reg)),
-                _ => {}
+            regs.push(reg);
+        }
+        // No XMM register survives a call, so every `double` moves to the stack.
+        assemblies.extend(self.spill_xmm());
+        // Point RBP to the frame of the callee, saving the old one there.
+        assemblies.extend(vec![
+            X64::MovReg(Self::RAX, Self::RBP),
+            X64::MovReg(Self::RBP, Self::RSP),
+            X64::MovToStack(Self::SAVE_AREA, Self::RAX),
+        ]);
+        // Save the caller-saved registers holding values.
+        for (i, reg) in self.caller_saved().into_iter().enumerate() {
+            assemblies.push(X64::MovToStack(Self::SAVE_AREA + (i + 1) * 8, reg));
+        }
+        // Pass the first four arguments in registers and the rest on the stack.
+        let mut moves = Vec::new();
+        let mut copies = Vec::new();
+        for (i, reg) in regs.into_iter().enumerate() {
+            let arg_regs = match reg.class() {
+                RegisterClass::General => Self::ARG_REGS,
+                RegisterClass::Xmm => Self::XMM_ARG_REGS,
+            };
+            match arg_regs.get(i) {
+                Some(to) => moves.push((*to, reg)),
+                _ => assemblies.push(X64::MovToStack(i * Self::WORD_SIZE, reg)),
+            }
+            // A variadic callee may read a `double` from the general register
+            // of its position, as it does not know the type in advance.
+            if variadic && reg.class() == RegisterClass::Xmm && i < Self::ARG_REGS.len() {
+                copies.push(X64::MovReg(Self::ARG_REGS[i], Self::XMM_ARG_REGS[i]));
             }
         }
+        assemblies.extend(Self::parallel_moves(moves));
+        assemblies.extend(copies);
         assemblies
     }
 
     pub fn call_epilog(&self) -> Vec<X64> {
-        // Clean the stack and restore caller-saved registers.
-        vec![
-            X64::AddNum(Self::RSP, Self::FRAME_SIZE),
-            X64::Pop(Self::R11),
-            X64::Pop(Self::R10),
-            X64::Pop(Self::R9),
-            X64::Pop(Self::R8),
-            X64::Pop(Self::RDX),
-            X64::Pop(Self::RCX),
-        ]
+        // The callee preserves RBP, which still points to its frame.
+        let mut asms = Vec::new();
+        for (i, reg) in self.caller_saved().into_iter().enumerate() {
+            asms.push(X64::MovFromStack(reg, Self::SAVE_AREA + (i + 1) * 8));
+        }
+        asms.push(X64::MovFromStack(Self::RBP, Self::SAVE_AREA));
+        asms
+    }
+
+    // Store every virtual register in an XMM register to the stack, or drop it
+    // if it is dead. The registers keep their values until they are allocated
+    // again.
+    fn spill_xmm(&mut self) -> Vec<X64> {
+        let mut vregs: Vec<_> = self
+            .vreg_map
+            .iter()
+            .filter_map(|(vreg, status)| match status {
+                RegStatus::Reg(reg) if reg.class() == RegisterClass::Xmm => Some((*vreg, *reg)),
+                _ => None,
+            })
+            .collect();
+        vregs.sort();
+        let mut asms = Vec::new();
+        for (vreg, reg) in vregs {
+            self.vreg_map.remove(&vreg);
+            self.x64regs.push(reg);
+            if self.live[self.position].contains(&vreg) {
+                let offset = self.alloc_stack();
+                asms.push(X64::MovToStack(offset, reg));
+                self.vreg_map.insert(vreg, RegStatus::Stack(offset));
+            }
+        }
+        asms
+    }
+
+    // The caller-saved registers that hold virtual registers.
+    fn caller_saved(&self) -> Vec<Register> {
+        Self::CALLER_SAVED
+            .iter()
+            .filter(|reg| {
+                self.vreg_map
+                    .values()
+                    .any(|status| *status == RegStatus::Reg(**reg))
+            })
+            .copied()
+            .collect()
+    }
+
+    // Start allocating the instruction at `position`, which uses `vregs`.
+    // Its operands will not be chosen as spill victims.
+    pub fn begin(&mut self, position: usize, vregs: Vec<Register>) {
+        self.position = position;
+        self.busy = vregs;
+    }
+
+    pub fn snapshot(&self) -> AllocState {
+        AllocState {
+            vreg_map: self.vreg_map.clone(),
+            x64regs: self.x64regs.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: &AllocState) {
+        self.vreg_map = state.vreg_map.clone();
+        self.x64regs = state.x64regs.clone();
+    }
+
+    // Move every virtual register to where `state` expects it, then adopt `state`.
+    // Virtual registers missing from `state` are dead. The slot of a dead value
+    // may hold another one by now, so the moves are parallel: each goes once no
+    // pending move still reads its destination. RAX is free at block
+    // boundaries, so it serves as the scratch register that breaks cycles and
+    // carries copies between slots. XMM5 breaks cycles of `double` values.
+    pub fn reconcile(&mut self, state: &AllocState) -> Vec<X64> {
+        let mut moves: Vec<_> = state
+            .vreg_map
+            .iter()
+            .filter_map(|(vreg, to)| match self.vreg_map.get(vreg) {
+                Some(from) if from != to => Some((*vreg, *to, *from)),
+                _ => None,
+            })
+            .collect();
+        // Stores go first and reloads last, ties broken by the lower virtual
+        // register.
+        moves.sort_by_key(|(vreg, to, from)| {
+            let rank = match (from, to) {
+                (_, RegStatus::Stack(_)) => 0,
+                (RegStatus::Reg(_), RegStatus::Reg(_)) => 1,
+                (RegStatus::Stack(_), RegStatus::Reg(_)) => 2,
+            };
+            (rank, *vreg)
+        });
+        let mut asms = Vec::new();
+        while !moves.is_empty() {
+            let ready = moves
+                .iter()
+                .position(|(_, to, _)| moves.iter().all(|(_, _, from)| from != to));
+            let Some(index) = ready else {
+                // Every pending move is part of a cycle.
+                let (vreg, to, from) = moves.remove(0);
+                let scratch = match vreg.class() {
+                    RegisterClass::General => Self::RAX,
+                    RegisterClass::Xmm => Self::XMM5,
+                };
+                asms.push(match from {
+                    RegStatus::Reg(from) => X64::MovReg(scratch, from),
+                    RegStatus::Stack(from) => X64::MovFromStack(scratch, from),
+                });
+                moves.push((vreg, to, RegStatus::Reg(scratch)));
+                continue;
+            };
+            let (_, to, from) = moves.remove(index);
+            match (from, to) {
+                (RegStatus::Reg(from), RegStatus::Reg(to)) => asms.push(X64::MovReg(to, from)),
+                (RegStatus::Reg(from), RegStatus::Stack(to)) => {
+                    asms.push(X64::MovToStack(to, from))
+                }
+                (RegStatus::Stack(from), RegStatus::Reg(to)) => {
+                    asms.push(X64::MovFromStack(to, from))
+                }
+                (RegStatus::Stack(from), RegStatus::Stack(to)) => {
+                    // Keep the value of a broken cycle that RAX still holds.
+                    let saved = moves
+                        .iter()
+                        .any(|(_, _, from)| *from == RegStatus::Reg(Self::RAX));
+                    if saved {
+                        asms.push(X64::Push(Self::RAX));
+                    }
+                    asms.push(X64::MovFromStack(Self::RAX, from));
+                    asms.push(X64::MovToStack(to, Self::RAX));
+                    if saved {
+                        asms.push(X64::Pop(Self::RAX));
+                    }
+                }
+            }
+        }
+        self.restore(state);
+        asms
+    }
+
+    // Move `excess` of the `candidates`, in order, to the stack, counting
+    // those already there, so that they do not take a register while the
+    // loop that follows runs out of them.
+    pub fn split(&mut self, candidates: &[Register], excess: usize) -> Vec<X64> {
+        let spilled = candidates
+            .iter()
+            .filter(|vreg| matches!(self.vreg_map.get(vreg), Some(RegStatus::Stack(_))))
+            .count();
+        let victims: Vec<_> = candidates
+            .iter()
+            .filter_map(|vreg| match self.vreg_map.get(vreg) {
+                Some(RegStatus::Reg(reg)) => Some((*vreg, *reg)),
+                _ => None,
+            })
+            .take(excess.saturating_sub(spilled))
+            .collect();
+        let mut asms = Vec::new();
+        for (vreg, reg) in victims {
+            let offset = self.alloc_stack();
+            asms.push(X64::MovToStack(offset, reg));
+            self.vreg_map.insert(vreg, RegStatus::Stack(offset));
+            self.x64regs.push(reg);
+        }
+        asms
+    }
+
+    // Sequentialize the parallel register moves `(to, from)`, breaking cycles
+    // with RAX, or with XMM5 for `double` values.
+    fn parallel_moves(mut moves: Vec<(Register, Register)>) -> Vec<X64> {
+        moves.retain(|(to, from)| to != from);
+        let mut asms = Vec::new();
+        while !moves.is_empty() {
+            let ready = moves
+                .iter()
+                .position(|(to, _)| moves.iter().all(|(_, from)| from != to));
+            match ready {
+                Some(index) => {
+                    let (to, from) = moves.remove(index);
+                    asms.push(X64::MovReg(to, from));
+                }
+                None => {
+                    let (to, from) = moves.remove(0);
+                    let scratch = match to.class() {
+                        RegisterClass::General => Self::RAX,
+                        RegisterClass::Xmm => Self::XMM5,
+                    };
+                    asms.push(X64::MovReg(scratch, from));
+                    moves.push((to, scratch));
+                }
+            }
+        }
+        asms
+    }
+
+    // `idiv` divides RDX:RAX, leaving the quotient in RAX and the remainder in
+    // RDX, so whatever lives in RDX moves elsewhere first. `result` is the one
+    // copied back into `left`.
+    pub fn divide(&mut self, left: Register, right: Register, result: Register) -> Vec<X64> {
+        let mut asms = self.reserve(Self::RDX);
+        let (left_asms, left) = self.alloc(left);
+        let (right_asms, right) = self.alloc(right);
+        asms.extend(left_asms);
+        asms.extend(right_asms);
+        asms.extend(vec![
+            X64::MovReg(Self::RAX, left),
+            X64::Cqo,
+            X64::Idiv(Self::RAX, right),
+            X64::MovReg(left, result),
+        ]);
+        self.x64regs.push(Self::RDX);
+        asms
+    }
+
+    // Take `reg` out of allocation, moving the virtual register in it to
+    // another register or to the stack.
+    fn reserve(&mut self, reg: Register) -> Vec<X64> {
+        if let Some(index) = self.x64regs.iter().position(|r| *r == reg) {
+            self.x64regs.remove(index);
+            return Vec::new();
+        }
+        let vreg = self
+            .vreg_map
+            .iter()
+            .find(|(_, status)| **status == RegStatus::Reg(reg))
+            .map(|(vreg, _)| *vreg)
+            .unwrap();
+        let (mut asms, new) = self.ensure_reg(reg.class());
+        // The spilled victim may be the virtual register in `reg` itself.
+        if new != reg {
+            asms.push(X64::MovReg(new, reg));
+            self.vreg_map.insert(vreg, RegStatus::Reg(new));
+        }
+        asms
+    }
+
+    pub fn array_offset(&self, array: usize) -> usize {
+        self.arrays[array]
     }
 
     pub fn ret(&mut self, vreg: Register) -> Vec<X64> {
         let (mut asms, reg) = self.alloc(vreg);
-        asms.push(X64::MovReg(Self::RAX, reg));
+        let to = match reg.class() {
+            RegisterClass::General => Self::RAX,
+            RegisterClass::Xmm => Self::XMM0,
+        };
+        asms.push(X64::MovReg(to, reg));
         asms
     }
 
@@ -316,44 +515,101 @@ impl X64RegisterAllocator {
         if let reg @ Register::X64(_) = vreg {
             return (Vec::new(), reg);
         }
-        let (asms, reg) = match self.vreg_map.remove(&vreg) {
+        // A value on the stack keeps its slot until it has a register.
+        let (asms, reg) = match self.vreg_map.get(&vreg).copied() {
             Some(RegStatus::Reg(reg)) => (Vec::new(), reg),
             Some(RegStatus::Stack(offset)) => {
-                let (mut asms, reg) = self.ensure_reg();
+                let (mut asms, reg) = self.ensure_reg(vreg.class());
                 asms.push(X64::MovFromStack(reg, offset));
                 (asms, reg)
             }
-            None => self.ensure_reg(),
+            None => self.ensure_reg(vreg.class()),
         };
         self.vreg_map.insert(vreg, RegStatus::Reg(reg));
         (asms, reg)
     }
 
-    fn ensure_reg(&mut self) -> (Vec<X64>, Register) {
-        match self.x64regs.pop() {
-            Some(reg) => (Vec::new(), reg),
+    fn ensure_reg(&mut self, class: RegisterClass) -> (Vec<X64>, Register) {
+        match self.x64regs.iter().rposition(|reg| reg.class() == class) {
+            Some(index) => (Vec::new(), self.x64regs.remove(index)),
             None => {
-                let offset = self.alloc_stack();
-                for (vreg, status) in self.vreg_map.iter_mut() {
-                    // Make sure two consecutive calls will always get different x64 registers,
-                    // so two-operand x64 instruction could work correctly.
-                    if *vreg != self.last {
-                        if let RegStatus::Reg(reg) = *status {
-                            self.last = *vreg;
-                            let asms = vec![X64::MovToStack(offset, reg)];
-                            *status = RegStatus::Stack(offset);
-                            return (asms, reg);
-                        }
-                    }
+                // Never spill an operand of the current instruction. Ties are
+                // broken by the lower virtual register.
+                let victim = self
+                    .vreg_map
+                    .iter()
+                    .filter(|(vreg, status)| {
+                        matches!(status, RegStatus::Reg(reg) if reg.class() == class)
+                            && !self.busy.contains(vreg)
+                    })
+                    .map(|(vreg, _)| *vreg)
+                    .min_by_key(|vreg| {
+                        let index = match vreg {
+                            Register::Virtual(index) | Register::VirtualXmm(index) => *index,
+                            Register::X64(_) => 0,
+                        };
+                        (self.spill_priority(vreg), index)
+                    })
+                    .unwrap();
+                let reg = match self.vreg_map.remove(&victim) {
+                    Some(RegStatus::Reg(reg)) => reg,
+                    _ => unreachable!(),
+                };
+                // A value that is not live any more is dropped without a store.
+                if !self.live[self.position].contains(&victim) {
+                    return (Vec::new(), reg);
                 }
-                unreachable!()
+                let offset = self.alloc_stack();
+                self.vreg_map.insert(victim, RegStatus::Stack(offset));
+                (vec![X64::MovToStack(offset, reg)], reg)
             }
         }
     }
 
+    // Lower values are spilled first.
+    fn spill_priority(&self, vreg: &Register) -> usize {
+        let mut uses = self
+            .uses
+            .get(vreg)
+            .into_iter()
+            .flatten()
+            .filter(|(position, _)| *position > self.position);
+        match self.spill {
+            Spill::Cost => uses.map(|(_, weight)| weight).sum(),
+            // Positions follow the layout, so distances are exact within a block.
+            Spill::NextUse => match uses.next() {
+                Some((position, _)) => usize::MAX - (position - self.position),
+                None => 0,
+            },
+        }
+    }
+
+    // Take the lowest slot above the shadow space that no value still needs,
+    // forgetting the dead values left in slots.
     fn alloc_stack(&mut self) -> usize {
-        let offset = self.stack;
-        self.stack += Self::INT_SIZE;
+        let live = &self.live[self.position];
+        let busy = &self.busy;
+        self.vreg_map.retain(|vreg, status| {
+            matches!(status, RegStatus::Reg(_)) || live.contains(vreg) || busy.contains(vreg)
+        });
+        let taken: HashSet<_> = self
+            .vreg_map
+            .values()
+            .filter_map(|status| match status {
+                RegStatus::Stack(offset) => Some(*offset),
+                RegStatus::Reg(_) => None,
+            })
+            .collect();
+        let offset = (self.stack..)
+            .step_by(Self::WORD_SIZE)
+            .find(|offset| !taken.contains(offset))
+            .unwrap();
+        if offset + Self::WORD_SIZE > Self::FRAME_SIZE {
+            panic!(
+                "A function cannot spill more than {} values and parameters at once.",
+                Self::FRAME_SIZE / Self::WORD_SIZE
+            );
+        }
         offset
     }
 }
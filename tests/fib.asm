@@ -7,84 +7,55 @@
         push R13
         push R14
         push R15
+        push RBP
+        sub RSP, 1096
         mov R15, 2
-        mov R14, 1
         cmp RCX, R15
-        jle VR2
-        mov R14, 0
-        VR2:
-        cmp R14, 0
-        je VR2Start
-        mov R13, 1
-        mov R12, RCX
-        sub R12, R13
-        mov RAX, R12
-        pop R15
-        pop R14
-        pop R13
-        pop R12
-        pop RDI
-        pop RSI
-        pop RBX
-        ret
-        jmp VR2End
-        VR2Start:
-        mov R11, 1
+        jg fib_Block2
+        mov R14, 1
+        mov R13, RCX
+        sub R13, R14
+        mov R12, R13
+        mov R11, R12
+        jmp fib_Block4
+        fib_Block2:
+        mov R14, 1
+        mov R13, RCX
+        sub R13, R14
+        mov RAX, RBP
+        mov RBP, RSP
+        mov 1032[RBP], RAX
+        mov 1040[RBP], RCX
+        mov RCX, R13
+        call fib
+        mov RCX, 1040[RBP]
+        mov RBP, 1032[RBP]
+        mov R12, RAX
+        mov R11, 2
         mov R10, RCX
         sub R10, R11
-        push RCX
-        push RDX
-        push R8
-        push R9
-        push R10
-        push R11
-        sub RSP, 512
+        mov RAX, RBP
         mov RBP, RSP
-        mov 0[RBP], R10
+        mov 1032[RBP], RAX
+        mov 1040[RBP], RCX
+        mov 1048[RBP], R10
+        mov 1056[RBP], R11
         mov RCX, R10
         call fib
-        add RSP, 512
-        pop R11
-        pop R10
-        pop R9
-        pop R8
-        pop RDX
-        pop RCX
+        mov RCX, 1040[RBP]
+        mov R10, 1048[RBP]
+        mov R11, 1056[RBP]
+        mov RBP, 1032[RBP]
         mov R9, RAX
-        mov R8, 2
-        mov RDI, RCX
-        sub RDI, R8
-        push RCX
-        push RDX
-        push R8
-        push R9
-        push R10
-        push R11
-        sub RSP, 512
-        mov RBP, RSP
-        mov 0[RBP], RDI
-        mov RCX, RDI
-        call fib
-        add RSP, 512
-        pop R11
-        pop R10
-        pop R9
-        pop R8
-        pop RDX
-        pop RCX
-        mov RSI, RAX
-        mov RDX, R9
-        add RDX, RSI
-        mov RAX, RDX
-        pop R15
-        pop R14
-        pop R13
-        pop R12
-        pop RDI
-        pop RSI
-        pop RBX
-        ret
-        VR2End:
+        mov R8, R12
+        add R8, R9
+        mov RDI, R8
+        mov RSI, RDI
+        mov R11, RSI
+        fib_Block4:
+        mov RAX, R11
+        add RSP, 1096
+        pop RBP
         pop R15
         pop R14
         pop R13
@@ -103,35 +74,19 @@
         push R13
         push R14
         push R15
+        push RBP
+        sub RSP, 1096
         mov R15, 10
-        push RCX
-        push RDX
-        push R8
-        push R9
-        push R10
-        push R11
-        sub RSP, 512
+        mov RAX, RBP
         mov RBP, RSP
-        mov 0[RBP], R15
+        mov 1032[RBP], RAX
         mov RCX, R15
         call fib
-        add RSP, 512
-        pop R11
-        pop R10
-        pop R9
-        pop R8
-        pop RDX
-        pop RCX
+        mov RBP, 1032[RBP]
         mov R14, RAX
         mov RAX, R14
-        pop R15
-        pop R14
-        pop R13
-        pop R12
-        pop RDI
-        pop RSI
-        pop RBX
-        ret
+        add RSP, 1096
+        pop RBP
         pop R15
         pop R14
         pop R13
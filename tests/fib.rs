@@ -1,4 +1,4 @@
-use eac_compiler::{self, Opt};
+use eac_compiler::{self, golden, Opt};
 use std::fs;
 use std::path::PathBuf;
 
@@ -6,14 +6,25 @@ use std::path::PathBuf;
 fn fib() {
     let opt = Opt {
         input: PathBuf::from("."),
-        ast: false,
-        ssa: false,
-        cfg: false,
-        vasm: false,
-        asm: false,
+        ..Opt::default()
     };
     let source = fs::read_to_string("tests/fib.c").unwrap();
     let asm = eac_compiler::compile(&source, opt).unwrap();
     let expected = fs::read_to_string("tests/fib.asm").unwrap();
-    assert_eq!(asm, expected);
+    // Only changes to the code break the comparison, not to its layout.
+    assert_eq!(golden::normalize(&asm), golden::normalize(&expected));
+}
+
+#[test]
+fn fib_verify_all() {
+    let opt = Opt {
+        input: PathBuf::from("."),
+        verify_all: true,
+        ..Opt::default()
+    };
+    let source = fs::read_to_string("tests/fib.c").unwrap();
+    let asm = eac_compiler::compile(&source, opt).unwrap();
+    let expected = fs::read_to_string("tests/fib.asm").unwrap();
+    // Verifying between passes does not change the code.
+    assert_eq!(golden::normalize(&asm), golden::normalize(&expected));
 }
@@ -0,0 +1,18 @@
+use eac_compiler::{self, stress, Opt};
+use std::time::Instant;
+
+fn compile(source: &str) {
+    let start = Instant::now();
+    assert!(eac_compiler::compile(source, Opt::default()).is_some());
+    println!("compiled {} bytes in {:?}", source.len(), start.elapsed());
+}
+
+#[test]
+fn straight_line() {
+    compile(&stress::straight_line(1, 2000));
+}
+
+#[test]
+fn giant_cfg() {
+    compile(&stress::giant_cfg(1, 100));
+}